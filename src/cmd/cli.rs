@@ -1,30 +1,104 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::future::Future;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
 use anyhow::{bail, Result};
-use clap::Parser;
+use clap::parser::ValueSource;
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser, Subcommand};
+use hickory_proto::rr::rdata::opt::ClientSubnet;
+use hickory_proto::rr::RecordType;
+use time::OffsetDateTime;
+use tokio::task::JoinSet;
+use tokio::time::{sleep, Duration};
+use tracing::{event, Level};
+use uuid::Uuid;
 
-use crate::core::common::{ConnectMethod, IpOptions, IpProtocol, ListenOptions, LoggingOptions, PingOptions};
-use crate::core::config::Config;
+use crate::core::common::{
+    ClientResult, ConnectMethod, DnsOptions, HostRecord, HttpMethod, HttpOptions, HttpUrl, IpOptions, IpProtocol,
+    ListenOptions, LogRotation, LoggingOptions, MailOptions, OutputFormat, PingOptions, ProbeHealth, QuicOptions,
+    RecordFormat, ReportFormat, SignificanceResult, SortBy,
+};
+use crate::core::config::{profile_record_file, validate_config, Config, Profile};
 use crate::core::konst::{
-    BIND_ADDR_IPV4, BIND_ADDR_IPV6, BIND_PORT, CLI_HEADER_MSG, CONFIG_FILE, CURRENT_DIR, LOGFILE_NAME, LOGGING_JSON,
-    LOGGING_QUIET, LOGGING_SYSLOG, PING_INTERVAL, PING_NK_PEER, PING_REPEAT, PING_TIMEOUT,
+    AGENT_RESTART_DELAY, ANALYZE_ALPHA, APP_NAME, BIND_ADDR_IPV4, BIND_ADDR_IPV6, BIND_PORT, CLI_HEADER_MSG,
+    COMPARE_LATENCY_THRESHOLD, COMPARE_LOSS_THRESHOLD, CONFIG_FILE, CONFIG_VALIDATE_RESOLVE, CURRENT_DIR, DNS_NAT64,
+    EXPORT_BUFFER_SIZE, HTTP_PROBE_HEADER, INFLUX_MEASUREMENT, IP_CONCURRENCY, IP_RATE, LISTEN_CORRUPT, LISTEN_DELAY,
+    LISTEN_DROP, LISTEN_ECHO, LOGFILE_NAME, LOGGING_ADVISE, LOGGING_HISTOGRAM, LOGGING_KEEP, LOGGING_OTLP_HISTOGRAMS,
+    LOGGING_PLAIN, LOGGING_ROTATE_SIZE, LOGGING_SORT_DESC, LOGGING_SUMMARY_INTERVAL, LOGGING_VERBOSE, LOGGING_WATCH,
+    LOGGING_WATCH_INTERVAL, MAIL_STARTTLS, PING_ESCALATE, PING_INTERVAL, PING_PAYLOAD, PING_REPEAT, PING_TIMEOUT,
+    PING_TLS, PING_WARM, QUIC_ALPN, SCHEMA_VERSION,
 };
+use crate::core::manifest::{ManifestOptions, ManifestTarget, RunManifest, Vantage};
+use crate::core::targets::{load_targets, ScheduledTarget};
+use crate::dns::client::DnsClient;
+use crate::heartbeat::client::HeartbeatClient;
+use crate::http::client::HttpClient;
+use crate::icmp::client::IcmpClient;
+use crate::icmp::pmtu::PmtuClient;
+use crate::mail::client::MailClient;
+#[cfg(feature = "quic")]
+use crate::quic::client::QuicClient;
 use crate::tcp::client::TcpClient;
+#[cfg(feature = "server")]
 use crate::tcp::server::TcpServer;
+use crate::telnet::client::TelnetClient;
 use crate::udp::client::UdpClient;
+#[cfg(feature = "server")]
 use crate::udp::server::UdpServer;
+#[cfg(unix)]
+use crate::unix::client::UnixClient;
+use crate::util::assertion::{parse_assertion, Assertion};
+use crate::util::dns::{
+    discover_nat64, expand_service, is_srv_name, parse_provider, parse_record_type, resolution_provider,
+};
+use crate::util::handler::{info_handler, summary_handler, Cancel};
+use crate::util::message::{
+    compare_table_msg, destination_msg, health_table_msg, probe_identity_msg, replay_summary_table_msg,
+    resolver_views_table_msg, run_diff_table_msg, service_targets_msg, sla_report_table_msg, vantage_matrix_table_msg,
+};
+use crate::util::nat64::nat64_only;
+use crate::util::parser::{
+    dedup_destinations, parse_destinations, parse_header_name, parse_rfc3339, parse_sample, parse_scoped_ipaddr,
+    parse_size, parse_tag, parse_target, parse_url,
+};
+use crate::util::record::{convert_records, destination_latencies, destination_results, migrate_records, RecordReader};
+use crate::util::report::{read_log_records, sla_report, sla_report_html};
+use crate::util::result::diff_results;
+use crate::util::rules::{client_flows, server_flow, suggest_rules, FlowRole};
+use crate::util::stats::{kolmogorov_smirnov, mann_whitney_u};
+use crate::util::time::time_now_utc;
 use crate::util::validate::validate_local_ip;
 
-#[derive(Debug, Parser)]
+#[derive(Clone, Debug, Parser)]
 #[command(name = "nk")]
 #[command(bin_name = "nk")]
 #[command(version = env!("CARGO_PKG_VERSION"))]
 #[command(about = "NetKraken - Cross platform network connectivity tester", long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
 pub struct Cli {
-    /// Destination hostname or IP address
-    pub host: Option<String>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Destination hostname, IP address or
+    /// UNIX socket path in `-m unix|unixgram` mode,
+    /// followed by the destination port or
+    /// Listen port in `-l --listen` mode.
+    /// Multiple TCP/UDP destinations are given as host:port
+    pub destinations: Vec<String>,
 
-    /// Destination port or
-    /// Listen port in `-l --listen` mode
-    pub port: Option<u16>,
+    /// Read destinations from a file, one host:port per line,
+    /// `[[targets]]` tables in a `.toml` file, or
+    /// `host,port,protocol,label,timeout` rows in a `.csv` file.
+    /// TOML targets with a `schedule` are skipped outside of it
+    #[clap(long)]
+    pub targets_file: Option<String>,
+
+    /// Labelled destination shown by its label instead of its
+    /// addresses, eg: core-fw=10.1.1.1:443 (repeatable)
+    #[clap(long = "target", value_parser = parse_target)]
+    pub targets: Vec<(String, (String, u16))>,
 
     /// Repeat count (0 == max == 65535)
     #[clap(short, long, default_value_t = PING_REPEAT)]
@@ -38,6 +112,27 @@ pub struct Cli {
     #[clap(short, long, default_value_t = PING_TIMEOUT)]
     pub timeout: u16,
 
+    /// Send a payload of this many bytes after connecting and
+    /// verify the echo from a `--echo` server (TCP only)
+    #[clap(long, default_value_t = PING_PAYLOAD)]
+    pub payload: u32,
+
+    /// Also send a request over an established connection each
+    /// cycle, to compare with new connections (TCP only)
+    #[clap(long, default_value_t = PING_WARM)]
+    pub warm: bool,
+
+    /// Perform a TLS handshake after connecting, reporting the
+    /// protocol version, cipher suite and certificate expiry (TCP only)
+    #[clap(long, default_value_t = PING_TLS)]
+    pub tls: bool,
+
+    /// When a destination starts failing, collect evidence: resolve
+    /// the host again, attempt a TLS handshake, ping the default
+    /// gateway and trace the path (TCP only)
+    #[clap(long, default_value_t = PING_ESCALATE)]
+    pub escalate: bool,
+
     /// Connection Method
     #[clap(short, long, default_value_t = ConnectMethod::TCP)]
     pub method: ConnectMethod,
@@ -46,11 +141,33 @@ pub struct Cli {
     #[clap(short = 'I', long, default_value_t = IpProtocol::V4)]
     pub ip_proto: IpProtocol,
 
+    /// Maximum simultaneous IPv4 connections per host
+    #[clap(long, default_value_t = IP_CONCURRENCY)]
+    pub v4_concurrency: usize,
+
+    /// Maximum simultaneous IPv6 connections per host
+    #[clap(long, default_value_t = IP_CONCURRENCY)]
+    pub v6_concurrency: usize,
+
+    /// Maximum IPv4 connections started per second (0 is unlimited)
+    #[clap(long, default_value_t = IP_RATE)]
+    pub v4_rate: u16,
+
+    /// Maximum IPv6 connections started per second (0 is unlimited)
+    #[clap(long, default_value_t = IP_RATE)]
+    pub v6_rate: u16,
+
+    /// Keep the configured source when it repeatedly fails to bind,
+    /// instead of falling back to the wildcard source.
+    #[clap(long, default_value_t = false)]
+    pub no_source_fallback: bool,
+
     /// Source IPv4 Address
     #[clap(long, default_value = BIND_ADDR_IPV4)]
     pub src_v4: String,
 
-    /// Source IPv6 Address
+    /// Source IPv6 Address.
+    /// Link-local addresses require a zone ID (fe80::1%eth0)
     #[clap(long, default_value = BIND_ADDR_IPV6)]
     pub src_v6: String,
 
@@ -62,6 +179,30 @@ pub struct Cli {
     #[clap(short, long, default_value_t = false)]
     pub nk_peer: bool,
 
+    /// Issue STARTTLS in SMTP/IMAP mode
+    #[clap(long, default_value_t = MAIL_STARTTLS)]
+    pub starttls: bool,
+
+    /// Request method in HTTP mode
+    #[clap(long, value_enum, default_value_t = HttpMethod::Get)]
+    pub http_method: HttpMethod,
+
+    /// Header identifying the run and agent of HTTP requests, empty to disable
+    #[clap(long, default_value = HTTP_PROBE_HEADER, value_parser = parse_header_name)]
+    pub probe_header: String,
+
+    /// Agent name in the probe header, the hostname by default
+    #[clap(long, default_value = "")]
+    pub agent_name: String,
+
+    /// ALPN protocol to negotiate in QUIC mode
+    #[clap(long, default_value = QUIC_ALPN)]
+    pub alpn: String,
+
+    /// Discover the path MTU to the destination in ICMP mode
+    #[clap(long, default_value_t = false)]
+    pub pmtu: bool,
+
     /// Config filename.
     /// Search Path: $CWD/nk.toml
     #[clap(short, long, default_value = CONFIG_FILE)]
@@ -71,12 +212,41 @@ pub struct Cli {
     #[clap(long, default_value_t = false)]
     pub config_generate: bool,
 
+    /// Write a JSON manifest describing the run to a file
+    #[clap(long)]
+    pub manifest: Option<String>,
+
+    /// Print the firewall rules required to permit
+    /// the connections, then exit.
+    #[clap(long, default_value_t = false)]
+    pub suggest_rules: bool,
+
     // Server specific options
     // -----------------------
     /// Listen as a server
     #[clap(short, long, default_value_t = false)]
     pub listen: bool,
 
+    /// Echo back everything received from the client (TCP only)
+    #[clap(long, default_value_t = LISTEN_ECHO)]
+    pub echo: bool,
+
+    /// Delay server responses (in milliseconds)
+    #[clap(long, default_value_t = LISTEN_DELAY)]
+    pub delay: u16,
+
+    /// Drop a percentage of server responses
+    #[clap(long, default_value_t = LISTEN_DROP, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub drop: u8,
+
+    /// Corrupt a percentage of server responses
+    #[clap(long, default_value_t = LISTEN_CORRUPT, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub corrupt: u8,
+
+    /// Seed the dropped and corrupted responses, to repeat them on each run
+    #[clap(long)]
+    pub seed: Option<u64>,
+
     // Logging options
     // --------------
     /// Logging directory
@@ -84,9 +254,21 @@ pub struct Cli {
     pub dir: String,
 
     /// Logging filename
-    #[clap(short, long="file", default_value = LOGFILE_NAME)]
+    #[clap(short, long="file", alias = "log-file", default_value = LOGFILE_NAME)]
     pub file: String,
 
+    /// Rotate the log file when it reaches a size, eg: 10M (0 disables)
+    #[clap(long, default_value_t = LOGGING_ROTATE_SIZE, value_parser = parse_size)]
+    pub log_rotate_size: u64,
+
+    /// Rotate the log file on a schedule
+    #[clap(long, default_value_t = LogRotation::Never)]
+    pub log_rotate: LogRotation,
+
+    /// Number of rotated log files to keep
+    #[clap(long, default_value_t = LOGGING_KEEP)]
+    pub log_keep: u16,
+
     /// Log to file in JSON format
     #[clap(short, long, default_value_t = false)]
     pub json: bool,
@@ -98,137 +280,1475 @@ pub struct Cli {
     /// Silence terminal output
     #[clap(short, long, default_value_t = false)]
     pub quiet: bool,
+
+    /// Print a latency histogram of each destination in the summary
+    #[clap(long, default_value_t = LOGGING_HISTOGRAM)]
+    pub histogram: bool,
+
+    /// Print the likely causes of failures and the next commands to run after the summary
+    #[clap(long, default_value_t = LOGGING_ADVISE)]
+    pub advise: bool,
+
+    /// Only print results in the output format, without banners, headers or tables
+    #[clap(long, default_value_t = LOGGING_PLAIN)]
+    pub plain: bool,
+
+    /// Tag results with a key=value pair (repeatable)
+    #[clap(long = "tag", value_parser = parse_tag)]
+    pub tags: Vec<(String, String)>,
+
+    /// Terminal output format for client results
+    #[clap(long, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+
+    /// Show the 5-tuple used by each connection
+    #[clap(short, long, default_value_t = LOGGING_VERBOSE)]
+    pub verbose: bool,
+
+    /// Write results to a compact binary record file.
+    /// Use `nk convert` or `nk replay` to read it back.
+    #[clap(long, alias = "record")]
+    pub record_file: Option<String>,
+
+    /// Append results, and a final summary, to a CSV file
+    #[clap(long = "csv")]
+    pub csv_file: Option<String>,
+
+    /// Export results as spans to an OpenTelemetry collector
+    /// (OTLP/HTTP JSON), eg: http://localhost:4318
+    #[clap(long)]
+    pub otlp_endpoint: Option<String>,
+
+    /// Also export the latency of each destination as OTLP
+    /// exponential histogram metrics
+    #[clap(long, default_value_t = LOGGING_OTLP_HISTOGRAMS)]
+    pub otlp_histograms: bool,
+
+    /// Forward results to a syslog server in RFC5424 format,
+    /// eg: udp://host:514, tcp://host:514 or unix:///dev/log
+    #[clap(long)]
+    pub syslog_target: Option<String>,
+
+    /// Write an evidence bundle for each outage found by --escalate to a directory
+    #[clap(long)]
+    pub evidence_dir: Option<String>,
+
+    /// Write results to InfluxDB in line protocol, eg:
+    /// http://localhost:8086/api/v2/write?org=nk&bucket=probes
+    #[clap(long = "influx")]
+    pub influx_url: Option<String>,
+
+    /// InfluxDB measurement name
+    #[clap(long, default_value_t = INFLUX_MEASUREMENT.to_owned())]
+    pub influx_measurement: String,
+
+    /// Prefix added to InfluxDB tag names
+    #[clap(long, default_value = "")]
+    pub influx_tag_prefix: String,
+
+    /// InfluxDB API token
+    #[clap(long, env = "NK_INFLUX_TOKEN", hide_env_values = true)]
+    pub influx_token: Option<String>,
+
+    /// Buffer OTLP and InfluxDB exports in a directory while their
+    /// endpoint is unreachable, and send them once it is reachable
+    #[clap(long)]
+    pub export_buffer: Option<String>,
+
+    /// Maximum size of the export buffer of each endpoint, eg: 10M.
+    /// The oldest exports are dropped once it is full.
+    #[clap(long, default_value_t = EXPORT_BUFFER_SIZE, value_parser = parse_size)]
+    pub export_buffer_size: u64,
+
+    /// Pass/fail expression evaluated per destination at the end of the run,
+    /// eg: "p95 < 80 && loss < 0.5". Exits with an error if any destination fails.
+    #[clap(long = "assert", value_parser = parse_assertion)]
+    pub assertion: Option<Assertion>,
+
+    /// Only output every Nth connection result (1/N).
+    /// Statistics are calculated from all connections.
+    #[clap(long, default_value = "1/1", value_parser = parse_sample)]
+    pub sample: u16,
+
+    /// Print a summary of each interval (in seconds).
+    /// 0 disables interval summaries.
+    #[clap(long, default_value_t = LOGGING_SUMMARY_INTERVAL)]
+    pub summary_interval: u16,
+
+    /// Print a rolling summary of the last N probes to each
+    /// destination every `--summary-interval` seconds (default 5).
+    /// 0 disables rolling summaries.
+    #[clap(long, default_value_t = LOGGING_WATCH)]
+    pub watch: u16,
+
+    /// Order of the summary table: dest (by address), name
+    /// (by destination text), loss, p95 or avg.
+    #[clap(long, default_value_t = SortBy::Dest)]
+    pub sort_by: SortBy,
+
+    /// Sort the summary table in descending order
+    #[clap(long, default_value_t = LOGGING_SORT_DESC)]
+    pub sort_desc: bool,
+
+    /// Resolver to use (repeatable): system, IP[:port], https://URL,
+    /// mdns, hosts:FILE or kubernetes[:NAMESPACE].
+    /// Each resolver's answers are probed separately.
+    #[clap(long = "resolver", value_parser = parse_provider)]
+    pub resolvers: Vec<String>,
+
+    /// EDNS Client Subnet sent to resolvers (192.0.2.0/24).
+    /// Requires `--resolver`.
+    #[clap(long)]
+    pub client_subnet: Option<String>,
+
+    /// Expand the host into targets using its HTTPS/SVCB records.
+    /// SRV service names (_sip._tcp.example.com) are always expanded.
+    #[clap(long, default_value_t = false)]
+    pub svcb: bool,
+
+    /// Record type to query in DNS mode (A, AAAA, MX, TXT, ...).
+    /// Defaults to the A and AAAA records.
+    #[clap(long, value_parser = parse_record_type)]
+    pub record_type: Option<RecordType>,
+
+    /// Time a DNS failover was made (RFC3339), to measure
+    /// how long the new answers take to propagate in DNS mode.
+    #[clap(long, value_parser = parse_rfc3339)]
+    pub failover_time: Option<OffsetDateTime>,
+
+    /// Discover the NAT64 prefix with DNS64 and probe IPv4 only
+    /// hosts via NAT64. Requires `--ip-proto v6` or `all`.
+    #[clap(long, default_value_t = DNS_NAT64)]
+    pub nat64: bool,
+
+    /// Options given on the command line or in the environment,
+    /// which take the place of the config file options.
+    #[clap(skip)]
+    pub given: BTreeSet<String>,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum Command {
+    /// Convert a binary record file to JSON or CSV
+    Convert {
+        /// Record file written with `--record-file`
+        input: String,
+
+        /// Output format
+        #[clap(long, default_value_t = RecordFormat::Json)]
+        format: RecordFormat,
+
+        /// Output filename (default: stdout)
+        #[clap(short, long)]
+        output: Option<String>,
+    },
+    /// Upgrade stored records to the current schema version
+    Migrate {
+        /// Record file, or JSON records written with `nk convert`
+        input: String,
+
+        /// Output filename
+        output: String,
+    },
+    /// Test whether the latency of two destinations differs significantly
+    Analyze {
+        /// Record file written with `--record-file`
+        input: String,
+
+        /// Destinations (IP:port) to compare
+        #[clap(long, num_args = 2, value_names = ["A", "B"], required = true)]
+        compare: Vec<String>,
+
+        /// Significance level
+        #[clap(long, default_value_t = ANALYZE_ALPHA)]
+        alpha: f64,
+    },
+    /// Summarise the destinations of a record file, as the run that wrote it did
+    Replay {
+        /// Record file written with `--record-file`
+        input: String,
+
+        /// Include a latency histogram of each destination
+        #[clap(long, default_value_t = LOGGING_HISTOGRAM)]
+        histogram: bool,
+    },
+    /// Compare the loss and latency of each destination in two record files,
+    /// such as before and after a network change
+    Compare {
+        /// Record file of the first run, written with `--record-file`
+        before: String,
+
+        /// Record file of the second run, written with `--record-file`
+        after: String,
+
+        /// Average or P95 latency increase (%) that is a regression
+        #[clap(long, default_value_t = COMPARE_LATENCY_THRESHOLD)]
+        latency_threshold: f64,
+
+        /// Loss increase (percentage points) that is a regression
+        #[clap(long, default_value_t = COMPARE_LOSS_THRESHOLD)]
+        loss_threshold: f64,
+    },
+    /// Compare the same destinations from record files written by multiple vantage points
+    Vantages {
+        /// Record files written with `--record-file`, as `NAME=FILE`
+        /// to name the vantage point (default: the file name)
+        #[clap(required = true, num_args = 2..)]
+        inputs: Vec<String>,
+    },
+    /// Report the availability, latency percentiles and outages
+    /// of each destination over a period, from JSON log files
+    Report {
+        /// Log files written with `--json`
+        #[clap(default_value = LOGFILE_NAME)]
+        inputs: Vec<String>,
+
+        /// Start of the period (RFC3339)
+        #[clap(long, value_parser = parse_rfc3339)]
+        from: OffsetDateTime,
+
+        /// End of the period (RFC3339)
+        #[clap(long, value_parser = parse_rfc3339)]
+        to: OffsetDateTime,
+
+        /// Tag to group destinations by, eg: site
+        #[clap(long)]
+        group_by: Option<String>,
+
+        /// Output format
+        #[clap(long, default_value_t = ReportFormat::Text)]
+        format: ReportFormat,
+
+        /// Output filename (default: stdout)
+        #[clap(short, long)]
+        output: Option<String>,
+    },
+    /// Run a probe profile from the config file
+    Run {
+        /// Profile name, a `[profiles.<name>]` table in the config file
+        profile: String,
+
+        /// Config filename
+        #[clap(short, long, default_value = CONFIG_FILE)]
+        config: String,
+    },
+    /// Check the health of targets from the config file. A target is
+    /// healthy when its profiles pass by its policy (all or any).
+    Health {
+        /// Health target names, a `[health.<name>]` table in the config file (default: all)
+        targets: Vec<String>,
+
+        /// Config filename
+        #[clap(short, long, default_value = CONFIG_FILE)]
+        config: String,
+    },
+    /// Run the probe profiles from the config file concurrently until
+    /// stopped. Profiles run continuously unless they set a repeat
+    /// count, and a profile whose probe fails is restarted.
+    Agent {
+        /// Profile names, `[profiles.<name>]` tables in the config file (default: all)
+        profiles: Vec<String>,
+
+        /// Config filename
+        #[clap(short, long, default_value = CONFIG_FILE)]
+        config: String,
+    },
+    /// Manage config files
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Check that a config file, and the targets file used with it, are valid
+    Validate {
+        /// Config filename
+        #[clap(default_value = CONFIG_FILE)]
+        file: String,
+
+        /// Targets file to check, and resolve a sample of the hosts of
+        #[clap(long)]
+        targets_file: Option<String>,
+
+        /// Number of target hosts to resolve
+        #[clap(long, default_value_t = CONFIG_VALIDATE_RESOLVE)]
+        resolve: usize,
+
+        /// Check that the log, record and CSV files are writable
+        #[clap(long, default_value_t = false)]
+        check_sinks: bool,
+    },
 }
 
 impl Cli {
     pub fn init() -> Cli {
-        Cli::parse()
+        match Cli::try_parse_args(std::env::args_os(), &env_var) {
+            Ok(cli) => cli,
+            Err(e) => e.exit(),
+        }
+    }
+
+    /// Parse the options, with every option also read from an `NK_` or
+    /// `KRAKEN_` environment variable named after its long flag (`--src-v4`
+    /// is `NK_SRC_V4` or `KRAKEN_SRC_V4`), looked up with `env`. The command
+    /// line takes precedence over the environment, `NK_` over `KRAKEN_`,
+    /// and all of them over the config file.
+    pub fn try_parse_args<I, T>(args: I, env: &dyn Fn(&str) -> Option<String>) -> Result<Cli, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        let command = Cli::command();
+        let ids: Vec<clap::Id> = command.get_arguments().map(|arg| arg.get_id().clone()).collect();
+        // Environment values take the place of the defaults,
+        // but are given options unlike the defaults.
+        let mut from_env = BTreeSet::new();
+        let command = ids.into_iter().fold(command, |command, id| {
+            command.mut_arg(id, |arg| {
+                let (Some(long), None) = (arg.get_long(), arg.get_env()) else {
+                    return arg;
+                };
+                let name = long.to_uppercase().replace('-', "_");
+                let Some(value) = env(&format!("NK_{name}")).or_else(|| env(&format!("KRAKEN_{name}"))) else {
+                    return arg;
+                };
+                from_env.insert(arg.get_id().to_string());
+                match arg.get_action() {
+                    ArgAction::SetTrue => arg.default_value(env_flag(&value).to_string()),
+                    _ => arg.default_value(value),
+                }
+            })
+        });
+        let matches = command.try_get_matches_from(args)?;
+        let mut cli = Cli::from_arg_matches(&matches)?;
+        cli.given = matches
+            .ids()
+            .filter(|id| match matches.value_source(id.as_str()) {
+                Some(ValueSource::CommandLine) => true,
+                Some(ValueSource::DefaultValue) => from_env.contains(id.as_str()),
+                _ => false,
+            })
+            .map(|id| id.to_string())
+            .collect();
+        Ok(cli)
+    }
+
+    /// Set an option as though it was given on the command line.
+    fn give<T>(&mut self, id: &str, value: Option<T>) -> Option<T> {
+        if value.is_some() {
+            self.given.insert(id.to_owned());
+        }
+        value
+    }
+
+    fn apply_profile(&mut self, profile: &Profile) -> Result<()> {
+        self.method = profile.connect_method()?;
+        self.destinations = match profile.port {
+            Some(port) => vec![profile.host.to_owned(), port.to_string()],
+            None => vec![profile.host.to_owned()],
+        };
+        if let Some(src_ip) = &profile.src_ip {
+            match src_ip.contains(':') {
+                true => self.src_v6 = src_ip.to_owned(),
+                false => self.src_v4 = src_ip.to_owned(),
+            }
+        }
+        self.src_port = profile.src_port.unwrap_or(self.src_port);
+        self.repeat = self.give("repeat", profile.repeat).unwrap_or(self.repeat);
+        self.interval = self.give("interval", profile.interval).unwrap_or(self.interval);
+        self.timeout = self.give("timeout", profile.timeout).unwrap_or(self.timeout);
+        self.output = self.give("output", profile.output).unwrap_or(self.output);
+        Ok(())
     }
 
     pub async fn run(&self) -> Result<()> {
-        println!("{CLI_HEADER_MSG}");
-        let cli = Cli::parse();
+        let mut cli = self.clone();
+
+        // A profile is run as though its options were given on the command line,
+        // so they override the config file options.
+        if let Some(Command::Run { profile, config }) = &cli.command {
+            let profiles = Config::parse(config)?.profiles;
+            let Some(profile) = profiles.get(profile) else {
+                bail!("profile: `{profile}` not found in config file `{config}`");
+            };
+            cli.config = config.to_owned();
+            cli.apply_profile(profile)?;
+            cli.command = None;
+        }
+
+        // region:    ===== subcommands ===== //
+
+        // Subcommands write to stdout, so they
+        // run before the header is printed.
+        if let Some(command) = cli.command {
+            match command {
+                Command::Convert { input, format, output } => {
+                    let records = RecordReader::open(&input)?;
+                    match output {
+                        Some(output) => convert_records(records, format, &mut File::create(output)?)?,
+                        None => convert_records(records, format, &mut std::io::stdout().lock())?,
+                    }
+                }
+                Command::Migrate { input, output } => {
+                    let count = migrate_records(&input, &output)?;
+                    println!("Migrated {count} records to schema version {SCHEMA_VERSION} in `{output}`.");
+                }
+                Command::Analyze { input, compare, alpha } => {
+                    let latencies = destination_latencies(RecordReader::open(&input)?)?;
+                    let (a, b) = (&compare[0], &compare[1]);
+                    let samples = |dst: &String| match latencies.get(dst) {
+                        Some(samples) if samples.len() >= 2 => Ok(samples),
+                        Some(_) => bail!("{dst} requires at least 2 successful connections to compare"),
+                        None => bail!("{dst} not found in {input}"),
+                    };
+                    let (a_samples, b_samples) = (samples(a)?, samples(b)?);
+                    let results: Vec<SignificanceResult> = [
+                        ("Mann-Whitney U", mann_whitney_u(a_samples, b_samples)),
+                        ("Kolmogorov-Smirnov", kolmogorov_smirnov(a_samples, b_samples)),
+                    ]
+                    .into_iter()
+                    .map(|(test, result)| SignificanceResult {
+                        test: test.to_owned(),
+                        statistic: result.statistic,
+                        p_value: result.p_value,
+                        significant: result.p_value < alpha,
+                    })
+                    .collect();
+                    println!("{}", compare_table_msg(a, a_samples, b, b_samples, &results));
+                }
+                Command::Replay { input, histogram } => {
+                    let client_results = destination_results(RecordReader::open(&input)?)?;
+                    if client_results.is_empty() {
+                        bail!("{input} has no records to replay");
+                    }
+                    println!("{}", replay_summary_table_msg(&input, &client_results, histogram));
+                }
+                Command::Compare {
+                    before,
+                    after,
+                    latency_threshold,
+                    loss_threshold,
+                } => {
+                    let diffs = diff_results(
+                        &destination_results(RecordReader::open(&before)?)?,
+                        &destination_results(RecordReader::open(&after)?)?,
+                        latency_threshold,
+                        loss_threshold,
+                    );
+                    println!("{}", run_diff_table_msg(&before, &after, &diffs));
+                    match diffs.iter().filter(|d| d.regressed).count() {
+                        0 => {}
+                        1 => bail!("1 destination regressed."),
+                        regressed => bail!("{regressed} destinations regressed."),
+                    }
+                }
+                Command::Vantages { inputs } => {
+                    let mut vantages = Vec::new();
+                    for input in inputs {
+                        let (vantage, filename) = match input.split_once('=') {
+                            Some((vantage, filename)) => (vantage.to_owned(), filename.to_owned()),
+                            None => {
+                                let vantage = Path::new(&input).file_stem().unwrap_or_default();
+                                (vantage.to_string_lossy().to_string(), input.to_owned())
+                            }
+                        };
+                        vantages.push((vantage, destination_results(RecordReader::open(&filename)?)?));
+                    }
+                    println!("{}", vantage_matrix_table_msg(&vantages));
+                }
+                Command::Report {
+                    inputs,
+                    from,
+                    to,
+                    group_by,
+                    format,
+                    output,
+                } => {
+                    if from >= to {
+                        bail!("The report period must start before it ends.");
+                    }
+                    let mut records = Vec::new();
+                    for input in inputs {
+                        records.extend(read_log_records(BufReader::new(File::open(input)?), from, to)?);
+                    }
+                    let groups = sla_report(&records, group_by.as_deref());
+                    let report = match format {
+                        ReportFormat::Text => sla_report_table_msg(from, to, &groups),
+                        ReportFormat::Html => sla_report_html(from, to, &groups),
+                    };
+                    match output {
+                        Some(output) => std::fs::write(output, report)?,
+                        None => println!("{report}"),
+                    }
+                }
+                Command::Health { targets, config } => {
+                    let config_file = Config::parse(&config)?;
+                    if let Some(error) = config_file.validate().first() {
+                        bail!("config file: `{config}` is invalid: {error}");
+                    }
+                    let targets = match targets.is_empty() {
+                        true => config_file.health.keys().cloned().collect(),
+                        false => targets,
+                    };
+                    let mut unhealthy = Vec::new();
+                    for name in targets {
+                        let Some(health) = config_file.health.get(&name) else {
+                            bail!("health target: `{name}` not found in config file `{config}`");
+                        };
+                        let mut probes = Vec::new();
+                        for profile_name in &health.profiles {
+                            let profile = &config_file.profiles[profile_name];
+                            let protocol = profile.connect_method()?;
+                            // Each probe only prints its summary.
+                            let mut probe_cli = Cli::try_parse_args(["nk"], &env_var)?;
+                            probe_cli.config = config.to_owned();
+                            probe_cli.quiet = true;
+                            probe_cli.given.insert("quiet".to_owned());
+                            probe_cli.apply_profile(profile)?;
+                            probes.push(match Cli::probe(probe_cli).await {
+                                Ok(client_results) => ProbeHealth::new(profile_name, protocol, &client_results),
+                                Err(e) => ProbeHealth::failed(profile_name, protocol, e.to_string()),
+                            });
+                        }
+                        let healthy = health
+                            .policy
+                            .healthy(&probes.iter().map(|probe| probe.passed).collect::<Vec<bool>>());
+                        println!("{}", health_table_msg(&name, health.policy, healthy, &probes));
+                        if !healthy {
+                            unhealthy.push(name);
+                        }
+                    }
+                    match unhealthy.len() {
+                        0 => {}
+                        1 => bail!("Target {} is unhealthy.", unhealthy[0]),
+                        _ => bail!("Targets {} are unhealthy.", unhealthy.join(", ")),
+                    }
+                }
+                Command::Agent { profiles, config } => {
+                    let config_file = Config::parse(&config)?;
+                    if let Some(error) = config_file.validate().first() {
+                        bail!("config file: `{config}` is invalid: {error}");
+                    }
+                    let profiles = match profiles.is_empty() {
+                        true => config_file.profiles.keys().cloned().collect(),
+                        false => profiles,
+                    };
+                    if profiles.is_empty() {
+                        bail!("config file: `{config}` has no profiles to run");
+                    }
+                    // Ctrl-C stops the profiles, which are restarted until then.
+                    let cancel = Cancel::on_ctrl_c();
+                    let mut probes = JoinSet::new();
+                    for name in profiles {
+                        let Some(profile) = config_file.profiles.get(&name) else {
+                            bail!("profile: `{name}` not found in config file `{config}`");
+                        };
+                        // Record files start with a header, so each profile writes its own.
+                        let record_file = cli
+                            .record_file
+                            .as_ref()
+                            .or(config_file.logging_options.record_file.as_ref())
+                            .map(|record_file| profile_record_file(record_file, &name));
+                        probes.spawn(Cli::agent_probe(
+                            name,
+                            profile.clone(),
+                            config.to_owned(),
+                            record_file,
+                            cancel.clone(),
+                        ));
+                    }
+                    while probes.join_next().await.is_some() {}
+                }
+                // Profiles are applied to the probe options above.
+                Command::Run { .. } => unreachable!(),
+                Command::Config {
+                    command:
+                        ConfigCommand::Validate {
+                            file,
+                            targets_file,
+                            resolve,
+                            check_sinks,
+                        },
+                } => {
+                    let report = validate_config(&file, targets_file.as_deref(), resolve, check_sinks).await?;
+                    for error in &report.errors {
+                        println!("error: {error}");
+                    }
+                    for warning in &report.warnings {
+                        println!("warning: {warning}");
+                    }
+                    match report.errors.len() {
+                        0 => println!("Config file `{file}` is valid."),
+                        1 => bail!("Config file `{file}` has 1 error."),
+                        n => bail!("Config file `{file}` has {n} errors."),
+                    }
+                }
+            }
+            return Ok(());
+        }
+
+        // endregion: ===== subcommands ===== //
+
+        // Targets with their own protocol are each probed by their own client.
+        if let Some(targets_file) = cli.targets_file.as_ref().filter(|file| file.ends_with(".csv")) {
+            let targets = load_targets(targets_file, OffsetDateTime::now_utc())?;
+            Cli::probe_targets(cli, targets).await?;
+            return Ok(());
+        }
+        Cli::probe(cli).await?;
+        Ok(())
+    }
+
+    /// Run the probe of an agent profile for as long as the agent runs.
+    async fn agent_probe(name: String, profile: Profile, config: String, record_file: Option<String>, cancel: Cancel) {
+        let (profile, config, record_file) = (&profile, &config, &record_file);
+        Cli::supervise_probe(&name, &cancel, AGENT_RESTART_DELAY, || async move {
+            let mut probe_cli = Cli::try_parse_args(["nk"], &env_var)?;
+            probe_cli.config = config.to_owned();
+            probe_cli.repeat = 0;
+            probe_cli.given.insert("repeat".to_owned());
+            probe_cli.record_file = record_file.to_owned();
+            probe_cli.apply_profile(profile)?;
+            Cli::probe(probe_cli).await
+        })
+        .await
+    }
+
+    /// Run a probe until cancelled, restarting it after a delay when it fails,
+    /// or when it ends, as a probe does after its maximum number of requests.
+    async fn supervise_probe<F, Fut, T>(name: &str, cancel: &Cancel, restart_delay: u16, mut probe: F)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        loop {
+            match probe().await {
+                // A probe stopped by Ctrl-C is not restarted.
+                Ok(_) if cancel.is_cancelled() => return,
+                Ok(_) => {
+                    let msg = format!("Profile {name} ended, restarting in {restart_delay}ms.");
+                    eprintln!("{msg}");
+                    event!(target: APP_NAME, Level::WARN, "{msg}");
+                }
+                Err(e) => {
+                    let msg = format!("Profile {name} failed: {e}, restarting in {restart_delay}ms.");
+                    eprintln!("{msg}");
+                    event!(target: APP_NAME, Level::ERROR, "{msg}");
+                }
+            }
+            let delay = Duration::from_millis(restart_delay.into());
+            if cancel.run(sleep(delay)).await.is_none() {
+                return;
+            }
+        }
+    }
+
+    /// Probe the targets of a CSV targets file in parallel, each with its own
+    /// protocol and timeout, as though each was given on the command line.
+    async fn probe_targets(cli: Cli, targets: Vec<ScheduledTarget>) -> Result<Vec<ClientResult>> {
+        if !cli.destinations.is_empty() || !cli.targets.is_empty() {
+            bail!("Destinations cannot be given with a CSV targets file.");
+        }
+        let mut probes = JoinSet::new();
+        for target in targets {
+            let mut probe_cli = cli.clone();
+            probe_cli.targets_file = None;
+            probe_cli.method = target.protocol.unwrap_or(cli.method);
+            probe_cli.timeout = probe_cli.give("timeout", target.timeout).unwrap_or(cli.timeout);
+            let destination = destination_msg(&target.host, target.port);
+            match (target.label, target.port) {
+                // HTTP targets are URLs.
+                _ if probe_cli.method == ConnectMethod::HTTP => probe_cli.destinations = vec![target.host],
+                (Some(label), port) => probe_cli.targets = vec![(label, (target.host, port))],
+                (None, 0) => probe_cli.destinations = vec![target.host],
+                (None, port) => probe_cli.destinations = vec![target.host, port.to_string()],
+            }
+            probes.spawn(async move { (destination, Cli::probe(probe_cli).await) });
+        }
+
+        let mut probe_results = Vec::new();
+        let mut failed = Vec::new();
+        while let Some(probe) = probes.join_next().await {
+            match probe? {
+                (_, Ok(client_results)) => probe_results.extend(client_results),
+                // A failed target does not stop the other targets.
+                (destination, Err(e)) => {
+                    eprintln!("{destination} failed: {e}");
+                    failed.push(destination);
+                }
+            }
+        }
+        match failed.len() {
+            0 => Ok(probe_results),
+            1 => bail!("Target {} failed.", failed[0]),
+            failed => bail!("{failed} targets failed."),
+        }
+    }
+
+    /// Run the probe given by the CLI options, returning the client results.
+    async fn probe(cli: Cli) -> Result<Vec<ClientResult>> {
+        let mut probe_results: Vec<ClientResult> = Vec::new();
+
+        // JSON output is only JSON documents and plain output is only results,
+        // so they can be piped to other tools. Until the configuration file is
+        // loaded, informational messages follow the command line options.
+        let text_output = cli.output == OutputFormat::Text && !cli.plain;
+        if text_output {
+            println!("{CLI_HEADER_MSG}");
+        }
 
         // region:    ===== pre-required args ===== //
 
         if cli.config_generate {
             Config::generate()?;
-            return Ok(());
+            return Ok(probe_results);
         }
 
         // endregion: ===== pre-required args ===== //
 
         // Host and port are required. If we don't receive them
         // from the CLI, we should error out.
-        let host = cli.host.unwrap_or_default();
-        let port = cli.port.unwrap_or_default();
-        if host.is_empty() || port == 0 {
+        // UNIX sockets only require a path.
+        // Service names get their port from the SRV or HTTPS/SVCB records.
+        // Heartbeats do not require a destination.
+        // HTTP destinations are URLs, the host and port come from the URL.
+        let http_urls = match cli.method == ConnectMethod::HTTP && !cli.listen {
+            true => cli
+                .destinations
+                .iter()
+                .map(|url| parse_url(url))
+                .collect::<Result<Vec<HttpUrl>>>()?,
+            false => vec![],
+        };
+        let http_url = http_urls.first().cloned();
+        let mut target_resolvers: BTreeMap<String, String> = BTreeMap::new();
+        let mut target_labels: BTreeMap<String, String> = BTreeMap::new();
+        let mut destinations = match http_urls.is_empty() {
+            true => parse_destinations(&cli.destinations)?,
+            false => http_urls.iter().map(|url| (url.host.to_owned(), url.port)).collect(),
+        };
+        for (label, (host, port)) in &cli.targets {
+            target_labels.insert(destination_msg(host, *port), label.to_owned());
+            destinations.push((host.to_owned(), *port));
+        }
+        if let Some(targets_file) = &cli.targets_file {
+            let targets = load_targets(targets_file, OffsetDateTime::now_utc())?;
+            for target in &targets {
+                let destination = (target.host.to_owned(), target.port);
+                if let Some(resolver) = &target.resolver {
+                    target_resolvers.insert(target.host.to_owned(), resolver.to_owned());
+                }
+                if let Some(label) = &target.label {
+                    target_labels.insert(destination_msg(&target.host, target.port), label.to_owned());
+                }
+                if !target.active {
+                    if text_output {
+                        println!(
+                            "Skipping {}:{}, outside of the `{}` schedule.",
+                            target.host,
+                            target.port,
+                            target.schedule.as_deref().unwrap_or_default()
+                        );
+                    }
+                } else {
+                    destinations.push(destination);
+                }
+            }
+            // Every target being outside of its schedule is not an error,
+            // so scheduled runs can be started at any time.
+            if destinations.is_empty() && !targets.is_empty() {
+                if text_output {
+                    println!("No targets are scheduled to be probed.");
+                }
+                return Ok(probe_results);
+            }
+        }
+        // The same destination given more than once would be double counted.
+        let (destinations, duplicates) = dedup_destinations(destinations);
+        if text_output {
+            for (host, port) in duplicates {
+                println!("Ignoring duplicate destination {}.", destination_msg(&host, port));
+            }
+        }
+        let (host, port) = destinations.first().cloned().unwrap_or_default();
+        let path_only = matches!(cli.method, ConnectMethod::UNIX | ConnectMethod::UNIXGRAM);
+        let local_only = cli.method == ConnectMethod::HEARTBEAT;
+        let host_only = matches!(cli.method, ConnectMethod::ICMP | ConnectMethod::DNS);
+        let service = !cli.listen && !path_only && !local_only && (cli.svcb || is_srv_name(&host));
+        if !local_only
+            && (destinations.is_empty()
+                || destinations
+                    .iter()
+                    .any(|(host, port)| host.is_empty() || (*port == 0 && !path_only && !host_only && !service)))
+        {
             bail!("Destination host and port are required.");
         }
+        if destinations.len() > 1
+            && (cli.listen || service || !matches!(cli.method, ConnectMethod::TCP | ConnectMethod::UDP))
+        {
+            bail!("Multiple destinations are only supported by the TCP and UDP clients.");
+        }
 
         let config = match Config::load(&cli.config) {
             Ok(config) => {
-                println!("Using configuration file `{}`.\n", cli.config);
+                if text_output {
+                    println!("Using configuration file `{}`.\n", cli.config);
+                }
                 config
             }
             Err(_) => {
-                println!(
-                    "Configuration file `{}` not found. Using default configuration.\n",
-                    cli.config
-                );
+                if text_output {
+                    println!(
+                        "Configuration file `{}` not found. Using default configuration.\n",
+                        cli.config
+                    );
+                }
                 Config::default()
             }
         };
 
+        // Options given on the command line or in the environment
+        // take the place of the config file options.
+        let given = |id: &str| cli.given.contains(id);
         let ip_options = IpOptions {
-            ip_protocol: if cli.ip_proto != IpProtocol::V4 { cli.ip_proto } else { config.ip_options.ip_protocol },
+            ip_protocol: if given("ip_proto") { cli.ip_proto } else { config.ip_options.ip_protocol },
+            v4_concurrency: if given("v4_concurrency") { cli.v4_concurrency } else { config.ip_options.v4_concurrency },
+            v6_concurrency: if given("v6_concurrency") { cli.v6_concurrency } else { config.ip_options.v6_concurrency },
+            v4_rate: if given("v4_rate") { cli.v4_rate } else { config.ip_options.v4_rate },
+            v6_rate: if given("v6_rate") { cli.v6_rate } else { config.ip_options.v6_rate },
+            source_fallback: if given("no_source_fallback") {
+                !cli.no_source_fallback
+            } else {
+                config.ip_options.source_fallback
+            },
         };
 
-        // CLI options should override config file options.
-        // If a CLI option is NOT the same as the default,
-        // the option was set from the CLI. Therefore we should
-        // use the CLI option. Otherwise use the config file option.
         let ping_options = PingOptions {
-            repeat: if cli.repeat != PING_REPEAT { cli.repeat } else { config.ping_options.repeat },
-            interval: if cli.interval != PING_INTERVAL { cli.interval } else { config.ping_options.interval },
-            timeout: if cli.timeout != PING_TIMEOUT { cli.timeout } else { config.ping_options.timeout },
-            nk_peer: if cli.nk_peer != PING_NK_PEER { cli.nk_peer } else { config.ping_options.nk_peer },
+            repeat: if given("repeat") { cli.repeat } else { config.ping_options.repeat },
+            interval: if given("interval") { cli.interval } else { config.ping_options.interval },
+            timeout: if given("timeout") { cli.timeout } else { config.ping_options.timeout },
+            nk_peer: if given("nk_peer") { cli.nk_peer } else { config.ping_options.nk_peer },
+            payload: if given("payload") { cli.payload } else { config.ping_options.payload },
+            warm: if given("warm") { cli.warm } else { config.ping_options.warm },
+            tls: if given("tls") { cli.tls } else { config.ping_options.tls },
+            escalate: if given("escalate") { cli.escalate } else { config.ping_options.escalate },
         };
 
         let listen_options = ListenOptions {
-            nk_peer: if cli.nk_peer != PING_NK_PEER { cli.nk_peer } else { config.listen_options.nk_peer },
+            nk_peer: if given("nk_peer") { cli.nk_peer } else { config.listen_options.nk_peer },
+            echo: if given("echo") { cli.echo } else { config.listen_options.echo },
+            delay: if given("delay") { cli.delay } else { config.listen_options.delay },
+            drop: if given("drop") { cli.drop } else { config.listen_options.drop },
+            corrupt: if given("corrupt") { cli.corrupt } else { config.listen_options.corrupt },
+            seed: if given("seed") { cli.seed } else { config.listen_options.seed },
         };
+        if listen_options.drop as u16 + listen_options.corrupt as u16 > 100 {
+            bail!("--drop and --corrupt cannot exceed 100 percent combined.");
+        }
 
-        let logging_options = LoggingOptions {
-            file: if cli.file != LOGFILE_NAME { cli.file } else { config.logging_options.file },
-            dir: if cli.dir != CURRENT_DIR { cli.dir } else { config.logging_options.dir },
-            json: if cli.json != LOGGING_JSON { cli.json } else { config.logging_options.json },
-            quiet: if cli.quiet != LOGGING_QUIET { cli.quiet } else { config.logging_options.quiet },
-            syslog: if cli.syslog != LOGGING_SYSLOG { cli.syslog } else { config.logging_options.syslog },
+        let mail_options = MailOptions {
+            starttls: if given("starttls") { cli.starttls } else { config.mail_options.starttls },
+        };
+
+        // Requests are identified by the run, so service owners can find them in their logs.
+        let run_id = Uuid::new_v4().to_string();
+        let mut http_options = HttpOptions {
+            method: if given("http_method") { cli.http_method } else { config.http_options.method },
+            probe_header: if given("probe_header") { cli.probe_header } else { config.http_options.probe_header },
+            agent_name: if given("agent_name") { cli.agent_name } else { config.http_options.agent_name },
+            probe_identity: String::new(),
+        };
+        let agent_name = match http_options.agent_name.is_empty() {
+            true => hostname::get()
+                .map(|h| h.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            false => http_options.agent_name.to_owned(),
+        };
+        http_options.probe_identity = probe_identity_msg(&run_id, &agent_name);
+        let probe_identity = match cli.method == ConnectMethod::HTTP && !http_options.probe_header.is_empty() {
+            true => Some(http_options.probe_identity.to_owned()),
+            false => None,
+        };
+
+        let quic_options = QuicOptions {
+            alpn: if given("alpn") { cli.alpn } else { config.quic_options.alpn },
+        };
+
+        let mut logging_options = LoggingOptions {
+            file: if given("file") { cli.file } else { config.logging_options.file },
+            dir: if given("dir") { cli.dir } else { config.logging_options.dir },
+            json: if given("json") { cli.json } else { config.logging_options.json },
+            quiet: if given("quiet") { cli.quiet } else { config.logging_options.quiet },
+            histogram: if given("histogram") { cli.histogram } else { config.logging_options.histogram },
+            advise: if given("advise") { cli.advise } else { config.logging_options.advise },
+            plain: if given("plain") { cli.plain } else { config.logging_options.plain },
+            verbose: if given("verbose") { cli.verbose } else { config.logging_options.verbose },
+            syslog: if given("syslog") { cli.syslog } else { config.logging_options.syslog },
+            output: if given("output") { cli.output } else { config.logging_options.output },
+            // CLI tags are merged with the config file tags,
+            // overriding a config file tag with the same key.
+            tags: config.logging_options.tags.into_iter().chain(cli.tags).collect(),
+            // Target labels override config file labels for the same destination.
+            labels: config.logging_options.labels.into_iter().chain(target_labels).collect(),
+            record_file: cli.record_file.or(config.logging_options.record_file),
+            csv_file: cli.csv_file.or(config.logging_options.csv_file),
+            otlp_endpoint: cli.otlp_endpoint.or(config.logging_options.otlp_endpoint),
+            otlp_histograms: if given("otlp_histograms") {
+                cli.otlp_histograms
+            } else {
+                config.logging_options.otlp_histograms
+            },
+            syslog_target: cli.syslog_target.or(config.logging_options.syslog_target),
+            evidence_dir: cli.evidence_dir.or(config.logging_options.evidence_dir),
+            influx_url: cli.influx_url.or(config.logging_options.influx_url),
+            influx_measurement: if given("influx_measurement") {
+                cli.influx_measurement
+            } else {
+                config.logging_options.influx_measurement
+            },
+            influx_tag_prefix: if given("influx_tag_prefix") {
+                cli.influx_tag_prefix
+            } else {
+                config.logging_options.influx_tag_prefix
+            },
+            influx_token: cli.influx_token.or(config.logging_options.influx_token),
+            export_buffer: cli.export_buffer.or(config.logging_options.export_buffer),
+            export_buffer_size: if given("export_buffer_size") {
+                cli.export_buffer_size
+            } else {
+                config.logging_options.export_buffer_size
+            },
+            assertion: cli.assertion.or(config.logging_options.assertion),
+            sample: if given("sample") { cli.sample } else { config.logging_options.sample },
+            summary_interval: if given("summary_interval") {
+                cli.summary_interval
+            } else {
+                config.logging_options.summary_interval
+            },
+            watch: if given("watch") { cli.watch } else { config.logging_options.watch },
+            sort_by: if given("sort_by") { cli.sort_by } else { config.logging_options.sort_by },
+            sort_desc: if given("sort_desc") { cli.sort_desc } else { config.logging_options.sort_desc },
+            batch_size: config.logging_options.batch_size,
+            batch_interval: config.logging_options.batch_interval,
+        };
+        // Rolling summaries need an interval to be printed at.
+        if logging_options.watch > 0 && logging_options.summary_interval == 0 {
+            logging_options.summary_interval = LOGGING_WATCH_INTERVAL;
+        }
+
+        // CLI resolvers replace the config file resolvers.
+        let dns_options = DnsOptions {
+            resolvers: match cli.resolvers.is_empty() {
+                true => config.dns_options.resolvers,
+                false => cli.resolvers,
+            },
+            client_subnet: cli.client_subnet.or(config.dns_options.client_subnet),
+            // Targets file resolvers override the config file resolvers for the same host.
+            host_resolvers: config
+                .dns_options
+                .host_resolvers
+                .into_iter()
+                .chain(target_resolvers)
+                .collect(),
+            nat64: if given("nat64") { cli.nat64 } else { config.dns_options.nat64 },
         };
 
         // region:    ===== validators ===== //
 
+        // validate DNS resolvers
+        for resolver in dns_options.resolvers.iter().chain(dns_options.host_resolvers.values()) {
+            resolution_provider(resolver, None)?;
+        }
+        if let Some(client_subnet) = &dns_options.client_subnet {
+            if dns_options.resolvers.is_empty() {
+                bail!("A client subnet requires a DNS resolver.");
+            }
+            if client_subnet.parse::<ClientSubnet>().is_err() {
+                bail!("client subnet: `{client_subnet}` is invalid, expected IP/prefix");
+            }
+        }
+
+        if ping_options.tls && (cli.listen || cli.method != ConnectMethod::TCP) {
+            bail!("TLS handshakes are only supported by the TCP client.");
+        }
+        if ping_options.tls && (ping_options.nk_peer || ping_options.payload > 0) {
+            bail!("TLS handshakes cannot be combined with NetKraken peer messaging or payloads.");
+        }
+        if ping_options.escalate && (cli.listen || cli.method != ConnectMethod::TCP) {
+            bail!("Escalation is only supported by the TCP client.");
+        }
+        if logging_options.evidence_dir.is_some() && !ping_options.escalate {
+            bail!("Evidence bundles require --escalate.");
+        }
+
+        if cli.pmtu && (cli.listen || cli.method != ConnectMethod::ICMP) {
+            bail!("Path MTU discovery is only supported by the ICMP client.");
+        }
+
+        if dns_options.nat64 && ip_options.ip_protocol == IpProtocol::V4 {
+            bail!("NAT64 requires `--ip-proto v6` or `all`.");
+        }
+
         // validate source IP addresses
         if cli.src_v4 != BIND_ADDR_IPV4 {
             validate_local_ip(&cli.src_v4.parse()?)?;
         }
         if cli.src_v6 != BIND_ADDR_IPV6 {
-            validate_local_ip(&cli.src_v6.parse()?)?;
+            let (src_v6, _scope_id) = parse_scoped_ipaddr(&cli.src_v6)?;
+            validate_local_ip(&src_v6)?;
         }
 
         // endregion: ===== validators ===== //
 
-        match cli.method {
-            // ConnectMethod::HTTP => println!("http not implemented"),
-            // ConnectMethod::ICMP => println!("icmp not implemented"),
-            ConnectMethod::TCP => {
-                if cli.listen {
-                    let tcp_server = TcpServer {
-                        listen_ip: host,
-                        listen_port: port,
-                        logging_options,
-                        listen_options,
-                    };
-                    tcp_server.listen().await?;
-                } else {
-                    let tcp_client = TcpClient::new(
-                        host,
-                        port,
-                        Some(cli.src_v4),
-                        Some(cli.src_v6),
-                        Some(cli.src_port),
-                        logging_options,
-                        ping_options,
-                        ip_options,
+        if cli.suggest_rules {
+            let protocol = match cli.method.transport() {
+                Some(p) => p,
+                None => bail!(
+                    "{} connections do not require firewall rules.",
+                    cli.method.to_string().to_uppercase()
+                ),
+            };
+            let (flows, role) = match cli.listen {
+                true => {
+                    let (listen_ip, _scope_id) = parse_scoped_ipaddr(&host)?;
+                    (vec![server_flow(protocol, listen_ip, port)], FlowRole::Server)
+                }
+                false => {
+                    let host_record = HostRecord::new(&host, port).await;
+                    let (src_v4, _) = parse_scoped_ipaddr(&cli.src_v4)?;
+                    let (src_v6, _) = parse_scoped_ipaddr(&cli.src_v6)?;
+                    let flows = client_flows(
+                        protocol,
+                        src_v4,
+                        src_v6,
+                        cli.src_port,
+                        &host_record,
+                        ip_options.ip_protocol,
                     );
-                    tcp_client.connect().await?;
+                    if flows.is_empty() {
+                        bail!("{} did not resolve to an IP address", host);
+                    }
+                    (flows, FlowRole::Client)
                 }
+            };
+            println!("{}", suggest_rules(&flows, role));
+            return Ok(probe_results);
+        }
+
+        // NAT64 is only used when a prefix is discovered.
+        let nat64_prefix = match dns_options.nat64 && !cli.listen {
+            true => {
+                let nat64_prefix = discover_nat64(&dns_options).await;
+                let nat64_msg = match nat64_prefix {
+                    Some(prefix) => format!("Discovered NAT64 prefix {prefix}.\n"),
+                    None => "No NAT64 prefix was discovered, IPv4 only hosts are not synthesized.\n".to_owned(),
+                };
+                info_handler(&nat64_msg, &logging_options);
+                nat64_prefix
             }
-            ConnectMethod::UDP => {
-                if cli.listen {
-                    let udp_server = UdpServer {
-                        listen_ip: host,
-                        listen_port: port,
-                        logging_options,
-                        listen_options,
-                    };
-                    udp_server.listen().await?;
-                } else {
-                    let udp_client = UdpClient::new(
-                        host,
-                        port,
-                        Some(cli.src_v4),
-                        Some(cli.src_v6),
-                        Some(cli.src_port),
-                        logging_options,
-                        ping_options,
-                        ip_options,
-                    );
-                    udp_client.connect().await?;
+            false => None,
+        };
+
+        let start_time_utc = time_now_utc();
+        let manifest_options = ManifestOptions {
+            ping_options,
+            ip_options,
+            logging_options: logging_options.clone(),
+            listen_options,
+            mail_options,
+            dns_options: dns_options.clone(),
+            http_options: http_options.clone(),
+            quic_options: quic_options.clone(),
+        };
+        let vantage = Vantage::new(&cli.src_v4, &cli.src_v6);
+
+        // Service names expand into a target for each SRV or HTTPS/SVCB
+        // record, probed in priority order. Each target is tagged with
+        // the record it was expanded from.
+        let targets: Vec<(String, u16, BTreeMap<String, String>)> = match service {
+            true => {
+                let service_targets = expand_service(&host, port, &dns_options, cli.svcb).await?;
+                info_handler(&service_targets_msg(&host, &service_targets), &logging_options);
+                // Records for the same target are only probed once.
+                let (unique, duplicates) =
+                    dedup_destinations(service_targets.iter().map(|t| (t.host.to_owned(), t.port)).collect());
+                for (host, port) in duplicates {
+                    let duplicate_msg = format!("Ignoring duplicate target {}.", destination_msg(&host, port));
+                    info_handler(&duplicate_msg, &logging_options);
                 }
+                unique
+                    .into_iter()
+                    .filter_map(|(host, port)| {
+                        let target = service_targets.iter().find(|t| t.host == host && t.port == port)?;
+                        Some((host, port, target.tags()))
+                    })
+                    .collect()
             }
+            false => vec![(host.clone(), port, BTreeMap::new())],
+        };
+        let manifest_targets: Vec<ManifestTarget> = match destinations.len() > 1 {
+            true => destinations.clone(),
+            false => targets.iter().map(|(host, port, _)| (host.to_owned(), *port)).collect(),
         }
-        Ok(())
+        .into_iter()
+        .map(|(host, port)| ManifestTarget {
+            host,
+            port,
+            protocol: cli.method,
+            listen: cli.listen,
+        })
+        .collect();
+
+        let mut assertion_failed = false;
+        for (host, port, target_tags) in targets {
+            let mut logging_options = logging_options.clone();
+            logging_options.tags.extend(target_tags);
+
+            // Multiple destinations are probed together by a single client.
+            let client_destinations = match destinations.len() > 1 {
+                true => destinations.clone(),
+                false => vec![(host.clone(), port)],
+            };
+
+            // Each resolver view is probed separately
+            // when more than one resolver is configured.
+            let views = match cli.listen || cli.method.transport().is_none() {
+                true => vec![dns_options.clone()],
+                false => dns_options.views(),
+            };
+            let mut view_results: Vec<(String, Vec<ClientResult>)> = Vec::new();
+            for dns_options in views.iter().cloned() {
+                let resolver = dns_options.resolvers.first().cloned().unwrap_or_default();
+                if views.len() > 1 {
+                    info_handler(&format!("Resolver view: {resolver}\n"), &logging_options);
+                }
+                let client_results = match cli.method {
+                    ConnectMethod::HTTP => {
+                        if cli.listen {
+                            bail!("HTTP server is not supported.");
+                        }
+                        let url = match &http_url {
+                            Some(url) => url.clone(),
+                            None => bail!("HTTP mode requires a URL destination."),
+                        };
+                        let http_client = HttpClient::new(
+                            url,
+                            Some(cli.src_v4.clone()),
+                            Some(cli.src_v6.clone()),
+                            Some(cli.src_port),
+                            logging_options.clone(),
+                            ping_options,
+                            ip_options,
+                            dns_options,
+                            http_options.clone(),
+                        );
+                        http_client.connect().await
+                    }
+                    ConnectMethod::TCP => {
+                        if cli.listen {
+                            #[cfg(feature = "server")]
+                            {
+                                let tcp_server = TcpServer {
+                                    listen_ip: host.clone(),
+                                    listen_port: port,
+                                    logging_options: logging_options.clone(),
+                                    listen_options,
+                                };
+                                tcp_server.listen().await.map(|_| vec![])
+                            }
+                            #[cfg(not(feature = "server"))]
+                            bail!("TCP server requires the `server` feature.");
+                        } else {
+                            let tcp_client = TcpClient::new(
+                                client_destinations.clone(),
+                                Some(cli.src_v4.clone()),
+                                Some(cli.src_v6.clone()),
+                                Some(cli.src_port),
+                                logging_options.clone(),
+                                ping_options,
+                                ip_options,
+                                dns_options,
+                            );
+                            tcp_client.connect().await
+                        }
+                    }
+                    ConnectMethod::UDP => {
+                        if cli.listen {
+                            #[cfg(feature = "server")]
+                            {
+                                let udp_server = UdpServer {
+                                    listen_ip: host.clone(),
+                                    listen_port: port,
+                                    logging_options: logging_options.clone(),
+                                    listen_options,
+                                };
+                                udp_server.listen().await.map(|_| vec![])
+                            }
+                            #[cfg(not(feature = "server"))]
+                            bail!("UDP server requires the `server` feature.");
+                        } else {
+                            let udp_client = UdpClient::new(
+                                client_destinations.clone(),
+                                Some(cli.src_v4.clone()),
+                                Some(cli.src_v6.clone()),
+                                Some(cli.src_port),
+                                logging_options.clone(),
+                                ping_options,
+                                ip_options,
+                                dns_options,
+                            );
+                            udp_client.connect().await
+                        }
+                    }
+                    ConnectMethod::SMTP | ConnectMethod::IMAP => {
+                        if cli.listen {
+                            bail!("{} server is not supported.", cli.method.to_string().to_uppercase());
+                        }
+                        let mail_client = MailClient::new(
+                            host.clone(),
+                            port,
+                            Some(cli.src_v4.clone()),
+                            Some(cli.src_v6.clone()),
+                            Some(cli.src_port),
+                            cli.method,
+                            logging_options.clone(),
+                            ping_options,
+                            ip_options,
+                            dns_options,
+                            mail_options,
+                        );
+                        mail_client.connect().await
+                    }
+                    ConnectMethod::QUIC => {
+                        if cli.listen {
+                            bail!("QUIC server is not supported.");
+                        }
+                        #[cfg(feature = "quic")]
+                        {
+                            let quic_client = QuicClient::new(
+                                host.clone(),
+                                port,
+                                Some(cli.src_v4.clone()),
+                                Some(cli.src_v6.clone()),
+                                Some(cli.src_port),
+                                logging_options.clone(),
+                                ping_options,
+                                ip_options,
+                                dns_options,
+                                quic_options.clone(),
+                            );
+                            quic_client.connect().await
+                        }
+                        #[cfg(not(feature = "quic"))]
+                        bail!("QUIC probes require the `quic` feature.");
+                    }
+                    ConnectMethod::TELNET => {
+                        if cli.listen {
+                            bail!("TELNET server is not supported.");
+                        }
+                        let telnet_client = TelnetClient::new(
+                            host.clone(),
+                            port,
+                            Some(cli.src_v4.clone()),
+                            Some(cli.src_v6.clone()),
+                            Some(cli.src_port),
+                            logging_options.clone(),
+                            ping_options,
+                            ip_options,
+                            dns_options,
+                        );
+                        telnet_client.connect().await
+                    }
+                    ConnectMethod::ICMP => {
+                        if cli.listen {
+                            bail!("ICMP server is not supported.");
+                        }
+                        if cli.pmtu {
+                            let pmtu_client = PmtuClient::new(
+                                host.clone(),
+                                Some(cli.src_v4.clone()),
+                                Some(cli.src_v6.clone()),
+                                logging_options.clone(),
+                                ping_options,
+                                ip_options,
+                                dns_options,
+                            );
+                            pmtu_client.discover().await
+                        } else {
+                            let icmp_client = IcmpClient::new(
+                                host.clone(),
+                                Some(cli.src_v4.clone()),
+                                Some(cli.src_v6.clone()),
+                                logging_options.clone(),
+                                ping_options,
+                                ip_options,
+                                dns_options,
+                            );
+                            icmp_client.connect().await
+                        }
+                    }
+                    ConnectMethod::DNS => {
+                        if cli.listen {
+                            bail!("DNS server is not supported.");
+                        }
+                        let dns_client = DnsClient::new(
+                            host.clone(),
+                            cli.record_type,
+                            cli.failover_time,
+                            logging_options.clone(),
+                            ping_options,
+                            dns_options,
+                        );
+                        dns_client.connect().await
+                    }
+                    ConnectMethod::HEARTBEAT => {
+                        if cli.listen {
+                            bail!("HEARTBEAT server is not supported.");
+                        }
+                        let heartbeat_client = HeartbeatClient::new(logging_options.clone(), ping_options);
+                        heartbeat_client.connect().await
+                    }
+                    ConnectMethod::UNIX | ConnectMethod::UNIXGRAM => {
+                        if cli.listen {
+                            bail!("{} server is not supported.", cli.method.to_string().to_uppercase());
+                        }
+                        #[cfg(unix)]
+                        {
+                            let unix_client =
+                                UnixClient::new(host.clone(), cli.method, logging_options.clone(), ping_options);
+                            unix_client.connect().await
+                        }
+                        #[cfg(not(unix))]
+                        bail!(crate::util::platform::PlatformFeature::UnixSocket.unsupported_msg());
+                    }
+                };
+                match client_results {
+                    Ok(client_results) => {
+                        assertion_failed |= client_results.iter().any(|r| r.passed == Some(false));
+                        if let Some(prefix) = nat64_prefix {
+                            if nat64_only(&client_results, prefix) {
+                                let nat64_msg = format!(
+                                    "{} is only reachable via NAT64 ({prefix}).\n",
+                                    destination_msg(&host, port)
+                                );
+                                info_handler(&nat64_msg, &logging_options);
+                            }
+                        }
+                        view_results.push((resolver, client_results))
+                    }
+                    // A view that fails to resolve should not stop
+                    // the remaining views from being probed.
+                    Err(e) if views.len() > 1 => {
+                        eprintln!("{e}");
+                        view_results.push((resolver, vec![]));
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            if views.len() > 1 {
+                summary_handler(&resolver_views_table_msg(&host, port, &view_results), &logging_options);
+            }
+            probe_results.extend(view_results.into_iter().flat_map(|(_, client_results)| client_results));
+        }
+
+        if let Some(manifest_file) = cli.manifest {
+            // Results are only written to the log file in JSON or SYSLOG mode.
+            let mut artifacts = Vec::new();
+            if manifest_options.logging_options.json || manifest_options.logging_options.syslog {
+                let log_file =
+                    PathBuf::from(&manifest_options.logging_options.dir).join(&manifest_options.logging_options.file);
+                artifacts.push(log_file.display().to_string());
+            }
+            if let Some(record_file) = &manifest_options.logging_options.record_file {
+                artifacts.push(record_file.to_owned());
+            }
+            let manifest = RunManifest {
+                schema_version: SCHEMA_VERSION,
+                run_id,
+                probe_identity,
+                version: env!("CARGO_PKG_VERSION").to_owned(),
+                start_time_utc,
+                end_time_utc: time_now_utc(),
+                vantage,
+                targets: manifest_targets,
+                options: manifest_options,
+                artifacts,
+            };
+            manifest.write(&manifest_file)?;
+        }
+        if assertion_failed {
+            if let Some(assertion) = &logging_options.assertion {
+                bail!("assertion `{assertion}` failed");
+            }
+        }
+        Ok(probe_results)
+    }
+}
+
+/// Look up an environment variable of the process.
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+/// Return whether a flag is set by an environment value,
+/// which is any value other than a false one.
+fn env_flag(value: &str) -> bool {
+    !matches!(
+        value.trim().to_lowercase().as_str(),
+        "" | "0" | "false" | "f" | "no" | "n" | "off"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_are_taken_from_the_command_line_then_the_environment() {
+        let env = BTreeMap::from([
+            ("NK_TIMEOUT", "500".to_owned()),
+            ("KRAKEN_V4_RATE", "5".to_owned()),
+            // The default interval, which still takes the place of the config file.
+            ("NK_INTERVAL", PING_INTERVAL.to_string()),
+            ("KRAKEN_INTERVAL", "250".to_owned()),
+            ("KRAKEN_QUIET", "yes".to_owned()),
+            ("NK_VERBOSE", "false".to_owned()),
+        ]);
+        let env = |name: &str| env.get(name).cloned();
+        let cli = Cli::try_parse_args(["nk", "--timeout", "250", "192.0.2.1", "443"], &env).unwrap();
+
+        assert_eq!(cli.timeout, 250);
+        assert_eq!(cli.v4_rate, 5);
+        assert_eq!(cli.interval, PING_INTERVAL);
+        assert!(cli.quiet);
+        assert!(!cli.verbose);
+        for id in ["timeout", "v4_rate", "interval", "quiet", "verbose"] {
+            assert!(cli.given.contains(id), "{id} was given");
+        }
+        // Options left at their default are taken from the config file.
+        assert!(!cli.given.contains("repeat"));
+    }
+
+    #[tokio::test]
+    async fn agent_probes_are_restarted_until_cancelled() {
+        let (sender, cancel) = Cancel::channel();
+        let mut runs = 0;
+        Cli::supervise_probe("web", &cancel, 0, || {
+            runs += 1;
+            let result = match runs {
+                1 => Err(anyhow::anyhow!("bind failed")),
+                // A probe that ends after its maximum number of requests.
+                2 => Ok(()),
+                _ => {
+                    sender.send(true).unwrap();
+                    Ok(())
+                }
+            };
+            async move { result }
+        })
+        .await;
+        assert_eq!(runs, 3);
     }
 }
@@ -1,19 +1,29 @@
+use std::collections::BTreeMap;
 use std::fmt::Display;
-use std::net::{IpAddr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::sync::Arc;
 
 use anyhow::Result;
 use clap::ValueEnum;
+use hickory_proto::rr::rdata::opt::ClientSubnet;
 use serde_derive::{Deserialize, Serialize};
 use tabled::Tabled;
 
 use crate::core::konst::{
-    CURRENT_DIR, LOGFILE_NAME, LOGGING_JSON, LOGGING_QUIET, LOGGING_SYSLOG, PING_INTERVAL, PING_NK_PEER, PING_REPEAT,
-    PING_TIMEOUT,
+    BIND_PORT, CURRENT_DIR, EXPORT_BUFFER_SIZE, HTTP_PROBE_HEADER, INFLUX_MEASUREMENT, IP_BIND_FAILURE_BUDGET,
+    IP_CONCURRENCY, IP_RATE, IP_SOURCE_FALLBACK, LOGFILE_NAME, LOGGING_ADVISE, LOGGING_BATCH_INTERVAL,
+    LOGGING_BATCH_SIZE, LOGGING_HISTOGRAM, LOGGING_JSON, LOGGING_OTLP_HISTOGRAMS, LOGGING_PLAIN, LOGGING_QUIET,
+    LOGGING_SAMPLE, LOGGING_SORT_DESC, LOGGING_SUMMARY_INTERVAL, LOGGING_SYSLOG, LOGGING_VERBOSE, LOGGING_WATCH,
+    MAIL_STARTTLS, PING_ESCALATE, PING_INTERVAL, PING_NK_PEER, PING_PAYLOAD, PING_REPEAT, PING_TIMEOUT, PING_TLS,
+    PING_WARM, QUIC_ALPN,
 };
-use crate::util::time::{time_now_us, time_now_utc};
+use crate::util::assertion::Assertion;
+use crate::util::parser::{normalize_host, parse_destination, parse_resolver, parse_scoped_ipaddr, scoped_socket_addr};
+use crate::util::time::{calc_clock_offset_ms, calc_connect_ms, time_now_us, time_now_utc};
 
 #[allow(dead_code)]
-#[derive(Copy, Clone, Debug, Serialize)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ConnectResult {
     // Success
     Ping,
@@ -23,10 +33,33 @@ pub enum ConnectResult {
     Refused,
     Reset,
     Timeout,
+    #[default]
     Unknown,
 
     // Bind Error
     BindError,
+
+    // Local Resource Error
+    ResourceExhausted,
+
+    // Application Error
+    ProtocolError,
+    TlsError,
+    CertError,
+    HttpError,
+    NoAnswer,
+
+    // Heartbeat
+    Alive,
+
+    // Payload Error
+    Corrupt,
+
+    // ICMP Errors
+    NetworkUnreachable,
+    HostUnreachable,
+    Prohibited,
+    FragmentationNeeded,
 }
 impl Display for ConnectResult {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -38,18 +71,58 @@ impl Display for ConnectResult {
             ConnectResult::Timeout => write!(f, "timeout"),
             ConnectResult::Unknown => write!(f, "unknown"),
             ConnectResult::BindError => write!(f, "bind_error"),
+            ConnectResult::ResourceExhausted => write!(f, "resource_exhausted"),
+            ConnectResult::ProtocolError => write!(f, "protocol_error"),
+            ConnectResult::TlsError => write!(f, "tls_error"),
+            ConnectResult::CertError => write!(f, "cert_error"),
+            ConnectResult::HttpError => write!(f, "http_error"),
+            ConnectResult::NoAnswer => write!(f, "no_answer"),
+            ConnectResult::Alive => write!(f, "alive"),
+            ConnectResult::Corrupt => write!(f, "corrupt"),
+            ConnectResult::NetworkUnreachable => write!(f, "network_unreachable"),
+            ConnectResult::HostUnreachable => write!(f, "host_unreachable"),
+            ConnectResult::Prohibited => write!(f, "prohibited"),
+            ConnectResult::FragmentationNeeded => write!(f, "fragmentation_needed"),
         }
     }
 }
 
 #[allow(clippy::upper_case_acronyms)]
-#[derive(ValueEnum, Copy, Clone, Debug, Default, Serialize)]
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ConnectMethod {
     #[default]
     TCP,
     UDP,
-    // ICMP,
-    // HTTP,
+    SMTP,
+    IMAP,
+    UNIX,
+    UNIXGRAM,
+    TELNET,
+    HEARTBEAT,
+    ICMP,
+    HTTP,
+    DNS,
+    QUIC,
+}
+
+impl ConnectMethod {
+    /// Return the transport protocol used by the connection method.
+    /// UNIX sockets, ICMP and DNS resolution do not use a transport protocol.
+    pub fn transport(&self) -> Option<ConnectMethod> {
+        match self {
+            ConnectMethod::TCP
+            | ConnectMethod::SMTP
+            | ConnectMethod::IMAP
+            | ConnectMethod::TELNET
+            | ConnectMethod::HTTP => Some(ConnectMethod::TCP),
+            ConnectMethod::UDP | ConnectMethod::QUIC => Some(ConnectMethod::UDP),
+            ConnectMethod::UNIX
+            | ConnectMethod::UNIXGRAM
+            | ConnectMethod::HEARTBEAT
+            | ConnectMethod::ICMP
+            | ConnectMethod::DNS => None,
+        }
+    }
 }
 
 impl Display for ConnectMethod {
@@ -57,8 +130,189 @@ impl Display for ConnectMethod {
         match self {
             ConnectMethod::TCP => write!(f, "tcp"),
             ConnectMethod::UDP => write!(f, "udp"),
-            // ConnectMethod::ICMP => write!(f, "icmp"),
-            // ConnectMethod::HTTP => write!(f, "http"),
+            ConnectMethod::SMTP => write!(f, "smtp"),
+            ConnectMethod::IMAP => write!(f, "imap"),
+            ConnectMethod::UNIX => write!(f, "unix"),
+            ConnectMethod::UNIXGRAM => write!(f, "unixgram"),
+            ConnectMethod::TELNET => write!(f, "telnet"),
+            ConnectMethod::HEARTBEAT => write!(f, "heartbeat"),
+            ConnectMethod::ICMP => write!(f, "icmp"),
+            ConnectMethod::HTTP => write!(f, "http"),
+            ConnectMethod::DNS => write!(f, "dns"),
+            ConnectMethod::QUIC => write!(f, "quic"),
+        }
+    }
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum RecordFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+impl Display for RecordFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecordFormat::Json => write!(f, "json"),
+            RecordFormat::Csv => write!(f, "csv"),
+        }
+    }
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ReportFormat {
+    #[default]
+    Text,
+    Html,
+}
+
+impl Display for ReportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReportFormat::Text => write!(f, "text"),
+            ReportFormat::Html => write!(f, "html"),
+        }
+    }
+}
+
+/// A destination ordered by IP version, then numeric address and
+/// port, so 10.0.0.9 sorts before 10.0.0.10. Destinations that are
+/// not addresses, such as URLs, sort after addresses as text.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AddrKey {
+    named: bool,
+    addr: Option<(IpAddr, u16)>,
+    text: String,
+}
+
+impl AddrKey {
+    pub fn new(destination: &str) -> AddrKey {
+        // Series such as `10.0.0.1:443 (warm)` sort with their address,
+        // and scoped IPv6 addresses sort by the address without the zone.
+        let addr = destination.split_whitespace().next().unwrap_or_default();
+        let addr = match addr.split_once('%') {
+            Some((ip, zone)) => match zone.split_once(']') {
+                Some((_, port)) => format!("{ip}]{port}"),
+                None => ip.to_owned(),
+            },
+            None => addr.to_owned(),
+        };
+        let addr = match addr.parse::<SocketAddr>() {
+            Ok(addr) => Some((addr.ip(), addr.port())),
+            Err(_) => addr.parse::<IpAddr>().ok().map(|ip| (ip, 0)),
+        };
+        AddrKey {
+            named: addr.is_none(),
+            addr,
+            text: destination.to_owned(),
+        }
+    }
+}
+
+impl Display for AddrKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+/// Order of the destinations in the summary table.
+#[derive(ValueEnum, Copy, Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortBy {
+    #[default]
+    Dest,
+    Loss,
+    P95,
+    Avg,
+    Name,
+}
+
+impl Display for SortBy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SortBy::Dest => write!(f, "dest"),
+            SortBy::Loss => write!(f, "loss"),
+            SortBy::P95 => write!(f, "p95"),
+            SortBy::Avg => write!(f, "avg"),
+            SortBy::Name => write!(f, "name"),
+        }
+    }
+}
+
+/// How the probes of a health target combine into a single status.
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthPolicy {
+    #[default]
+    All,
+    Any,
+}
+
+impl HealthPolicy {
+    /// Return whether a target is healthy from whether each of its probes passed.
+    pub fn healthy(&self, passed: &[bool]) -> bool {
+        match self {
+            HealthPolicy::All => !passed.is_empty() && passed.iter().all(|p| *p),
+            HealthPolicy::Any => passed.iter().any(|p| *p),
+        }
+    }
+}
+
+impl Display for HealthPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HealthPolicy::All => write!(f, "all must pass"),
+            HealthPolicy::Any => write!(f, "any passes"),
+        }
+    }
+}
+
+/// When the log file is rotated, in addition to its size.
+#[derive(ValueEnum, Copy, Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogRotation {
+    #[default]
+    Never,
+    Hourly,
+    Daily,
+}
+
+impl LogRotation {
+    /// Return the rotation period a unix timestamp in microseconds is in.
+    pub fn period(&self, timestamp_us: u128) -> u128 {
+        match self {
+            LogRotation::Never => 0,
+            LogRotation::Hourly => timestamp_us / 3_600_000_000,
+            LogRotation::Daily => timestamp_us / 86_400_000_000,
+        }
+    }
+}
+
+impl Display for LogRotation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogRotation::Never => write!(f, "never"),
+            LogRotation::Hourly => write!(f, "hourly"),
+            LogRotation::Daily => write!(f, "daily"),
+        }
+    }
+}
+
+/// Format of the client results printed to the terminal.
+#[derive(ValueEnum, Copy, Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputFormat::Text => write!(f, "text"),
+            OutputFormat::Json => write!(f, "json"),
         }
     }
 }
@@ -91,10 +345,28 @@ pub enum LogLevel {
     TRACE,
 }
 
-#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
 #[serde(default)]
 pub struct IpOptions {
     pub ip_protocol: IpProtocol,
+    pub v4_concurrency: usize,
+    pub v6_concurrency: usize,
+    pub v4_rate: u16, // connections per second, 0 is unlimited
+    pub v6_rate: u16,
+    pub source_fallback: bool,
+}
+
+impl Default for IpOptions {
+    fn default() -> Self {
+        Self {
+            ip_protocol: IpProtocol::default(),
+            v4_concurrency: IP_CONCURRENCY,
+            v6_concurrency: IP_CONCURRENCY,
+            v4_rate: IP_RATE,
+            v6_rate: IP_RATE,
+            source_fallback: IP_SOURCE_FALLBACK,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -103,8 +375,44 @@ pub struct LoggingOptions {
     pub file: String,
     pub dir: String,
     pub quiet: bool,
+    /// Print a latency histogram of each destination in the summary.
+    pub histogram: bool,
+    /// Print the likely causes of failures and the next commands to run after the summary.
+    pub advise: bool,
+    /// Only print results, without banners, headers or tables.
+    pub plain: bool,
+    pub verbose: bool,
     pub json: bool,
     pub syslog: bool,
+    pub output: OutputFormat,
+    pub tags: BTreeMap<String, String>,
+    /// Labels shown instead of the addresses of `host:port` destinations.
+    pub labels: BTreeMap<String, String>,
+    pub record_file: Option<String>,
+    pub csv_file: Option<String>,
+    pub otlp_endpoint: Option<String>,
+    /// Export the latency of each destination as OTLP exponential histograms.
+    pub otlp_histograms: bool,
+    /// Syslog server that each result is forwarded to, eg: udp://host:514
+    pub syslog_target: Option<String>,
+    /// Directory that an evidence bundle is written to for each outage.
+    pub evidence_dir: Option<String>,
+    pub influx_url: Option<String>,
+    pub influx_measurement: String,
+    pub influx_tag_prefix: String,
+    pub influx_token: Option<String>,
+    /// Directory that OTLP and InfluxDB exports are buffered in
+    /// while their endpoint is unreachable.
+    pub export_buffer: Option<String>,
+    pub export_buffer_size: u64, // bytes per exporter
+    pub assertion: Option<Assertion>,
+    pub sample: u16,
+    pub summary_interval: u16,
+    pub watch: u16, // probes per destination, 0 disables
+    pub sort_by: SortBy,
+    pub sort_desc: bool,
+    pub batch_size: u16,
+    pub batch_interval: u16, // milliseconds
 }
 
 impl Default for LoggingOptions {
@@ -113,9 +421,75 @@ impl Default for LoggingOptions {
             file: LOGFILE_NAME.to_owned(),
             dir: CURRENT_DIR.to_owned(),
             quiet: LOGGING_QUIET,
+            histogram: LOGGING_HISTOGRAM,
+            advise: LOGGING_ADVISE,
+            plain: LOGGING_PLAIN,
+            verbose: LOGGING_VERBOSE,
             json: LOGGING_JSON,
             syslog: LOGGING_SYSLOG,
+            output: OutputFormat::default(),
+            tags: BTreeMap::new(),
+            labels: BTreeMap::new(),
+            record_file: None,
+            csv_file: None,
+            otlp_endpoint: None,
+            otlp_histograms: LOGGING_OTLP_HISTOGRAMS,
+            syslog_target: None,
+            evidence_dir: None,
+            influx_url: None,
+            influx_measurement: INFLUX_MEASUREMENT.to_owned(),
+            influx_tag_prefix: String::new(),
+            influx_token: None,
+            export_buffer: None,
+            export_buffer_size: EXPORT_BUFFER_SIZE,
+            assertion: None,
+            sample: LOGGING_SAMPLE,
+            summary_interval: LOGGING_SUMMARY_INTERVAL,
+            watch: LOGGING_WATCH,
+            sort_by: SortBy::default(),
+            sort_desc: LOGGING_SORT_DESC,
+            batch_size: LOGGING_BATCH_SIZE,
+            batch_interval: LOGGING_BATCH_INTERVAL,
+        }
+    }
+}
+
+impl LoggingOptions {
+    /// Return the label of a `host:port` destination.
+    pub fn label(&self, host: &str, port: u16) -> Option<&String> {
+        let host = normalize_host(host);
+        self.labels
+            .iter()
+            .find(|(destination, _)| {
+                matches!(parse_destination(destination), Ok((h, p)) if normalize_host(&h) == host && p == port)
+            })
+            .map(|(_, label)| label)
+    }
+
+    /// Return the label of each address labelled hosts resolved to.
+    pub fn socket_labels(&self, hosts: &[HostRecord]) -> BTreeMap<String, String> {
+        let mut socket_labels = BTreeMap::new();
+        for host in hosts {
+            if let Some(label) = self.label(&host.host, host.port) {
+                for socket in host.ipv4_sockets.iter().chain(&host.ipv6_sockets) {
+                    socket_labels.insert(socket.to_string(), label.to_owned());
+                }
+            }
         }
+        socket_labels
+    }
+
+    /// Return the label of a destination probed without a port, a name
+    /// queried, a UNIX socket path or a host sending heartbeats, which
+    /// is labelled on any port.
+    pub fn path_labels(&self, path: &str) -> BTreeMap<String, String> {
+        let host = normalize_host(path);
+        self.labels
+            .iter()
+            .find(|(destination, _)| matches!(parse_destination(destination), Ok((h, _)) if normalize_host(&h) == host))
+            .map(|(_, label)| (path.to_owned(), label.to_owned()))
+            .into_iter()
+            .collect()
     }
 }
 
@@ -126,6 +500,10 @@ pub struct PingOptions {
     pub interval: u16,
     pub timeout: u16,
     pub nk_peer: bool,
+    pub payload: u32, // bytes, 0 does not send a payload
+    pub warm: bool,
+    pub tls: bool,
+    pub escalate: bool,
 }
 
 impl Default for PingOptions {
@@ -135,16 +513,339 @@ impl Default for PingOptions {
             interval: PING_INTERVAL,
             timeout: PING_TIMEOUT,
             nk_peer: PING_NK_PEER,
+            payload: PING_PAYLOAD,
+            warm: PING_WARM,
+            tls: PING_TLS,
+            escalate: PING_ESCALATE,
         }
     }
 }
 
 #[derive(Copy, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ListenOptions {
     pub nk_peer: bool,
+    pub echo: bool,
+    pub delay: u16,  // milliseconds
+    pub drop: u8,    // percent of responses
+    pub corrupt: u8, // percent of responses
+    pub seed: Option<u64>,
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DnsOptions {
+    pub resolvers: Vec<String>,
+    pub client_subnet: Option<String>,
+    /// Resolvers chosen for specific hosts.
+    pub host_resolvers: BTreeMap<String, String>,
+    /// Synthesize IPv6 addresses for IPv4 only hosts
+    /// with the discovered NAT64 prefix.
+    pub nat64: bool,
+}
+
+impl DnsOptions {
+    /// Return the resolver socket addresses.
+    /// Resolvers are validated when the options are built.
+    pub fn resolver_addrs(&self) -> Vec<SocketAddr> {
+        self.resolvers.iter().filter_map(|r| parse_resolver(r).ok()).collect()
+    }
+
+    /// Return the resolver for a host, the host's own resolver
+    /// if it has one, otherwise the first resolver.
+    pub fn provider(&self, host: &str) -> Option<&String> {
+        let host = normalize_host(host);
+        self.host_resolvers
+            .iter()
+            .find(|(h, _)| normalize_host(h) == host)
+            .map(|(_, resolver)| resolver)
+            .or(self.resolvers.first())
+    }
+
+    /// Return the EDNS client subnet.
+    /// The client subnet is validated when the options are built.
+    pub fn client_subnet(&self) -> Option<ClientSubnet> {
+        self.client_subnet.as_ref().and_then(|s| s.parse().ok())
+    }
+
+    /// Return a set of DNS options for each resolver.
+    /// Each resolver view is probed separately.
+    pub fn views(&self) -> Vec<DnsOptions> {
+        match self.resolvers.len() > 1 {
+            true => self
+                .resolvers
+                .iter()
+                .map(|resolver| DnsOptions {
+                    resolvers: vec![resolver.to_owned()],
+                    client_subnet: self.client_subnet.clone(),
+                    host_resolvers: self.host_resolvers.clone(),
+                    nat64: self.nat64,
+                })
+                .collect(),
+            false => vec![self.clone()],
+        }
+    }
+}
+
+/// A target expanded from a SRV or HTTPS/SVCB record.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ServiceTarget {
+    pub service: String,
+    pub host: String,
+    pub port: u16,
+    pub priority: u16,
+    pub weight: u16,
+}
+
+impl ServiceTarget {
+    /// Tags recording the service record a target was expanded from.
+    pub fn tags(&self) -> BTreeMap<String, String> {
+        BTreeMap::from([
+            ("service".to_owned(), self.service.to_owned()),
+            ("priority".to_owned(), self.priority.to_string()),
+            ("weight".to_owned(), self.weight.to_string()),
+        ])
+    }
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MailOptions {
+    pub starttls: bool,
+}
+
+impl Default for MailOptions {
+    fn default() -> Self {
+        Self {
+            starttls: MAIL_STARTTLS,
+        }
+    }
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HttpMethod {
+    #[default]
+    Get,
+    Head,
+}
+
+impl Display for HttpMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HttpMethod::Get => write!(f, "GET"),
+            HttpMethod::Head => write!(f, "HEAD"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpOptions {
+    pub method: HttpMethod,
+    /// Header sent with each request to identify the run and agent
+    /// in the access logs of the service. Empty to disable.
+    pub probe_header: String,
+    /// Name of the agent in the probe header, the hostname if empty.
+    pub agent_name: String,
+    /// Value of the probe header, set for each run.
+    #[serde(skip)]
+    pub probe_identity: String,
+}
+
+impl Default for HttpOptions {
+    fn default() -> Self {
+        Self {
+            method: HttpMethod::default(),
+            probe_header: HTTP_PROBE_HEADER.to_owned(),
+            agent_name: String::new(),
+            probe_identity: String::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QuicOptions {
+    pub alpn: String,
+}
+
+impl Default for QuicOptions {
+    fn default() -> Self {
+        Self {
+            alpn: QUIC_ALPN.to_owned(),
+        }
+    }
+}
+
+/// An `http://` or `https://` URL to check.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HttpUrl {
+    pub tls: bool,
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl Display for HttpUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let scheme = match self.tls {
+            true => "https",
+            false => "http",
+        };
+        match self.host.contains(':') {
+            true => write!(f, "{scheme}://[{}]:{}{}", self.host, self.port, self.path),
+            false => write!(f, "{scheme}://{}:{}{}", self.host, self.port, self.path),
+        }
+    }
+}
+
+/// A destination in a CSV targets file, probed with its own protocol
+/// and, if it has one, its own timeout in milliseconds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CsvTarget {
+    pub host: String,
+    pub port: u16,
+    pub protocol: ConnectMethod,
+    pub label: Option<String>,
+    pub timeout: Option<u16>,
+}
+
+/// A syslog server that results are forwarded to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SyslogTarget {
+    Udp(String, u16),
+    Tcp(String, u16),
+    Unix(String),
+}
+
+impl Display for SyslogTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SyslogTarget::Udp(host, port) if host.contains(':') => write!(f, "udp://[{host}]:{port}"),
+            SyslogTarget::Udp(host, port) => write!(f, "udp://{host}:{port}"),
+            SyslogTarget::Tcp(host, port) if host.contains(':') => write!(f, "tcp://[{host}]:{port}"),
+            SyslogTarget::Tcp(host, port) => write!(f, "tcp://{host}:{port}"),
+            SyslogTarget::Unix(path) => write!(f, "unix://{path}"),
+        }
+    }
+}
+
+/// Timings of an HTTP(S) request. The DNS time is the time to resolve
+/// the host, the other times are in milliseconds from the start of the
+/// connection. Times are -1 if the stage was not reached.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct HttpRecord {
+    pub url: String,
+    pub method: HttpMethod,
+    pub status: u16,
+    pub dns_time: f64,
+    pub connect_time: f64,
+    pub tls_time: f64,
+    pub first_byte_time: f64,
+    pub total_time: f64,
+    pub bytes: u64,
+}
+
+/// Details of a mail protocol (SMTP/IMAP) conversation.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MailRecord {
+    pub connect_time: f64,
+    pub banner_time: f64,
+    pub starttls_time: f64,
+    pub banner: String,
+    pub capabilities: Vec<String>,
+    pub starttls_offered: bool,
+    pub starttls_accepted: bool,
+}
+
+/// Details of a telnet (RFC854) and RFC2217 negotiation.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TelnetRecord {
+    pub connect_time: f64,
+    pub negotiation_time: f64,
+    pub commands: Vec<String>,
+    pub com_port: Option<bool>,
+}
+
+/// Details of a TLS handshake over a TCP connection. The certificate
+/// expiry is the number of days until the server certificate expires.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TlsRecord {
+    pub handshake_time: f64,
+    pub version: String,
+    pub cipher_suite: String,
+    pub cert_expiry_days: Option<i64>,
+}
+
+/// The answers to a resolution probe. After a failover, the propagation
+/// delay is the number of seconds until the answers changed, and
+/// `ttl_exceeded` is set when old answers outlived their TTL.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DnsRecord {
+    pub answers: Vec<String>,
+    pub ttl: u32,
+    pub changed: bool,
+    pub ttl_exceeded: bool,
+    pub propagation_delay: Option<f64>,
+    #[serde(default)]
+    pub record_type: String, // Added in schema version 10
+    #[serde(default)]
+    pub response_code: String, // Added in schema version 10
+}
+
+/// Details of a payload echoed by the server.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PayloadRecord {
+    pub size: u32,
+    pub time: f64,
+    pub verified: bool,
+}
+
+/// Timings reported by a NetKraken peer.
+/// One way times are measured across both clocks,
+/// `clock_offset` estimates how far apart the clocks are.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PeerRecord {
+    pub uuid: String,
+    pub one_way_time: f64,
+    pub round_trip_time: f64,
+    #[serde(default)]
+    pub receive_time_utc: String, // Added in schema version 5
+    #[serde(default)]
+    pub return_time: f64, // Added in schema version 6
+    #[serde(default)]
+    pub clock_offset: f64, // Added in schema version 6
+}
+
+impl PeerRecord {
+    /// Build a peer record from a NetKraken peer reply received at `return_timestamp`.
+    /// Peers that do not report a reply timestamp have no return time or clock offset.
+    pub fn new(message: NetKrakenMessage, return_timestamp: u128, round_trip_time: f64) -> PeerRecord {
+        let (return_time, clock_offset) = match message.reply_timestamp {
+            0 => (-1.0, 0.0),
+            reply_timestamp => (
+                calc_connect_ms(reply_timestamp, return_timestamp),
+                calc_clock_offset_ms(
+                    message.send_timestamp,
+                    message.receive_timestamp,
+                    reply_timestamp,
+                    return_timestamp,
+                ),
+            ),
+        };
+        PeerRecord {
+            uuid: message.uuid,
+            one_way_time: message.one_way_time_ms,
+            round_trip_time,
+            receive_time_utc: message.receive_time_utc,
+            return_time,
+            clock_offset,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct ConnectRecord {
     pub result: ConnectResult,
     pub protocol: ConnectMethod,
@@ -153,6 +854,104 @@ pub struct ConnectRecord {
     pub time: f64,
     pub success: bool,
     pub error_msg: Option<String>, // Original error message
+    pub mail: Option<MailRecord>,
+    pub telnet: Option<TelnetRecord>,
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    #[serde(default)]
+    pub payload: Option<PayloadRecord>, // Added in schema version 2
+    #[serde(default)]
+    pub peer: Option<PeerRecord>, // Added in schema version 3
+    #[serde(default)]
+    pub warm: bool, // Added in schema version 4
+    #[serde(default)]
+    pub http: Option<HttpRecord>, // Added in schema version 7
+    #[serde(default)]
+    pub tls: Option<TlsRecord>, // Added in schema version 8
+    #[serde(default)]
+    pub dns: Option<DnsRecord>, // Added in schema version 9
+    #[serde(default)]
+    pub label: Option<String>, // Added in schema version 11
+}
+
+impl ConnectRecord {
+    /// Return the label of the destination, or its address if it is not labelled.
+    pub fn display_destination(&self) -> &str {
+        self.label.as_deref().unwrap_or(&self.destination)
+    }
+
+    /// Return the series a record belongs to. Requests over an
+    /// established connection are a separate series to new connections.
+    pub fn series(&self) -> String {
+        match self.warm {
+            true => format!("{} (warm)", self.destination),
+            false => self.destination.to_owned(),
+        }
+    }
+
+    /// Return the time of each phase of a multi-phase probe,
+    /// in the order the phases occurred.
+    /// Single-phase probes do not have a breakdown.
+    pub fn phases(&self) -> Vec<(Phase, f64)> {
+        let mut phases = Vec::new();
+        if let Some(mail) = &self.mail {
+            phases.push((Phase::Connect, mail.connect_time));
+            if mail.banner_time >= 0.0 {
+                phases.push((Phase::App, mail.banner_time - mail.connect_time));
+            }
+            if mail.starttls_time >= 0.0 {
+                phases.push((Phase::Tls, mail.starttls_time));
+            }
+        }
+        if let Some(payload) = &self.payload {
+            phases.push((Phase::Connect, self.time));
+            phases.push((Phase::App, payload.time));
+        }
+        if let Some(http) = &self.http {
+            if http.connect_time >= 0.0 {
+                phases.push((Phase::Connect, http.connect_time));
+            }
+            let request_time = match http.tls_time >= 0.0 {
+                true => {
+                    phases.push((Phase::Tls, http.tls_time - http.connect_time));
+                    http.tls_time
+                }
+                false => http.connect_time,
+            };
+            if http.first_byte_time >= 0.0 {
+                phases.push((Phase::App, http.first_byte_time - request_time));
+            }
+        }
+        if let Some(tls) = &self.tls {
+            if tls.handshake_time >= 0.0 {
+                phases.push((Phase::Connect, self.time));
+                phases.push((Phase::Tls, tls.handshake_time));
+            }
+        }
+        if let Some(telnet) = &self.telnet {
+            phases.push((Phase::Connect, telnet.connect_time));
+            if telnet.negotiation_time >= 0.0 {
+                phases.push((Phase::App, telnet.negotiation_time - telnet.connect_time));
+            }
+        }
+        phases
+    }
+
+    /// Return the 5-tuple used by the connection.
+    /// Only connections over an IP transport have a 5-tuple.
+    pub fn five_tuple(&self) -> Option<FiveTuple> {
+        let protocol = self.protocol.transport()?;
+        let source = self.source.parse::<SocketAddr>().ok()?;
+        let destination = self.destination.parse::<SocketAddr>().ok()?;
+
+        Some(FiveTuple {
+            protocol,
+            src_ip: source.ip(),
+            src_port: source.port(),
+            dst_ip: destination.ip(),
+            dst_port: destination.port(),
+        })
+    }
 }
 
 impl Display for ConnectRecord {
@@ -182,11 +981,109 @@ error: {}
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct FiveTuple {
+    pub protocol: ConnectMethod,
+    pub src_ip: IpAddr,
+    pub src_port: u16,
+    pub dst_ip: IpAddr,
+    pub dst_port: u16,
+}
+
+impl Display for FiveTuple {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{},{},{},{},{}",
+            self.protocol, self.src_ip, self.src_port, self.dst_ip, self.dst_port
+        )
+    }
+}
+
+/// Whether lost connections occur independently or in bursts.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LossPattern {
+    #[default]
+    None,
+    Random,
+    Bursty,
+}
+
+impl Display for LossPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LossPattern::None => write!(f, "none"),
+            LossPattern::Random => write!(f, "random"),
+            LossPattern::Bursty => write!(f, "bursty"),
+        }
+    }
+}
+
+/// A timed phase of a multi-phase probe.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Connect,
+    Tls,
+    App,
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Phase::Connect => write!(f, "connect"),
+            Phase::Tls => write!(f, "tls"),
+            Phase::App => write!(f, "app"),
+        }
+    }
+}
+
+/// Loss burst statistics for a destination.
+/// `p` and `r` are the Gilbert-Elliott transition probabilities
+/// from success to loss and from loss to success.
+#[derive(Copy, Clone, Debug, Default, Serialize)]
+pub struct LossBursts {
+    pub bursts: u16,
+    pub longest: u16,
+    pub mean_length: f64,
+    pub p: f64,
+    pub r: f64,
+    pub pattern: LossPattern,
+}
+
+/// A probe kept for the summary of its destination, in the order
+/// it was sent. Failed probes have no time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProbeSample {
+    pub time: Option<f64>,
+    pub timestamp: Option<String>,
+}
+
+impl ProbeSample {
+    /// A sample of a probe that has just completed.
+    pub fn new(record: &ConnectRecord) -> ProbeSample {
+        ProbeSample {
+            time: record.success.then_some(record.time),
+            timestamp: Some(time_now_utc()),
+        }
+    }
+
+    /// A sample of a stored record, which has no timestamp.
+    pub fn from_record(record: &ConnectRecord) -> ProbeSample {
+        ProbeSample {
+            time: record.success.then_some(record.time),
+            timestamp: None,
+        }
+    }
+}
+
 pub struct ClientSummary {
     pub send_count: u16,
-    pub latencies: Vec<f64>,
+    pub samples: Vec<ProbeSample>,
 }
 
+#[derive(Clone, Debug, Serialize)]
 pub struct ClientResult {
     pub destination: String,
     pub protocol: ConnectMethod,
@@ -197,13 +1094,76 @@ pub struct ClientResult {
     pub min: f64,
     pub max: f64,
     pub avg: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub jitter: f64,         // Mean absolute difference between consecutive times
+    pub std_dev: f64,        // Population standard deviation of the times
+    pub histogram: Vec<u16>, // Replies in each latency bucket of HISTOGRAM_BOUNDS
+    pub loss_bursts: LossBursts,
+    pub first_failure: Option<String>, // Time of the first failed probe
+    pub last_failure: Option<String>,  // Time of the last failed probe
+    pub phases: Vec<(Phase, f64)>,     // Average time of each phase
+    pub passed: Option<bool>,          // Whether the summary passes the `--assert` expression
+    pub label: Option<String>,         // Label of the destination, shown instead of its address
+}
+impl ClientResult {
+    /// Return the label of the destination, or its address if it is not labelled.
+    pub fn display_destination(&self) -> &str {
+        self.label.as_deref().unwrap_or(&self.destination)
+    }
+}
+
+/// The result of one of the probes of a health target. A probe passes
+/// when every destination replied and passed the `--assert` expression.
+#[derive(Clone, Debug)]
+pub struct ProbeHealth {
+    pub profile: String,
+    pub protocol: ConnectMethod,
+    pub destinations: Vec<String>,
+    pub sent: u16,
+    pub received: u16,
+    pub passed: bool,
+    pub error: Option<String>,
 }
+
+impl ProbeHealth {
+    pub fn new(profile: &str, protocol: ConnectMethod, client_results: &[ClientResult]) -> ProbeHealth {
+        ProbeHealth {
+            profile: profile.to_owned(),
+            protocol,
+            destinations: client_results
+                .iter()
+                .map(|r| r.display_destination().to_owned())
+                .collect(),
+            sent: client_results.iter().map(|r| r.sent).sum(),
+            received: client_results.iter().map(|r| r.received).sum(),
+            passed: !client_results.is_empty()
+                && client_results.iter().all(|r| r.received > 0 && r.passed != Some(false)),
+            error: None,
+        }
+    }
+
+    pub fn failed(profile: &str, protocol: ConnectMethod, error: String) -> ProbeHealth {
+        ProbeHealth {
+            profile: profile.to_owned(),
+            protocol,
+            destinations: Vec::new(),
+            sent: 0,
+            received: 0,
+            passed: false,
+            error: Some(error),
+        }
+    }
+}
+
 impl Tabled for ClientResult {
     const LENGTH: usize = 42;
 
     fn fields(&self) -> Vec<std::borrow::Cow<'_, str>> {
         vec![
-            self.destination.clone().into(),
+            self.display_destination().to_owned().into(),
             self.protocol.to_string().to_uppercase().into(),
             self.sent.to_string().into(),
             self.received.to_string().into(),
@@ -230,6 +1190,133 @@ impl Tabled for ClientResult {
     }
 }
 
+/// Requests a server received from a source.
+#[cfg(feature = "server")]
+#[derive(Clone, Debug)]
+pub struct SourceSummary {
+    pub source: String,
+    pub requests: u64,
+    pub bytes: u64,
+    pub avg_one_way: f64,
+}
+
+/// A likely cause of the failures of a run, and a command
+/// to narrow it down.
+#[derive(Clone, Debug)]
+pub struct Advice {
+    pub cause: String,
+    pub command: String,
+}
+
+/// The ports of a host probed on several ports, and the services
+/// the probes found on the open ports, such as banners and TLS versions.
+#[derive(Clone, Debug, Default)]
+pub struct HostServices {
+    pub host: String,
+    pub open: Vec<u16>,
+    pub closed: Vec<u16>,
+    pub services: Vec<(u16, String)>,
+}
+
+/// Result of a path MTU discovery to a destination address.
+/// `fragmentation_at` is the router that reported a smaller
+/// next hop MTU, if any.
+#[derive(Clone, Debug)]
+pub struct PmtuRecord {
+    pub destination: String,
+    pub path_mtu: Option<u16>,
+    pub interface_mtu: u16,
+    pub fragmentation_at: Option<IpAddr>,
+    pub reported_mtu: Option<u16>,
+}
+
+/// Evidence collected when a destination starts failing.
+/// `addresses` are the addresses the host resolves to again,
+/// `tls` is a TLS handshake to the destination, `gateway` an
+/// ICMP echo to the default gateway and `path` the routers
+/// on the path to the destination. `unsupported` lists the
+/// checks that cannot be made on this platform.
+#[derive(Clone, Debug, Serialize)]
+pub struct Escalation {
+    pub destination: String,
+    pub host: String,
+    pub time: String,
+    pub result: ConnectResult,
+    pub addresses: Vec<String>,
+    pub tls: ConnectRecord,
+    pub gateway: Option<ConnectRecord>,
+    pub path: Vec<Hop>,
+    pub unsupported: Vec<String>,
+}
+
+/// The records around an outage of a destination, the evidence collected
+/// when it started and the routing table at the time, so an incident can
+/// be handed over as a single file. `before` ends with the failed record.
+#[derive(Clone, Debug, Serialize)]
+pub struct EvidenceBundle {
+    pub schema_version: u16,
+    pub destination: String,
+    pub label: Option<String>,
+    pub host: String,
+    pub started: String,
+    pub before: Vec<ConnectRecord>,
+    pub after: Vec<ConnectRecord>,
+    pub routes: Vec<String>,
+    pub escalation: Option<Escalation>,
+}
+
+/// A router on the path to a destination.
+/// Hops that do not reply have no router and a time of -1.
+#[derive(Clone, Debug, Serialize)]
+pub struct Hop {
+    pub ttl: u8,
+    pub router: Option<IpAddr>,
+    pub time: f64,
+}
+
+/// The summaries of a destination in two runs, and whether
+/// its latency or loss regressed beyond the thresholds.
+#[derive(Clone, Debug)]
+pub struct RunDiff {
+    pub destination: String,
+    pub before: Option<ClientResult>,
+    pub after: Option<ClientResult>,
+    pub regressed: bool,
+}
+
+/// Result of a significance test between two destinations.
+#[derive(Clone, Debug)]
+pub struct SignificanceResult {
+    pub test: String,
+    pub statistic: f64,
+    pub p_value: f64,
+    pub significant: bool,
+}
+impl Tabled for SignificanceResult {
+    const LENGTH: usize = 4;
+
+    fn fields(&self) -> Vec<std::borrow::Cow<'_, str>> {
+        vec![
+            self.test.clone().into(),
+            format!("{:.3}", self.statistic).into(),
+            format!("{:.4}", self.p_value).into(),
+            match self.significant {
+                true => "yes".into(),
+                false => "no".into(),
+            },
+        ]
+    }
+
+    fn headers() -> Vec<std::borrow::Cow<'static, str>> {
+        vec![
+            std::borrow::Cow::Borrowed("Test"),
+            std::borrow::Cow::Borrowed("Statistic"),
+            std::borrow::Cow::Borrowed("p-value"),
+            std::borrow::Cow::Borrowed("Significant"),
+        ]
+    }
+}
+
 #[derive(Clone, Default, Debug, Deserialize, Serialize)]
 pub struct NetKrakenMessage {
     pub uuid: String,
@@ -237,6 +1324,8 @@ pub struct NetKrakenMessage {
     pub send_timestamp: u128,
     pub receive_time_utc: String,
     pub receive_timestamp: u128,
+    #[serde(default)]
+    pub reply_timestamp: u128,
     pub one_way_time_ms: f64,
     pub round_trip_time_utc: String,
     pub round_trip_timestamp: u128,
@@ -248,7 +1337,6 @@ pub struct NetKrakenMessage {
 }
 
 impl NetKrakenMessage {
-    #[allow(dead_code)]
     pub fn new(
         uuid: &String,
         source: &String,
@@ -268,7 +1356,7 @@ impl NetKrakenMessage {
         Ok(message)
     }
 
-    pub fn _to_json(&self) -> Result<String> {
+    pub fn to_json(&self) -> Result<String> {
         let json_string = serde_json::to_string(&self)?;
         Ok(json_string)
     }
@@ -287,13 +1375,19 @@ impl HostRecord {
         let mut ipv4_sockets = vec![];
         let mut ipv6_sockets = vec![];
 
-        let host_port = format!("{}:{}", host, port);
-        if let Ok(sockets) = tokio::net::lookup_host(host_port).await {
-            for socket in sockets {
-                match socket.is_ipv4() {
-                    true => ipv4_sockets.push(socket),
-                    false => ipv6_sockets.push(socket),
-                }
+        // IP addresses do not need to be resolved. This also allows
+        // IPv6 addresses with a zone ID which cannot be resolved.
+        let sockets = match parse_scoped_ipaddr(host) {
+            Ok((ip, scope_id)) => vec![scoped_socket_addr(ip, scope_id, port)],
+            Err(_) => match tokio::net::lookup_host(format!("{}:{}", host, port)).await {
+                Ok(sockets) => sockets.collect(),
+                Err(_) => vec![],
+            },
+        };
+        for socket in sockets {
+            match socket.is_ipv4() {
+                true => ipv4_sockets.push(socket),
+                false => ipv6_sockets.push(socket),
             }
         }
         HostRecord {
@@ -335,7 +1429,74 @@ impl Display for HostRecord {
 pub struct IpPort {
     pub ipv4: IpAddr,
     pub ipv6: IpAddr,
+    pub ipv6_scope_id: u32,
     pub port: u16,
+    pub fallback: SourceFallback,
+}
+
+impl IpPort {
+    /// Return the source address to bind to, matching
+    /// the IP version of the destination socket.
+    pub fn bind_addr(&self, dst_socket: &SocketAddr) -> SocketAddr {
+        if self.fallback.active[family(dst_socket)].load(Ordering::Relaxed) {
+            return wildcard_source(dst_socket);
+        }
+        match dst_socket.is_ipv4() {
+            true => SocketAddr::new(self.ipv4, self.port),
+            false => scoped_socket_addr(self.ipv6, self.ipv6_scope_id, self.port),
+        }
+    }
+
+    /// Record whether binding to the source for a destination succeeded.
+    /// After too many failures in a row, the wildcard source is used instead.
+    pub fn record_bind(&self, dst_socket: &SocketAddr, bound: bool) {
+        let source = self.bind_addr(dst_socket);
+        let wildcard = wildcard_source(dst_socket);
+        if !self.fallback.enabled || source == wildcard {
+            return;
+        }
+        let failures = &self.fallback.failures[family(dst_socket)];
+        if bound {
+            failures.store(0, Ordering::Relaxed);
+            return;
+        }
+        let count = failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if count >= IP_BIND_FAILURE_BUDGET && !self.fallback.active[family(dst_socket)].swap(true, Ordering::Relaxed) {
+            eprintln!("Binding to source {source} failed {count} times in a row, falling back to {wildcard}.");
+        }
+    }
+}
+
+/// Falls back to the wildcard source when binding to the configured
+/// source fails repeatedly, so probes continue through local interface
+/// changes. Shared by every probe from the source.
+#[derive(Debug, Clone, Default)]
+pub struct SourceFallback {
+    pub enabled: bool,
+    // Consecutive bind failures, for IPv4 and IPv6 sources.
+    failures: Arc<[AtomicU16; 2]>,
+    active: Arc<[AtomicBool; 2]>,
+}
+
+impl SourceFallback {
+    pub fn new(enabled: bool) -> SourceFallback {
+        SourceFallback {
+            enabled,
+            ..Default::default()
+        }
+    }
+}
+
+fn family(socket: &SocketAddr) -> usize {
+    usize::from(socket.is_ipv6())
+}
+
+/// The default source for a destination, any address and port.
+fn wildcard_source(dst_socket: &SocketAddr) -> SocketAddr {
+    match dst_socket.is_ipv4() {
+        true => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), BIND_PORT),
+        false => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), BIND_PORT),
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -346,7 +1507,134 @@ pub struct HostResults {
 
 #[cfg(test)]
 mod tests {
-    use crate::core::common::HostRecord;
+
+    use crate::core::common::{
+        AddrKey, ConnectMethod, ConnectRecord, ConnectResult, HealthPolicy, HostRecord, IpPort, MailRecord,
+        NetKrakenMessage, PeerRecord, Phase, SourceFallback,
+    };
+
+    fn connect_record(protocol: ConnectMethod, source: &str, destination: &str) -> ConnectRecord {
+        ConnectRecord {
+            result: ConnectResult::Pong,
+            protocol,
+            source: source.to_owned(),
+            destination: destination.to_owned(),
+            time: 1.0,
+            success: true,
+            error_msg: None,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn health_policies_roll_up_probes() {
+        assert!(HealthPolicy::All.healthy(&[true, true]));
+        assert!(!HealthPolicy::All.healthy(&[true, false]));
+        assert!(!HealthPolicy::All.healthy(&[]));
+        assert!(HealthPolicy::Any.healthy(&[false, true]));
+        assert!(!HealthPolicy::Any.healthy(&[false, false]));
+    }
+
+    #[test]
+    fn peer_record_has_return_time_and_clock_offset() {
+        // 2ms each way, peer clock 5ms ahead.
+        let message = NetKrakenMessage {
+            send_timestamp: 10_000,
+            receive_timestamp: 17_000,
+            reply_timestamp: 18_000,
+            one_way_time_ms: 7.0,
+            ..Default::default()
+        };
+
+        let peer = PeerRecord::new(message, 15_000, 5.0);
+        assert_eq!(peer.one_way_time, 7.0);
+        assert_eq!(peer.return_time, -1.0);
+        assert_eq!(peer.clock_offset, 5.0);
+    }
+
+    #[test]
+    fn peer_record_without_reply_timestamp_has_no_clock_offset() {
+        let message = NetKrakenMessage {
+            send_timestamp: 10_000,
+            receive_timestamp: 12_000,
+            one_way_time_ms: 2.0,
+            ..Default::default()
+        };
+
+        let peer = PeerRecord::new(message, 14_000, 4.0);
+        assert_eq!(peer.return_time, -1.0);
+        assert_eq!(peer.clock_offset, 0.0);
+    }
+
+    #[test]
+    fn warm_records_are_a_separate_series() {
+        let mut record = connect_record(ConnectMethod::TCP, "192.0.2.1:50000", "192.0.2.2:443");
+        assert_eq!(record.series(), "192.0.2.2:443");
+
+        record.warm = true;
+        assert_eq!(record.series(), "192.0.2.2:443 (warm)");
+    }
+
+    #[test]
+    fn addr_keys_order_by_version_then_address() {
+        let mut destinations = vec![
+            "https://example.com/",
+            "[2001:db8::1]:443",
+            "10.0.0.10:443",
+            "10.0.0.9:443 (warm)",
+            "[fe80::1%eth0]:443",
+            "10.0.0.9:443",
+            "10.0.0.9",
+        ];
+        destinations.sort_by_cached_key(|destination| AddrKey::new(destination));
+        assert_eq!(
+            destinations,
+            [
+                "10.0.0.9",
+                "10.0.0.9:443",
+                "10.0.0.9:443 (warm)",
+                "10.0.0.10:443",
+                "[2001:db8::1]:443",
+                "[fe80::1%eth0]:443",
+                "https://example.com/",
+            ]
+        );
+    }
+
+    #[test]
+    fn phases_of_mail_probe() {
+        let mut record = connect_record(ConnectMethod::SMTP, "198.51.100.1:13337", "198.51.100.2:25");
+        record.mail = Some(MailRecord {
+            connect_time: 1.0,
+            banner_time: 3.5,
+            starttls_time: 2.0,
+            ..Default::default()
+        });
+
+        assert_eq!(
+            record.phases(),
+            vec![(Phase::Connect, 1.0), (Phase::App, 2.5), (Phase::Tls, 2.0)]
+        );
+        assert!(connect_record(ConnectMethod::TCP, "", "").phases().is_empty());
+    }
+
+    #[test]
+    fn five_tuple_with_ip_transport() {
+        let record = connect_record(ConnectMethod::SMTP, "198.51.100.1:13337", "[2001:db8::1]:25");
+        let five_tuple = record.five_tuple().unwrap();
+
+        assert_eq!(five_tuple.protocol, ConnectMethod::TCP);
+        assert_eq!(five_tuple.src_port, 13337);
+        assert_eq!(five_tuple.dst_port, 25);
+        assert_eq!(five_tuple.to_string(), "tcp,198.51.100.1,13337,2001:db8::1,25");
+    }
+
+    #[test]
+    fn five_tuple_without_ip_transport() {
+        let record = connect_record(ConnectMethod::UNIX, "unnamed", "/var/run/docker.sock");
+
+        assert!(record.five_tuple().is_none());
+    }
 
     #[tokio::test]
     async fn host_record_empty() {
@@ -363,6 +1651,14 @@ mod tests {
         assert_eq!(host_record, expected);
     }
 
+    #[tokio::test]
+    async fn host_record_with_scoped_ipv6_is_not_resolved() {
+        let host_record = HostRecord::new("fe80::1%3", 443).await;
+
+        assert!(host_record.ipv4_sockets.is_empty());
+        assert_eq!(host_record.ipv6_sockets[0].to_string(), "[fe80::1%3]:443");
+    }
+
     #[tokio::test]
     async fn host_record_not_empty() {
         let domain = "windows.com";
@@ -373,4 +1669,48 @@ mod tests {
         assert!(!host_record.ipv4_sockets.is_empty());
         assert!(!host_record.ipv6_sockets.is_empty());
     }
+
+    #[test]
+    fn source_falls_back_after_repeated_bind_failures() {
+        let src = IpPort {
+            ipv4: "192.0.2.10".parse().unwrap(),
+            ipv6: "::".parse().unwrap(),
+            ipv6_scope_id: 0,
+            port: 5000,
+            fallback: SourceFallback::new(true),
+        };
+        let dst = "198.51.100.1:443".parse().unwrap();
+
+        src.record_bind(&dst, false);
+        src.record_bind(&dst, false);
+        src.record_bind(&dst, true);
+        src.record_bind(&dst, false);
+        src.record_bind(&dst, false);
+        assert_eq!(src.bind_addr(&dst).to_string(), "192.0.2.10:5000");
+
+        src.record_bind(&dst, false);
+        assert_eq!(src.bind_addr(&dst).to_string(), "0.0.0.0:0");
+        // Each family falls back separately.
+        assert_eq!(
+            src.bind_addr(&"[2001:db8::1]:443".parse().unwrap()).to_string(),
+            "[::]:5000"
+        );
+    }
+
+    #[test]
+    fn source_fallback_can_be_disabled() {
+        let src = IpPort {
+            ipv4: "192.0.2.10".parse().unwrap(),
+            ipv6: "::".parse().unwrap(),
+            ipv6_scope_id: 0,
+            port: 0,
+            fallback: SourceFallback::new(false),
+        };
+        let dst = "198.51.100.1:443".parse().unwrap();
+
+        for _ in 0..10 {
+            src.record_bind(&dst, false);
+        }
+        assert_eq!(src.bind_addr(&dst).to_string(), "192.0.2.10:0");
+    }
 }
@@ -1,15 +1,26 @@
-use std::fs::{read_to_string, File};
+use std::collections::BTreeMap;
+use std::fs::{read_to_string, File, OpenOptions};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use clap::ValueEnum;
 
+use hickory_proto::rr::rdata::opt::ClientSubnet;
 use serde_derive::{Deserialize, Serialize};
+use time::OffsetDateTime;
 
 use toml::from_str;
 
-use crate::core::common::{IpOptions, ListenOptions, LoggingOptions, PingOptions};
+use crate::core::common::{
+    ConnectMethod, DnsOptions, HealthPolicy, HostRecord, HttpOptions, IpOptions, IpProtocol, ListenOptions,
+    LoggingOptions, MailOptions, OutputFormat, PingOptions, QuicOptions, SyslogTarget,
+};
 use crate::core::konst::CONFIG_FILE;
+use crate::core::targets::load_targets;
+use crate::util::dns::{resolution_provider, resolve_host};
+use crate::util::parser::{parse_destination, parse_header_name, parse_ipaddr, parse_syslog_target, parse_url};
+use crate::util::platform::PlatformFeature;
 
 /// Configuration options for NetKraken
 #[derive(Deserialize, Debug, Default, Serialize)]
@@ -18,6 +29,57 @@ pub struct Config {
     pub ip_options: IpOptions,
     pub logging_options: LoggingOptions,
     pub listen_options: ListenOptions,
+    #[serde(default)]
+    pub mail_options: MailOptions,
+    #[serde(default)]
+    pub dns_options: DnsOptions,
+    #[serde(default)]
+    pub http_options: HttpOptions,
+    #[serde(default)]
+    pub quic_options: QuicOptions,
+    /// Named probes run with `nk run <profile>`.
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Targets whose health combines several profiles, checked with `nk health`.
+    #[serde(default)]
+    pub health: BTreeMap<String, HealthTarget>,
+}
+
+/// A target whose health is the rollup of the probes of several profiles.
+#[derive(Deserialize, Debug, Default, Serialize, Clone)]
+#[serde(default)]
+pub struct HealthTarget {
+    pub profiles: Vec<String>,
+    pub policy: HealthPolicy,
+}
+
+/// A named probe in the config file. The options of a profile
+/// override the config file options when the profile is run.
+#[derive(Deserialize, Debug, Default, Serialize, Clone)]
+#[serde(default)]
+pub struct Profile {
+    pub method: Option<String>,
+    pub host: String,
+    pub port: Option<u16>,
+    pub src_ip: Option<String>,
+    pub src_port: Option<u16>,
+    pub repeat: Option<u16>,
+    pub interval: Option<u16>,
+    pub timeout: Option<u16>,
+    pub output: Option<OutputFormat>,
+}
+
+impl Profile {
+    /// Return the connection method, the default method if it is not set.
+    pub fn connect_method(&self) -> Result<ConnectMethod> {
+        match &self.method {
+            Some(method) => match ConnectMethod::from_str(method, true) {
+                Ok(method) => Ok(method),
+                Err(_) => bail!("method `{method}` is invalid"),
+            },
+            None => Ok(ConnectMethod::default()),
+        }
+    }
 }
 
 impl Config {
@@ -32,6 +94,104 @@ impl Config {
         Ok(config)
     }
 
+    /// Parse a config file, reporting where it is invalid.
+    pub fn parse(filename: &str) -> Result<Config> {
+        let contents = match read_to_string(filename) {
+            Ok(contents) => contents,
+            Err(e) => bail!("config file: `{filename}` could not be read: {e}"),
+        };
+        match from_str(&contents) {
+            Ok(config) => Ok(config),
+            Err(e) => bail!("config file: `{filename}` is invalid: {e}"),
+        }
+    }
+
+    /// Return the options that parse but cannot be used.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        let dns_options = &self.dns_options;
+        for resolver in dns_options.resolvers.iter().chain(dns_options.host_resolvers.values()) {
+            if let Err(e) = resolution_provider(resolver, None) {
+                errors.push(format!("dns_options: {e}"));
+            }
+        }
+        if let Some(client_subnet) = &dns_options.client_subnet {
+            if dns_options.resolvers.is_empty() {
+                errors.push("dns_options: a client subnet requires a DNS resolver".to_owned());
+            }
+            if client_subnet.parse::<ClientSubnet>().is_err() {
+                errors.push(format!(
+                    "dns_options: client subnet `{client_subnet}` is invalid, expected IP/prefix"
+                ));
+            }
+        }
+        if dns_options.nat64 && self.ip_options.ip_protocol == IpProtocol::V4 {
+            errors.push("dns_options: NAT64 requires an ip_protocol of `v6` or `all`".to_owned());
+        }
+        if self.ping_options.tls && (self.ping_options.nk_peer || self.ping_options.payload > 0) {
+            errors.push(
+                "ping_options: TLS handshakes cannot be combined with NetKraken peer messaging or payloads".to_owned(),
+            );
+        }
+        if let Err(e) = parse_header_name(&self.http_options.probe_header) {
+            errors.push(format!("http_options: probe {e}"));
+        }
+        if self.listen_options.drop as u16 + self.listen_options.corrupt as u16 > 100 {
+            errors.push("listen_options: drop and corrupt cannot exceed 100 percent combined".to_owned());
+        }
+        if self.logging_options.sample == 0 {
+            errors.push("logging_options: sample must be at least 1".to_owned());
+        }
+        if let Some(otlp_endpoint) = &self.logging_options.otlp_endpoint {
+            if let Err(e) = parse_url(otlp_endpoint) {
+                errors.push(format!("logging_options: OTLP endpoint {e}"));
+            }
+        }
+        if let Some(syslog_target) = &self.logging_options.syslog_target {
+            if let Err(e) = parse_syslog_target(syslog_target) {
+                errors.push(format!("logging_options: {e}"));
+            }
+        }
+        if let Some(influx_url) = &self.logging_options.influx_url {
+            if let Err(e) = parse_url(influx_url) {
+                errors.push(format!("logging_options: InfluxDB {e}"));
+            }
+        }
+        if self.logging_options.influx_measurement.is_empty() {
+            errors.push("logging_options: InfluxDB measurement cannot be empty".to_owned());
+        }
+        for destination in self.logging_options.labels.keys() {
+            if !matches!(parse_destination(destination), Ok((_, port)) if port > 0) {
+                errors.push(format!(
+                    "logging_options: label destination `{destination}` is invalid, expected host:port"
+                ));
+            }
+        }
+        for (name, profile) in &self.profiles {
+            if let Err(e) = profile.connect_method() {
+                errors.push(format!("profiles.{name}: {e}"));
+            }
+            if profile.host.is_empty() {
+                errors.push(format!("profiles.{name}: host is required"));
+            }
+            if let Some(src_ip) = &profile.src_ip {
+                // Link-local sources have a zone ID.
+                if let Err(e) = parse_ipaddr(src_ip.split('%').next().unwrap_or_default()) {
+                    errors.push(format!("profiles.{name}: {e}"));
+                }
+            }
+        }
+        for (name, health) in &self.health {
+            if health.profiles.is_empty() {
+                errors.push(format!("health.{name}: at least one profile is required"));
+            }
+            for profile in health.profiles.iter().filter(|p| !self.profiles.contains_key(*p)) {
+                errors.push(format!("health.{name}: profile `{profile}` not found"));
+            }
+        }
+        errors
+    }
+
     /// Generate a default config file
     pub fn generate() -> Result<()> {
         // If config file exists don't overwrite it.
@@ -51,3 +211,195 @@ impl Config {
         Ok(())
     }
 }
+
+/// Problems found by validating a config file. Errors stop the
+/// config being used, warnings are hosts that did not resolve.
+#[derive(Debug, Default)]
+pub struct ConfigReport {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+/// Validate a config file and the targets file it is used with.
+/// The first `resolve` target hosts are resolved, and with `check_sinks`
+/// the log, record and CSV files are checked to be writable.
+pub async fn validate_config(
+    filename: &str,
+    targets_file: Option<&str>,
+    resolve: usize,
+    check_sinks: bool,
+) -> Result<ConfigReport> {
+    let config = Config::parse(filename)?;
+    let mut report = ConfigReport {
+        errors: config.validate(),
+        warnings: Vec::new(),
+    };
+
+    if let Some(targets_file) = targets_file {
+        match load_targets(targets_file, OffsetDateTime::now_utc()) {
+            Ok(targets) => {
+                for (i, target) in targets.iter().enumerate() {
+                    let mut dns_options = config.dns_options.clone();
+                    if let Some(resolver) = &target.resolver {
+                        if let Err(e) = resolution_provider(resolver, None) {
+                            report.errors.push(format!("targets file: {}: {e}", target.host));
+                            continue;
+                        }
+                        dns_options
+                            .host_resolvers
+                            .insert(target.host.to_owned(), resolver.to_owned());
+                    }
+                    if i >= resolve {
+                        continue;
+                    }
+                    let host_record = HostRecord::new(&target.host, target.port).await;
+                    let resolved_hosts = resolve_host(vec![host_record], &dns_options).await;
+                    if resolved_hosts
+                        .iter()
+                        .all(|record| record.ipv4_sockets.is_empty() && record.ipv6_sockets.is_empty())
+                    {
+                        report
+                            .warnings
+                            .push(format!("{} did not resolve to an IP address", target.host));
+                    }
+                }
+            }
+            Err(e) => report.errors.push(e.to_string()),
+        }
+    }
+
+    // The same config runs across a mixed fleet, so options
+    // that are unsupported on this platform are warnings.
+    let mut features = Vec::new();
+    if config.ping_options.escalate {
+        features.push(("ping_options: escalate".to_owned(), PlatformFeature::RouteTable));
+        features.push(("ping_options: escalate".to_owned(), PlatformFeature::ErrorQueue));
+    }
+    if let Some(syslog_target) = &config.logging_options.syslog_target {
+        if let Ok(SyslogTarget::Unix(_)) = parse_syslog_target(syslog_target) {
+            features.push(("logging_options: syslog target".to_owned(), PlatformFeature::UnixSocket));
+        }
+    }
+    for (name, profile) in &config.profiles {
+        if let Ok(ConnectMethod::UNIX | ConnectMethod::UNIXGRAM) = profile.connect_method() {
+            features.push((format!("profiles.{name}"), PlatformFeature::UnixSocket));
+        }
+    }
+    for (option, feature) in features.into_iter().filter(|(_, feature)| !feature.supported()) {
+        report.warnings.push(format!("{option}: {}", feature.unsupported_msg()));
+    }
+
+    if check_sinks {
+        let logging_options = &config.logging_options;
+        let log_file = PathBuf::from(&logging_options.dir).join(&logging_options.file);
+        let sinks = [
+            ("log file", Some(log_file.to_string_lossy().to_string())),
+            ("record file", logging_options.record_file.clone()),
+            ("CSV file", logging_options.csv_file.clone()),
+        ];
+        for (sink, path) in sinks {
+            if let Some(path) = path {
+                if let Err(e) = check_writable(Path::new(&path)) {
+                    report
+                        .errors
+                        .push(format!("logging_options: {sink} `{path}` is not writable: {e}"));
+                }
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Return the record file of a profile run by the agent, the record
+/// file name with the profile name added, eg: probes-core-fw.nkr
+pub fn profile_record_file(record_file: &str, profile: &str) -> String {
+    let path = Path::new(record_file);
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(extension) => format!("{stem}-{profile}.{}", extension.to_string_lossy()),
+        None => format!("{stem}-{profile}"),
+    };
+    path.with_file_name(name).to_string_lossy().to_string()
+}
+
+/// Check that a file can be appended to, or created when it does not exist.
+/// Files that do not exist are not created.
+fn check_writable(path: &Path) -> std::io::Result<()> {
+    if path.exists() {
+        return OpenOptions::new().append(true).open(path).map(|_| ());
+    }
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let metadata = dir.metadata()?;
+    match metadata.is_dir() && !metadata.permissions().readonly() {
+        true => Ok(()),
+        false => Err(std::io::ErrorKind::PermissionDenied.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_config_is_valid() {
+        assert!(Config::default().validate().is_empty());
+    }
+
+    #[test]
+    fn unusable_options_are_reported() {
+        let config: Config = from_str(
+            "[ping_options]\ntls = true\npayload = 64\n\n[ip_options]\n\n[logging_options]\nsample = 0\n\n\
+             [listen_options]\n\n[dns_options]\nresolvers = [\"dns.example\"]\nnat64 = true\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.validate(),
+            vec![
+                "dns_options: resolver: `dns.example` is invalid, expected system, IP[:port], https://URL, mdns, hosts:FILE or kubernetes[:NAMESPACE]".to_owned(),
+                "dns_options: NAT64 requires an ip_protocol of `v6` or `all`".to_owned(),
+                "ping_options: TLS handshakes cannot be combined with NetKraken peer messaging or payloads".to_owned(),
+                "logging_options: sample must be at least 1".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn profiles_are_read_and_validated() {
+        let config: Config = from_str(
+            "[ping_options]\n\n[ip_options]\n\n[logging_options]\n\n[listen_options]\n\n\
+             [profiles.core-fw]\nmethod = \"udp\"\nhost = \"10.1.1.1\"\nport = 53\nsrc_ip = \"fe80::1%eth0\"\n\n\
+             [profiles.broken]\nmethod = \"smtp2\"\nsrc_ip = \"10.1.1\"\n\n\
+             [health.core]\nprofiles = [\"core-fw\", \"core-rtr\"]\npolicy = \"any\"\n",
+        )
+        .unwrap();
+
+        let profile = &config.profiles["core-fw"];
+        assert_eq!(profile.connect_method().unwrap(), ConnectMethod::UDP);
+        assert_eq!(profile.port, Some(53));
+        assert_eq!(profile.repeat, None);
+        assert_eq!(config.health["core"].policy, HealthPolicy::Any);
+        assert_eq!(
+            config.validate(),
+            vec![
+                "profiles.broken: method `smtp2` is invalid".to_owned(),
+                "profiles.broken: host is required".to_owned(),
+                "profiles.broken: source address: `10.1.1` is invalid".to_owned(),
+                "health.core: profile `core-rtr` not found".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn profile_record_files_are_named_by_profile() {
+        assert_eq!(profile_record_file("probes.nkr", "core-fw"), "probes-core-fw.nkr");
+        assert_eq!(
+            profile_record_file("/var/lib/nk/probes", "web"),
+            "/var/lib/nk/probes-web"
+        );
+    }
+}
@@ -1,18 +1,108 @@
+pub const AGENT_RESTART_DELAY: u16 = 5000;
+pub const ANALYZE_ALPHA: f64 = 0.05;
 pub const APP_NAME: &str = "NK";
 pub const BIND_ADDR_IPV4: &str = "0.0.0.0";
 pub const BIND_ADDR_IPV6: &str = "::";
 pub const BIND_PORT: u16 = 0;
 pub const BUFFER_SIZE: usize = 100;
+pub const COMPARE_LATENCY_THRESHOLD: f64 = 20.0;
+pub const COMPARE_LOSS_THRESHOLD: f64 = 1.0;
 pub const CONFIG_FILE: &str = "nk.toml";
+pub const CONFIG_VALIDATE_RESOLVE: usize = 5;
+pub const DNS_CACHE_SIZE: usize = 1024;
+pub const DNS_NEGATIVE_TTL: u32 = 30;
+pub const DNS_NAT64: bool = false;
+pub const DNS_PORT: u16 = 53;
+pub const DNS_TIMEOUT: u16 = 2000;
+pub const EVIDENCE_RECORDS: usize = 10;
+#[cfg(feature = "export")]
+pub const EXPONENTIAL_HISTOGRAM_BUCKETS: usize = 160;
+#[cfg(feature = "export")]
+pub const EXPONENTIAL_HISTOGRAM_SCALE: i32 = 8;
+pub const EXPORT_BUFFER_SIZE: u64 = 10 << 20;
+#[cfg(feature = "export")]
+pub const EXPORT_RETRY_INTERVAL: u16 = 5000;
+#[cfg(feature = "export")]
+pub const EXPORT_TIMEOUT: u16 = 5000;
+pub const ESCALATE_HOP_TIMEOUT: u16 = 1000;
+pub const ESCALATE_MAX_HOPS: u8 = 16;
+pub const ESCALATE_TRACE_PORT: u16 = 33434;
+// Upper bounds of the latency histogram buckets in milliseconds.
+pub const HISTOGRAM_BOUNDS: [f64; 9] = [1.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0, 1000.0];
+pub const HISTOGRAM_WIDTH: usize = 40;
+pub const INFLUX_MEASUREMENT: &str = "netkraken";
+pub const HTTPS_PORT: u16 = 443;
+pub const HTTP_PROBE_HEADER: &str = "X-NetKraken-Probe";
+pub const IP_CONCURRENCY: usize = BUFFER_SIZE;
+pub const IP_RATE: u16 = 0;
+pub const IP_BIND_FAILURE_BUDGET: u16 = 3;
+pub const IP_SOURCE_FALLBACK: bool = true;
+pub const ICMP_ECHO_REPLY: u8 = 0;
+pub const ICMP_ECHO_REQUEST: u8 = 8;
+pub const ICMPV6_ECHO_REPLY: u8 = 129;
+pub const ICMPV6_ECHO_REQUEST: u8 = 128;
+pub const ICMP_PMTU_ATTEMPTS: u8 = 2;
+pub const KUBERNETES_CLUSTER_DOMAIN: &str = "cluster.local";
+pub const KUBERNETES_NAMESPACE: &str = "default";
+pub const LISTEN_CORRUPT: u8 = 0;
+pub const LISTEN_DELAY: u16 = 0;
+pub const LISTEN_DROP: u8 = 0;
+pub const LISTEN_ECHO: bool = false;
+pub const LOSS_BURST_CORRELATION: f64 = 0.3;
+pub const MAIL_STARTTLS: bool = false;
+pub const MDNS_ADDR: &str = "224.0.0.251:5353";
+pub const NAT64_DISCOVERY_HOST: &str = "ipv4only.arpa";
 pub const MAX_PACKET_SIZE: usize = 512;
+pub const MAX_DNS_PACKET_SIZE: usize = 4096;
 pub const CURRENT_DIR: &str = ".";
 pub const LOGFILE_NAME: &str = "nk.log";
+pub const LOGGING_KEEP: u16 = 5;
+pub const LOGGING_ROTATE_SIZE: u64 = 0;
 pub const LOGGING_JSON: bool = false;
 pub const LOGGING_SYSLOG: bool = false;
 pub const LOGGING_QUIET: bool = false;
+pub const LOGGING_HISTOGRAM: bool = false;
+pub const LOGGING_ADVISE: bool = false;
+pub const LOGGING_OTLP_HISTOGRAMS: bool = false;
+pub const LOGGING_PLAIN: bool = false;
+pub const LOGGING_VERBOSE: bool = false;
+pub const LOGGING_SAMPLE: u16 = 1;
+pub const LOGGING_SUMMARY_INTERVAL: u16 = 0;
+pub const LOGGING_WATCH: u16 = 0;
+pub const LOGGING_WATCH_INTERVAL: u16 = 5;
+pub const LOGGING_SORT_DESC: bool = false;
+pub const LOGGING_BATCH_SIZE: u16 = 100;
+pub const LOGGING_BATCH_INTERVAL: u16 = 1000;
+pub const RECORD_FILE_MAGIC: &[u8; 4] = b"NKRF";
+pub const RUN_RECORDS: usize = 1000;
+pub const RUN_WINDOW: u16 = 100;
+pub const SCHEMA_VERSION: u16 = 11;
+// Upper bound of the back off after a UDP receive error, in milliseconds.
+pub const UDP_RECV_BACKOFF: u16 = 1000;
+#[cfg(feature = "export")]
+pub const OTLP_SERVICE_NAME: &str = "netkraken";
+#[cfg(feature = "export")]
+pub const OTLP_TRACES_PATH: &str = "/v1/traces";
+#[cfg(feature = "export")]
+pub const OTLP_METRICS_PATH: &str = "/v1/metrics";
+pub const SYSLOG_PORT: u16 = 514;
+pub const RESOLV_CONF: &str = "/etc/resolv.conf";
 pub const PING_MSG: &str = "!!! Death to the demoness, Allegra Geller! Death to eXistenZ !!!";
 pub const PING_REPEAT: u16 = 4;
 pub const PING_TIMEOUT: u16 = 3000;
 pub const PING_INTERVAL: u16 = 1000;
+pub const PING_ESCALATE: bool = false;
 pub const PING_NK_PEER: bool = false;
+pub const PING_PAYLOAD: u32 = 0;
+pub const PING_TLS: bool = false;
+pub const PING_WARM: bool = false;
+pub const QUIC_ALPN: &str = "h3";
+pub const TELNET_IAC: u8 = 255;
+pub const TELNET_DONT: u8 = 254;
+pub const TELNET_DO: u8 = 253;
+pub const TELNET_WONT: u8 = 252;
+pub const TELNET_WILL: u8 = 251;
+pub const TELNET_SB: u8 = 250;
+pub const TELNET_SE: u8 = 240;
+pub const TELNET_COM_PORT_OPTION: u8 = 44;
 pub const CLI_HEADER_MSG: &str = "NetKraken - Cross platform network connectivity tester\n";
@@ -0,0 +1,149 @@
+use std::fs::File;
+use std::io::Write;
+
+use anyhow::Result;
+use local_ip_address::list_afinet_netifas;
+use serde_derive::Serialize;
+
+use crate::core::common::{
+    ConnectMethod, DnsOptions, HttpOptions, IpOptions, ListenOptions, LoggingOptions, MailOptions, PingOptions,
+    QuicOptions,
+};
+
+/// Describes a run so the evidence it produced can be archived.
+#[derive(Debug, Serialize)]
+pub struct RunManifest {
+    pub schema_version: u16,
+    pub run_id: String,
+    /// Value of the header that identified the requests of HTTP probes.
+    pub probe_identity: Option<String>,
+    pub version: String,
+    pub start_time_utc: String,
+    pub end_time_utc: String,
+    pub vantage: Vantage,
+    pub targets: Vec<ManifestTarget>,
+    pub options: ManifestOptions,
+    pub artifacts: Vec<String>,
+}
+
+impl RunManifest {
+    /// Write the manifest to a file as JSON.
+    pub fn write(&self, filename: &str) -> Result<()> {
+        let json = serde_json::to_string_pretty(&self)?;
+        let mut file = File::create(filename)?;
+        file.write_all(json.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// The host a run was executed from.
+#[derive(Debug, Serialize)]
+pub struct Vantage {
+    pub hostname: String,
+    pub src_ipv4: String,
+    pub src_ipv6: String,
+    pub interfaces: Vec<VantageInterface>,
+}
+
+impl Vantage {
+    pub fn new(src_ipv4: &str, src_ipv6: &str) -> Vantage {
+        let hostname = hostname::get()
+            .map(|h| h.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        let interfaces = list_afinet_netifas()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(name, ip)| VantageInterface {
+                name,
+                address: ip.to_string(),
+            })
+            .collect();
+
+        Vantage {
+            hostname,
+            src_ipv4: src_ipv4.to_owned(),
+            src_ipv6: src_ipv6.to_owned(),
+            interfaces,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct VantageInterface {
+    pub name: String,
+    pub address: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ManifestTarget {
+    pub host: String,
+    pub port: u16,
+    pub protocol: ConnectMethod,
+    pub listen: bool,
+}
+
+/// The options applied to a run after merging
+/// the config file and CLI options.
+#[derive(Debug, Serialize)]
+pub struct ManifestOptions {
+    pub ping_options: PingOptions,
+    pub ip_options: IpOptions,
+    pub logging_options: LoggingOptions,
+    pub listen_options: ListenOptions,
+    pub mail_options: MailOptions,
+    pub dns_options: DnsOptions,
+    pub http_options: HttpOptions,
+    pub quic_options: QuicOptions,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::konst::SCHEMA_VERSION;
+
+    #[test]
+    fn manifest_is_written_as_json() {
+        let manifest = RunManifest {
+            schema_version: SCHEMA_VERSION,
+            run_id: "run-1".to_owned(),
+            probe_identity: None,
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            start_time_utc: "2026-10-17T00:00:00Z".to_owned(),
+            end_time_utc: "2026-10-17T00:01:00Z".to_owned(),
+            vantage: Vantage::new("0.0.0.0", "::"),
+            targets: vec![ManifestTarget {
+                host: "192.0.2.1".to_owned(),
+                port: 443,
+                protocol: ConnectMethod::TCP,
+                listen: false,
+            }],
+            options: ManifestOptions {
+                ping_options: PingOptions::default(),
+                ip_options: IpOptions::default(),
+                logging_options: LoggingOptions::default(),
+                listen_options: ListenOptions::default(),
+                mail_options: MailOptions::default(),
+                dns_options: DnsOptions::default(),
+                http_options: HttpOptions::default(),
+                quic_options: QuicOptions::default(),
+            },
+            artifacts: vec!["records.nkr".to_owned()],
+        };
+        let filename = std::env::temp_dir().join(format!("nk-manifest-{}.json", uuid::Uuid::new_v4()));
+        manifest.write(filename.to_str().unwrap()).unwrap();
+
+        let written: serde_json::Value = serde_json::from_slice(&std::fs::read(&filename).unwrap()).unwrap();
+        let _ = std::fs::remove_file(&filename);
+
+        assert_eq!(written["schema_version"], SCHEMA_VERSION);
+        assert_eq!(written["run_id"], "run-1");
+        assert_eq!(written["targets"][0]["host"], "192.0.2.1");
+        assert_eq!(written["targets"][0]["protocol"], "TCP");
+        assert_eq!(
+            written["options"]["ping_options"]["repeat"],
+            PingOptions::default().repeat
+        );
+        assert_eq!(written["artifacts"], serde_json::json!(["records.nkr"]));
+    }
+}
@@ -1,3 +1,5 @@
 pub mod common;
 pub mod config;
 pub mod konst;
+pub mod manifest;
+pub mod targets;
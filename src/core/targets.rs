@@ -0,0 +1,296 @@
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+
+use anyhow::{bail, Result};
+use serde_derive::Deserialize;
+use time::{OffsetDateTime, Weekday};
+
+use crate::core::common::ConnectMethod;
+use crate::util::parser::{parse_csv_targets, parse_destination};
+
+/// A destination in a TOML targets file.
+#[derive(Debug, Deserialize)]
+pub struct Target {
+    pub host: String,
+    pub port: u16,
+    /// Name of a schedule in the `[schedules]` table.
+    #[serde(default)]
+    pub schedule: Option<String>,
+    /// Resolver used for the host instead of the default resolver.
+    #[serde(default)]
+    pub resolver: Option<String>,
+    /// Label shown instead of the host's addresses.
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+/// When a target may be probed, in UTC.
+/// `days = ["sat", "sun"]` and `hours = "22:00-06:00"`.
+/// A window that ends before it starts runs past midnight.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Schedule {
+    #[serde(default)]
+    pub days: Vec<String>,
+    #[serde(default)]
+    pub hours: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TargetsFile {
+    #[serde(default)]
+    schedules: BTreeMap<String, Schedule>,
+    targets: Vec<Target>,
+}
+
+/// A destination loaded from a targets file, with the name of its schedule,
+/// its resolver, its label, and its protocol and timeout if it has them.
+#[derive(Debug, PartialEq)]
+pub struct ScheduledTarget {
+    pub host: String,
+    pub port: u16,
+    pub schedule: Option<String>,
+    pub resolver: Option<String>,
+    pub label: Option<String>,
+    /// Protocol the target is probed with instead of the `--method`.
+    pub protocol: Option<ConnectMethod>,
+    pub timeout: Option<u16>,
+    pub active: bool,
+}
+
+impl Schedule {
+    /// Return whether a target with this schedule may be probed at `now`.
+    pub fn is_active(&self, now: OffsetDateTime) -> Result<bool> {
+        let mut days = Vec::new();
+        for day in &self.days {
+            days.push(parse_weekday(day)?);
+        }
+        if !days.is_empty() && !days.contains(&now.weekday()) {
+            return Ok(false);
+        }
+        let (start, end) = match &self.hours {
+            Some(hours) => parse_hours(hours)?,
+            None => return Ok(true),
+        };
+        let minute = u16::from(now.hour()) * 60 + u16::from(now.minute());
+        match start < end {
+            true => Ok(start <= minute && minute < end),
+            false => Ok(minute >= start || minute < end),
+        }
+    }
+}
+
+fn parse_weekday(day: &str) -> Result<Weekday> {
+    let weekday = match day.to_lowercase().as_str() {
+        "mon" => Weekday::Monday,
+        "tue" => Weekday::Tuesday,
+        "wed" => Weekday::Wednesday,
+        "thu" => Weekday::Thursday,
+        "fri" => Weekday::Friday,
+        "sat" => Weekday::Saturday,
+        "sun" => Weekday::Sunday,
+        _ => bail!("schedule: `{day}` is invalid, expected mon, tue, wed, thu, fri, sat or sun"),
+    };
+    Ok(weekday)
+}
+
+/// Parse a `HH:MM-HH:MM` window into minutes since midnight.
+fn parse_hours(hours: &str) -> Result<(u16, u16)> {
+    let minutes = |time: &str| -> Option<u16> {
+        let (hour, minute) = time.trim().split_once(':')?;
+        let (hour, minute) = (hour.parse::<u16>().ok()?, minute.parse::<u16>().ok()?);
+        match hour < 24 && minute < 60 {
+            true => Some(hour * 60 + minute),
+            false => None,
+        }
+    };
+    let window = hours
+        .split_once('-')
+        .and_then(|(start, end)| Some((minutes(start)?, minutes(end)?)));
+    match window {
+        Some((start, end)) if start != end => Ok((start, end)),
+        _ => bail!("schedule: `{hours}` is invalid, expected HH:MM-HH:MM"),
+    }
+}
+
+/// Load destinations from a targets file.
+/// TOML files (`.toml`) have a `[[targets]]` table for each destination,
+/// CSV files (`.csv`) have a `host,port,protocol,label,timeout` row for
+/// each destination, and other files have a `host:port` destination on each line.
+pub fn load_targets(filename: &str, now: OffsetDateTime) -> Result<Vec<ScheduledTarget>> {
+    let contents = match read_to_string(filename) {
+        Ok(contents) => contents,
+        Err(e) => bail!("targets file: `{filename}` could not be read: {e}"),
+    };
+    if filename.ends_with(".csv") {
+        let targets = match parse_csv_targets(&contents) {
+            Ok(targets) => targets,
+            Err(e) => bail!("targets file: `{filename}` is invalid\n{e}"),
+        };
+        return Ok(targets
+            .into_iter()
+            .map(|target| ScheduledTarget {
+                host: target.host,
+                port: target.port,
+                schedule: None,
+                resolver: None,
+                label: target.label,
+                protocol: Some(target.protocol),
+                timeout: target.timeout,
+                active: true,
+            })
+            .collect());
+    }
+    parse_targets(&contents, filename.ends_with(".toml"), now)
+}
+
+/// Parse the contents of a targets file.
+/// Blank lines and lines starting with `#` are ignored.
+/// Targets in a TOML file are only active during their schedule.
+pub fn parse_targets(contents: &str, is_toml: bool, now: OffsetDateTime) -> Result<Vec<ScheduledTarget>> {
+    if is_toml {
+        let targets_file: TargetsFile = toml::from_str(contents)?;
+        let mut targets = Vec::new();
+        for target in targets_file.targets {
+            let active = match &target.schedule {
+                Some(name) => match targets_file.schedules.get(name) {
+                    Some(schedule) => schedule.is_active(now)?,
+                    None => bail!("targets file: schedule `{name}` is not defined"),
+                },
+                None => true,
+            };
+            targets.push(ScheduledTarget {
+                host: target.host,
+                port: target.port,
+                schedule: target.schedule,
+                resolver: target.resolver,
+                label: target.label,
+                protocol: None,
+                timeout: None,
+                active,
+            });
+        }
+        return Ok(targets);
+    }
+    contents
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (host, port) = parse_destination(line)?;
+            Ok(ScheduledTarget {
+                host,
+                port,
+                schedule: None,
+                resolver: None,
+                label: None,
+                protocol: None,
+                timeout: None,
+                active: true,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn destinations(targets: Vec<ScheduledTarget>) -> Vec<(String, u16, bool)> {
+        targets.into_iter().map(|t| (t.host, t.port, t.active)).collect()
+    }
+
+    #[test]
+    fn parse_targets_from_lines() {
+        let contents = "# web servers\nhost1:443\n\n  [2001:db8::1]:8080  \n";
+        let now = OffsetDateTime::now_utc();
+
+        assert_eq!(
+            destinations(parse_targets(contents, false, now).unwrap()),
+            vec![("host1".to_owned(), 443, true), ("2001:db8::1".to_owned(), 8080, true)]
+        );
+        assert!(parse_targets("host1:https", false, now).is_err());
+    }
+
+    #[test]
+    fn parse_targets_from_toml() {
+        let contents = "[[targets]]\nhost = \"host1\"\nport = 443\n\n[[targets]]\nhost = \"host2\"\nport = 8080\n";
+
+        assert_eq!(
+            destinations(parse_targets(contents, true, OffsetDateTime::now_utc()).unwrap()),
+            vec![("host1".to_owned(), 443, true), ("host2".to_owned(), 8080, true)]
+        );
+    }
+
+    #[test]
+    fn targets_can_choose_a_resolver() {
+        let contents = "[[targets]]\nhost = \"printer.local\"\nport = 631\nresolver = \"mdns\"\n\n[[targets]]\nhost = \"host1\"\nport = 443\n";
+        let targets = parse_targets(contents, true, OffsetDateTime::now_utc()).unwrap();
+
+        assert_eq!(targets[0].resolver.as_deref(), Some("mdns"));
+        assert_eq!(targets[1].resolver, None);
+    }
+
+    #[test]
+    fn targets_can_have_a_label() {
+        let contents = "[[targets]]\nhost = \"10.1.1.1\"\nport = 443\nlabel = \"core-fw\"\n\n[[targets]]\nhost = \"host1\"\nport = 443\n";
+        let targets = parse_targets(contents, true, OffsetDateTime::now_utc()).unwrap();
+
+        assert_eq!(targets[0].label.as_deref(), Some("core-fw"));
+        assert_eq!(targets[1].label, None);
+    }
+
+    #[test]
+    fn scheduled_targets_are_only_active_during_their_schedule() {
+        let contents = r#"
+            [schedules]
+            business = { days = ["mon", "tue", "wed", "thu", "fri"], hours = "09:00-17:00" }
+            nightly = { hours = "22:00-06:00" }
+
+            [[targets]]
+            host = "host1"
+            port = 443
+            schedule = "business"
+
+            [[targets]]
+            host = "host2"
+            port = 443
+            schedule = "nightly"
+        "#;
+        // Days after Monday 2024-01-01 00:00 UTC.
+        let at = |day: i64, hour: i64, minute: i64| {
+            OffsetDateTime::from_unix_timestamp(1_704_067_200 + day * 86_400 + hour * 3_600 + minute * 60).unwrap()
+        };
+        let active = |now| -> Vec<bool> {
+            parse_targets(contents, true, now)
+                .unwrap()
+                .iter()
+                .map(|t| t.active)
+                .collect()
+        };
+
+        assert_eq!(active(at(0, 10, 30)), vec![true, false]);
+        assert_eq!(active(at(0, 23, 0)), vec![false, true]);
+        assert_eq!(active(at(1, 5, 59)), vec![false, true]);
+        assert_eq!(active(at(5, 10, 30)), vec![false, false]);
+    }
+
+    #[test]
+    fn invalid_schedules_are_rejected() {
+        let now = OffsetDateTime::now_utc();
+        let schedule = |days: &[&str], hours: Option<&str>| Schedule {
+            days: days.iter().map(|d| d.to_string()).collect(),
+            hours: hours.map(|h| h.to_owned()),
+        };
+
+        assert!(schedule(&["monday"], None).is_active(now).is_err());
+        assert!(schedule(&[], Some("9-17")).is_active(now).is_err());
+        assert!(schedule(&[], Some("09:00-24:00")).is_active(now).is_err());
+        assert!(schedule(&[], Some("09:00-09:00")).is_active(now).is_err());
+        assert!(parse_targets(
+            "[[targets]]\nhost = \"host1\"\nport = 443\nschedule = \"nightly\"\n",
+            true,
+            now
+        )
+        .is_err());
+    }
+}
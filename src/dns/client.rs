@@ -0,0 +1,247 @@
+use anyhow::{bail, Result};
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::RecordType;
+use time::OffsetDateTime;
+use tokio::time::{timeout, Duration};
+
+use crate::core::common::{
+    ClientResult, ClientSummary, ConnectMethod, ConnectRecord, ConnectResult, DnsOptions, DnsRecord, LoggingOptions,
+    PingOptions, ProbeSample,
+};
+use crate::core::konst::{RUN_RECORDS, RUN_WINDOW};
+use crate::util::advice::AdviceAccumulator;
+use crate::util::csv::CsvWriter;
+use crate::util::dns::{resolver_answers, response_code_name, system_resolver, AnswerMonitor};
+use crate::util::handler::{
+    advice_handler, console_handler, info_handler, io_error_switch_handler, loop_handler, resource_warning_handler,
+    summary_handler, summary_log_handler, Cancel, StatsListener,
+};
+use crate::util::message::{
+    dns_changes_table_msg, interval_summary_table_msg, path_header_msg, path_summary_table_msg,
+};
+use crate::util::record::RecordSampler;
+use crate::util::result::{assert_results, client_summary_result, label_results, sort_results, IntervalAccumulator};
+use crate::util::run::RunHandle;
+use crate::util::sink::RecordSinks;
+use crate::util::time::{calc_connect_ms, time_now_us, time_now_utc};
+
+/// Resolution only client. Times queries for a name, by default its
+/// A and AAAA records, and monitors the answers over time, recording
+/// when they change and, after a known failover, how long the change
+/// took to propagate.
+#[derive(Debug)]
+pub struct DnsClient {
+    pub host: String,
+    pub record_type: Option<RecordType>,
+    pub failover_time: Option<OffsetDateTime>,
+    pub logging_options: LoggingOptions,
+    pub ping_options: PingOptions,
+    pub dns_options: DnsOptions,
+    /// Queries the statistics of the run while it is in progress.
+    pub run_handle: RunHandle,
+}
+
+impl DnsClient {
+    pub fn new(
+        host: String,
+        record_type: Option<RecordType>,
+        failover_time: Option<OffsetDateTime>,
+        logging_options: LoggingOptions,
+        ping_options: PingOptions,
+        dns_options: DnsOptions,
+    ) -> DnsClient {
+        DnsClient {
+            host,
+            record_type,
+            failover_time,
+            logging_options,
+            ping_options,
+            dns_options,
+            run_handle: RunHandle::new(RUN_WINDOW, RUN_RECORDS),
+        }
+    }
+
+    pub async fn connect(&self) -> Result<Vec<ClientResult>> {
+        // TTLs are only returned by a DNS resolver.
+        let resolver = match self
+            .dns_options
+            .resolver_addrs()
+            .into_iter()
+            .next()
+            .or_else(system_resolver)
+        {
+            Some(resolver) => resolver,
+            None => bail!("DNS mode requires a DNS resolver."),
+        };
+        let client_subnet = self.dns_options.client_subnet();
+        let record_types = match self.record_type {
+            Some(record_type) => vec![record_type],
+            None => vec![RecordType::A, RecordType::AAAA],
+        };
+        let record_type = record_types
+            .iter()
+            .map(|record_type| record_type.to_string())
+            .collect::<Vec<String>>()
+            .join(",");
+
+        let mut samples: Vec<ProbeSample> = Vec::new();
+        let mut changes: Vec<(String, DnsRecord)> = Vec::new();
+        let mut answer_monitor = AnswerMonitor::new(self.failover_time);
+
+        let mut count: u16 = 0;
+        let mut send_count: u16 = 0;
+        let mut record_sink = RecordSinks::create(&self.logging_options)?;
+        let mut record_sampler = RecordSampler::new(self.logging_options.sample);
+        let mut csv_writer = match &self.logging_options.csv_file {
+            Some(csv_file) => Some(CsvWriter::append(csv_file)?),
+            None => None,
+        };
+        let mut interval_accumulator =
+            IntervalAccumulator::new(self.logging_options.summary_interval, self.logging_options.watch);
+        let mut advice_accumulator = AdviceAccumulator::default();
+        let labels = self.logging_options.path_labels(&self.host);
+
+        let ping_header = path_header_msg(&self.host, ConnectMethod::DNS);
+        let ping_header = format!("{ping_header} (resolver {resolver}, {record_type} records)");
+        info_handler(&ping_header, &self.logging_options);
+
+        // Ctrl-C cancels the client, interrupting the requests in flight,
+        // and the results collected so far are summarised.
+        let cancel = Cancel::on_ctrl_c();
+        // SIGQUIT prints the statistics of the run so far.
+        let _stats_listener = StatsListener::spawn(self.run_handle.clone());
+        let throttle = self.run_handle.start_run();
+
+        loop {
+            // The interval between requests is interrupted when cancelled.
+            let next = loop_handler(count, self.ping_options.repeat, self.ping_options.interval);
+            match cancel.run(next).await {
+                Some(false) => count += 1,
+                _ => break,
+            }
+
+            // The query in flight is dropped when cancelled.
+            let query = throttle.run(async {
+                let mut result = ConnectRecord {
+                    result: ConnectResult::Unknown,
+                    protocol: ConnectMethod::DNS,
+                    source: resolver.to_string(),
+                    destination: self.host.to_owned(),
+                    time: -1.0,
+                    success: false,
+                    error_msg: None,
+                    tags: self.logging_options.tags.clone(),
+                    label: labels.get(&self.host).cloned(),
+                    ..Default::default()
+                };
+
+                let tick = Duration::from_millis(self.ping_options.timeout.into());
+                let pre_query_timestamp = time_now_us();
+                let answers = timeout(
+                    tick,
+                    resolver_answers(&self.host, &record_types, resolver, client_subnet),
+                );
+                match answers.await {
+                    Ok(Ok((answers, ttl, response_code))) => {
+                        let query_time = calc_connect_ms(pre_query_timestamp, time_now_us());
+                        let mut dns_record = answer_monitor.observe(answers, ttl, OffsetDateTime::now_utc());
+                        dns_record.record_type = record_type.to_owned();
+                        dns_record.response_code = response_code_name(response_code);
+                        match (response_code, dns_record.answers.is_empty()) {
+                            (ResponseCode::NoError, false) => {
+                                result.result = ConnectResult::Pong;
+                                result.success = true;
+                                result.time = query_time;
+                            }
+                            (ResponseCode::NoError | ResponseCode::NXDomain, _) => {
+                                result.result = ConnectResult::NoAnswer;
+                                result.error_msg = Some(format!("{} has no {record_type} records", self.host));
+                            }
+                            (response_code, _) => {
+                                result.result = ConnectResult::ProtocolError;
+                                result.error_msg = Some(format!("DNS query to {resolver} failed: {response_code}"));
+                            }
+                        }
+                        if dns_record.changed {
+                            changes.push((time_now_utc(), dns_record.clone()));
+                        }
+                        result.dns = Some(dns_record);
+                    }
+                    Ok(Err(e)) => {
+                        result.error_msg = Some(e.to_string());
+                        result.result = match e.downcast::<std::io::Error>() {
+                            Ok(e) => io_error_switch_handler(e),
+                            Err(_) => ConnectResult::ProtocolError,
+                        };
+                    }
+                    Err(e) => {
+                        result.error_msg = Some(e.to_string());
+                        result.result = ConnectResult::Timeout;
+                    }
+                }
+                result
+            });
+            let Some(result) = cancel.run(query).await else {
+                break;
+            };
+
+            samples.push(ProbeSample::new(&result));
+            interval_accumulator.push(&result);
+            advice_accumulator.push(&result);
+            self.run_handle.push(&result);
+
+            if record_sampler.sample() {
+                console_handler(&result, &self.logging_options).await;
+                if let Some(record_sink) = record_sink.as_mut() {
+                    record_sink.push(result.clone())?;
+                }
+                if let Some(csv_writer) = csv_writer.as_mut() {
+                    csv_writer.write(&result)?;
+                }
+            }
+
+            if let Some(mut interval_results) = interval_accumulator.take_due() {
+                label_results(&mut interval_results, &labels);
+                let interval_table = interval_summary_table_msg(
+                    self.logging_options.summary_interval,
+                    self.logging_options.watch,
+                    &interval_results,
+                    &self.logging_options.tags,
+                );
+                summary_handler(&interval_table, &self.logging_options);
+            }
+
+            send_count += 1;
+        }
+
+        let client_summary = ClientSummary { send_count, samples };
+        let mut client_results = vec![client_summary_result(&self.host, ConnectMethod::DNS, client_summary)];
+        sort_results(&mut client_results, &self.logging_options);
+        assert_results(&mut client_results, &self.logging_options);
+        label_results(&mut client_results, &labels);
+
+        let summary_table = path_summary_table_msg(
+            &self.host,
+            ConnectMethod::DNS,
+            &client_results,
+            &self.logging_options.tags,
+            self.logging_options.histogram,
+        );
+        summary_handler(&summary_table, &self.logging_options);
+        resource_warning_handler(&self.run_handle, &self.logging_options);
+        advice_handler(&advice_accumulator, &self.logging_options);
+        if !changes.is_empty() {
+            summary_handler(&dns_changes_table_msg(&self.host, &changes), &self.logging_options);
+        }
+        summary_log_handler(&client_results, &self.logging_options);
+        if let Some(csv_writer) = csv_writer.as_mut() {
+            csv_writer.write_summary(&client_results)?;
+        }
+
+        if let Some(record_sink) = record_sink.as_mut() {
+            record_sink.flush()?;
+        }
+
+        Ok(client_results)
+    }
+}
@@ -0,0 +1,197 @@
+use anyhow::Result;
+
+use crate::core::common::{
+    ClientResult, ClientSummary, ConnectMethod, ConnectRecord, ConnectResult, LoggingOptions, PingOptions, ProbeSample,
+};
+use crate::core::konst::{RUN_RECORDS, RUN_WINDOW};
+use crate::util::advice::AdviceAccumulator;
+use crate::util::csv::CsvWriter;
+use crate::util::handler::{
+    advice_handler, console_handler, info_handler, loop_handler, resource_warning_handler, summary_handler,
+    summary_log_handler, Cancel, StatsListener,
+};
+use crate::util::message::{interval_summary_table_msg, path_header_msg, path_summary_table_msg};
+use crate::util::record::RecordSampler;
+use crate::util::result::{assert_results, client_summary_result, label_results, sort_results, IntervalAccumulator};
+use crate::util::run::RunHandle;
+use crate::util::sink::RecordSinks;
+use crate::util::time::{calc_connect_ms, time_now_us};
+
+/// Heartbeat client. Each cycle records that the host running
+/// NetKraken is alive, so a silent host can be told apart from a
+/// network where every target is down. The time of a heartbeat is
+/// the time since the previous heartbeat.
+#[derive(Debug)]
+pub struct HeartbeatClient {
+    pub hostname: String,
+    pub logging_options: LoggingOptions,
+    pub ping_options: PingOptions,
+    /// Queries the statistics of the run while it is in progress.
+    pub run_handle: RunHandle,
+}
+
+impl HeartbeatClient {
+    pub fn new(logging_options: LoggingOptions, ping_options: PingOptions) -> HeartbeatClient {
+        let hostname = hostname::get()
+            .map(|h| h.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        HeartbeatClient {
+            hostname,
+            logging_options,
+            ping_options,
+            run_handle: RunHandle::new(RUN_WINDOW, RUN_RECORDS),
+        }
+    }
+
+    pub async fn connect(&self) -> Result<Vec<ClientResult>> {
+        let mut samples: Vec<ProbeSample> = Vec::new();
+
+        let mut count: u16 = 0;
+        let mut send_count: u16 = 0;
+        let mut record_sink = RecordSinks::create(&self.logging_options)?;
+        let mut record_sampler = RecordSampler::new(self.logging_options.sample);
+        let mut csv_writer = match &self.logging_options.csv_file {
+            Some(csv_file) => Some(CsvWriter::append(csv_file)?),
+            None => None,
+        };
+        let mut interval_accumulator =
+            IntervalAccumulator::new(self.logging_options.summary_interval, self.logging_options.watch);
+        let mut advice_accumulator = AdviceAccumulator::default();
+        let labels = self.logging_options.path_labels(&self.hostname);
+
+        let ping_header = path_header_msg(&self.hostname, ConnectMethod::HEARTBEAT);
+        info_handler(&ping_header, &self.logging_options);
+
+        // Ctrl-C cancels the client, interrupting the requests in flight,
+        // and the results collected so far are summarised.
+        let cancel = Cancel::on_ctrl_c();
+        // SIGQUIT prints the statistics of the run so far.
+        let _stats_listener = StatsListener::spawn(self.run_handle.clone());
+
+        let mut previous_timestamp = time_now_us();
+        loop {
+            // The interval between requests is interrupted when cancelled.
+            let next = loop_handler(count, self.ping_options.repeat, self.ping_options.interval);
+            match cancel.run(next).await {
+                Some(false) => count += 1,
+                _ => break,
+            }
+
+            let timestamp = time_now_us();
+            let mut result = heartbeat(&self.hostname, calc_connect_ms(previous_timestamp, timestamp));
+            previous_timestamp = timestamp;
+            result.tags = self.logging_options.tags.clone();
+            result.label = labels.get(&result.destination).cloned();
+            samples.push(ProbeSample::new(&result));
+            interval_accumulator.push(&result);
+            advice_accumulator.push(&result);
+            self.run_handle.push(&result);
+
+            if record_sampler.sample() {
+                console_handler(&result, &self.logging_options).await;
+                if let Some(record_sink) = record_sink.as_mut() {
+                    record_sink.push(result.clone())?;
+                }
+                if let Some(csv_writer) = csv_writer.as_mut() {
+                    csv_writer.write(&result)?;
+                }
+            }
+
+            if let Some(mut interval_results) = interval_accumulator.take_due() {
+                label_results(&mut interval_results, &labels);
+                let interval_table = interval_summary_table_msg(
+                    self.logging_options.summary_interval,
+                    self.logging_options.watch,
+                    &interval_results,
+                    &self.logging_options.tags,
+                );
+                summary_handler(&interval_table, &self.logging_options);
+            }
+
+            send_count += 1;
+        }
+
+        let client_summary = ClientSummary { send_count, samples };
+        let mut client_results = vec![client_summary_result(
+            &self.hostname,
+            ConnectMethod::HEARTBEAT,
+            client_summary,
+        )];
+        sort_results(&mut client_results, &self.logging_options);
+        assert_results(&mut client_results, &self.logging_options);
+        label_results(&mut client_results, &labels);
+
+        let summary_table = path_summary_table_msg(
+            &self.hostname,
+            ConnectMethod::HEARTBEAT,
+            &client_results,
+            &self.logging_options.tags,
+            self.logging_options.histogram,
+        );
+        summary_handler(&summary_table, &self.logging_options);
+        resource_warning_handler(&self.run_handle, &self.logging_options);
+        advice_handler(&advice_accumulator, &self.logging_options);
+        summary_log_handler(&client_results, &self.logging_options);
+        if let Some(csv_writer) = csv_writer.as_mut() {
+            csv_writer.write_summary(&client_results)?;
+        }
+
+        if let Some(record_sink) = record_sink.as_mut() {
+            record_sink.flush()?;
+        }
+
+        Ok(client_results)
+    }
+}
+
+fn heartbeat(hostname: &str, time: f64) -> ConnectRecord {
+    ConnectRecord {
+        result: ConnectResult::Alive,
+        protocol: ConnectMethod::HEARTBEAT,
+        source: hostname.to_owned(),
+        destination: hostname.to_owned(),
+        time,
+        success: true,
+        error_msg: None,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn each_cycle_records_a_heartbeat() {
+        let client = HeartbeatClient::new(
+            LoggingOptions {
+                quiet: true,
+                ..Default::default()
+            },
+            PingOptions {
+                repeat: 3,
+                interval: 50,
+                ..Default::default()
+            },
+        );
+        let mut records = client.run_handle.subscribe();
+        let results = client.connect().await.unwrap();
+        client.run_handle.end();
+
+        let mut heartbeats = Vec::new();
+        while let Some(record) = records.recv().await {
+            heartbeats.push(record);
+        }
+        assert_eq!(heartbeats.len(), 3);
+        assert!(heartbeats
+            .iter()
+            .all(|r| r.success && r.result == ConnectResult::Alive && r.destination == client.hostname));
+        // The time of a heartbeat is the time since the previous one.
+        assert!(heartbeats[1].time >= 50.0 && heartbeats[2].time >= 50.0);
+
+        assert_eq!(results[0].sent, 3);
+        assert_eq!(results[0].received, 3);
+        assert_eq!(results[0].protocol, ConnectMethod::HEARTBEAT);
+    }
+}
@@ -0,0 +1,500 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+
+use anyhow::{bail, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::{timeout, Duration};
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::TlsConnector;
+
+use crate::core::common::{
+    ClientResult, ClientSummary, ConnectMethod, ConnectRecord, ConnectResult, DnsOptions, HostRecord, HttpOptions,
+    HttpRecord, HttpUrl, IpOptions, IpPort, IpProtocol, LoggingOptions, PingOptions, ProbeSample, SourceFallback,
+};
+use crate::core::konst::{BIND_ADDR_IPV4, BIND_ADDR_IPV6, BIND_PORT, RUN_RECORDS, RUN_WINDOW};
+use crate::tcp::client::get_tcp_socket;
+use crate::util::advice::AdviceAccumulator;
+use crate::util::csv::CsvWriter;
+use crate::util::dns::resolve_host;
+use crate::util::handler::{
+    advice_handler, bind_error_switch_handler, console_handler, info_handler, io_error_switch_handler, loop_handler,
+    resource_warning_handler, summary_handler, summary_log_handler, Cancel, StatsListener,
+};
+use crate::util::message::{client_summary_table_msg, interval_summary_table_msg, ping_header_msg, resolved_ips_msg};
+use crate::util::parser::{http_status_line, parse_ipaddr, parse_scoped_ipaddr};
+use crate::util::record::RecordSampler;
+use crate::util::result::{
+    assert_results, client_summary_result, label_results, sort_results, IntervalAccumulator, PhaseAccumulator,
+};
+use crate::util::run::RunHandle;
+use crate::util::schedule::schedule_sockets;
+use crate::util::sink::RecordSinks;
+use crate::util::time::{calc_connect_ms, time_now_us};
+use crate::util::tls::{tls_connector, tls_error_result};
+
+/// HTTP(S) client. Requests a URL from each address of its host,
+/// timing DNS resolution, the TCP connection, the TLS handshake,
+/// the first byte of the response and the whole response.
+#[derive(Debug)]
+pub struct HttpClient {
+    pub url: HttpUrl,
+    pub src_ipv4: Option<IpAddr>,
+    pub src_ipv6: Option<IpAddr>,
+    pub src_ipv6_scope_id: u32,
+    pub src_port: u16,
+    pub logging_options: LoggingOptions,
+    pub ping_options: PingOptions,
+    pub ip_options: IpOptions,
+    pub dns_options: DnsOptions,
+    pub http_options: HttpOptions,
+    /// Queries the statistics of the run while it is in progress.
+    pub run_handle: RunHandle,
+}
+
+impl HttpClient {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        url: HttpUrl,
+        src_ipv4: Option<String>,
+        src_ipv6: Option<String>,
+        src_port: Option<u16>,
+        logging_options: LoggingOptions,
+        ping_options: PingOptions,
+        ip_options: IpOptions,
+        dns_options: DnsOptions,
+        http_options: HttpOptions,
+    ) -> HttpClient {
+        let src_ipv4 = match src_ipv4 {
+            Some(x) => parse_ipaddr(&x).ok(),
+            None => parse_ipaddr(BIND_ADDR_IPV4).ok(),
+        };
+
+        let (src_ipv6, src_ipv6_scope_id) = match src_ipv6 {
+            Some(x) => parse_scoped_ipaddr(&x).ok().unzip(),
+            None => parse_scoped_ipaddr(BIND_ADDR_IPV6).ok().unzip(),
+        };
+        let src_ipv6_scope_id = src_ipv6_scope_id.unwrap_or_default();
+
+        let src_port = src_port.unwrap_or(BIND_PORT);
+
+        HttpClient {
+            url,
+            src_ipv4,
+            src_ipv6,
+            src_ipv6_scope_id,
+            src_port,
+            logging_options,
+            ping_options,
+            ip_options,
+            dns_options,
+            http_options,
+            run_handle: RunHandle::new(RUN_WINDOW, RUN_RECORDS),
+        }
+    }
+
+    pub async fn connect(&self) -> Result<Vec<ClientResult>> {
+        let src_ip_port = IpPort {
+            // These should never be None at this point as they are set in the HttpClient::new() constructor.
+            ipv4: self.src_ipv4.unwrap(),
+            ipv6: self.src_ipv6.unwrap(),
+            ipv6_scope_id: self.src_ipv6_scope_id,
+            port: self.src_port,
+            fallback: SourceFallback::new(self.ip_options.source_fallback),
+        };
+
+        // Only HTTPS URLs need a TLS connector.
+        let tls_connector = match self.url.tls {
+            true => Some(tls_connector()),
+            false => None,
+        };
+
+        // Resolve the host once up front so resolution errors stop the run.
+        // The host is resolved again for each request to time DNS.
+        let (resolved_host, _dns_time) = self.resolve().await;
+        match resolved_host.ipv4_sockets.is_empty() && resolved_host.ipv6_sockets.is_empty() {
+            true => bail!("{} did not resolve to an IP address", resolved_host.host),
+            false => info_handler(&resolved_ips_msg(&resolved_host), &self.logging_options),
+        }
+
+        let mut results_map: HashMap<String, Vec<ProbeSample>> = HashMap::new();
+        let socket_labels = self.logging_options.socket_labels(std::slice::from_ref(&resolved_host));
+
+        let mut count: u16 = 0;
+        let mut send_count: u16 = 0;
+        let mut record_sink = RecordSinks::create(&self.logging_options)?;
+        let mut record_sampler = RecordSampler::new(self.logging_options.sample);
+        let mut csv_writer = match &self.logging_options.csv_file {
+            Some(csv_file) => Some(CsvWriter::append(csv_file)?),
+            None => None,
+        };
+        let mut interval_accumulator =
+            IntervalAccumulator::new(self.logging_options.summary_interval, self.logging_options.watch);
+        let mut advice_accumulator = AdviceAccumulator::default();
+        let mut phase_accumulator = PhaseAccumulator::default();
+
+        let ping_header = ping_header_msg(&self.url.host, self.url.port, ConnectMethod::HTTP);
+        let ping_header = format!("{ping_header} ({} {})", self.http_options.method, self.url);
+        info_handler(&ping_header, &self.logging_options);
+
+        // Ctrl-C cancels the client, interrupting the requests in flight,
+        // and the results collected so far are summarised.
+        let cancel = Cancel::on_ctrl_c();
+        // SIGQUIT prints the statistics of the run so far.
+        let _stats_listener = StatsListener::spawn(self.run_handle.clone());
+        let throttle = self.run_handle.start_run();
+
+        loop {
+            // The interval between requests is interrupted when cancelled.
+            let next = loop_handler(count, self.ping_options.repeat, self.ping_options.interval);
+            match cancel.run(next).await {
+                Some(false) => count += 1,
+                _ => break,
+            }
+
+            let (host_record, dns_time) = self.resolve().await;
+            let sockets = match self.ip_options.ip_protocol {
+                IpProtocol::All => [host_record.ipv4_sockets, host_record.ipv6_sockets].concat(),
+                IpProtocol::V4 => host_record.ipv4_sockets,
+                IpProtocol::V6 => host_record.ipv6_sockets,
+            };
+            if sockets.is_empty() {
+                let resolve_msg = format!("{} did not resolve to an IP address", self.url.host);
+                info_handler(&resolve_msg, &self.logging_options);
+            }
+
+            let round = schedule_sockets(sockets, self.ip_options, &throttle, |dst_socket| {
+                let src_ip_port = src_ip_port.clone();
+                let tls_connector = tls_connector.clone();
+                async move {
+                    //
+                    connect_host(
+                        src_ip_port,
+                        dst_socket,
+                        &self.url,
+                        self.ping_options,
+                        &self.http_options,
+                        dns_time,
+                        tls_connector,
+                    )
+                    .await
+                }
+            });
+            // The requests in flight are dropped when cancelled.
+            let Some(results) = cancel.run(round).await else {
+                break;
+            };
+
+            for mut result in results {
+                result.tags = self.logging_options.tags.clone();
+                result.label = socket_labels.get(&result.destination).cloned();
+                // Addresses can change between requests.
+                results_map
+                    .entry(result.destination.to_owned())
+                    .or_default()
+                    .push(ProbeSample::new(&result));
+                interval_accumulator.push(&result);
+                advice_accumulator.push(&result);
+                self.run_handle.push(&result);
+                phase_accumulator.push(&result);
+
+                if record_sampler.sample() {
+                    console_handler(&result, &self.logging_options).await;
+                    if let Some(record_sink) = record_sink.as_mut() {
+                        record_sink.push(result.clone())?;
+                    }
+                    if let Some(csv_writer) = csv_writer.as_mut() {
+                        csv_writer.write(&result)?;
+                    }
+                }
+            }
+
+            if let Some(mut interval_results) = interval_accumulator.take_due() {
+                label_results(&mut interval_results, &socket_labels);
+                let interval_table = interval_summary_table_msg(
+                    self.logging_options.summary_interval,
+                    self.logging_options.watch,
+                    &interval_results,
+                    &self.logging_options.tags,
+                );
+                summary_handler(&interval_table, &self.logging_options);
+            }
+
+            send_count += 1;
+        }
+
+        let mut client_results: Vec<ClientResult> = Vec::new();
+        for (addr, samples) in results_map {
+            let client_summary = ClientSummary { send_count, samples };
+            let mut summary_msg = client_summary_result(&addr, ConnectMethod::HTTP, client_summary);
+            summary_msg.phases = phase_accumulator.averages(&addr);
+            client_results.push(summary_msg)
+        }
+        sort_results(&mut client_results, &self.logging_options);
+        assert_results(&mut client_results, &self.logging_options);
+        label_results(&mut client_results, &socket_labels);
+
+        let summary_table = client_summary_table_msg(
+            &self.url.host,
+            self.url.port,
+            ConnectMethod::HTTP,
+            &client_results,
+            &self.logging_options.tags,
+            self.logging_options.histogram,
+        );
+        summary_handler(&summary_table, &self.logging_options);
+        resource_warning_handler(&self.run_handle, &self.logging_options);
+        advice_handler(&advice_accumulator, &self.logging_options);
+        summary_log_handler(&client_results, &self.logging_options);
+        if let Some(csv_writer) = csv_writer.as_mut() {
+            csv_writer.write_summary(&client_results)?;
+        }
+
+        if let Some(record_sink) = record_sink.as_mut() {
+            record_sink.flush()?;
+        }
+
+        Ok(client_results)
+    }
+
+    /// Resolve the URL host, returning the host record and the time taken.
+    async fn resolve(&self) -> (HostRecord, f64) {
+        let host_record = HostRecord {
+            host: self.url.host.to_owned(),
+            port: self.url.port,
+            ipv4_sockets: vec![],
+            ipv6_sockets: vec![],
+        };
+        let pre_dns_timestamp = time_now_us();
+        let host_record = resolve_host(vec![host_record.clone()], &self.dns_options)
+            .await
+            .pop()
+            .unwrap_or(host_record);
+        (host_record, calc_connect_ms(pre_dns_timestamp, time_now_us()))
+    }
+}
+
+async fn connect_host(
+    src: IpPort,
+    dst_socket: SocketAddr,
+    url: &HttpUrl,
+    ping_options: PingOptions,
+    http_options: &HttpOptions,
+    dns_time: f64,
+    tls_connector: Option<TlsConnector>,
+) -> ConnectRecord {
+    let mut http_record = HttpRecord {
+        url: url.to_string(),
+        method: http_options.method,
+        dns_time,
+        connect_time: -1.0,
+        tls_time: -1.0,
+        first_byte_time: -1.0,
+        total_time: -1.0,
+        ..Default::default()
+    };
+
+    // Bind the source socket to the same IP Version as the destination socket.
+    let bind_addr = src.bind_addr(&dst_socket);
+    let src_socket = get_tcp_socket(bind_addr);
+    src.record_bind(&dst_socket, src_socket.is_ok());
+
+    // If the source socket is an error, we could not bind to the socket.
+    let src_socket = match src_socket {
+        Ok(socket) => socket,
+        Err(e) => {
+            return ConnectRecord {
+                result: bind_error_switch_handler(e.as_ref()),
+                protocol: ConnectMethod::HTTP,
+                source: bind_addr.to_string(),
+                destination: dst_socket.to_string(),
+                time: -1.0,
+                success: false,
+                error_msg: Some("Error binding to socket".to_owned()),
+                http: Some(http_record),
+                ..Default::default()
+            };
+        }
+    };
+
+    let local_addr = src_socket
+        .local_addr()
+        // This should never fail because we always
+        // pass a bound socket.
+        .unwrap_or_else(|_| panic!("ERROR GETTING TCP SOCKET LOCAL ADDRESS"))
+        .to_string();
+
+    let mut conn_record = ConnectRecord {
+        result: ConnectResult::Unknown,
+        protocol: ConnectMethod::HTTP,
+        source: local_addr,
+        destination: dst_socket.to_string(),
+        time: -1.0,
+        success: false,
+        error_msg: None,
+        ..Default::default()
+    };
+
+    // record timestamp before connection
+    let pre_conn_timestamp = time_now_us();
+
+    let tick = Duration::from_millis(ping_options.timeout.into());
+    let stream = match timeout(tick, src_socket.connect(dst_socket)).await {
+        Ok(Ok(stream)) => stream,
+        // Connection error
+        Ok(Err(e)) => {
+            conn_record.error_msg = Some(e.to_string());
+            conn_record.result = io_error_switch_handler(e);
+            conn_record.http = Some(http_record);
+            return conn_record;
+        }
+        // Timeout error
+        Err(e) => {
+            conn_record.error_msg = Some(e.to_string());
+            conn_record.result = io_error_switch_handler(e.into());
+            conn_record.http = Some(http_record);
+            return conn_record;
+        }
+    };
+    http_record.connect_time = calc_connect_ms(pre_conn_timestamp, time_now_us());
+
+    conn_record.source = stream
+        .local_addr()
+        // This should never fail. If we have a TCP stream,
+        // we should have always have a local address.
+        .unwrap_or_else(|_| panic!("ERROR GETTING TCP STREAM LOCAL ADDRESS"))
+        .to_string();
+
+    // The timeout covers the TLS handshake and the request separately.
+    let request = match tls_connector {
+        Some(tls_connector) => {
+            // IP address hosts are verified against the certificate IP SANs.
+            let server_name = match ServerName::try_from(url.host.to_owned()) {
+                Ok(server_name) => server_name,
+                Err(e) => {
+                    conn_record.error_msg = Some(e.to_string());
+                    conn_record.result = ConnectResult::TlsError;
+                    conn_record.http = Some(http_record);
+                    return conn_record;
+                }
+            };
+            match timeout(tick, tls_connector.connect(server_name, stream)).await {
+                Ok(Ok(tls_stream)) => {
+                    http_record.tls_time = calc_connect_ms(pre_conn_timestamp, time_now_us());
+                    timeout(
+                        tick,
+                        http_request(tls_stream, url, http_options, pre_conn_timestamp, &mut http_record),
+                    )
+                    .await
+                }
+                Ok(Err(e)) => {
+                    conn_record.error_msg = Some(e.to_string());
+                    conn_record.result = tls_error_result(&e);
+                    conn_record.http = Some(http_record);
+                    return conn_record;
+                }
+                Err(e) => Err(e),
+            }
+        }
+        None => {
+            timeout(
+                tick,
+                http_request(stream, url, http_options, pre_conn_timestamp, &mut http_record),
+            )
+            .await
+        }
+    };
+
+    match request {
+        Ok(Ok(status)) if status < 400 => {
+            conn_record.success = true;
+            conn_record.result = ConnectResult::Pong;
+            conn_record.time = http_record.total_time;
+        }
+        Ok(Ok(status)) => {
+            conn_record.error_msg = Some(format!("HTTP status {status}"));
+            conn_record.result = ConnectResult::HttpError;
+        }
+        // IO errors are mapped the same as a TCP connection,
+        // anything else is an unexpected reply from the server.
+        Ok(Err(e)) => {
+            conn_record.error_msg = Some(e.to_string());
+            conn_record.result = match e.downcast::<std::io::Error>() {
+                Ok(e) => io_error_switch_handler(e),
+                Err(_) => ConnectResult::ProtocolError,
+            };
+        }
+        // Timeout error
+        Err(e) => {
+            conn_record.error_msg = Some(e.to_string());
+            conn_record.result = ConnectResult::Timeout;
+        }
+    }
+    conn_record.http = Some(http_record);
+
+    conn_record
+}
+
+/// Send a request and read the whole response, returning the status code.
+/// The connection is closed by the server after the response.
+async fn http_request<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    url: &HttpUrl,
+    http_options: &HttpOptions,
+    pre_conn_timestamp: u128,
+    http_record: &mut HttpRecord,
+) -> Result<u16> {
+    let host = match (url.host.contains(':'), url.port, url.tls) {
+        (true, _, _) => format!("[{}]:{}", url.host, url.port),
+        (false, 80, false) | (false, 443, true) => url.host.to_owned(),
+        (false, port, _) => format!("{}:{port}", url.host),
+    };
+    // The probe header lets the owners of a service correlate requests with a run.
+    let probe_header = match http_options.probe_header.is_empty() {
+        true => String::new(),
+        false => format!("{}: {}\r\n", http_options.probe_header, http_options.probe_identity),
+    };
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: {host}\r\nUser-Agent: netkraken/{}\r\n{probe_header}Accept: */*\r\nConnection: close\r\n\r\n",
+        http_options.method,
+        url.path,
+        env!("CARGO_PKG_VERSION"),
+    );
+    stream.write_all(request.as_bytes()).await?;
+    stream.flush().await?;
+
+    let mut response = Vec::new();
+    let mut buffer = [0u8; 8192];
+    let status = loop {
+        let len = stream.read(&mut buffer).await?;
+        if len == 0 {
+            bail!("connection closed by the server");
+        }
+        if response.is_empty() {
+            http_record.first_byte_time = calc_connect_ms(pre_conn_timestamp, time_now_us());
+        }
+        response.extend_from_slice(&buffer[..len]);
+        if let Some(i) = response.iter().position(|b| *b == b'\n') {
+            let line = String::from_utf8_lossy(&response[..i]);
+            match http_status_line(&line) {
+                Some(status) => break status,
+                None => bail!("invalid HTTP status line: {}", line.trim_end()),
+            }
+        }
+    };
+    http_record.status = status;
+
+    let mut bytes = response.len() as u64;
+    loop {
+        match stream.read(&mut buffer).await {
+            Ok(0) => break,
+            Ok(len) => bytes += len as u64,
+            // Servers often close a TLS connection without a close_notify.
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    http_record.bytes = bytes;
+    http_record.total_time = calc_connect_ms(pre_conn_timestamp, time_now_us());
+
+    Ok(status)
+}
@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+
+use anyhow::{bail, Result};
+use futures::StreamExt;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+use tokio::time::{timeout_at, Duration, Instant};
+
+use crate::core::common::{
+    ClientResult, ClientSummary, ConnectMethod, ConnectRecord, ConnectResult, DnsOptions, HostRecord, HostResults,
+    IpOptions, IpPort, IpProtocol, LoggingOptions, PingOptions, ProbeSample, SourceFallback,
+};
+use crate::core::konst::{
+    BIND_ADDR_IPV4, BIND_ADDR_IPV6, BUFFER_SIZE, MAX_PACKET_SIZE, PING_MSG, RUN_RECORDS, RUN_WINDOW,
+};
+use crate::icmp::packet::{echo_reply, echo_request};
+use crate::util::advice::AdviceAccumulator;
+use crate::util::csv::CsvWriter;
+use crate::util::dns::resolve_host;
+use crate::util::handler::{
+    advice_handler, bind_error_switch_handler, console_handler, info_handler, loop_handler, resource_warning_handler,
+    summary_handler, summary_log_handler, Cancel, StatsListener,
+};
+use crate::util::message::{interval_summary_table_msg, path_header_msg, path_summary_table_msg, resolved_ips_msg};
+use crate::util::parser::{parse_ipaddr, parse_scoped_ipaddr};
+use crate::util::record::RecordSampler;
+use crate::util::result::{assert_results, client_summary_result, sort_results, IntervalAccumulator};
+use crate::util::run::RunHandle;
+use crate::util::schedule::{schedule_sockets, Throttle};
+use crate::util::sink::RecordSinks;
+use crate::util::time::{calc_connect_ms, time_now_us};
+
+/// ICMP echo client. Unprivileged ICMP sockets are used when the
+/// platform allows them, otherwise a raw socket is used which
+/// requires elevated privileges.
+#[derive(Debug)]
+pub struct IcmpClient {
+    pub dst_ip: String,
+    pub src_ipv4: Option<IpAddr>,
+    pub src_ipv6: Option<IpAddr>,
+    pub src_ipv6_scope_id: u32,
+    pub logging_options: LoggingOptions,
+    pub ping_options: PingOptions,
+    pub ip_options: IpOptions,
+    pub dns_options: DnsOptions,
+    /// Queries the statistics of the run while it is in progress.
+    pub run_handle: RunHandle,
+}
+
+impl IcmpClient {
+    pub fn new(
+        dst_ip: String,
+        src_ipv4: Option<String>,
+        src_ipv6: Option<String>,
+        logging_options: LoggingOptions,
+        ping_options: PingOptions,
+        ip_options: IpOptions,
+        dns_options: DnsOptions,
+    ) -> IcmpClient {
+        let src_ipv4 = match src_ipv4 {
+            Some(x) => parse_ipaddr(&x).ok(),
+            None => parse_ipaddr(BIND_ADDR_IPV4).ok(),
+        };
+
+        let (src_ipv6, src_ipv6_scope_id) = match src_ipv6 {
+            Some(x) => parse_scoped_ipaddr(&x).ok().unzip(),
+            None => parse_scoped_ipaddr(BIND_ADDR_IPV6).ok().unzip(),
+        };
+        let src_ipv6_scope_id = src_ipv6_scope_id.unwrap_or_default();
+
+        IcmpClient {
+            dst_ip,
+            src_ipv4,
+            src_ipv6,
+            src_ipv6_scope_id,
+            logging_options,
+            ping_options,
+            ip_options,
+            dns_options,
+            run_handle: RunHandle::new(RUN_WINDOW, RUN_RECORDS),
+        }
+    }
+
+    pub async fn connect(&self) -> Result<Vec<ClientResult>> {
+        let src_ip_port = IpPort {
+            // These should never be None at this point as they are set in the IcmpClient::new() constructor.
+            ipv4: self.src_ipv4.unwrap(),
+            ipv6: self.src_ipv6.unwrap(),
+            ipv6_scope_id: self.src_ipv6_scope_id,
+            port: 0,
+            fallback: SourceFallback::new(self.ip_options.source_fallback),
+        };
+
+        // Resolve the destination host to IPv4 and IPv6 addresses.
+        let host_records = HostRecord::new(&self.dst_ip, 0).await;
+        let hosts = vec![host_records.clone()];
+        let resolved_hosts = resolve_host(hosts, &self.dns_options).await;
+
+        // Check if the host resolved to an IPv4 or IPv6 addresses.
+        // If not, return an error.
+        for record in &resolved_hosts {
+            match record.ipv4_sockets.is_empty() && record.ipv6_sockets.is_empty() {
+                true => bail!("{} did not resolve to an IP address", record.host),
+                false => {
+                    info_handler(&resolved_ips_msg(record), &self.logging_options);
+                }
+            }
+        }
+
+        // ICMP destinations do not have a port, so results
+        // are keyed by the destination IP address.
+        let mut results_map: HashMap<String, HashMap<String, Vec<ProbeSample>>> = HashMap::new();
+        for record in &resolved_hosts {
+            let sockets = match &self.ip_options.ip_protocol {
+                IpProtocol::All => [record.ipv4_sockets.clone(), record.ipv6_sockets.clone()].concat(),
+                IpProtocol::V4 => record.ipv4_sockets.clone(),
+                IpProtocol::V6 => record.ipv6_sockets.clone(),
+            };
+            let addrs = results_map.entry(record.host.to_owned()).or_default();
+            for socket in sockets {
+                addrs.insert(socket.ip().to_string(), vec![]);
+            }
+        }
+
+        let mut count: u16 = 0;
+        let mut send_count: u16 = 0;
+        let mut record_sink = RecordSinks::create(&self.logging_options)?;
+        let mut record_sampler = RecordSampler::new(self.logging_options.sample);
+        let mut csv_writer = match &self.logging_options.csv_file {
+            Some(csv_file) => Some(CsvWriter::append(csv_file)?),
+            None => None,
+        };
+        let mut interval_accumulator =
+            IntervalAccumulator::new(self.logging_options.summary_interval, self.logging_options.watch);
+        let mut advice_accumulator = AdviceAccumulator::default();
+
+        let ping_header = path_header_msg(&self.dst_ip, ConnectMethod::ICMP);
+        info_handler(&ping_header, &self.logging_options);
+
+        // Ctrl-C cancels the client, interrupting the requests in flight,
+        // and the results collected so far are summarised.
+        let cancel = Cancel::on_ctrl_c();
+        // SIGQUIT prints the statistics of the run so far.
+        let _stats_listener = StatsListener::spawn(self.run_handle.clone());
+        let throttle = self.run_handle.start_run();
+
+        loop {
+            // The interval between requests is interrupted when cancelled.
+            let next = loop_handler(count, self.ping_options.repeat, self.ping_options.interval);
+            match cancel.run(next).await {
+                Some(false) => count += 1,
+                _ => break,
+            }
+
+            let round = futures::stream::iter(resolved_hosts.clone())
+                .map(|host_record| {
+                    let src_ip_port = src_ip_port.clone();
+                    let throttle = &throttle;
+                    async move {
+                        //
+                        process_host(
+                            src_ip_port,
+                            host_record,
+                            self.ping_options,
+                            self.ip_options,
+                            throttle,
+                            count,
+                        )
+                        .await
+                    }
+                })
+                .buffer_unordered(BUFFER_SIZE)
+                .collect::<Vec<HostResults>>();
+            // The requests in flight are dropped when cancelled.
+            let Some(host_results) = cancel.run(round).await else {
+                break;
+            };
+
+            for host in host_results {
+                for mut result in host.results {
+                    result.tags = self.logging_options.tags.clone();
+                    results_map
+                        // This should never fail
+                        .get_mut(&host.host)
+                        .unwrap()
+                        // This should never fail
+                        .get_mut(&result.destination)
+                        .unwrap()
+                        .push(ProbeSample::new(&result));
+                    interval_accumulator.push(&result);
+                    advice_accumulator.push(&result);
+                    self.run_handle.push(&result);
+
+                    if record_sampler.sample() {
+                        console_handler(&result, &self.logging_options).await;
+                        if let Some(record_sink) = record_sink.as_mut() {
+                            record_sink.push(result.clone())?;
+                        }
+                        if let Some(csv_writer) = csv_writer.as_mut() {
+                            csv_writer.write(&result)?;
+                        }
+                    }
+                }
+            }
+
+            if let Some(interval_results) = interval_accumulator.take_due() {
+                let interval_table = interval_summary_table_msg(
+                    self.logging_options.summary_interval,
+                    self.logging_options.watch,
+                    &interval_results,
+                    &self.logging_options.tags,
+                );
+                summary_handler(&interval_table, &self.logging_options);
+            }
+
+            send_count += 1;
+        }
+
+        let mut client_results: Vec<ClientResult> = Vec::new();
+        for (_, addrs) in results_map {
+            for (addr, samples) in addrs {
+                let client_summary = ClientSummary { send_count, samples };
+                client_results.push(client_summary_result(&addr, ConnectMethod::ICMP, client_summary))
+            }
+        }
+        sort_results(&mut client_results, &self.logging_options);
+        assert_results(&mut client_results, &self.logging_options);
+
+        let summary_table = path_summary_table_msg(
+            &self.dst_ip,
+            ConnectMethod::ICMP,
+            &client_results,
+            &self.logging_options.tags,
+            self.logging_options.histogram,
+        );
+        summary_handler(&summary_table, &self.logging_options);
+        resource_warning_handler(&self.run_handle, &self.logging_options);
+        advice_handler(&advice_accumulator, &self.logging_options);
+        summary_log_handler(&client_results, &self.logging_options);
+        if let Some(csv_writer) = csv_writer.as_mut() {
+            csv_writer.write_summary(&client_results)?;
+        }
+
+        if let Some(record_sink) = record_sink.as_mut() {
+            record_sink.flush()?;
+        }
+
+        Ok(client_results)
+    }
+}
+
+async fn process_host(
+    src_ip_port: IpPort,
+    host_record: HostRecord,
+    ping_options: PingOptions,
+    ip_options: IpOptions,
+    throttle: &Throttle,
+    sequence: u16,
+) -> HostResults {
+    // Create a vector of sockets based on the IP protocol.
+    let sockets = match ip_options.ip_protocol {
+        IpProtocol::All => [host_record.ipv4_sockets, host_record.ipv6_sockets].concat(),
+        IpProtocol::V4 => host_record.ipv4_sockets,
+        IpProtocol::V6 => host_record.ipv6_sockets,
+    };
+
+    let results: Vec<ConnectRecord> = schedule_sockets(sockets, ip_options, throttle, |dst_socket| {
+        let src_ip_port = src_ip_port.clone();
+        async move {
+            //
+            ping_host(src_ip_port, dst_socket, ping_options, sequence).await
+        }
+    })
+    .await;
+
+    HostResults {
+        host: host_record.host,
+        results,
+    }
+}
+
+/// Create an ICMP socket for the IP version of the destination.
+/// Returns the socket and whether it is a raw socket.
+pub fn get_icmp_socket(bind_addr: SocketAddr) -> std::io::Result<(UdpSocket, bool)> {
+    let (domain, protocol) = match bind_addr.is_ipv4() {
+        true => (Domain::IPV4, Protocol::ICMPV4),
+        false => (Domain::IPV6, Protocol::ICMPV6),
+    };
+    let (socket, raw) = match Socket::new(domain, Type::DGRAM, Some(protocol)) {
+        Ok(socket) => (socket, false),
+        Err(_) => (Socket::new(domain, Type::RAW, Some(protocol))?, true),
+    };
+    socket.set_nonblocking(true)?;
+    socket.bind(&bind_addr.into())?;
+    Ok((UdpSocket::from_std(socket.into())?, raw))
+}
+
+pub async fn ping_host(src: IpPort, dst_socket: SocketAddr, ping_options: PingOptions, sequence: u16) -> ConnectRecord {
+    // Bind the source socket to the same IP Version as the destination socket.
+    let bind_addr = src.bind_addr(&dst_socket);
+
+    let mut conn_record = ConnectRecord {
+        result: ConnectResult::Unknown,
+        protocol: ConnectMethod::ICMP,
+        source: bind_addr.ip().to_string(),
+        destination: dst_socket.ip().to_string(),
+        time: -1.0,
+        success: false,
+        error_msg: None,
+        ..Default::default()
+    };
+
+    let icmp_socket = get_icmp_socket(bind_addr);
+    src.record_bind(&dst_socket, icmp_socket.is_ok());
+    let (socket, raw) = match icmp_socket {
+        Ok(s) => s,
+        Err(e) => {
+            conn_record.result = bind_error_switch_handler(&e);
+            conn_record.error_msg = Some(format!("Error creating ICMP socket: {e}"));
+            return conn_record;
+        }
+    };
+
+    if let Err(e) = socket.connect(dst_socket).await {
+        conn_record.error_msg = Some(e.to_string());
+        return conn_record;
+    }
+
+    // Once connected, the local address is confirmed by
+    // the route to the destination.
+    if let Ok(addr) = socket.local_addr() {
+        conn_record.source = addr.ip().to_string();
+    }
+
+    // Unprivileged ICMP sockets replace the identifier with
+    // the local port, so only raw sockets match on it.
+    let ipv6 = dst_socket.is_ipv6();
+    let identifier = std::process::id() as u16;
+    let request = echo_request(ipv6, identifier, sequence, PING_MSG.as_bytes());
+
+    // record timestamp before connection
+    let pre_conn_timestamp = time_now_us();
+
+    if let Err(e) = socket.send(&request).await {
+        conn_record.error_msg = Some(e.to_string());
+        return conn_record;
+    }
+
+    // Wait for a reply, ignoring replies to other requests.
+    let deadline = Instant::now() + Duration::from_millis(ping_options.timeout.into());
+    let mut buffer = vec![0u8; MAX_PACKET_SIZE];
+    loop {
+        match timeout_at(deadline, socket.recv(&mut buffer)).await {
+            Ok(Ok(len)) => match echo_reply(ipv6, &buffer[..len]) {
+                Some((id, seq)) if seq == sequence && (!raw || id == identifier) => {
+                    // Record timestamp after connection
+                    let post_conn_timestamp = time_now_us();
+
+                    conn_record.success = true;
+                    conn_record.result = ConnectResult::Pong;
+                    conn_record.time = calc_connect_ms(pre_conn_timestamp, post_conn_timestamp);
+                    break;
+                }
+                _ => continue,
+            },
+            Ok(Err(e)) => {
+                conn_record.error_msg = Some(e.to_string());
+                break;
+            }
+            Err(e) => {
+                conn_record.result = ConnectResult::Timeout;
+                conn_record.error_msg = Some(e.to_string());
+                break;
+            }
+        }
+    }
+
+    conn_record
+}
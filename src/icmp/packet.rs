@@ -0,0 +1,86 @@
+use crate::core::konst::{ICMPV6_ECHO_REPLY, ICMPV6_ECHO_REQUEST, ICMP_ECHO_REPLY, ICMP_ECHO_REQUEST};
+
+/// Build an ICMP (or ICMPv6) echo request.
+/// The kernel calculates the checksum of ICMPv6 packets.
+pub fn echo_request(ipv6: bool, identifier: u16, sequence: u16, payload: &[u8]) -> Vec<u8> {
+    let icmp_type = match ipv6 {
+        true => ICMPV6_ECHO_REQUEST,
+        false => ICMP_ECHO_REQUEST,
+    };
+    let mut packet = vec![icmp_type, 0, 0, 0];
+    packet.extend_from_slice(&identifier.to_be_bytes());
+    packet.extend_from_slice(&sequence.to_be_bytes());
+    packet.extend_from_slice(payload);
+
+    if !ipv6 {
+        let checksum = checksum(&packet);
+        packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+    }
+    packet
+}
+
+/// Parse an ICMP (or ICMPv6) echo reply, returning its identifier and sequence.
+/// Raw IPv4 sockets receive the IP header, which is skipped.
+pub fn echo_reply(ipv6: bool, packet: &[u8]) -> Option<(u16, u16)> {
+    let (reply_type, packet) = match ipv6 {
+        true => (ICMPV6_ECHO_REPLY, packet),
+        false => {
+            let header_len = match packet.first() {
+                Some(b) if b >> 4 == 4 => ((b & 0x0f) as usize) * 4,
+                _ => 0,
+            };
+            (ICMP_ECHO_REPLY, packet.get(header_len..)?)
+        }
+    };
+    if packet.len() < 8 || packet[0] != reply_type {
+        return None;
+    }
+    let identifier = u16::from_be_bytes([packet[4], packet[5]]);
+    let sequence = u16::from_be_bytes([packet[6], packet[7]]);
+    Some((identifier, sequence))
+}
+
+/// RFC 1071 internet checksum.
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn echo_request_checksum_is_valid() {
+        let packet = echo_request(false, 0x1234, 1, b"NetKraken");
+
+        assert_eq!(packet[0], ICMP_ECHO_REQUEST);
+        assert_eq!(checksum(&packet), 0);
+    }
+
+    #[test]
+    fn echo_reply_with_ip_header_is_parsed() {
+        let mut reply = echo_request(false, 0x1234, 7, b"NetKraken");
+        reply[0] = ICMP_ECHO_REPLY;
+        let mut packet = vec![0x45];
+        packet.extend_from_slice(&[0; 19]);
+        packet.extend_from_slice(&reply);
+
+        assert_eq!(echo_reply(false, &reply), Some((0x1234, 7)));
+        assert_eq!(echo_reply(false, &packet), Some((0x1234, 7)));
+    }
+
+    #[test]
+    fn echo_request_is_not_a_reply() {
+        let packet = echo_request(true, 0x1234, 7, b"NetKraken");
+
+        assert_eq!(echo_reply(true, &packet), None);
+        assert_eq!(echo_reply(true, &packet[..4]), None);
+    }
+}
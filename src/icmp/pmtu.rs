@@ -0,0 +1,484 @@
+use std::net::{IpAddr, SocketAddr};
+
+use anyhow::{bail, Result};
+#[cfg(target_os = "linux")]
+use local_ip_address::list_afinet_netifas;
+use tokio::io::Interest;
+use tokio::net::UdpSocket;
+use tokio::time::{timeout_at, Duration, Instant};
+
+use crate::core::common::{
+    ClientResult, ConnectMethod, ConnectResult, DnsOptions, HostRecord, IpOptions, IpPort, IpProtocol, LoggingOptions,
+    PingOptions, PmtuRecord, SourceFallback,
+};
+use crate::core::konst::{BIND_ADDR_IPV4, BIND_ADDR_IPV6, ICMP_PMTU_ATTEMPTS, PING_MSG};
+use crate::icmp::client::get_icmp_socket;
+use crate::icmp::packet::{echo_reply, echo_request};
+use crate::util::dns::resolve_host;
+use crate::util::handler::{info_handler, result_handler, summary_handler};
+use crate::util::message::{path_header_msg, pmtu_table_msg, resolved_ips_msg};
+use crate::util::parser::{parse_ipaddr, parse_scoped_ipaddr};
+use crate::util::platform::PlatformFeature;
+use crate::util::recverr::{ErrorQueue, IcmpError};
+use crate::util::time::{calc_connect_ms, time_now_us};
+
+/// Length of the IPv4 and ICMP echo headers.
+const IPV4_HEADER_LEN: u16 = 28;
+/// Length of the IPv6 and ICMPv6 echo headers.
+const IPV6_HEADER_LEN: u16 = 48;
+
+/// Path MTU discovery client. Sends ICMP echo requests that may
+/// not be fragmented, searching for the largest packet that reaches
+/// each address of the destination.
+#[derive(Debug)]
+pub struct PmtuClient {
+    pub dst_ip: String,
+    pub src_ipv4: Option<IpAddr>,
+    pub src_ipv6: Option<IpAddr>,
+    pub src_ipv6_scope_id: u32,
+    pub logging_options: LoggingOptions,
+    pub ping_options: PingOptions,
+    pub ip_options: IpOptions,
+    pub dns_options: DnsOptions,
+}
+
+impl PmtuClient {
+    pub fn new(
+        dst_ip: String,
+        src_ipv4: Option<String>,
+        src_ipv6: Option<String>,
+        logging_options: LoggingOptions,
+        ping_options: PingOptions,
+        ip_options: IpOptions,
+        dns_options: DnsOptions,
+    ) -> PmtuClient {
+        let src_ipv4 = match src_ipv4 {
+            Some(x) => parse_ipaddr(&x).ok(),
+            None => parse_ipaddr(BIND_ADDR_IPV4).ok(),
+        };
+
+        let (src_ipv6, src_ipv6_scope_id) = match src_ipv6 {
+            Some(x) => parse_scoped_ipaddr(&x).ok().unzip(),
+            None => parse_scoped_ipaddr(BIND_ADDR_IPV6).ok().unzip(),
+        };
+        let src_ipv6_scope_id = src_ipv6_scope_id.unwrap_or_default();
+
+        PmtuClient {
+            dst_ip,
+            src_ipv4,
+            src_ipv6,
+            src_ipv6_scope_id,
+            logging_options,
+            ping_options,
+            ip_options,
+            dns_options,
+        }
+    }
+
+    pub async fn discover(&self) -> Result<Vec<ClientResult>> {
+        if !PlatformFeature::PathMtu.supported() {
+            bail!(PlatformFeature::PathMtu.unsupported_msg());
+        }
+
+        let src_ip_port = IpPort {
+            // These should never be None at this point as they are set in the PmtuClient::new() constructor.
+            ipv4: self.src_ipv4.unwrap(),
+            ipv6: self.src_ipv6.unwrap(),
+            ipv6_scope_id: self.src_ipv6_scope_id,
+            port: 0,
+            fallback: SourceFallback::new(self.ip_options.source_fallback),
+        };
+
+        let host_records = HostRecord::new(&self.dst_ip, 0).await;
+        let resolved_hosts = resolve_host(vec![host_records], &self.dns_options).await;
+
+        for record in &resolved_hosts {
+            match record.ipv4_sockets.is_empty() && record.ipv6_sockets.is_empty() {
+                true => bail!("{} did not resolve to an IP address", record.host),
+                false => {
+                    info_handler(&resolved_ips_msg(record), &self.logging_options);
+                }
+            }
+        }
+
+        let ping_header = path_header_msg(&self.dst_ip, ConnectMethod::ICMP);
+        info_handler(&format!("{ping_header} (path MTU discovery)"), &self.logging_options);
+
+        let mut pmtu_records = Vec::new();
+        for record in resolved_hosts {
+            let sockets = match &self.ip_options.ip_protocol {
+                IpProtocol::All => [record.ipv4_sockets, record.ipv6_sockets].concat(),
+                IpProtocol::V4 => record.ipv4_sockets,
+                IpProtocol::V6 => record.ipv6_sockets,
+            };
+            for dst_socket in sockets {
+                pmtu_records
+                    .push(discover_path(&src_ip_port, dst_socket, &self.ping_options, &self.logging_options).await);
+            }
+        }
+
+        summary_handler(&pmtu_table_msg(&self.dst_ip, &pmtu_records), &self.logging_options);
+
+        // The discovered MTUs have no latencies to summarise.
+        Ok(vec![])
+    }
+}
+
+/// Outcome of probing one packet size.
+#[derive(Debug, PartialEq)]
+enum Probe {
+    Passed(f64),
+    TooBig(Option<IpAddr>, Option<u16>),
+    Lost,
+    Failed(String),
+}
+
+/// Binary search for the largest packet size that passes. The
+/// interface MTU is tried first, as most paths carry it, then
+/// any next hop MTU reported by a router.
+#[derive(Debug)]
+pub struct MtuSearch {
+    min: u16,
+    // Largest size that passed, or one less than `min`.
+    passed: u32,
+    // Smallest size that failed, or one more than the maximum.
+    failed: u32,
+    probed: bool,
+    hint: Option<u16>,
+}
+
+impl MtuSearch {
+    pub fn new(min: u16, max: u16) -> MtuSearch {
+        MtuSearch {
+            min,
+            passed: u32::from(min) - 1,
+            failed: u32::from(max) + 1,
+            probed: false,
+            hint: None,
+        }
+    }
+
+    /// Returns the next size to probe, or None once the search is done.
+    pub fn next_size(&mut self) -> Option<u16> {
+        if self.failed - self.passed <= 1 {
+            return None;
+        }
+        let size = match self.hint.take().map(u32::from) {
+            Some(hint) if hint > self.passed && hint < self.failed => hint,
+            _ if !self.probed => self.failed - 1,
+            _ => self.passed + (self.failed - self.passed) / 2,
+        };
+        self.probed = true;
+        u16::try_from(size).ok()
+    }
+
+    pub fn passed(&mut self, size: u16) {
+        self.passed = self.passed.max(size.into());
+    }
+
+    /// A reported MTU also fails every larger size.
+    pub fn failed(&mut self, size: u16, reported_mtu: Option<u16>) {
+        self.failed = self.failed.min(size.into());
+        if let Some(mtu) = reported_mtu {
+            self.failed = self.failed.min(u32::from(mtu) + 1);
+        }
+        self.hint = reported_mtu;
+    }
+
+    /// The largest size that passed, if any.
+    pub fn path_mtu(&self) -> Option<u16> {
+        u16::try_from(self.passed).ok().filter(|passed| *passed >= self.min)
+    }
+}
+
+async fn discover_path(
+    src: &IpPort,
+    dst_socket: SocketAddr,
+    ping_options: &PingOptions,
+    logging_options: &LoggingOptions,
+) -> PmtuRecord {
+    let ipv6 = dst_socket.is_ipv6();
+    let mut pmtu_record = PmtuRecord {
+        destination: dst_socket.ip().to_string(),
+        path_mtu: None,
+        interface_mtu: 0,
+        fragmentation_at: None,
+        reported_mtu: None,
+    };
+
+    let (socket, raw) = match pmtu_socket(src, dst_socket).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Error creating ICMP socket to {}: {e}", pmtu_record.destination);
+            return pmtu_record;
+        }
+    };
+    let error_queue = ErrorQueue::new(&socket, ipv6);
+    pmtu_record.interface_mtu = match interface_mtu(&socket, ipv6) {
+        Ok(mtu) => mtu,
+        Err(e) => {
+            eprintln!("Error reading the interface MTU to {}: {e}", pmtu_record.destination);
+            return pmtu_record;
+        }
+    };
+
+    let header_len = match ipv6 {
+        true => IPV6_HEADER_LEN,
+        false => IPV4_HEADER_LEN,
+    };
+    let mut search = MtuSearch::new(header_len, pmtu_record.interface_mtu);
+    let mut sequence: u16 = 0;
+    while let Some(size) = search.next_size() {
+        let payload: Vec<u8> = PING_MSG.bytes().cycle().take(usize::from(size - header_len)).collect();
+        let mut probe = Probe::Lost;
+        for _ in 0..ICMP_PMTU_ATTEMPTS {
+            sequence = sequence.wrapping_add(1);
+            probe = probe_size(
+                &socket,
+                raw,
+                ipv6,
+                error_queue.as_ref(),
+                &payload,
+                sequence,
+                ping_options,
+            )
+            .await;
+            if probe != Probe::Lost {
+                break;
+            }
+        }
+
+        let msg = format!("proto=ICMP dst={} size={size}", pmtu_record.destination);
+        let probe_msg = match probe {
+            Probe::Passed(time) => {
+                search.passed(size);
+                format!("{} => {msg} time={time:.3}ms", ConnectResult::Pong)
+            }
+            Probe::TooBig(offender, reported_mtu) => {
+                search.failed(size, reported_mtu);
+                pmtu_record.fragmentation_at = offender;
+                pmtu_record.reported_mtu = reported_mtu;
+                let from = offender.map(|ip| ip.to_string()).unwrap_or("-".to_owned());
+                let mtu = reported_mtu.map(|mtu| mtu.to_string()).unwrap_or("-".to_owned());
+                format!("{} => {msg} from={from} mtu={mtu}", ConnectResult::FragmentationNeeded)
+            }
+            Probe::Lost => {
+                search.failed(size, None);
+                format!("{} => {msg}", ConnectResult::Timeout)
+            }
+            Probe::Failed(e) => {
+                search.failed(size, None);
+                format!("{} => {msg} error={e}", ConnectResult::Unknown)
+            }
+        };
+        result_handler(&probe_msg, logging_options);
+    }
+
+    pmtu_record.path_mtu = search.path_mtu();
+    pmtu_record
+}
+
+/// Create an ICMP socket connected to the destination
+/// that sends packets with the DF bit set.
+async fn pmtu_socket(src: &IpPort, dst_socket: SocketAddr) -> std::io::Result<(UdpSocket, bool)> {
+    let icmp_socket = get_icmp_socket(src.bind_addr(&dst_socket));
+    src.record_bind(&dst_socket, icmp_socket.is_ok());
+    let (socket, raw) = icmp_socket?;
+    socket.connect(dst_socket).await?;
+    set_mtu_probe(&socket, dst_socket.is_ipv6())?;
+    Ok((socket, raw))
+}
+
+async fn probe_size(
+    socket: &UdpSocket,
+    raw: bool,
+    ipv6: bool,
+    error_queue: Option<&ErrorQueue>,
+    payload: &[u8],
+    sequence: u16,
+    ping_options: &PingOptions,
+) -> Probe {
+    // Unprivileged ICMP sockets replace the identifier with
+    // the local port, so only raw sockets match on it.
+    let identifier = std::process::id() as u16;
+    let request = echo_request(ipv6, identifier, sequence, payload);
+
+    // Discard errors for earlier probes that arrived after they timed out.
+    if let Some(error_queue) = error_queue {
+        while error_queue.take_icmp_error().is_some() {}
+    }
+
+    let pre_conn_timestamp = time_now_us();
+    if let Err(e) = socket.send(&request).await {
+        return Probe::Failed(e.to_string());
+    }
+
+    // Routers that cannot forward the packet return an ICMP error,
+    // which is read from the socket error queue. A pending error
+    // is returned by the next receive on the socket.
+    let deadline = Instant::now() + Duration::from_millis(ping_options.timeout.into());
+    let mut buffer = vec![0u8; usize::from(u16::MAX)];
+    loop {
+        match timeout_at(deadline, socket.ready(Interest::READABLE | Interest::ERROR)).await {
+            Ok(Ok(ready)) if ready.is_error() => {
+                // The error readiness is cleared once no error is pending.
+                let probe = socket.try_io(Interest::ERROR, || {
+                    if let Some(icmp_error) = error_queue.and_then(|error_queue| error_queue.take_icmp_error()) {
+                        return Ok(icmp_probe(icmp_error));
+                    }
+                    match socket.take_error()? {
+                        Some(e) => Ok(Probe::Failed(e.to_string())),
+                        None => Err(std::io::ErrorKind::WouldBlock.into()),
+                    }
+                });
+                if let Ok(probe) = probe {
+                    return probe;
+                }
+            }
+            Ok(Ok(_)) => match socket.try_recv(&mut buffer) {
+                Ok(len) => match echo_reply(ipv6, &buffer[..len]) {
+                    Some((id, seq)) if seq == sequence && (!raw || id == identifier) => {
+                        return Probe::Passed(calc_connect_ms(pre_conn_timestamp, time_now_us()));
+                    }
+                    _ => continue,
+                },
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+                Err(e) => {
+                    return match error_queue.and_then(|error_queue| error_queue.take_icmp_error()) {
+                        Some(icmp_error) => icmp_probe(icmp_error),
+                        None => Probe::Failed(e.to_string()),
+                    };
+                }
+            },
+            Ok(Err(e)) => return Probe::Failed(e.to_string()),
+            Err(_) => return Probe::Lost,
+        }
+    }
+}
+
+/// Map the ICMP error that failed a probe to its outcome.
+fn icmp_probe(icmp_error: IcmpError) -> Probe {
+    match icmp_error.connect_result() {
+        ConnectResult::FragmentationNeeded => {
+            let reported_mtu = u16::try_from(icmp_error.mtu).ok().filter(|mtu| *mtu > 0);
+            Probe::TooBig(icmp_error.offender, reported_mtu)
+        }
+        _ => Probe::Failed(icmp_error.to_string()),
+    }
+}
+
+/// Set the DF bit on sent packets, ignoring the path MTU cached
+/// by the kernel so that larger packets are still sent.
+#[cfg(target_os = "linux")]
+fn set_mtu_probe(socket: &UdpSocket, ipv6: bool) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let (level, name, value) = match ipv6 {
+        true => (libc::IPPROTO_IPV6, libc::IPV6_MTU_DISCOVER, libc::IPV6_PMTUDISC_PROBE),
+        false => (libc::IPPROTO_IP, libc::IP_MTU_DISCOVER, libc::IP_PMTUDISC_PROBE),
+    };
+    // SAFETY: `value` is a valid c_int that outlives the call.
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    match result {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+/// Read the MTU of the interface used to reach a connected socket's peer.
+/// The route MTU is used when the interface is not found, although an
+/// earlier discovery may have lowered it.
+#[cfg(target_os = "linux")]
+fn interface_mtu(socket: &UdpSocket, ipv6: bool) -> std::io::Result<u16> {
+    use std::os::fd::AsRawFd;
+
+    let local_ip = socket.local_addr()?.ip();
+    let interface_mtu = list_afinet_netifas()
+        .ok()
+        .and_then(|interfaces| interfaces.into_iter().find(|(_, ip)| *ip == local_ip))
+        .and_then(|(name, _)| std::fs::read_to_string(format!("/sys/class/net/{name}/mtu")).ok())
+        .and_then(|mtu| mtu.trim().parse::<u32>().ok());
+    // Loopback interfaces have an MTU larger than an IP packet.
+    if let Some(mtu) = interface_mtu {
+        return Ok(u16::try_from(mtu).unwrap_or(u16::MAX));
+    }
+
+    let (level, name) = match ipv6 {
+        true => (libc::IPPROTO_IPV6, libc::IPV6_MTU),
+        false => (libc::IPPROTO_IP, libc::IP_MTU),
+    };
+    let mut mtu: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: `mtu` and `len` are valid for writes and outlive the call.
+    let result = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &mut mtu as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    match result {
+        0 => Ok(u16::try_from(mtu).unwrap_or(u16::MAX)),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_mtu_probe(_socket: &UdpSocket, _ipv6: bool) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn interface_mtu(_socket: &UdpSocket, _ipv6: bool) -> std::io::Result<u16> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mtu_search_finds_largest_passing_size() {
+        let mut search = MtuSearch::new(IPV4_HEADER_LEN, 1500);
+        let mut probes = vec![];
+        while let Some(size) = search.next_size() {
+            probes.push(size);
+            match size <= 1400 {
+                true => search.passed(size),
+                false => search.failed(size, None),
+            }
+        }
+        assert_eq!(probes[0], 1500);
+        assert_eq!(search.path_mtu(), Some(1400));
+    }
+
+    #[test]
+    fn mtu_search_tries_reported_mtu() {
+        let mut search = MtuSearch::new(IPV4_HEADER_LEN, 1500);
+        assert_eq!(search.next_size(), Some(1500));
+        search.failed(1500, Some(1420));
+        assert_eq!(search.next_size(), Some(1420));
+        search.passed(1420);
+        assert_eq!(search.next_size(), None);
+        assert_eq!(search.path_mtu(), Some(1420));
+    }
+
+    #[test]
+    fn mtu_search_without_passing_size_has_no_mtu() {
+        let mut search = MtuSearch::new(IPV4_HEADER_LEN, 1500);
+        while let Some(size) = search.next_size() {
+            search.failed(size, None);
+        }
+        assert_eq!(search.path_mtu(), None);
+    }
+}
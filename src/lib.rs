@@ -0,0 +1,20 @@
+//! NetKraken probes the network connectivity of hosts over TCP, UDP,
+//! ICMP, HTTP and other protocols. The `nk` binary is a thin wrapper
+//! around the clients of this crate, which other programs can embed to
+//! run connectivity checks and consume the results of each probe.
+
+pub mod cmd;
+pub mod core;
+pub mod dns;
+pub mod heartbeat;
+pub mod http;
+pub mod icmp;
+pub mod mail;
+#[cfg(feature = "quic")]
+pub mod quic;
+pub mod tcp;
+pub mod telnet;
+pub mod udp;
+#[cfg(unix)]
+pub mod unix;
+pub mod util;
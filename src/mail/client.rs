@@ -0,0 +1,354 @@
+use std::net::{IpAddr, SocketAddr};
+
+use anyhow::{bail, Result};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration};
+
+use crate::core::common::{
+    ClientResult, ConnectMethod, ConnectRecord, ConnectResult, DnsOptions, IpOptions, IpPort, LoggingOptions,
+    MailOptions, MailRecord, PingOptions,
+};
+use crate::tcp::client::get_tcp_socket;
+use crate::util::handler::{bind_error_switch_handler, io_error_switch_handler};
+use crate::util::parser::{imap_capabilities, smtp_reply_line};
+use crate::util::probe::{ProbeClient, ProbeRunner};
+use crate::util::time::{calc_connect_ms, time_now_us};
+
+/// Mail protocol (SMTP/IMAP) client. Connects to a mail server,
+/// reads the greeting banner, discovers the server capabilities
+/// and optionally issues a STARTTLS command.
+#[derive(Debug)]
+pub struct MailClient {
+    pub runner: ProbeRunner,
+    pub protocol: ConnectMethod,
+    pub mail_options: MailOptions,
+}
+
+impl MailClient {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        dst_ip: String,
+        dst_port: u16,
+        src_ipv4: Option<String>,
+        src_ipv6: Option<String>,
+        src_port: Option<u16>,
+        protocol: ConnectMethod,
+        logging_options: LoggingOptions,
+        ping_options: PingOptions,
+        ip_options: IpOptions,
+        dns_options: DnsOptions,
+        mail_options: MailOptions,
+    ) -> MailClient {
+        MailClient {
+            runner: ProbeRunner::new(
+                vec![(dst_ip, dst_port)],
+                src_ipv4,
+                src_ipv6,
+                src_port,
+                logging_options,
+                ping_options,
+                ip_options,
+                dns_options,
+            ),
+            protocol,
+            mail_options,
+        }
+    }
+
+    pub async fn connect(&self) -> Result<Vec<ClientResult>> {
+        self.runner.run(self).await
+    }
+}
+
+impl ProbeClient for MailClient {
+    fn protocol(&self) -> ConnectMethod {
+        self.protocol
+    }
+
+    async fn probe_once(
+        &self,
+        src: IpPort,
+        _host: &str,
+        dst_socket: SocketAddr,
+        ping_options: PingOptions,
+    ) -> ConnectRecord {
+        connect_host(src, dst_socket, self.protocol, ping_options, self.mail_options).await
+    }
+}
+
+async fn connect_host(
+    src: IpPort,
+    dst_socket: SocketAddr,
+    protocol: ConnectMethod,
+    ping_options: PingOptions,
+    mail_options: MailOptions,
+) -> ConnectRecord {
+    // Bind the source socket to the same IP Version as the destination socket.
+    let bind_addr = src.bind_addr(&dst_socket);
+    let src_socket = get_tcp_socket(bind_addr);
+    src.record_bind(&dst_socket, src_socket.is_ok());
+
+    // If the source socket is an error, we could not bind to the socket.
+    let src_socket = match src_socket {
+        Ok(socket) => socket,
+        Err(e) => {
+            return ConnectRecord {
+                result: bind_error_switch_handler(e.as_ref()),
+                protocol,
+                source: bind_addr.to_string(),
+                destination: dst_socket.to_string(),
+                time: -1.0,
+                success: false,
+                error_msg: Some("Error binding to socket".to_owned()),
+                ..Default::default()
+            };
+        }
+    };
+
+    let local_addr = src_socket
+        .local_addr()
+        // This should never fail because we always
+        // pass a bound socket.
+        .unwrap_or_else(|_| panic!("ERROR GETTING TCP SOCKET LOCAL ADDRESS"))
+        .to_string();
+
+    let mut conn_record = ConnectRecord {
+        result: ConnectResult::Unknown,
+        protocol,
+        source: local_addr,
+        destination: dst_socket.to_string(),
+        time: -1.0,
+        success: false,
+        error_msg: None,
+        ..Default::default()
+    };
+
+    // record timestamp before connection
+    let pre_conn_timestamp = time_now_us();
+
+    let tick = Duration::from_millis(ping_options.timeout.into());
+    let stream = match timeout(tick, src_socket.connect(dst_socket)).await {
+        Ok(Ok(stream)) => stream,
+        // Connection error
+        Ok(Err(e)) => {
+            conn_record.error_msg = Some(e.to_string());
+            conn_record.result = io_error_switch_handler(e);
+            return conn_record;
+        }
+        // Timeout error
+        Err(e) => {
+            conn_record.error_msg = Some(e.to_string());
+            conn_record.result = io_error_switch_handler(e.into());
+            return conn_record;
+        }
+    };
+
+    conn_record.source = stream
+        .local_addr()
+        // This should never fail. If we have a TCP stream,
+        // we should have always have a local address.
+        .unwrap_or_else(|_| panic!("ERROR GETTING TCP STREAM LOCAL ADDRESS"))
+        .to_string();
+
+    let mut mail_record = MailRecord {
+        connect_time: calc_connect_ms(pre_conn_timestamp, time_now_us()),
+        banner_time: -1.0,
+        starttls_time: -1.0,
+        ..Default::default()
+    };
+
+    // The timeout covers the whole conversation with the mail server.
+    let session = match protocol {
+        ConnectMethod::IMAP => timeout(
+            tick,
+            imap_session(stream, mail_options, pre_conn_timestamp, &mut mail_record),
+        )
+        .await
+        .map_err(anyhow::Error::from),
+        _ => timeout(
+            tick,
+            smtp_session(stream, mail_options, pre_conn_timestamp, &mut mail_record),
+        )
+        .await
+        .map_err(anyhow::Error::from),
+    };
+
+    match session {
+        Ok(Ok(())) => {
+            conn_record.success = true;
+            conn_record.result = ConnectResult::Pong;
+            conn_record.time = mail_record.banner_time;
+        }
+        // IO errors are mapped the same as a TCP connection,
+        // anything else is an unexpected reply from the server.
+        Ok(Err(e)) => {
+            conn_record.error_msg = Some(e.to_string());
+            conn_record.result = match e.downcast::<std::io::Error>() {
+                Ok(e) => io_error_switch_handler(e),
+                Err(_) => ConnectResult::ProtocolError,
+            };
+        }
+        // Timeout error
+        Err(e) => {
+            conn_record.error_msg = Some(e.to_string());
+            conn_record.result = ConnectResult::Timeout;
+        }
+    }
+    conn_record.mail = Some(mail_record);
+
+    conn_record
+}
+
+/// Read the greeting, send EHLO and optionally STARTTLS to an SMTP server.
+async fn smtp_session(
+    stream: TcpStream,
+    mail_options: MailOptions,
+    pre_conn_timestamp: u128,
+    mail_record: &mut MailRecord,
+) -> Result<()> {
+    let ehlo = match stream.local_addr()?.ip() {
+        IpAddr::V4(ip) => format!("EHLO [{ip}]\r\n"),
+        IpAddr::V6(ip) => format!("EHLO [IPv6:{ip}]\r\n"),
+    };
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    let (code, lines) = read_smtp_reply(&mut reader).await?;
+    mail_record.banner_time = calc_connect_ms(pre_conn_timestamp, time_now_us());
+    mail_record.banner = lines.first().cloned().unwrap_or_default();
+    if code != 220 {
+        bail!("unexpected SMTP greeting: {code} {}", mail_record.banner);
+    }
+
+    writer.write_all(ehlo.as_bytes()).await?;
+    let (code, lines) = read_smtp_reply(&mut reader).await?;
+    if code != 250 {
+        bail!("unexpected SMTP EHLO reply: {code}");
+    }
+    // The first line of the EHLO reply is the server greeting,
+    // the remaining lines are the supported extensions.
+    mail_record.capabilities = lines
+        .iter()
+        .skip(1)
+        .filter_map(|l| l.split_whitespace().next())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    mail_record.starttls_offered = mail_record.capabilities.iter().any(|c| c == "STARTTLS");
+
+    if !mail_options.starttls {
+        writer.write_all(b"QUIT\r\n").await?;
+        return Ok(());
+    }
+    if !mail_record.starttls_offered {
+        bail!("STARTTLS is not offered by the server");
+    }
+
+    let pre_starttls_timestamp = time_now_us();
+    writer.write_all(b"STARTTLS\r\n").await?;
+    let (code, lines) = read_smtp_reply(&mut reader).await?;
+    mail_record.starttls_time = calc_connect_ms(pre_starttls_timestamp, time_now_us());
+    if code != 220 {
+        bail!("STARTTLS rejected: {code} {}", lines.join(" "));
+    }
+    mail_record.starttls_accepted = true;
+
+    Ok(())
+}
+
+/// Read a (possibly multi-line) SMTP reply.
+/// Returns the reply code and the text of each line.
+async fn read_smtp_reply(reader: &mut BufReader<OwnedReadHalf>) -> Result<(u16, Vec<String>)> {
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            bail!("connection closed by the server");
+        }
+        match smtp_reply_line(&line) {
+            Some((code, last, text)) => {
+                lines.push(text.to_owned());
+                if last {
+                    return Ok((code, lines));
+                }
+            }
+            None => bail!("invalid SMTP reply: {}", line.trim_end()),
+        }
+    }
+}
+
+/// Read the greeting, request the capabilities and optionally
+/// send STARTTLS to an IMAP server.
+async fn imap_session(
+    stream: TcpStream,
+    mail_options: MailOptions,
+    pre_conn_timestamp: u128,
+    mail_record: &mut MailRecord,
+) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    let mut greeting = String::new();
+    if reader.read_line(&mut greeting).await? == 0 {
+        bail!("connection closed by the server");
+    }
+    mail_record.banner_time = calc_connect_ms(pre_conn_timestamp, time_now_us());
+    mail_record.banner = greeting.trim_end().to_owned();
+
+    let status = mail_record.banner.to_ascii_uppercase();
+    if !status.starts_with("* OK") && !status.starts_with("* PREAUTH") {
+        bail!("unexpected IMAP greeting: {}", mail_record.banner);
+    }
+
+    // Servers commonly advertise their capabilities in the greeting,
+    // otherwise they need to be requested.
+    mail_record.capabilities = imap_capabilities(&greeting);
+    if mail_record.capabilities.is_empty() {
+        writer.write_all(b"nk1 CAPABILITY\r\n").await?;
+        let (ok, lines) = read_imap_reply(&mut reader, "nk1").await?;
+        if !ok {
+            bail!("IMAP CAPABILITY command failed");
+        }
+        mail_record.capabilities = lines.iter().flat_map(|l| imap_capabilities(l)).collect();
+    }
+    mail_record.starttls_offered = mail_record.capabilities.iter().any(|c| c == "STARTTLS");
+
+    if !mail_options.starttls {
+        writer.write_all(b"nk2 LOGOUT\r\n").await?;
+        return Ok(());
+    }
+    if !mail_record.starttls_offered {
+        bail!("STARTTLS is not offered by the server");
+    }
+
+    let pre_starttls_timestamp = time_now_us();
+    writer.write_all(b"nk3 STARTTLS\r\n").await?;
+    let (ok, lines) = read_imap_reply(&mut reader, "nk3").await?;
+    mail_record.starttls_time = calc_connect_ms(pre_starttls_timestamp, time_now_us());
+    if !ok {
+        bail!("STARTTLS rejected: {}", lines.last().cloned().unwrap_or_default());
+    }
+    mail_record.starttls_accepted = true;
+
+    Ok(())
+}
+
+/// Read IMAP response lines until the tagged completion response.
+/// Returns if the tagged response was `OK` and all the lines read.
+async fn read_imap_reply(reader: &mut BufReader<OwnedReadHalf>, tag: &str) -> Result<(bool, Vec<String>)> {
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            bail!("connection closed by the server");
+        }
+        let line = line.trim_end().to_owned();
+        if let Some(status) = line.strip_prefix(tag) {
+            let ok = status.trim_start().to_ascii_uppercase().starts_with("OK");
+            lines.push(line);
+            return Ok((ok, lines));
+        }
+        lines.push(line);
+    }
+}
@@ -1,22 +1,23 @@
-mod cmd;
-mod core;
-mod tcp;
-mod udp;
-mod util;
-
 use std::process::ExitCode;
 
 use tracing::{event, Level};
-use tracing_appender::rolling;
 
-use crate::cmd::cli::Cli;
-use crate::core::konst::APP_NAME;
+use netkraken::cmd::cli::Cli;
+use netkraken::core::konst::APP_NAME;
+use netkraken::util::rotate::RotatingFile;
 
 #[tokio::main]
 async fn main() -> ExitCode {
     let cli = Cli::init();
 
-    let file_appender = rolling::never(&cli.dir, &cli.file);
+    let file_appender = match RotatingFile::open(&cli.dir, &cli.file, cli.log_rotate_size, cli.log_rotate, cli.log_keep)
+    {
+        Ok(file_appender) => file_appender,
+        Err(e) => {
+            eprintln!("Unable to open log file `{}`: {e}", cli.file);
+            return ExitCode::from(1);
+        }
+    };
     let (logfile, _guard) = tracing_appender::non_blocking(file_appender);
 
     let tracer = tracing_subscriber::fmt()
@@ -0,0 +1,175 @@
+use std::net::SocketAddr;
+
+use anyhow::Result;
+use quinn::{ClientConfig, ConnectionError, Endpoint};
+use tokio::time::{timeout, Duration};
+
+use crate::core::common::{
+    ClientResult, ConnectMethod, ConnectRecord, ConnectResult, DnsOptions, IpOptions, IpPort, LoggingOptions,
+    PingOptions, QuicOptions,
+};
+use crate::util::handler::bind_error_switch_handler;
+use crate::util::probe::{ProbeClient, ProbeRunner};
+use crate::util::time::{calc_connect_ms, time_now_us};
+use crate::util::tls::{quic_client_config, tls_alert_result};
+
+/// QUIC client. Times the QUIC handshake, including the TLS 1.3
+/// handshake it carries, to each address of a host.
+#[derive(Debug)]
+pub struct QuicClient {
+    pub runner: ProbeRunner,
+    pub quic_options: QuicOptions,
+}
+
+impl QuicClient {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        dst_ip: String,
+        dst_port: u16,
+        src_ipv4: Option<String>,
+        src_ipv6: Option<String>,
+        src_port: Option<u16>,
+        logging_options: LoggingOptions,
+        ping_options: PingOptions,
+        ip_options: IpOptions,
+        dns_options: DnsOptions,
+        quic_options: QuicOptions,
+    ) -> QuicClient {
+        QuicClient {
+            runner: ProbeRunner::new(
+                vec![(dst_ip, dst_port)],
+                src_ipv4,
+                src_ipv6,
+                src_port,
+                logging_options,
+                ping_options,
+                ip_options,
+                dns_options,
+            ),
+            quic_options,
+        }
+    }
+
+    pub async fn connect(&self) -> Result<Vec<ClientResult>> {
+        let client_config = quic_client_config(&self.quic_options.alpn)?;
+        self.runner.run(&QuicProbe { client_config }).await
+    }
+}
+
+/// Probes with the client configuration of a run.
+struct QuicProbe {
+    client_config: ClientConfig,
+}
+
+impl ProbeClient for QuicProbe {
+    fn protocol(&self) -> ConnectMethod {
+        ConnectMethod::QUIC
+    }
+
+    async fn probe_once(
+        &self,
+        src: IpPort,
+        host: &str,
+        dst_socket: SocketAddr,
+        ping_options: PingOptions,
+    ) -> ConnectRecord {
+        connect_host(src, dst_socket, host, ping_options, self.client_config.clone()).await
+    }
+}
+
+async fn connect_host(
+    src: IpPort,
+    dst_socket: SocketAddr,
+    host: &str,
+    ping_options: PingOptions,
+    client_config: ClientConfig,
+) -> ConnectRecord {
+    // Bind the source socket to the same IP Version as the destination socket.
+    let bind_addr = src.bind_addr(&dst_socket);
+    let endpoint = Endpoint::client(bind_addr);
+    src.record_bind(&dst_socket, endpoint.is_ok());
+    let endpoint = match endpoint {
+        Ok(endpoint) => endpoint,
+        Err(e) => {
+            return ConnectRecord {
+                result: bind_error_switch_handler(&e),
+                protocol: ConnectMethod::QUIC,
+                source: bind_addr.to_string(),
+                destination: dst_socket.to_string(),
+                time: -1.0,
+                success: false,
+                error_msg: Some("Error binding to socket".to_owned()),
+                ..Default::default()
+            };
+        }
+    };
+
+    let local_addr = endpoint
+        .local_addr()
+        // This should never fail because the endpoint
+        // owns a bound socket.
+        .unwrap_or_else(|_| panic!("ERROR GETTING QUIC ENDPOINT LOCAL ADDRESS"))
+        .to_string();
+
+    let mut conn_record = ConnectRecord {
+        result: ConnectResult::Unknown,
+        protocol: ConnectMethod::QUIC,
+        source: local_addr,
+        destination: dst_socket.to_string(),
+        time: -1.0,
+        success: false,
+        error_msg: None,
+        ..Default::default()
+    };
+
+    // record timestamp before connection
+    let pre_conn_timestamp = time_now_us();
+
+    // The server name is used for SNI and to verify the certificate.
+    let connecting = match endpoint.connect_with(client_config, dst_socket, host) {
+        Ok(connecting) => connecting,
+        Err(e) => {
+            conn_record.error_msg = Some(e.to_string());
+            conn_record.result = ConnectResult::TlsError;
+            return conn_record;
+        }
+    };
+
+    let tick = Duration::from_millis(ping_options.timeout.into());
+    match timeout(tick, connecting).await {
+        Ok(Ok(connection)) => {
+            conn_record.time = calc_connect_ms(pre_conn_timestamp, time_now_us());
+            conn_record.success = true;
+            conn_record.result = ConnectResult::Pong;
+            connection.close(0u32.into(), b"");
+        }
+        // Handshake error
+        Ok(Err(e)) => {
+            conn_record.error_msg = Some(e.to_string());
+            conn_record.result = quic_error_result(&e);
+        }
+        // Timeout error
+        Err(e) => {
+            conn_record.error_msg = Some(e.to_string());
+            conn_record.result = ConnectResult::Timeout;
+        }
+    }
+    endpoint.close(0u32.into(), b"");
+
+    conn_record
+}
+
+/// Map a failed QUIC handshake to a result. TLS alerts are carried
+/// in the crypto error range of the transport error codes (RFC9001).
+fn quic_error_result(e: &ConnectionError) -> ConnectResult {
+    let code = match e {
+        ConnectionError::TransportError(e) => e.code,
+        ConnectionError::ConnectionClosed(close) => close.error_code,
+        ConnectionError::TimedOut => return ConnectResult::Timeout,
+        _ => return ConnectResult::ProtocolError,
+    };
+    match u64::from(code) {
+        code @ 0x100..=0x1ff => tls_alert_result((code & 0xff) as u8),
+        _ => ConnectResult::ProtocolError,
+    }
+}
@@ -1,254 +1,239 @@
-use std::net::{IpAddr, SocketAddr};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-
-use anyhow::{bail, Result};
-use futures::StreamExt;
-use tokio::net::TcpSocket;
-use tokio::signal;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+
+use anyhow::Result;
+use time::OffsetDateTime;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpSocket, TcpStream};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
 use tokio::time::{timeout, Duration};
+use tokio_rustls::rustls::pki_types::ServerName;
+use uuid::Uuid;
 
 use crate::core::common::{
-    ClientResult, ClientSummary, ConnectMethod, ConnectRecord, ConnectResult, HostRecord, HostResults, IpOptions,
-    IpPort, IpProtocol, LoggingOptions, PingOptions,
+    ClientResult, ConnectMethod, ConnectRecord, ConnectResult, DnsOptions, Escalation, IpOptions, IpPort,
+    LoggingOptions, NetKrakenMessage, OutputFormat, PayloadRecord, PeerRecord, PingOptions, TlsRecord,
 };
-use crate::core::konst::{BIND_ADDR_IPV4, BIND_ADDR_IPV6, BIND_PORT, BUFFER_SIZE};
-use crate::util::dns::resolve_host;
-use crate::util::handler::{io_error_switch_handler, log_handler2, loop_handler};
-use crate::util::message::{client_result_msg, client_summary_table_msg, ping_header_msg, resolved_ips_msg};
-use crate::util::parser::parse_ipaddr;
-use crate::util::result::{client_summary_result, get_results_map};
+use crate::core::konst::{MAX_PACKET_SIZE, PING_MSG};
+use crate::tcp::escalate::{escalate, EscalationTracker};
+use crate::tcp::evidence::EvidenceCollector;
+use crate::util::frame::{frame, read_frame};
+use crate::util::handler::{bind_error_switch_handler, info_handler, io_error_switch_handler, summary_handler};
+use crate::util::message::{escalation_json_msg, escalation_table_msg};
+use crate::util::parser::nk_msg_reader;
+use crate::util::probe::{ProbeClient, ProbeRunner};
+use crate::util::recverr::ErrorQueue;
+use crate::util::stream::ProbeStream;
 use crate::util::time::{calc_connect_ms, time_now_us};
+use crate::util::tls::{cert_expiry_days, tls_connector, tls_error_result};
 
 #[derive(Debug)]
 pub struct TcpClient {
-    pub dst_ip: String,
-    pub dst_port: u16,
-    pub src_ipv4: Option<IpAddr>,
-    pub src_ipv6: Option<IpAddr>,
-    pub src_port: u16,
-    pub logging_options: LoggingOptions,
-    pub ping_options: PingOptions,
-    pub ip_options: IpOptions,
+    pub runner: ProbeRunner,
 }
 
 impl TcpClient {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
-        dst_ip: String,
-        dst_port: u16,
+        destinations: Vec<(String, u16)>,
         src_ipv4: Option<String>,
         src_ipv6: Option<String>,
         src_port: Option<u16>,
         logging_options: LoggingOptions,
         ping_options: PingOptions,
         ip_options: IpOptions,
+        dns_options: DnsOptions,
     ) -> TcpClient {
-        let src_ipv4 = match src_ipv4 {
-            Some(x) => parse_ipaddr(&x).ok(),
-            None => parse_ipaddr(BIND_ADDR_IPV4).ok(),
-        };
-
-        let src_ipv6 = match src_ipv6 {
-            Some(x) => parse_ipaddr(&x).ok(),
-            None => parse_ipaddr(BIND_ADDR_IPV6).ok(),
-        };
-
-        let src_port = src_port.unwrap_or(BIND_PORT);
-
         TcpClient {
-            dst_ip,
-            dst_port,
-            src_ipv4,
-            src_ipv6,
-            src_port,
-            logging_options,
-            ping_options,
-            ip_options,
+            runner: ProbeRunner::new(
+                destinations,
+                src_ipv4,
+                src_ipv6,
+                src_port,
+                logging_options,
+                ping_options,
+                ip_options,
+                dns_options,
+            ),
         }
     }
 
-    pub async fn connect(&self) -> Result<()> {
-        let src_ip_port = IpPort {
-            // These should never be None at this point as they are set in the TcpClient::new() constructor.
-            ipv4: self.src_ipv4.unwrap(),
-            ipv6: self.src_ipv6.unwrap(),
-            port: self.src_port,
-        };
+    /// Run the client in a task of its own, yielding the record
+    /// of each probe as it completes.
+    pub fn connect_stream(self) -> ProbeStream {
+        let records = self.runner.run_handle.subscribe();
+        let run = tokio::spawn(async move {
+            let results = self.connect().await;
+            self.runner.run_handle.end();
+            results
+        });
+        ProbeStream::new(records, run)
+    }
 
-        // Resolve the destination host to IPv4 and IPv6 addresses.
-        let host_records = HostRecord::new(&self.dst_ip, self.dst_port).await;
-        let hosts = vec![host_records.clone()];
-        let resolved_hosts = resolve_host(hosts).await;
-
-        // Check if the host resolved to an IPv4 or IPv6 addresses.
-        // If not, return an error.
-        for record in &resolved_hosts {
-            match record.ipv4_sockets.is_empty() && record.ipv6_sockets.is_empty() {
-                true => bail!("{} did not resolve to an IP address", record.host),
-                false => {
-                    let resolved_host_msg = resolved_ips_msg(record);
-                    println!("{resolved_host_msg}");
-                }
-            }
-        }
+    /// Run the client, calling `callback` with the record of each probe
+    /// as it completes, and returning the summary of each destination.
+    pub async fn run_with<F, Fut>(self, callback: F) -> Result<Vec<ClientResult>>
+    where
+        F: FnMut(ConnectRecord) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        self.connect_stream().run_with(callback).await
+    }
 
-        // Filter the resolved hosts based on the IP protocol.
-        let mut filtered_hosts = Vec::new();
-        for record in &resolved_hosts {
-            let mut record = record.clone();
-            match &self.ip_options.ip_protocol {
-                IpProtocol::All => {
-                    filtered_hosts.push(record);
-                }
-                IpProtocol::V4 => {
-                    record.ipv6_sockets.clear();
-                    filtered_hosts.push(record);
-                }
-                IpProtocol::V6 => {
-                    record.ipv4_sockets.clear();
-                    filtered_hosts.push(record);
-                }
-            }
-        }
+    pub async fn connect(&self) -> Result<Vec<ClientResult>> {
+        // Evidence is collected in the background when a destination starts failing.
+        let evidence_collector = match &self.runner.logging_options.evidence_dir {
+            Some(evidence_dir) => Some(EvidenceCollector::create(evidence_dir)?),
+            None => None,
+        };
+        let probe = TcpProbe {
+            runner: &self.runner,
+            warm_connections: Mutex::new(HashMap::new()),
+            escalations: std::sync::Mutex::new(Escalations {
+                tracker: EscalationTracker::default(),
+                evidence_collector,
+                pending: Vec::new(),
+            }),
+        };
+        self.runner.run(&probe).await
+    }
+}
 
-        let mut results_map = get_results_map(&filtered_hosts);
+/// Probes with the connections and escalations of a run.
+struct TcpProbe<'a> {
+    runner: &'a ProbeRunner,
+    // Established connections used by warm requests.
+    warm_connections: Mutex<HashMap<SocketAddr, TcpStream>>,
+    escalations: std::sync::Mutex<Escalations>,
+}
 
-        let mut count: u16 = 0;
-        let mut send_count: u16 = 0;
+/// The outages of a run, with the escalation collecting evidence
+/// of each outage and the bundle the evidence is written to.
+struct Escalations {
+    tracker: EscalationTracker,
+    evidence_collector: Option<EvidenceCollector>,
+    pending: Vec<(Option<usize>, JoinHandle<Escalation>)>,
+}
 
-        let ping_header = ping_header_msg(&self.dst_ip, self.dst_port, ConnectMethod::TCP);
-        println!("{ping_header}");
+impl ProbeClient for TcpProbe<'_> {
+    fn protocol(&self) -> ConnectMethod {
+        ConnectMethod::TCP
+    }
 
-        // This is a signal handler that listens for a Ctrl-C signal.
-        // When the signal is received, it sets the cancel flag to true.
-        // If the cancel flag is True we break the loop and exit the program.
-        let cancel = Arc::new(AtomicBool::new(false));
-        let c = cancel.clone();
-        tokio::spawn(async move {
-            // TODO: this will eventually move to a channel signalling mechanism.
-            signal::ctrl_c().await.unwrap();
-            c.store(true, Ordering::SeqCst);
-        });
+    async fn probe_once(
+        &self,
+        src: IpPort,
+        host: &str,
+        dst_socket: SocketAddr,
+        ping_options: PingOptions,
+    ) -> ConnectRecord {
+        connect_host(src, dst_socket, host, ping_options).await
+    }
 
-        loop {
-            if cancel.load(Ordering::SeqCst) {
-                break;
-            }
-            match loop_handler(count, self.ping_options.repeat, self.ping_options.interval).await {
-                true => break,
-                false => count += 1,
-            }
+    async fn probe(
+        &self,
+        src: IpPort,
+        host: &str,
+        dst_socket: SocketAddr,
+        ping_options: PingOptions,
+    ) -> Vec<ConnectRecord> {
+        let mut results = vec![self.probe_once(src.clone(), host, dst_socket, ping_options).await];
+        if ping_options.warm {
+            results.push(warm_host(src, dst_socket, ping_options, &self.warm_connections).await);
+        }
+        results
+    }
 
-            let host_results: Vec<HostResults> = futures::stream::iter(resolved_hosts.clone())
-                .map(|host_record| {
-                    let src_ip_port = src_ip_port.clone();
-                    async move {
-                        //
-                        process_host(src_ip_port, host_record, self.ping_options, self.ip_options).await
-                    }
-                })
-                .buffer_unordered(BUFFER_SIZE)
-                .collect()
-                .await;
-
-            for host in host_results {
-                for result in host.results {
-                    results_map
-                        // This should never fail
-                        .get_mut(&host.host)
-                        .unwrap()
-                        // This should never fail
-                        .get_mut(&result.destination)
-                        .unwrap()
-                        .push(result.time);
-
-                    let success_msg = client_result_msg(&result);
-                    log_handler2(&result, &success_msg, &self.logging_options).await;
-                }
-            }
+    fn inspect(&self, src: &IpPort, host: &str, record: &ConnectRecord) {
+        // This should never fail, the lock is never held across an await or a panic.
+        let mut escalations = self.escalations.lock().unwrap();
+        if let Some(evidence_collector) = escalations.evidence_collector.as_mut() {
+            evidence_collector.push(record);
+        }
 
-            send_count += 1;
+        if !self.runner.ping_options.escalate || !escalations.tracker.starts_outage(record) {
+            return;
         }
+        let Ok(dst_socket) = record.destination.parse::<SocketAddr>() else {
+            return;
+        };
+        let outage_msg = format!("{} started failing, collecting evidence.", record.display_destination());
+        info_handler(&outage_msg, &self.runner.logging_options);
+        let bundle = escalations
+            .evidence_collector
+            .as_mut()
+            .map(|evidence_collector| evidence_collector.open(host, record));
+        let escalation = tokio::spawn(escalate(
+            src.clone(),
+            host.to_owned(),
+            dst_socket,
+            record.result,
+            self.runner.ping_options,
+            self.runner.dns_options.clone(),
+        ));
+        escalations.pending.push((bundle, escalation));
+    }
 
-        let mut client_results: Vec<ClientResult> = Vec::new();
-        for (_, addrs) in results_map {
-            for (addr, latencies) in addrs {
-                let client_summary = ClientSummary { send_count, latencies };
-                let summary_msg = client_summary_result(&addr, ConnectMethod::TCP, client_summary);
-                client_results.push(summary_msg)
+    async fn finish(&self) -> Result<()> {
+        let logging_options = &self.runner.logging_options;
+        let (pending, mut evidence_collector) = {
+            // This should never fail, the lock is never held across an await or a panic.
+            let mut escalations = self.escalations.lock().unwrap();
+            (
+                std::mem::take(&mut escalations.pending),
+                escalations.evidence_collector.take(),
+            )
+        };
+        for (bundle, escalation) in pending {
+            let escalation = escalation.await.ok();
+            if let Some(escalation) = &escalation {
+                let escalation_msg = match logging_options.output {
+                    OutputFormat::Text => escalation_table_msg(escalation),
+                    OutputFormat::Json => escalation_json_msg(escalation),
+                };
+                summary_handler(&escalation_msg, logging_options);
+            }
+            if let (Some(evidence_collector), Some(bundle)) = (evidence_collector.as_mut(), bundle) {
+                let path = evidence_collector.write(bundle, escalation)?;
+                let evidence_msg = format!("Evidence bundle written to {}", path.display());
+                info_handler(&evidence_msg, logging_options);
             }
         }
-        client_results.sort_by_key(|x| x.destination.to_owned());
-
-        let summary_table = client_summary_table_msg(&self.dst_ip, self.dst_port, ConnectMethod::TCP, &client_results);
-        println!("{}", summary_table);
-
+        // Warm connections are closed gracefully, rather than reset when dropped.
+        let tick = Duration::from_millis(self.runner.ping_options.timeout.into());
+        let warm_connections = std::mem::take(&mut *self.warm_connections.lock().await);
+        for (_, stream) in warm_connections {
+            close_stream(stream, tick).await;
+        }
         Ok(())
     }
 }
 
-async fn process_host(
-    src_ip_port: IpPort,
-    host_record: HostRecord,
-    ping_options: PingOptions,
-    ip_options: IpOptions,
-) -> HostResults {
-    // Create a vector of sockets based on the IP protocol.
-    let sockets = match ip_options.ip_protocol {
-        IpProtocol::All => [host_record.ipv4_sockets, host_record.ipv6_sockets].concat(),
-        IpProtocol::V4 => host_record.ipv4_sockets,
-        IpProtocol::V6 => host_record.ipv6_sockets,
-    };
-
-    let results: Vec<ConnectRecord> = futures::stream::iter(sockets)
-        .map(|dst_socket| {
-            let src_ip_port = src_ip_port.clone();
-            async move {
-                //
-                connect_host(src_ip_port, dst_socket, ping_options).await
-            }
-        })
-        .buffer_unordered(BUFFER_SIZE)
-        .collect()
-        .await;
-
-    HostResults {
-        host: host_record.host,
-        results,
-    }
-}
+pub async fn connect_host(src: IpPort, dst_socket: SocketAddr, host: &str, ping_options: PingOptions) -> ConnectRecord {
+    // Bind the source socket to the same IP Version as the destination socket.
+    let bind_addr = src.bind_addr(&dst_socket);
+    let src_socket = get_tcp_socket(bind_addr);
+    src.record_bind(&dst_socket, src_socket.is_ok());
 
-async fn connect_host(src: IpPort, dst_socket: SocketAddr, ping_options: PingOptions) -> ConnectRecord {
-    let (bind_addr, src_socket) = match &dst_socket.is_ipv4() {
-        // Bind the source socket to the same IP Version as the destination socket.
-        true => {
-            let bind_ipv4_addr = SocketAddr::new(src.ipv4, src.port);
-            let socket = get_tcp_socket(bind_ipv4_addr).ok();
-            (bind_ipv4_addr, socket)
-        }
-        false => {
-            let bind_ipv6_addr = SocketAddr::new(src.ipv6, src.port);
-            let socket = get_tcp_socket(bind_ipv6_addr).ok();
-            (bind_ipv6_addr, socket)
+    // If the source socket is an error, we could not bind to the socket.
+    let src_socket = match src_socket {
+        Ok(socket) => socket,
+        Err(e) => {
+            return ConnectRecord {
+                result: bind_error_switch_handler(e.as_ref()),
+                protocol: ConnectMethod::TCP,
+                source: bind_addr.to_string(),
+                destination: dst_socket.to_string(),
+                time: -1.0,
+                success: false,
+                error_msg: Some("Error binding to socket".to_owned()),
+                ..Default::default()
+            };
         }
     };
 
-    // If the source socket is None, we could not bind to the socket.
-    if src_socket.is_none() {
-        return ConnectRecord {
-            result: ConnectResult::BindError,
-            protocol: ConnectMethod::TCP,
-            source: bind_addr.to_string(),
-            destination: dst_socket.to_string(),
-            time: -1.0,
-            success: false,
-            error_msg: Some("Error binding to socket".to_owned()),
-        };
-    }
-    // Unwrap the socket because we have already checked that it is not None.
-    let src_socket = src_socket.unwrap();
-
     let local_addr = src_socket
         .local_addr()
         // This should never fail because we always
@@ -264,15 +249,20 @@ async fn connect_host(src: IpPort, dst_socket: SocketAddr, ping_options: PingOpt
         time: -1.0,
         success: false,
         error_msg: None,
+        ..Default::default()
     };
 
+    // ICMP errors received while connecting are read from the
+    // socket's error queue to report why the connection failed.
+    let error_queue = ErrorQueue::new(&src_socket, dst_socket.is_ipv6());
+
     // record timestamp before connection
     let pre_conn_timestamp = time_now_us();
 
     let tick = Duration::from_millis(ping_options.timeout.into());
     match timeout(tick, src_socket.connect(dst_socket)).await {
         Ok(s) => match s {
-            Ok(stream) => {
+            Ok(mut stream) => {
                 // Update conn record
                 // Calculate the round trip time
                 let post_conn_timestamp = time_now_us();
@@ -288,8 +278,75 @@ async fn connect_host(src: IpPort, dst_socket: SocketAddr, ping_options: PingOpt
                 conn_record.result = ConnectResult::Pong;
                 conn_record.time = connection_time;
 
-                // TODO:
-                // send/receive nk message
+                if ping_options.tls {
+                    // The connection is only used for the handshake.
+                    tls_handshake(&mut conn_record, stream, host, tick).await;
+                    return conn_record;
+                }
+
+                if ping_options.nk_peer {
+                    let uuid = Uuid::new_v4().to_string();
+                    // These should not fail, the message only contains strings and numbers.
+                    let nk_msg =
+                        NetKrakenMessage::new(&uuid, &conn_record.source, &conn_record.destination, ConnectMethod::TCP);
+                    let message = nk_msg.and_then(|m| m.to_json()).unwrap_or_default();
+
+                    let pre_msg_timestamp = time_now_us();
+                    match timeout(tick, exchange_nk_msg(&mut stream, &message)).await {
+                        // Replies from a server that is not a NetKraken peer are ignored.
+                        Ok(Ok(Some(m))) if m.nk_peer && m.uuid == uuid => {
+                            let post_msg_timestamp = time_now_us();
+                            let round_trip_time = calc_connect_ms(pre_msg_timestamp, post_msg_timestamp);
+                            conn_record.peer = Some(PeerRecord::new(m, post_msg_timestamp, round_trip_time));
+                        }
+                        Ok(Ok(_)) => {}
+                        Ok(Err(e)) => conn_record.error_msg = Some(e.to_string()),
+                        Err(_) => conn_record.error_msg = Some("NetKraken peer did not reply".to_owned()),
+                    }
+                }
+
+                if ping_options.payload > 0 {
+                    let pre_payload_timestamp = time_now_us();
+                    match timeout(tick, exchange_payload(&mut stream, ping_options.payload)).await {
+                        Ok(Ok(verified)) => {
+                            let post_payload_timestamp = time_now_us();
+                            conn_record.payload = Some(PayloadRecord {
+                                size: ping_options.payload,
+                                time: calc_connect_ms(pre_payload_timestamp, post_payload_timestamp),
+                                verified,
+                            });
+                            if !verified {
+                                conn_record.error_msg = Some("Echoed payload does not match".to_owned());
+                            }
+                        }
+                        Ok(Err(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                            conn_record.error_msg = Some("Echoed payload was truncated".to_owned());
+                            conn_record.payload = Some(PayloadRecord {
+                                size: ping_options.payload,
+                                time: -1.0,
+                                verified: false,
+                            });
+                        }
+                        Ok(Err(e)) => {
+                            conn_record.error_msg = Some(e.to_string());
+                            conn_record.result = io_error_switch_handler(e);
+                            conn_record.success = false;
+                            conn_record.time = -1.0;
+                        }
+                        Err(e) => {
+                            conn_record.error_msg = Some(e.to_string());
+                            conn_record.result = io_error_switch_handler(e.into());
+                            conn_record.success = false;
+                            conn_record.time = -1.0;
+                        }
+                    }
+                    if conn_record.payload.as_ref().is_some_and(|p| !p.verified) {
+                        conn_record.result = ConnectResult::Corrupt;
+                        conn_record.success = false;
+                        conn_record.time = -1.0;
+                    }
+                }
+                close_stream(stream, tick).await;
             }
             // Connection timeout
             Err(e) => {
@@ -305,10 +362,186 @@ async fn connect_host(src: IpPort, dst_socket: SocketAddr, ping_options: PingOpt
             conn_record.error_msg = Some(error_msg);
         }
     };
+    if !conn_record.success {
+        if let Some(icmp_error) = error_queue.and_then(|q| q.take_icmp_error()) {
+            conn_record.result = icmp_error.connect_result();
+            conn_record.error_msg = Some(icmp_error.to_string());
+        }
+    }
+    conn_record
+}
+
+/// Perform a TLS handshake over a connection, verifying the server
+/// certificate for the host. A failed handshake fails the connection.
+async fn tls_handshake(conn_record: &mut ConnectRecord, stream: TcpStream, host: &str, tick: Duration) {
+    let mut tls_record = TlsRecord {
+        handshake_time: -1.0,
+        ..Default::default()
+    };
+    let pre_tls_timestamp = time_now_us();
+    let result = match ServerName::try_from(host.to_owned()) {
+        Ok(server_name) => match timeout(tick, tls_connector().connect(server_name, stream)).await {
+            Ok(Ok(mut tls_stream)) => {
+                tls_record.handshake_time = calc_connect_ms(pre_tls_timestamp, time_now_us());
+                let (_, connection) = tls_stream.get_ref();
+                tls_record.version = connection
+                    .protocol_version()
+                    .map(|v| format!("{v:?}"))
+                    .unwrap_or_default();
+                tls_record.cipher_suite = connection
+                    .negotiated_cipher_suite()
+                    .map(|s| format!("{:?}", s.suite()))
+                    .unwrap_or_default();
+                tls_record.cert_expiry_days = connection
+                    .peer_certificates()
+                    .and_then(|certs| certs.first())
+                    .and_then(|cert| cert_expiry_days(cert, OffsetDateTime::now_utc()));
+                // Send a close_notify alert and a FIN, rather than resetting the connection.
+                let _ = timeout(tick, tls_stream.shutdown()).await;
+                Ok(())
+            }
+            Ok(Err(e)) => Err((tls_error_result(&e), e.to_string())),
+            Err(e) => Err((ConnectResult::Timeout, e.to_string())),
+        },
+        Err(e) => Err((ConnectResult::TlsError, e.to_string())),
+    };
+    if let Err((result, error_msg)) = result {
+        conn_record.result = result;
+        conn_record.error_msg = Some(error_msg);
+        conn_record.success = false;
+        conn_record.time = -1.0;
+    }
+    conn_record.tls = Some(tls_record);
+}
+
+/// Send a NetKraken message and read the reply.
+/// Returns `None` if the reply is not a NetKraken message.
+async fn exchange_nk_msg(stream: &mut TcpStream, message: &str) -> std::io::Result<Option<NetKrakenMessage>> {
+    stream.write_all(&frame(message.as_bytes())).await?;
+    let reply = read_frame(stream).await?;
+    Ok(reply.and_then(|r| nk_msg_reader(&String::from_utf8_lossy(&r))))
+}
+
+/// Send a request over an established connection to the destination,
+/// first connecting if there is no connection yet. The connection is
+/// kept for the next cycle unless the request fails.
+async fn warm_host(
+    src: IpPort,
+    dst_socket: SocketAddr,
+    ping_options: PingOptions,
+    warm_connections: &Mutex<HashMap<SocketAddr, TcpStream>>,
+) -> ConnectRecord {
+    let bind_addr = src.bind_addr(&dst_socket);
+    let mut conn_record = ConnectRecord {
+        result: ConnectResult::Unknown,
+        protocol: ConnectMethod::TCP,
+        source: bind_addr.to_string(),
+        destination: dst_socket.to_string(),
+        time: -1.0,
+        success: false,
+        error_msg: None,
+        warm: true,
+        ..Default::default()
+    };
+
+    let tick = Duration::from_millis(ping_options.timeout.into());
+    let stream = warm_connections.lock().await.remove(&dst_socket);
+    let stream = match stream {
+        Some(stream) => Ok(stream),
+        None => match get_tcp_socket(bind_addr) {
+            Ok(socket) => {
+                src.record_bind(&dst_socket, true);
+                match timeout(tick, socket.connect(dst_socket)).await {
+                    Ok(stream) => stream,
+                    Err(e) => Err(e.into()),
+                }
+            }
+            Err(e) => {
+                src.record_bind(&dst_socket, false);
+                conn_record.result = bind_error_switch_handler(e.as_ref());
+                conn_record.error_msg = Some(e.to_string());
+                return conn_record;
+            }
+        },
+    };
+    let mut stream = match stream {
+        Ok(stream) => stream,
+        Err(e) => {
+            conn_record.error_msg = Some(e.to_string());
+            conn_record.result = io_error_switch_handler(e);
+            return conn_record;
+        }
+    };
+    if let Ok(addr) = stream.local_addr() {
+        conn_record.source = addr.to_string();
+    }
+
+    let pre_request_timestamp = time_now_us();
+    match timeout(tick, warm_request(&mut stream, ping_options.nk_peer)).await {
+        Ok(Ok(())) => {
+            let post_request_timestamp = time_now_us();
+            conn_record.success = true;
+            conn_record.result = ConnectResult::Pong;
+            conn_record.time = calc_connect_ms(pre_request_timestamp, post_request_timestamp);
+            warm_connections.lock().await.insert(dst_socket, stream);
+        }
+        Ok(Err(e)) => {
+            conn_record.error_msg = Some(e.to_string());
+            conn_record.result = io_error_switch_handler(e);
+        }
+        Err(e) => {
+            conn_record.error_msg = Some(e.to_string());
+            conn_record.result = io_error_switch_handler(e.into());
+        }
+    }
     conn_record
 }
 
-fn get_tcp_socket(bind_addr: SocketAddr) -> Result<TcpSocket> {
+/// Send a request and wait for the response.
+/// NetKraken peers expect requests to be framed.
+async fn warm_request(stream: &mut TcpStream, nk_peer: bool) -> std::io::Result<()> {
+    let closed = || std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Connection closed by server");
+    if nk_peer {
+        stream.write_all(&frame(PING_MSG.as_bytes())).await?;
+        return read_frame(stream).await?.map(|_| ()).ok_or_else(closed);
+    }
+    stream.write_all(PING_MSG.as_bytes()).await?;
+    let mut buffer = vec![0u8; MAX_PACKET_SIZE];
+    match stream.read(&mut buffer).await? {
+        0 => Err(closed()),
+        _ => Ok(()),
+    }
+}
+
+/// Send a payload of `size` bytes and read back the echo from the server,
+/// returning whether the echo matches the payload byte for byte.
+async fn exchange_payload(stream: &mut TcpStream, size: u32) -> std::io::Result<bool> {
+    let payload: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+    let mut echo = vec![0u8; payload.len()];
+
+    let (mut reader, mut writer) = stream.split();
+    // Write and read concurrently so large payloads cannot
+    // deadlock on full socket buffers.
+    tokio::try_join!(
+        async {
+            writer.write_all(&payload).await?;
+            writer.shutdown().await
+        },
+        reader.read_exact(&mut echo),
+    )?;
+    Ok(echo == payload)
+}
+
+/// Close a connection gracefully, sending a FIN rather than a reset.
+/// Data from the server that was not read is discarded first, as
+/// closing a socket with unread data resets the connection.
+pub async fn close_stream(mut stream: TcpStream, tick: Duration) {
+    let _ = timeout(tick, stream.shutdown()).await;
+    let mut buffer = vec![0u8; MAX_PACKET_SIZE];
+    while matches!(stream.try_read(&mut buffer), Ok(len) if len > 0) {}
+}
+
+pub fn get_tcp_socket(bind_addr: SocketAddr) -> Result<TcpSocket> {
     let socket = match bind_addr.is_ipv4() {
         true => TcpSocket::new_v4()?,
         false => TcpSocket::new_v6()?,
@@ -316,3 +549,70 @@ fn get_tcp_socket(bind_addr: SocketAddr) -> Result<TcpSocket> {
     socket.bind(bind_addr)?;
     Ok(socket)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn connections_are_closed_without_a_reset() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream.write_all(b"banner").await.unwrap();
+            let mut buffer = [0u8; 16];
+            stream.read(&mut buffer).await
+        });
+
+        // The banner is left unread when the connection is closed.
+        let stream = TcpStream::connect(server_addr).await.unwrap();
+        stream.readable().await.unwrap();
+        close_stream(stream, Duration::from_secs(1)).await;
+
+        assert_eq!(server.await.unwrap().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn records_are_streamed_as_probes_complete() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                drop(stream);
+            }
+        });
+
+        let client = TcpClient::new(
+            vec![("127.0.0.1".to_owned(), port)],
+            None,
+            None,
+            None,
+            LoggingOptions {
+                quiet: true,
+                ..Default::default()
+            },
+            PingOptions {
+                repeat: 2,
+                interval: 0,
+                ..Default::default()
+            },
+            IpOptions::default(),
+            DnsOptions::default(),
+        );
+        let mut stream = client.connect_stream();
+        let mut records = Vec::new();
+        while let Some(record) = stream.next().await {
+            records.push(record);
+        }
+        let results = stream.finish().await.unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert!(records
+            .iter()
+            .all(|r| r.success && r.destination == format!("127.0.0.1:{port}")));
+        assert_eq!(results[0].received, 2);
+    }
+}
@@ -0,0 +1,395 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+
+use tokio::io::Interest;
+use tokio::net::UdpSocket;
+use tokio::time::{timeout_at, Duration, Instant};
+
+use crate::core::common::{ConnectRecord, ConnectResult, DnsOptions, Escalation, Hop, HostRecord, IpPort, PingOptions};
+use crate::core::konst::{ESCALATE_HOP_TIMEOUT, ESCALATE_MAX_HOPS, ESCALATE_TRACE_PORT, PING_MSG};
+use crate::icmp::client::ping_host;
+use crate::tcp::client::connect_host;
+use crate::util::dns::resolve_host;
+use crate::util::platform::PlatformFeature;
+use crate::util::recverr::{ErrorQueue, IcmpError, IcmpListener};
+use crate::util::time::{calc_connect_ms, time_now_us, time_now_utc};
+
+/// Tracks which destinations are failing, so evidence is
+/// only collected once when a destination starts failing.
+#[derive(Default)]
+pub struct EscalationTracker {
+    failing: HashSet<String>,
+}
+
+impl EscalationTracker {
+    /// Return whether a record starts an outage of its destination.
+    /// Warm requests and failures to bind the source do not.
+    pub fn starts_outage(&mut self, record: &ConnectRecord) -> bool {
+        if record.warm || record.result == ConnectResult::BindError {
+            return false;
+        }
+        match record.success {
+            true => {
+                self.failing.remove(&record.destination);
+                false
+            }
+            false => self.failing.insert(record.destination.to_owned()),
+        }
+    }
+}
+
+/// Collect evidence of why a destination started failing.
+/// The host is resolved again, a TLS handshake is attempted,
+/// the default gateway is pinged and the path is traced together.
+pub async fn escalate(
+    src: IpPort,
+    host: String,
+    dst_socket: SocketAddr,
+    result: ConnectResult,
+    ping_options: PingOptions,
+    dns_options: DnsOptions,
+) -> Escalation {
+    let time = time_now_utc();
+    let tls_options = PingOptions {
+        nk_peer: false,
+        payload: 0,
+        warm: false,
+        tls: true,
+        ..ping_options
+    };
+    let (addresses, tls, gateway, path) = tokio::join!(
+        resolve_addresses(&host, dst_socket.port(), &dns_options),
+        connect_host(src.clone(), dst_socket, &host, tls_options),
+        check_gateway(&src, dst_socket, ping_options),
+        trace_path(&src, dst_socket),
+    );
+    // The gateway is found from the routing table, and the path is
+    // traced from ICMP errors, which need an error queue or raw socket.
+    let mut unsupported = Vec::new();
+    if !PlatformFeature::RouteTable.supported() {
+        unsupported.push(PlatformFeature::RouteTable.unsupported_msg());
+    }
+    if path.is_none() {
+        unsupported.push(format!(
+            "{}, and raw ICMP sockets need privileges",
+            PlatformFeature::ErrorQueue.unsupported_msg()
+        ));
+    }
+    Escalation {
+        destination: dst_socket.to_string(),
+        host,
+        time,
+        result,
+        addresses,
+        tls,
+        gateway,
+        path: path.unwrap_or_default(),
+        unsupported,
+    }
+}
+
+async fn resolve_addresses(host: &str, port: u16, dns_options: &DnsOptions) -> Vec<String> {
+    let host_record = HostRecord::new(host, port).await;
+    resolve_host(vec![host_record], dns_options)
+        .await
+        .into_iter()
+        .flat_map(|record| [record.ipv4_sockets, record.ipv6_sockets].concat())
+        .map(|socket| socket.to_string())
+        .collect()
+}
+
+/// Ping the default gateway for the IP version of the destination.
+async fn check_gateway(src: &IpPort, dst_socket: SocketAddr, ping_options: PingOptions) -> Option<ConnectRecord> {
+    let gateway = default_gateway(dst_socket.is_ipv6())?;
+    Some(ping_host(src.clone(), gateway, ping_options, 1).await)
+}
+
+/// Where the ICMP errors for the datagrams of a hop are read from.
+enum HopErrors {
+    Queue(ErrorQueue),
+    Raw(IcmpListener),
+}
+
+/// Trace the routers on the path to a destination with UDP datagrams
+/// of an increasing TTL. A router that drops a datagram replies with
+/// an ICMP time exceeded error, read from the socket error queue or a
+/// raw ICMP socket, and the destination host replies with a port
+/// unreachable error. Returns None when ICMP errors cannot be read.
+async fn trace_path(src: &IpPort, dst_socket: SocketAddr) -> Option<Vec<Hop>> {
+    let mut hops = Vec::new();
+    for ttl in 1..=ESCALATE_MAX_HOPS {
+        let mut trace_socket = dst_socket;
+        trace_socket.set_port(ESCALATE_TRACE_PORT + u16::from(ttl));
+        let Ok((socket, error_queue)) = hop_socket(src, trace_socket, ttl).await else {
+            break;
+        };
+        // Error queues are only supported on Linux.
+        let hop_errors = match error_queue {
+            Some(error_queue) => HopErrors::Queue(error_queue),
+            None => match socket.local_addr().ok().and_then(IcmpListener::new) {
+                Some(listener) => HopErrors::Raw(listener),
+                None if ttl == 1 => return None,
+                None => break,
+            },
+        };
+
+        let pre_conn_timestamp = time_now_us();
+        if socket.send(PING_MSG.as_bytes()).await.is_err() {
+            break;
+        }
+        match hop_error(&socket, &hop_errors).await {
+            Some(icmp_error) => {
+                hops.push(Hop {
+                    ttl,
+                    router: icmp_error.offender,
+                    time: calc_connect_ms(pre_conn_timestamp, time_now_us()),
+                });
+                if !icmp_error.is_time_exceeded() {
+                    break;
+                }
+            }
+            None => hops.push(Hop {
+                ttl,
+                router: None,
+                time: -1.0,
+            }),
+        }
+    }
+    Some(hops)
+}
+
+/// Create a UDP socket connected to the destination that sends with a TTL.
+async fn hop_socket(src: &IpPort, dst_socket: SocketAddr, ttl: u8) -> std::io::Result<(UdpSocket, Option<ErrorQueue>)> {
+    let socket = UdpSocket::bind(src.bind_addr(&dst_socket)).await?;
+    socket.connect(dst_socket).await?;
+    match dst_socket.is_ipv6() {
+        true => socket2::SockRef::from(&socket).set_unicast_hops_v6(ttl.into())?,
+        false => socket.set_ttl(ttl.into())?,
+    }
+    let error_queue = ErrorQueue::new(&socket, dst_socket.is_ipv6());
+    Ok((socket, error_queue))
+}
+
+/// Wait for the ICMP error for a hop. Hops that do not reply time out.
+async fn hop_error(socket: &UdpSocket, hop_errors: &HopErrors) -> Option<IcmpError> {
+    let deadline = Instant::now() + Duration::from_millis(ESCALATE_HOP_TIMEOUT.into());
+    let error_queue = match hop_errors {
+        HopErrors::Queue(error_queue) => error_queue,
+        HopErrors::Raw(listener) => return timeout_at(deadline, listener.recv_icmp_error()).await.ok()?,
+    };
+    loop {
+        match timeout_at(deadline, socket.ready(Interest::ERROR)).await {
+            Ok(Ok(ready)) if ready.is_error() => {
+                // The error readiness is cleared once no error is pending.
+                let icmp_error = socket.try_io(Interest::ERROR, || {
+                    if let Some(icmp_error) = error_queue.take_icmp_error() {
+                        return Ok(Some(icmp_error));
+                    }
+                    match socket.take_error()? {
+                        Some(_) => Ok(None),
+                        None => Err(std::io::ErrorKind::WouldBlock.into()),
+                    }
+                });
+                if let Ok(icmp_error) = icmp_error {
+                    return icmp_error;
+                }
+            }
+            Ok(Ok(_)) => continue,
+            _ => return None,
+        }
+    }
+}
+
+/// Return the default gateway from the kernel routing table.
+#[cfg(target_os = "linux")]
+fn default_gateway(ipv6: bool) -> Option<SocketAddr> {
+    use crate::util::parser::{parse_scoped_ipaddr, scoped_socket_addr};
+
+    match ipv6 {
+        false => {
+            let routes = std::fs::read_to_string("/proc/net/route").ok()?;
+            ipv4_gateway(&routes).map(|ip| SocketAddr::new(ip.into(), 0))
+        }
+        // IPv6 gateways are usually link local, so are scoped to their interface.
+        true => {
+            let routes = std::fs::read_to_string("/proc/net/ipv6_route").ok()?;
+            let (ip, interface) = ipv6_gateway(&routes)?;
+            let (ip, scope_id) = parse_scoped_ipaddr(&format!("{ip}%{interface}")).ok()?;
+            Some(scoped_socket_addr(ip, scope_id, 0))
+        }
+    }
+}
+
+/// Return the default gateway from the routing socket, with `route get`.
+#[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
+fn default_gateway(ipv6: bool) -> Option<SocketAddr> {
+    use crate::util::parser::{parse_scoped_ipaddr, scoped_socket_addr};
+
+    let family = match ipv6 {
+        true => "-inet6",
+        false => "-inet",
+    };
+    let output = std::process::Command::new("route")
+        .args(["-n", "get", family, "default"])
+        .output()
+        .ok()?;
+    let gateway = route_get_gateway(&String::from_utf8_lossy(&output.stdout))?;
+    let (ip, scope_id) = parse_scoped_ipaddr(&gateway).ok()?;
+    Some(scoped_socket_addr(ip, scope_id, 0))
+}
+
+/// The routing table is only read on Linux and the BSDs.
+#[cfg(not(any(target_os = "linux", target_os = "freebsd", target_os = "openbsd")))]
+fn default_gateway(_ipv6: bool) -> Option<SocketAddr> {
+    None
+}
+
+/// Return a snapshot of the kernel routing tables, one route per line.
+#[cfg(target_os = "linux")]
+pub fn route_snapshot() -> Vec<String> {
+    ["/proc/net/route", "/proc/net/ipv6_route"]
+        .iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .flat_map(|routes| {
+            routes
+                .lines()
+                .map(|line| line.trim_end().to_owned())
+                .collect::<Vec<String>>()
+        })
+        .collect()
+}
+
+/// Return a snapshot of the routing tables, one route per line, with `netstat -rn`.
+#[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
+pub fn route_snapshot() -> Vec<String> {
+    match std::process::Command::new("netstat").arg("-rn").output() {
+        Ok(output) => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| line.trim_end().to_owned())
+            .filter(|line| !line.is_empty())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// The routing table is only read on Linux and the BSDs.
+#[cfg(not(any(target_os = "linux", target_os = "freebsd", target_os = "openbsd")))]
+pub fn route_snapshot() -> Vec<String> {
+    Vec::new()
+}
+
+/// Return the gateway of the default route in `/proc/net/route`.
+/// Addresses are hex in network byte order read as a little endian integer.
+#[cfg(target_os = "linux")]
+fn ipv4_gateway(routes: &str) -> Option<std::net::Ipv4Addr> {
+    routes.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (destination, gateway, mask) = (fields.get(1)?, fields.get(2)?, fields.get(7)?);
+        if *destination != "00000000" || *mask != "00000000" {
+            return None;
+        }
+        match u32::from_str_radix(gateway, 16).ok()? {
+            0 => None,
+            gateway => Some(gateway.to_le_bytes().into()),
+        }
+    })
+}
+
+/// Return the gateway and interface of the default route in `/proc/net/ipv6_route`.
+#[cfg(target_os = "linux")]
+fn ipv6_gateway(routes: &str) -> Option<(std::net::Ipv6Addr, String)> {
+    routes.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (destination, prefix, gateway, interface) =
+            (fields.first()?, fields.get(1)?, fields.get(4)?, fields.get(9)?);
+        if destination.chars().any(|c| c != '0') || *prefix != "00" {
+            return None;
+        }
+        match u128::from_str_radix(gateway, 16).ok()? {
+            0 => None,
+            gateway => Some((gateway.into(), interface.to_string())),
+        }
+    })
+}
+
+/// Return the gateway in the output of `route get`, eg: `gateway: fe80::1%em0`
+#[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
+fn route_get_gateway(output: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let (key, value) = line.trim().split_once(':')?;
+        match key == "gateway" {
+            true => Some(value.trim().to_owned()),
+            false => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connect_record(success: bool) -> ConnectRecord {
+        ConnectRecord {
+            result: match success {
+                true => ConnectResult::Ping,
+                false => ConnectResult::Timeout,
+            },
+            protocol: crate::core::common::ConnectMethod::TCP,
+            source: "127.0.0.1:50000".to_owned(),
+            destination: "192.0.2.1:443".to_owned(),
+            time: -1.0,
+            success,
+            error_msg: None,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn outages_start_once_until_the_destination_recovers() {
+        let mut tracker = EscalationTracker::default();
+        let starts: Vec<bool> = [true, false, false, true, false]
+            .into_iter()
+            .map(|success| tracker.starts_outage(&connect_record(success)))
+            .collect();
+
+        assert_eq!(starts, [false, true, false, false, true]);
+    }
+
+    #[test]
+    fn bind_errors_do_not_start_an_outage() {
+        let mut tracker = EscalationTracker::default();
+        let mut record = connect_record(false);
+        record.result = ConnectResult::BindError;
+
+        assert!(!tracker.starts_outage(&record));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn default_gateways_are_read_from_the_routing_table() {
+        let routes = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n\
+            eth0\t0000A8C0\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0\n\
+            eth0\t00000000\t0101A8C0\t0003\t0\t0\t100\t00000000\t0\t0\t0\n";
+        assert_eq!(ipv4_gateway(routes), Some("192.168.1.1".parse().unwrap()));
+
+        let routes = "20010db8000000000000000000000000 40 00000000000000000000000000000000 00 00000000000000000000000000000000 00000100 00000001 00000000 00000001 eth0\n\
+            00000000000000000000000000000000 00 00000000000000000000000000000000 00 fe800000000000000000000000000001 00000400 00000002 00000000 00000003 eth0\n";
+        assert_eq!(
+            ipv6_gateway(routes),
+            Some(("fe80::1".parse().unwrap(), "eth0".to_owned()))
+        );
+        assert_eq!(ipv4_gateway("Iface\tDestination\tGateway\n"), None);
+    }
+
+    #[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
+    #[test]
+    fn default_gateways_are_read_from_route_get() {
+        let output = "   route to: default\ndestination: default\n       mask: default\n    gateway: 192.168.1.1\n  \
+            fib: 0\n  interface: em0\n      flags: <UP,GATEWAY,DONE,STATIC>\n";
+        assert_eq!(route_get_gateway(output), Some("192.168.1.1".to_owned()));
+
+        let output = "   route to: ::\ndestination: ::\n    gateway: fe80::1%em0\n  interface: em0\n";
+        assert_eq!(route_get_gateway(output), Some("fe80::1%em0".to_owned()));
+        assert_eq!(route_get_gateway("route: route has not been found\n"), None);
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use time::OffsetDateTime;
+
+use crate::core::common::{ConnectRecord, Escalation, EvidenceBundle};
+use crate::core::konst::{EVIDENCE_RECORDS, SCHEMA_VERSION};
+use crate::tcp::escalate::route_snapshot;
+
+/// Collects an evidence bundle for each outage. The records before an
+/// outage come from a history of recent records per destination, and
+/// the records after it are added until the bundle is full.
+pub struct EvidenceCollector {
+    dir: PathBuf,
+    history: HashMap<String, VecDeque<ConnectRecord>>,
+    bundles: Vec<(String, EvidenceBundle)>,
+}
+
+impl EvidenceCollector {
+    pub fn create(dir: &str) -> Result<EvidenceCollector> {
+        fs::create_dir_all(dir)?;
+        Ok(EvidenceCollector {
+            dir: PathBuf::from(dir),
+            history: HashMap::new(),
+            bundles: Vec::new(),
+        })
+    }
+
+    /// Add a record to the history of its destination
+    /// and to the open bundles of its destination.
+    pub fn push(&mut self, record: &ConnectRecord) {
+        for (_, bundle) in self.bundles.iter_mut() {
+            if bundle.destination == record.destination && bundle.after.len() < EVIDENCE_RECORDS {
+                bundle.after.push(record.clone());
+            }
+        }
+        let history = self.history.entry(record.destination.to_owned()).or_default();
+        if history.len() == EVIDENCE_RECORDS {
+            history.pop_front();
+        }
+        history.push_back(record.clone());
+    }
+
+    /// Open a bundle for an outage started by a record that has been pushed.
+    /// Returns the bundle id used to write it once the escalation completes.
+    pub fn open(&mut self, host: &str, record: &ConnectRecord) -> usize {
+        let now = OffsetDateTime::now_utc();
+        let bundle = EvidenceBundle {
+            schema_version: SCHEMA_VERSION,
+            destination: record.destination.to_owned(),
+            label: record.label.to_owned(),
+            host: host.to_owned(),
+            started: now.to_string(),
+            before: self
+                .history
+                .get(&record.destination)
+                .map(|history| history.iter().cloned().collect())
+                .unwrap_or_default(),
+            after: Vec::new(),
+            routes: route_snapshot(),
+            escalation: None,
+        };
+        self.bundles.push((bundle_name(now, &record.destination), bundle));
+        self.bundles.len() - 1
+    }
+
+    /// Write a bundle with the evidence collected for its outage.
+    pub fn write(&mut self, id: usize, escalation: Option<Escalation>) -> Result<PathBuf> {
+        let (name, bundle) = &mut self.bundles[id];
+        bundle.escalation = escalation;
+        let path = Path::new(&self.dir).join(name);
+        serde_json::to_writer_pretty(File::create(&path)?, bundle)?;
+        Ok(path)
+    }
+}
+
+/// Return the file name of a bundle, from the time the outage
+/// started and its destination, eg: nk-outage-20240101T000000Z-192.0.2.1_443.json
+fn bundle_name(started: OffsetDateTime, destination: &str) -> String {
+    let destination: String = destination
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() || c == '.' {
+            true => c,
+            false => '_',
+        })
+        .collect();
+    format!(
+        "nk-outage-{:04}{:02}{:02}T{:02}{:02}{:02}Z-{}.json",
+        started.year(),
+        u8::from(started.month()),
+        started.day(),
+        started.hour(),
+        started.minute(),
+        started.second(),
+        destination.trim_matches('_')
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::common::{ConnectMethod, ConnectResult};
+
+    fn connect_record(destination: &str, success: bool) -> ConnectRecord {
+        ConnectRecord {
+            result: match success {
+                true => ConnectResult::Ping,
+                false => ConnectResult::Timeout,
+            },
+            protocol: ConnectMethod::TCP,
+            source: "127.0.0.1:50000".to_owned(),
+            destination: destination.to_owned(),
+            time: -1.0,
+            success,
+            error_msg: None,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn bundles_are_named_by_start_time_and_destination() {
+        let started = OffsetDateTime::from_unix_timestamp(1_704_067_205).unwrap();
+        assert_eq!(
+            bundle_name(started, "192.0.2.1:443"),
+            "nk-outage-20240101T000005Z-192.0.2.1_443.json"
+        );
+        assert_eq!(
+            bundle_name(started, "[2001:db8::1]:443"),
+            "nk-outage-20240101T000005Z-2001_db8__1__443.json"
+        );
+    }
+
+    #[test]
+    fn bundles_hold_the_records_around_an_outage() {
+        let dir = std::env::temp_dir().join(format!("nk-evidence-{}", uuid::Uuid::new_v4()));
+        let mut collector = EvidenceCollector::create(dir.to_str().unwrap()).unwrap();
+        for _ in 0..EVIDENCE_RECORDS + 2 {
+            collector.push(&connect_record("192.0.2.1:443", true));
+        }
+        collector.push(&connect_record("192.0.2.2:443", false));
+        let failed = connect_record("192.0.2.1:443", false);
+        collector.push(&failed);
+        let id = collector.open("example.com", &failed);
+        for _ in 0..EVIDENCE_RECORDS + 2 {
+            collector.push(&connect_record("192.0.2.1:443", false));
+        }
+
+        let path = collector.write(id, None).unwrap();
+        let bundle: serde_json::Value = serde_json::from_reader(File::open(&path).unwrap()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let before = bundle["before"].as_array().unwrap();
+        assert_eq!(before.len(), EVIDENCE_RECORDS);
+        assert_eq!(before.last().unwrap()["success"], false);
+        assert!(before.iter().all(|record| record["destination"] == "192.0.2.1:443"));
+        assert_eq!(bundle["after"].as_array().unwrap().len(), EVIDENCE_RECORDS);
+        assert_eq!(bundle["host"], "example.com");
+    }
+}
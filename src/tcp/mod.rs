@@ -1,2 +1,5 @@
 pub mod client;
+pub mod escalate;
+pub mod evidence;
+#[cfg(feature = "server")]
 pub mod server;
@@ -1,14 +1,21 @@
+use std::sync::{Arc, Mutex};
+
 use anyhow::Result;
 
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
 use tokio::net::TcpListener;
+use tokio::time::{sleep, Duration};
 
 use crate::core::common::{ConnectMethod, ConnectResult, ListenOptions, LogLevel, LoggingOptions};
 use crate::core::konst::{BIND_ADDR_IPV4, BIND_PORT, MAX_PACKET_SIZE};
-use crate::util::handler::log_handler;
+use crate::util::csv::CsvWriter;
+use crate::util::fault::{choose_fault, corrupt, fault_rng, Fault};
+use crate::util::frame::{frame, read_frame};
+use crate::util::handler::{info_handler, log_handler};
 use crate::util::message::{server_conn_success_msg, server_start_msg};
-use crate::util::parser::{nk_msg_reader, parse_ipaddr};
+use crate::util::parser::{nk_msg_reader, parse_scoped_ipaddr, scoped_socket_addr};
+use crate::util::result::server_record;
 use crate::util::time::{calc_connect_ms, time_now_us, time_now_utc};
 
 pub struct TcpServer {
@@ -20,66 +27,139 @@ pub struct TcpServer {
 
 impl TcpServer {
     pub async fn listen(&self) -> Result<()> {
-        let listen_ip = parse_ipaddr(&self.listen_ip)?;
+        let (listen_ip, scope_id) = parse_scoped_ipaddr(&self.listen_ip)?;
 
-        let bind_addr = format!("{}:{}", listen_ip, self.listen_port);
+        let bind_addr = scoped_socket_addr(listen_ip, scope_id, self.listen_port);
 
         let listener = TcpListener::bind(&bind_addr).await?;
 
         let start_msg = server_start_msg(ConnectMethod::TCP, &listen_ip, &self.listen_port);
-        println!("{}", start_msg);
+        info_handler(&start_msg, &self.logging_options);
+
+        // Requests from every client are appended to the same CSV file.
+        let csv_writer = match &self.logging_options.csv_file {
+            Some(csv_file) => Some(Arc::new(Mutex::new(CsvWriter::append(csv_file)?))),
+            None => None,
+        };
+        // Connections share one source of failures, so a seeded server repeats them.
+        let fault_rng = Arc::new(Mutex::new(fault_rng(&self.listen_options)));
 
         loop {
             let logging_options = self.logging_options.clone();
             let listen_options = self.listen_options;
+            let csv_writer = csv_writer.clone();
+            let fault_rng = fault_rng.clone();
             // Receive stream
             let (mut stream, _) = listener.accept().await?;
 
             tokio::spawn(async move {
-                let receive_time_utc = time_now_utc();
-                let receive_time_stamp = time_now_us();
-
-                let mut buffer = vec![0u8; MAX_PACKET_SIZE];
-
+                let peer_addr = stream.peer_addr()?.to_string();
+                let local_addr = stream.local_addr()?.to_string();
                 let (mut reader, mut writer) = stream.split();
-                let len = reader.read(&mut buffer).await?;
-                buffer.truncate(len);
-                let mut client_server_time = 0.0;
 
-                match listen_options.nk_peer && len > 0 {
-                    false => {
-                        writer.write_all(&buffer).await?;
+                // Respond to each request until the client closes the connection,
+                // so clients can send requests over an established connection.
+                let mut requests: u32 = 0;
+                loop {
+                    let mut buffer = vec![0u8; MAX_PACKET_SIZE];
+                    let len = match (listen_options.echo, listen_options.nk_peer) {
+                        // Read until the client shuts down its write half.
+                        (true, _) => {
+                            buffer.clear();
+                            reader.read_to_end(&mut buffer).await?
+                        }
+                        // NetKraken peers send length prefixed messages.
+                        (false, true) => {
+                            buffer = read_frame(&mut reader).await?.unwrap_or_default();
+                            buffer.len()
+                        }
+                        (false, false) => reader.read(&mut buffer).await?,
+                    };
+                    // A connection without a request is still logged once.
+                    if len == 0 && requests > 0 {
+                        break;
                     }
-                    true => {
-                        let data_string = &String::from_utf8_lossy(&buffer);
-                        match nk_msg_reader(data_string) {
-                            Some(mut m) => {
-                                let connection_time = calc_connect_ms(m.send_timestamp, receive_time_stamp);
-                                client_server_time = connection_time;
-
-                                m.receive_time_utc = receive_time_utc;
-                                m.receive_timestamp = receive_time_stamp;
-                                m.one_way_time_ms = connection_time;
-
-                                let json_message = serde_json::to_string(&m)?;
-                                writer.write_all(json_message.as_bytes()).await?;
+                    requests += 1;
+                    let receive_time_utc = time_now_utc();
+                    let receive_time_stamp = time_now_us();
+                    buffer.truncate(len);
+                    let mut client_server_time = 0.0;
+
+                    let mut response = match listen_options.nk_peer && len > 0 {
+                        false => buffer.clone(),
+                        true => {
+                            let data_string = &String::from_utf8_lossy(&buffer);
+                            match nk_msg_reader(data_string) {
+                                Some(mut m) => {
+                                    let connection_time = calc_connect_ms(m.send_timestamp, receive_time_stamp);
+                                    client_server_time = connection_time;
+
+                                    m.receive_time_utc = receive_time_utc;
+                                    m.receive_timestamp = receive_time_stamp;
+                                    m.one_way_time_ms = connection_time;
+                                    m.nk_peer = true;
+                                    m.reply_timestamp = time_now_us();
+
+                                    let json_message = serde_json::to_string(&m)?;
+                                    json_message.into_bytes()
+                                }
+                                None => data_string.as_bytes().to_vec(),
                             }
-                            None => writer.write_all(data_string.as_bytes()).await?,
                         }
+                    };
+
+                    if listen_options.nk_peer && !listen_options.echo && len > 0 {
+                        response = frame(&response);
                     }
-                }
 
-                let msg = server_conn_success_msg(
-                    ConnectResult::Ping,
-                    ConnectMethod::TCP,
-                    &stream.peer_addr()?.to_string(),
-                    &stream.local_addr()?.to_string(),
-                    client_server_time,
-                );
-                log_handler(LogLevel::INFO, &msg, &logging_options).await;
-
-                // Flush buffer
-                buffer.clear();
+                    let fault = {
+                        let mut rng = fault_rng.lock().unwrap();
+                        let fault = choose_fault(&listen_options, &mut *rng);
+                        if fault == Some(Fault::Corrupt) {
+                            corrupt(&mut response, &mut *rng);
+                        }
+                        fault
+                    };
+                    if listen_options.delay > 0 {
+                        sleep(Duration::from_millis(listen_options.delay.into())).await;
+                    }
+                    if fault != Some(Fault::Drop) {
+                        writer.write_all(&response).await?;
+                    }
+
+                    let msg = server_conn_success_msg(
+                        ConnectResult::Ping,
+                        ConnectMethod::TCP,
+                        &peer_addr,
+                        &local_addr,
+                        client_server_time,
+                    );
+                    let msg = match fault {
+                        Some(fault) => format!("{msg} fault={fault}"),
+                        None => msg,
+                    };
+                    log_handler(LogLevel::INFO, &msg, &logging_options).await;
+
+                    if let Some(csv_writer) = &csv_writer {
+                        // Requests after the first on a connection are warm.
+                        let record = server_record(
+                            ConnectMethod::TCP,
+                            &peer_addr,
+                            &local_addr,
+                            client_server_time,
+                            requests > 1,
+                            &logging_options,
+                        );
+                        if let Ok(mut csv_writer) = csv_writer.lock() {
+                            csv_writer.write(&record)?;
+                        }
+                    }
+
+                    // Echo mode reads the whole stream as a single request.
+                    if listen_options.echo || len == 0 {
+                        break;
+                    }
+                }
 
                 Ok::<(), anyhow::Error>(())
             });
@@ -0,0 +1,283 @@
+use std::net::SocketAddr;
+
+use anyhow::{bail, Result};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration};
+
+use crate::core::common::{
+    ClientResult, ConnectMethod, ConnectRecord, ConnectResult, DnsOptions, IpOptions, IpPort, LoggingOptions,
+    PingOptions, TelnetRecord,
+};
+use crate::core::konst::{MAX_PACKET_SIZE, TELNET_COM_PORT_OPTION, TELNET_DO, TELNET_DONT, TELNET_IAC, TELNET_WILL};
+use crate::tcp::client::{close_stream, get_tcp_socket};
+use crate::util::handler::{bind_error_switch_handler, io_error_switch_handler};
+use crate::util::parser::{telnet_command_name, telnet_commands};
+use crate::util::probe::{ProbeClient, ProbeRunner};
+use crate::util::time::{calc_connect_ms, time_now_us};
+
+/// Telnet client. Connects to a terminal server port and validates
+/// that the telnet negotiation begins, offering the RFC2217
+/// COM-PORT-OPTION to detect serial console access.
+#[derive(Debug)]
+pub struct TelnetClient {
+    pub runner: ProbeRunner,
+}
+
+impl TelnetClient {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        dst_ip: String,
+        dst_port: u16,
+        src_ipv4: Option<String>,
+        src_ipv6: Option<String>,
+        src_port: Option<u16>,
+        logging_options: LoggingOptions,
+        ping_options: PingOptions,
+        ip_options: IpOptions,
+        dns_options: DnsOptions,
+    ) -> TelnetClient {
+        TelnetClient {
+            runner: ProbeRunner::new(
+                vec![(dst_ip, dst_port)],
+                src_ipv4,
+                src_ipv6,
+                src_port,
+                logging_options,
+                ping_options,
+                ip_options,
+                dns_options,
+            ),
+        }
+    }
+
+    pub async fn connect(&self) -> Result<Vec<ClientResult>> {
+        self.runner.run(self).await
+    }
+}
+
+impl ProbeClient for TelnetClient {
+    fn protocol(&self) -> ConnectMethod {
+        ConnectMethod::TELNET
+    }
+
+    async fn probe_once(
+        &self,
+        src: IpPort,
+        _host: &str,
+        dst_socket: SocketAddr,
+        ping_options: PingOptions,
+    ) -> ConnectRecord {
+        connect_host(src, dst_socket, ping_options).await
+    }
+}
+
+async fn connect_host(src: IpPort, dst_socket: SocketAddr, ping_options: PingOptions) -> ConnectRecord {
+    // Bind the source socket to the same IP Version as the destination socket.
+    let bind_addr = src.bind_addr(&dst_socket);
+    let src_socket = get_tcp_socket(bind_addr);
+    src.record_bind(&dst_socket, src_socket.is_ok());
+
+    // If the source socket is an error, we could not bind to the socket.
+    let src_socket = match src_socket {
+        Ok(socket) => socket,
+        Err(e) => {
+            return ConnectRecord {
+                result: bind_error_switch_handler(e.as_ref()),
+                protocol: ConnectMethod::TELNET,
+                source: bind_addr.to_string(),
+                destination: dst_socket.to_string(),
+                time: -1.0,
+                success: false,
+                error_msg: Some("Error binding to socket".to_owned()),
+                ..Default::default()
+            };
+        }
+    };
+
+    let local_addr = src_socket
+        .local_addr()
+        // This should never fail because we always
+        // pass a bound socket.
+        .unwrap_or_else(|_| panic!("ERROR GETTING TCP SOCKET LOCAL ADDRESS"))
+        .to_string();
+
+    let mut conn_record = ConnectRecord {
+        result: ConnectResult::Unknown,
+        protocol: ConnectMethod::TELNET,
+        source: local_addr,
+        destination: dst_socket.to_string(),
+        time: -1.0,
+        success: false,
+        error_msg: None,
+        ..Default::default()
+    };
+
+    // record timestamp before connection
+    let pre_conn_timestamp = time_now_us();
+
+    let tick = Duration::from_millis(ping_options.timeout.into());
+    let mut stream = match timeout(tick, src_socket.connect(dst_socket)).await {
+        Ok(Ok(stream)) => stream,
+        // Connection error
+        Ok(Err(e)) => {
+            conn_record.error_msg = Some(e.to_string());
+            conn_record.result = io_error_switch_handler(e);
+            return conn_record;
+        }
+        // Timeout error
+        Err(e) => {
+            conn_record.error_msg = Some(e.to_string());
+            conn_record.result = io_error_switch_handler(e.into());
+            return conn_record;
+        }
+    };
+
+    conn_record.source = stream
+        .local_addr()
+        // This should never fail. If we have a TCP stream,
+        // we should have always have a local address.
+        .unwrap_or_else(|_| panic!("ERROR GETTING TCP STREAM LOCAL ADDRESS"))
+        .to_string();
+
+    let mut telnet_record = TelnetRecord {
+        connect_time: calc_connect_ms(pre_conn_timestamp, time_now_us()),
+        negotiation_time: -1.0,
+        ..Default::default()
+    };
+
+    // The timeout covers the whole negotiation. If the negotiation began
+    // but the server never answered the COM-PORT-OPTION offer, the telnet
+    // service is still reachable.
+    let session = timeout(
+        tick,
+        telnet_session(&mut stream, pre_conn_timestamp, &mut telnet_record),
+    )
+    .await;
+    close_stream(stream, tick).await;
+
+    match session {
+        Ok(Ok(())) => {
+            conn_record.success = true;
+            conn_record.result = ConnectResult::Pong;
+            conn_record.time = telnet_record.negotiation_time;
+        }
+        // IO errors are mapped the same as a TCP connection,
+        // anything else is an unexpected reply from the server.
+        Ok(Err(e)) => {
+            conn_record.error_msg = Some(e.to_string());
+            conn_record.result = match e.downcast::<std::io::Error>() {
+                Ok(e) => io_error_switch_handler(e),
+                Err(_) => ConnectResult::ProtocolError,
+            };
+        }
+        Err(_) if !telnet_record.commands.is_empty() => {
+            conn_record.success = true;
+            conn_record.result = ConnectResult::Pong;
+            conn_record.time = telnet_record.negotiation_time;
+        }
+        // Timeout error
+        Err(e) => {
+            conn_record.error_msg = Some(e.to_string());
+            conn_record.result = ConnectResult::Timeout;
+        }
+    }
+    conn_record.telnet = Some(telnet_record);
+
+    conn_record
+}
+
+/// Offer the RFC2217 COM-PORT-OPTION and read the server negotiation
+/// until the server answers the offer.
+async fn telnet_session(
+    stream: &mut TcpStream,
+    pre_conn_timestamp: u128,
+    telnet_record: &mut TelnetRecord,
+) -> Result<()> {
+    stream
+        .write_all(&[TELNET_IAC, TELNET_WILL, TELNET_COM_PORT_OPTION])
+        .await?;
+
+    let mut buffer = vec![0u8; MAX_PACKET_SIZE];
+    // Bytes of a command split across reads.
+    let mut pending: Vec<u8> = Vec::new();
+    let mut received = 0;
+    loop {
+        let len = stream.read(&mut buffer).await?;
+        if len == 0 {
+            match telnet_record.commands.is_empty() {
+                true => bail!("connection closed by the server before telnet negotiation"),
+                false => return Ok(()),
+            }
+        }
+        received += len;
+
+        pending.extend_from_slice(&buffer[..len]);
+        let (commands, parsed) = telnet_commands(&pending);
+        pending.drain(..parsed);
+        if !commands.is_empty() && telnet_record.negotiation_time < 0.0 {
+            telnet_record.negotiation_time = calc_connect_ms(pre_conn_timestamp, time_now_us());
+        }
+        for (command, option) in commands {
+            telnet_record.commands.push(telnet_command_name(command, option));
+            if option == TELNET_COM_PORT_OPTION {
+                match command {
+                    TELNET_DO => telnet_record.com_port = Some(true),
+                    TELNET_DONT => telnet_record.com_port = Some(false),
+                    _ => {}
+                }
+            }
+        }
+
+        if telnet_record.com_port.is_some() {
+            return Ok(());
+        }
+        // A service that sends a large amount of data
+        // without any negotiation is not a telnet server.
+        if telnet_record.commands.is_empty() && received >= MAX_PACKET_SIZE {
+            bail!("no telnet negotiation received from the server");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::common::SourceFallback;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn commands_split_across_reads_are_parsed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut offer = [0u8; 3];
+            stream.read_exact(&mut offer).await.unwrap();
+            // IAC DO COM-PORT-OPTION, split after the IAC.
+            stream.write_all(&[TELNET_IAC]).await.unwrap();
+            stream.flush().await.unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            stream.write_all(&[TELNET_DO, TELNET_COM_PORT_OPTION]).await.unwrap();
+            // The client closes the connection once the offer is answered.
+            let mut buffer = [0u8; 16];
+            stream.read(&mut buffer).await
+        });
+
+        let src = IpPort {
+            ipv4: "0.0.0.0".parse().unwrap(),
+            ipv6: "::".parse().unwrap(),
+            ipv6_scope_id: 0,
+            port: 0,
+            fallback: SourceFallback::new(false),
+        };
+        let record = connect_host(src, server_addr, PingOptions::default()).await;
+
+        assert!(record.success);
+        let telnet = record.telnet.unwrap();
+        assert_eq!(telnet.commands, ["DO COM-PORT-OPTION"]);
+        assert_eq!(telnet.com_port, Some(true));
+        // The connection is closed gracefully rather than reset.
+        assert_eq!(server.await.unwrap().unwrap(), 0);
+    }
+}
@@ -1,331 +1,723 @@
-use std::net::{IpAddr, SocketAddr};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-
-use anyhow::{bail, Result};
-use futures::StreamExt;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use tokio::io::Interest;
 use tokio::net::UdpSocket;
-use tokio::signal;
-use tokio::time::{timeout, Duration};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout, Duration};
+use uuid::Uuid;
 
 use crate::core::common::{
-    ClientResult, ClientSummary, ConnectMethod, ConnectRecord, ConnectResult, HostRecord, HostResults, IpOptions,
-    IpPort, IpProtocol, LoggingOptions, PingOptions,
+    ClientResult, ConnectMethod, ConnectRecord, ConnectResult, DnsOptions, IpOptions, IpPort, LoggingOptions,
+    NetKrakenMessage, PeerRecord, PingOptions,
 };
-use crate::core::konst::{BIND_ADDR_IPV4, BIND_ADDR_IPV6, BIND_PORT, BUFFER_SIZE, MAX_PACKET_SIZE, PING_MSG};
-use crate::util::dns::resolve_host;
-use crate::util::handler::{io_error_switch_handler, log_handler2, loop_handler};
-use crate::util::message::{client_result_msg, client_summary_table_msg, ping_header_msg, resolved_ips_msg};
-use crate::util::parser::parse_ipaddr;
-use crate::util::result::{client_summary_result, get_results_map};
+use crate::core::konst::{MAX_PACKET_SIZE, PING_MSG, UDP_RECV_BACKOFF};
+use crate::util::handler::{bind_error_switch_handler, info_handler, io_error_switch_handler};
+use crate::util::parser::nk_msg_reader;
+use crate::util::probe::{ProbeClient, ProbeRunner};
+use crate::util::recverr::{ErrorQueue, IcmpError};
+use crate::util::stream::ProbeStream;
 use crate::util::time::{calc_connect_ms, time_now_us};
 
+/// UDP client. Sends each round of probes on its interval without
+/// waiting for the replies to the previous round, over sockets shared
+/// by every round, on top of the probe runner.
+#[derive(Debug)]
 pub struct UdpClient {
-    pub dst_ip: String,
-    pub dst_port: u16,
-    pub src_ipv4: Option<IpAddr>,
-    pub src_ipv6: Option<IpAddr>,
-    pub src_port: u16,
-    pub output_options: LoggingOptions,
-    pub ping_options: PingOptions,
-    pub ip_options: IpOptions,
+    pub runner: ProbeRunner,
 }
 
 impl UdpClient {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
-        dst_ip: String,
-        dst_port: u16,
+        destinations: Vec<(String, u16)>,
         src_ipv4: Option<String>,
         src_ipv6: Option<String>,
         src_port: Option<u16>,
-        output_options: LoggingOptions,
+        logging_options: LoggingOptions,
         ping_options: PingOptions,
         ip_options: IpOptions,
+        dns_options: DnsOptions,
     ) -> UdpClient {
-        let src_ipv4 = match src_ipv4 {
-            Some(x) => parse_ipaddr(&x).ok(),
-            None => parse_ipaddr(BIND_ADDR_IPV4).ok(),
-        };
+        UdpClient {
+            runner: ProbeRunner::new(
+                destinations,
+                src_ipv4,
+                src_ipv6,
+                src_port,
+                logging_options,
+                ping_options,
+                ip_options,
+                dns_options,
+            ),
+        }
+    }
 
-        let src_ipv6 = match src_ipv6 {
-            Some(x) => parse_ipaddr(&x).ok(),
-            None => parse_ipaddr(BIND_ADDR_IPV6).ok(),
+    /// Run the client in a task of its own, yielding the record
+    /// of each probe as it completes.
+    pub fn connect_stream(self) -> ProbeStream {
+        let records = self.runner.run_handle.subscribe();
+        let run = tokio::spawn(async move {
+            let results = self.connect().await;
+            self.runner.run_handle.end();
+            results
+        });
+        ProbeStream::new(records, run)
+    }
+
+    /// Run the client, calling `callback` with the record of each probe
+    /// as it completes, and returning the summary of each destination.
+    pub async fn run_with<F, Fut>(self, callback: F) -> Result<Vec<ClientResult>>
+    where
+        F: FnMut(ConnectRecord) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        self.connect_stream().run_with(callback).await
+    }
+
+    pub async fn connect(&self) -> Result<Vec<ClientResult>> {
+        let probe = UdpProbe {
+            logging_options: &self.runner.logging_options,
+            probers: UdpProbers::default(),
         };
+        self.runner.run(&probe).await
+    }
+}
+
+/// Probes with the sockets of a run.
+struct UdpProbe<'a> {
+    logging_options: &'a LoggingOptions,
+    probers: UdpProbers,
+}
 
-        let src_port = src_port.unwrap_or(BIND_PORT);
+impl ProbeClient for UdpProbe<'_> {
+    fn protocol(&self) -> ConnectMethod {
+        ConnectMethod::UDP
+    }
 
-        UdpClient {
-            dst_ip,
-            dst_port,
-            src_ipv4,
-            src_ipv6,
-            src_port,
-            output_options,
-            ping_options,
-            ip_options,
+    async fn probe_once(
+        &self,
+        src: IpPort,
+        _host: &str,
+        dst_socket: SocketAddr,
+        ping_options: PingOptions,
+    ) -> ConnectRecord {
+        self.probers.probe(&src, dst_socket, ping_options).await
+    }
+
+    // Replies are matched to their probe, so rounds can overlap.
+    fn pipelined(&self) -> bool {
+        true
+    }
+
+    async fn finish(&self) -> Result<()> {
+        for (dst_socket, late) in self.probers.late_replies() {
+            let late_msg = format!("{dst_socket} sent {late} late replies, after their probe timed out.");
+            info_handler(&late_msg, self.logging_options);
         }
+        Ok(())
     }
+}
 
-    pub async fn connect(&self) -> Result<()> {
-        let src_ip_port = IpPort {
-            // These should never be None at this point as they are set in the UdpClient::new() constructor.
-            ipv4: self.src_ipv4.unwrap(),
-            ipv6: self.src_ipv6.unwrap(),
-            port: self.src_port,
-        };
+/// The sockets of a run, one for each source address and destination
+/// family. Sockets are bound on first use and shared by every probe in
+/// every cycle, rather than binding a socket for each probe.
+#[derive(Default)]
+struct UdpProbers {
+    probers: Mutex<HashMap<SocketAddr, Arc<UdpProber>>>,
+}
 
-        // Resolve the destination host to IPv4 and IPv6 addresses.
-        let host_records = HostRecord::new(&self.dst_ip, self.dst_port).await;
-        let hosts = vec![host_records.clone()];
-        let resolved_hosts = resolve_host(hosts).await;
-
-        // Check if the host resolved to an IPv4 or IPv6 addresses.
-        // If not, return an error.
-        for record in &resolved_hosts {
-            match record.ipv4_sockets.is_empty() && record.ipv6_sockets.is_empty() {
-                true => bail!("{} did not resolve to an IP address", record.host),
-                false => {
-                    let resolved_host_msg = resolved_ips_msg(record);
-                    println!("{resolved_host_msg}");
-                }
-            }
+impl UdpProbers {
+    /// Send a probe to a destination from the socket for its family.
+    async fn probe(&self, src: &IpPort, dst_socket: SocketAddr, ping_options: PingOptions) -> ConnectRecord {
+        let bind_addr = src.bind_addr(&dst_socket);
+        match self.prober(src, bind_addr, dst_socket) {
+            Ok(prober) => prober.probe(dst_socket, ping_options).await,
+            Err(e) => ConnectRecord {
+                result: bind_error_switch_handler(&e),
+                protocol: ConnectMethod::UDP,
+                source: bind_addr.to_string(),
+                destination: dst_socket.to_string(),
+                time: -1.0,
+                success: false,
+                error_msg: Some(e.to_string()),
+                ..Default::default()
+            },
         }
+    }
 
-        // Filter the resolved hosts based on the IP protocol.
-        let mut filtered_hosts = Vec::new();
-        for record in &resolved_hosts {
-            let mut record = record.clone();
-            match &self.ip_options.ip_protocol {
-                IpProtocol::All => {
-                    filtered_hosts.push(record);
-                }
-                IpProtocol::V4 => {
-                    record.ipv6_sockets.clear();
-                    filtered_hosts.push(record);
-                }
-                IpProtocol::V6 => {
-                    record.ipv4_sockets.clear();
-                    filtered_hosts.push(record);
-                }
-            }
+    /// Return the socket bound to a source, binding it on first use.
+    /// A source that could not be bound is retried by the next probe.
+    fn prober(&self, src: &IpPort, bind_addr: SocketAddr, dst_socket: SocketAddr) -> io::Result<Arc<UdpProber>> {
+        // This should never fail, the lock is never held across an await or a panic.
+        let mut probers = self.probers.lock().unwrap();
+        if let Some(prober) = probers.get(&bind_addr) {
+            return Ok(prober.clone());
         }
+        let prober = UdpProber::bind(bind_addr);
+        src.record_bind(&dst_socket, prober.is_ok());
+        let prober = Arc::new(prober?);
+        probers.insert(bind_addr, prober.clone());
+        Ok(prober)
+    }
 
-        let mut results_map = get_results_map(&filtered_hosts);
+    /// The number of replies from each destination received after their probe timed out.
+    fn late_replies(&self) -> Vec<(SocketAddr, u32)> {
+        // This should never fail, the lock is never held across an await or a panic.
+        let mut late_replies: Vec<(SocketAddr, u32)> = self
+            .probers
+            .lock()
+            .unwrap()
+            .values()
+            .flat_map(|prober| prober.late_replies())
+            .collect();
+        late_replies.sort();
+        late_replies
+    }
+}
 
-        let mut count: u16 = 0;
-        let mut send_count: u16 = 0;
+/// A reply to a probe and the timestamp it was received at, or
+/// the ICMP error the destination of the probe answered with.
+type Reply = Result<(Vec<u8>, u128), IcmpError>;
 
-        let ping_header = ping_header_msg(&self.dst_ip, self.dst_port, ConnectMethod::UDP);
-        println!("{ping_header}");
+/// A probe waiting for its reply.
+struct Outstanding {
+    uuid: String,
+    destination: SocketAddr,
+    reply: oneshot::Sender<Reply>,
+}
 
-        // This is a signal handler that listens for a Ctrl-C signal.
-        // When the signal is received, it sets the cancel flag to true.
-        // If the cancel flag is True we break the loop and exit the program.
-        let cancel = Arc::new(AtomicBool::new(false));
-        let c = cancel.clone();
-        tokio::spawn(async move {
-            // this will eventually move to a channel signalling mechanism.
-            signal::ctrl_c().await.unwrap();
-            // Your handler here
-            c.store(true, Ordering::SeqCst);
-        });
+/// A UDP socket shared by the probes to every destination of a family.
+/// A receive task matches replies to outstanding probes, so probes can
+/// overlap and replies that arrive after their probe timed out are
+/// counted as late instead of being taken for the reply to the next probe.
+/// ICMP errors, such as port unreachable, are read from the error queue
+/// of the socket where it has one (Linux), and fail the probe to the
+/// destination they were sent for.
+struct UdpProber {
+    socket: Arc<UdpSocket>,
+    sources: Mutex<HashMap<SocketAddr, String>>,
+    sequence: AtomicU32,
+    outstanding: Arc<Mutex<BTreeMap<u32, Outstanding>>>,
+    late: Arc<Mutex<BTreeMap<SocketAddr, u32>>>,
+    receiver: JoinHandle<()>,
+}
 
-        loop {
-            if cancel.load(Ordering::SeqCst) {
-                break;
+impl UdpProber {
+    /// Bind a socket and start receiving replies.
+    fn bind(bind_addr: SocketAddr) -> io::Result<UdpProber> {
+        let socket = std::net::UdpSocket::bind(bind_addr)?;
+        socket.set_nonblocking(true)?;
+        let socket = Arc::new(UdpSocket::from_std(socket)?);
+        let error_queue = ErrorQueue::new(socket.as_ref(), bind_addr.is_ipv6());
+        let outstanding = Arc::new(Mutex::new(BTreeMap::new()));
+        let late = Arc::new(Mutex::new(BTreeMap::new()));
+        let receiver = tokio::spawn(receive_replies(
+            socket.clone(),
+            error_queue,
+            outstanding.clone(),
+            late.clone(),
+        ));
+
+        Ok(UdpProber {
+            socket,
+            sources: Mutex::new(HashMap::new()),
+            sequence: AtomicU32::new(0),
+            outstanding,
+            late,
+            receiver,
+        })
+    }
+
+    /// Send a probe and wait for its reply.
+    async fn probe(&self, dst_socket: SocketAddr, ping_options: PingOptions) -> ConnectRecord {
+        let mut conn_record = ConnectRecord {
+            result: ConnectResult::Unknown,
+            protocol: ConnectMethod::UDP,
+            source: self.source(dst_socket),
+            destination: dst_socket.to_string(),
+            time: -1.0,
+            success: false,
+            error_msg: None,
+            ..Default::default()
+        };
+
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        let uuid = Uuid::new_v4().to_string();
+        let message = match ping_options.nk_peer {
+            false => format!("{PING_MSG} {sequence}"),
+            true => {
+                // These should not fail, the message only contains strings and numbers.
+                let nk_msg =
+                    NetKrakenMessage::new(&uuid, &conn_record.source, &conn_record.destination, ConnectMethod::UDP);
+                nk_msg.and_then(|m| m.to_json()).unwrap_or_default()
             }
-            match loop_handler(count, self.ping_options.repeat, self.ping_options.interval).await {
-                true => break,
-                false => count += 1,
+        };
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.outstanding().insert(
+            sequence,
+            Outstanding {
+                uuid: uuid.to_owned(),
+                destination: dst_socket,
+                reply: reply_tx,
+            },
+        );
+
+        // record timestamp before connection
+        let pre_conn_timestamp = time_now_us();
+
+        if let Err(e) = self.socket.send_to(message.as_bytes(), dst_socket).await {
+            self.outstanding().remove(&sequence);
+            conn_record.error_msg = Some(e.to_string());
+            conn_record.result = io_error_switch_handler(e);
+            return conn_record;
+        }
+
+        // Wait for a reply
+        let tick = Duration::from_millis(ping_options.timeout.into());
+        let reply = match timeout(tick, reply_rx).await {
+            Ok(Ok(reply)) => Ok(reply),
+            // The receive task stopped.
+            Ok(Err(e)) => Err(io::Error::other(e)),
+            Err(e) => {
+                // A reply that raced the timeout arrived too late.
+                if self.outstanding().remove(&sequence).is_none() {
+                    *self.late.lock().unwrap().entry(dst_socket).or_default() += 1;
+                }
+                Err(e.into())
             }
+        };
+
+        match reply {
+            Ok(Ok((data, post_conn_timestamp))) => {
+                // Calculate the round trip time
+                let connection_time = calc_connect_ms(pre_conn_timestamp, post_conn_timestamp);
+
+                conn_record.success = true;
+                conn_record.result = ConnectResult::Pong;
+                conn_record.time = connection_time;
+
+                if ping_options.nk_peer && !data.is_empty() {
+                    let data_string = &String::from_utf8_lossy(&data);
 
-            let host_results: Vec<HostResults> = futures::stream::iter(resolved_hosts.clone())
-                .map(|host_record| {
-                    let src_ip_port = src_ip_port.clone();
-                    async move {
-                        //
-                        process_host(src_ip_port, host_record, self.ping_options, self.ip_options).await
+                    // Handle a reply from a NetKraken peer. Replies
+                    // from a plain echo server are ignored.
+                    if let Some(m) = nk_msg_reader(data_string) {
+                        if m.nk_peer && m.uuid == uuid {
+                            conn_record.peer = Some(PeerRecord::new(m, post_conn_timestamp, connection_time));
+                        }
                     }
-                })
-                .buffer_unordered(BUFFER_SIZE)
-                .collect()
-                .await;
-
-            for host in host_results {
-                for result in host.results {
-                    results_map
-                        // This should never fail
-                        .get_mut(&host.host)
-                        .unwrap()
-                        // This should never fail
-                        .get_mut(&result.destination)
-                        .unwrap()
-                        .push(result.time);
-
-                    let success_msg = client_result_msg(&result);
-                    log_handler2(&result, &success_msg, &self.output_options).await;
                 }
             }
-            send_count += 1;
-        }
-
-        let mut client_results: Vec<ClientResult> = Vec::new();
-        for (_, addrs) in results_map {
-            for (addr, latencies) in addrs {
-                let client_summary = ClientSummary { send_count, latencies };
-                let client_summary = client_summary_result(&addr, ConnectMethod::UDP, client_summary);
-                client_results.push(client_summary)
+            Ok(Err(icmp_error)) => {
+                conn_record.error_msg = Some(icmp_error.to_string());
+                conn_record.result = icmp_error.connect_result();
+            }
+            Err(e) => {
+                conn_record.error_msg = Some(e.to_string());
+                conn_record.result = io_error_switch_handler(e);
             }
         }
-        client_results.sort_by_key(|x| x.destination.to_owned());
 
-        let summary_table = client_summary_table_msg(&self.dst_ip, self.dst_port, ConnectMethod::UDP, &client_results);
-        println!("{}", summary_table);
+        conn_record
+    }
+
+    /// The source address of probes to a destination. The shared socket
+    /// is not connected, the address is confirmed by the route to the
+    /// destination the first time it is probed.
+    fn source(&self, dst_socket: SocketAddr) -> String {
+        // This should never fail, the lock is never held across an await or a panic.
+        let mut sources = self.sources.lock().unwrap();
+        sources
+            .entry(dst_socket)
+            .or_insert_with(|| {
+                // This should never fail, the socket is bound.
+                let local_addr = self.socket.local_addr().unwrap();
+                let route = std::net::UdpSocket::bind(SocketAddr::new(local_addr.ip(), 0))
+                    .and_then(|socket| socket.connect(dst_socket).and_then(|_| socket.local_addr()));
+                match route {
+                    Ok(route) => SocketAddr::new(route.ip(), local_addr.port()).to_string(),
+                    Err(_) => local_addr.to_string(),
+                }
+            })
+            .to_owned()
+    }
 
-        Ok(())
+    /// The number of replies from each destination received after their probe timed out.
+    fn late_replies(&self) -> Vec<(SocketAddr, u32)> {
+        // This should never fail, the lock is never held across an await or a panic.
+        self.late
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(dst, late)| (*dst, *late))
+            .collect()
+    }
+
+    fn outstanding(&self) -> std::sync::MutexGuard<'_, BTreeMap<u32, Outstanding>> {
+        // This should never fail, the lock is never held across an await or a panic.
+        self.outstanding.lock().unwrap()
+    }
+}
+
+impl Drop for UdpProber {
+    fn drop(&mut self) {
+        self.receiver.abort();
     }
 }
 
-async fn process_host(
-    src_ip_port: IpPort,
-    host_record: HostRecord,
-    ping_options: PingOptions,
-    ip_options: IpOptions,
-) -> HostResults {
-    // Create a vector of sockets based on the IP protocol.
-    let sockets = match ip_options.ip_protocol {
-        IpProtocol::All => [host_record.ipv4_sockets, host_record.ipv6_sockets].concat(),
-        IpProtocol::V4 => host_record.ipv4_sockets,
-        IpProtocol::V6 => host_record.ipv6_sockets,
-    };
-
-    let results: Vec<ConnectRecord> = futures::stream::iter(sockets)
-        .map(|dst_socket| {
-            let src_ip_port = src_ip_port.clone();
-            async move {
-                //
-                connect_host(src_ip_port, dst_socket, ping_options).await
+/// Receive replies and hand each one to the probe it answers.
+async fn receive_replies(
+    socket: Arc<UdpSocket>,
+    error_queue: Option<ErrorQueue>,
+    outstanding: Arc<Mutex<BTreeMap<u32, Outstanding>>>,
+    late: Arc<Mutex<BTreeMap<SocketAddr, u32>>>,
+) {
+    let mut buffer = vec![0u8; MAX_PACKET_SIZE];
+    let mut backoff: u64 = 1;
+    loop {
+        let received = tokio::select! {
+            // ICMP errors, such as port unreachable, are queued with the
+            // destination of the datagram they were sent for, and fail the
+            // probe to it. The socket is not readable until a reply arrives.
+            _ = socket.ready(Interest::ERROR), if error_queue.is_some() => {
+                // Error readiness is cleared once the error queue is drained.
+                let _ = socket.try_io(Interest::ERROR, || {
+                    let take_icmp_error = || error_queue.as_ref().and_then(|q| q.take_icmp_error_to());
+                    while let Some((icmp_error, destination)) = take_icmp_error() {
+                        if let Some(probe) = destination.and_then(|d| take_probe_to(&outstanding, d)) {
+                            let _ = probe.reply.send(Err(icmp_error));
+                        }
+                    }
+                    Err::<(), _>(io::ErrorKind::WouldBlock.into())
+                });
+                continue;
             }
-        })
-        .buffer_unordered(BUFFER_SIZE)
-        .collect()
-        .await;
+            received = socket.recv_from(&mut buffer) => received,
+        };
+        let (len, from) = match received {
+            Ok(received) => {
+                backoff = 1;
+                received
+            }
+            // ICMP errors are also reported once by the next receive on
+            // some platforms, which cannot match them to a probe.
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionRefused | io::ErrorKind::Interrupted
+                ) =>
+            {
+                continue
+            }
+            // Other errors can repeat on every receive, back off
+            // rather than spinning until the socket recovers.
+            Err(_) => {
+                sleep(Duration::from_millis(backoff)).await;
+                backoff = (backoff * 2).min(UDP_RECV_BACKOFF.into());
+                continue;
+            }
+        };
+        let timestamp = time_now_us();
+        let data = buffer[..len].to_vec();
 
-    HostResults {
-        host: host_record.host,
-        results,
+        let mut outstanding = outstanding.lock().unwrap();
+        match match_reply(&outstanding, from, &data).and_then(|sequence| outstanding.remove(&sequence)) {
+            Some(probe) => {
+                let _ = probe.reply.send(Ok((data, timestamp)));
+            }
+            None => *late.lock().unwrap().entry(from).or_default() += 1,
+        }
     }
 }
 
-async fn connect_host(src: IpPort, dst_socket: SocketAddr, ping_options: PingOptions) -> ConnectRecord {
-    let bind_addr = match &dst_socket.is_ipv4() {
-        // Bind the source socket to the same IP Version as the destination socket.
-        true => SocketAddr::new(src.ipv4, src.port),
-        false => SocketAddr::new(src.ipv6, src.port),
-    };
+/// Find the outstanding probe to the sender of a reply that it answers.
+/// NetKraken peers reply with the UUID of the probe and echo servers with
+/// its message and sequence number, which only answer the probe they name.
+/// Other replies are taken for the reply to the oldest outstanding probe
+/// to the sender.
+fn match_reply(outstanding: &BTreeMap<u32, Outstanding>, from: SocketAddr, data: &[u8]) -> Option<u32> {
+    let mut probes = outstanding
+        .iter()
+        .filter(|(_, probe)| same_socket(probe.destination, from));
+    let data = String::from_utf8_lossy(data);
+    if let Some(m) = nk_msg_reader(&data) {
+        return probes
+            .find(|(_, probe)| probe.uuid == m.uuid)
+            .map(|(sequence, _)| *sequence);
+    }
+    match data.strip_prefix(PING_MSG) {
+        Some(sequence) => {
+            let sequence = sequence.trim().parse::<u32>().ok()?;
+            probes.find(|(s, _)| **s == sequence).map(|(sequence, _)| *sequence)
+        }
+        None => probes.next().map(|(sequence, _)| *sequence),
+    }
+}
 
-    let src_socket = UdpSocket::bind(bind_addr).await.ok();
+/// Remove the oldest outstanding probe to a destination.
+fn take_probe_to(outstanding: &Mutex<BTreeMap<u32, Outstanding>>, destination: SocketAddr) -> Option<Outstanding> {
+    // This should never fail, the lock is never held across an await or a panic.
+    let mut outstanding = outstanding.lock().unwrap();
+    let sequence = outstanding
+        .iter()
+        .find(|(_, probe)| same_socket(probe.destination, destination))
+        .map(|(sequence, _)| *sequence)?;
+    outstanding.remove(&sequence)
+}
 
-    // If the source socket is None, we could not bind to the socket.
-    if src_socket.is_none() {
-        return ConnectRecord {
-            result: ConnectResult::BindError,
-            protocol: ConnectMethod::UDP,
-            source: bind_addr.to_string(),
-            destination: dst_socket.to_string(),
-            time: -1.0,
-            success: false,
-            error_msg: None,
+/// Return whether two addresses are the same socket, ignoring IPv6 flow and scope IDs.
+fn same_socket(a: SocketAddr, b: SocketAddr) -> bool {
+    a.ip() == b.ip() && a.port() == b.port()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::common::{IpProtocol, SourceFallback};
+
+    fn outstanding(probes: &[(u32, &str, &str)]) -> BTreeMap<u32, Outstanding> {
+        probes
+            .iter()
+            .map(|(sequence, uuid, destination)| {
+                let probe = Outstanding {
+                    uuid: uuid.to_string(),
+                    destination: destination.parse().unwrap(),
+                    reply: oneshot::channel().0,
+                };
+                (*sequence, probe)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn replies_from_nk_peers_are_matched_by_uuid() {
+        let outstanding = outstanding(&[(1, "a", "192.0.2.1:53"), (2, "b", "192.0.2.1:53")]);
+        let from = "192.0.2.1:53".parse().unwrap();
+        let reply = |uuid: &str| {
+            NetKrakenMessage::new(&uuid.to_owned(), &"".to_owned(), &"".to_owned(), ConnectMethod::UDP)
+                .and_then(|m| m.to_json())
+                .unwrap()
         };
+
+        assert_eq!(match_reply(&outstanding, from, reply("b").as_bytes()), Some(2));
+        assert_eq!(match_reply(&outstanding, from, reply("c").as_bytes()), None);
     }
-    // Unwrap the socket because we have already checked that it is not None.
-    let src_socket = src_socket.unwrap();
-
-    let reader = Arc::new(src_socket);
-    let writer = reader.clone();
-
-    // TODO: this should never fail, validate this assumption.
-    let local_addr = &writer.local_addr().unwrap().to_string();
-
-    let mut conn_record = ConnectRecord {
-        result: ConnectResult::Unknown,
-        protocol: ConnectMethod::UDP,
-        source: local_addr.to_owned(),
-        destination: dst_socket.to_string(),
-        time: -1.0,
-        success: false,
-        error_msg: None,
-    };
-
-    // record timestamp before connection
-    let pre_conn_timestamp = time_now_us();
-
-    // TODO: need to investigate if this can error
-    let _ = writer.connect(dst_socket).await;
-
-    match ping_options.nk_peer {
-        false => {
-            // TODO: need to investigate if this can error
-            // This should not error if connect was successful.
-            let _ = writer.send(PING_MSG.as_bytes()).await;
-        }
-        true => {
-            // let mut nk_msg = NetKrakenMessage::new(
-            //     &uuid.to_string(),
-            //     &writer.local_addr()?.to_string(),
-            //     &peer_addr.to_string(),
-            //     ConnectMethod::UDP,
-            // )?;
-            // nk_msg.uuid = uuid.to_string();
-
-            // let payload = serde_json::to_string(&nk_msg)?;
-
-            // writer.send(payload.as_bytes()).await?;
-        }
+
+    #[test]
+    fn echoed_replies_are_matched_by_sequence() {
+        let outstanding = outstanding(&[(1, "a", "192.0.2.1:53"), (2, "b", "192.0.2.1:53")]);
+        let from = "192.0.2.1:53".parse().unwrap();
+
+        assert_eq!(
+            match_reply(&outstanding, from, format!("{PING_MSG} 2").as_bytes()),
+            Some(2)
+        );
+        // Echoes of probes that are no longer outstanding do not answer another probe.
+        assert_eq!(
+            match_reply(&outstanding, from, format!("{PING_MSG} 7").as_bytes()),
+            None
+        );
+        assert_eq!(match_reply(&outstanding, from, PING_MSG.as_bytes()), None);
     }
 
-    // Wait for a reply
-    let tick = Duration::from_millis(ping_options.timeout.into());
-    let mut buffer = vec![0u8; MAX_PACKET_SIZE];
+    #[test]
+    fn other_replies_answer_the_oldest_probe_to_the_sender() {
+        let outstanding = outstanding(&[
+            (1, "a", "192.0.2.2:53"),
+            (2, "b", "192.0.2.1:53"),
+            (3, "c", "192.0.2.1:53"),
+        ]);
+
+        assert_eq!(
+            match_reply(&outstanding, "192.0.2.1:53".parse().unwrap(), b"pong"),
+            Some(2)
+        );
+        assert_eq!(
+            match_reply(&outstanding, "192.0.2.3:53".parse().unwrap(), b"pong"),
+            None
+        );
+    }
 
-    match timeout(tick, reader.recv_from(&mut buffer)).await {
-        Ok(result) => {
-            if let Ok((len, _addr)) = result {
-                // received_count += 1;
+    #[tokio::test]
+    async fn replies_to_expired_probes_are_late() {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let from = server.local_addr().unwrap();
+        let outstanding = Arc::new(Mutex::new(outstanding(&[(2, "b", &from.to_string())])));
+        let late = Arc::new(Mutex::new(BTreeMap::new()));
+        let receiver = tokio::spawn(receive_replies(socket.clone(), None, outstanding.clone(), late.clone()));
+
+        // The probe with sequence 1 timed out and is no longer outstanding.
+        let reply = format!("{PING_MSG} 1");
+        server
+            .send_to(reply.as_bytes(), socket.local_addr().unwrap())
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        receiver.abort();
+
+        assert_eq!(late.lock().unwrap().get(&from), Some(&1));
+        assert!(outstanding.lock().unwrap().contains_key(&2));
+    }
 
-                // Record timestamp after connection
-                let post_conn_timestamp = time_now_us();
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn probes_to_closed_ports_are_refused() {
+        let closed = UdpSocket::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let src = IpPort {
+            ipv4: "0.0.0.0".parse().unwrap(),
+            ipv6: "::".parse().unwrap(),
+            ipv6_scope_id: 0,
+            port: 0,
+            fallback: SourceFallback::new(false),
+        };
+        let ping_options = PingOptions {
+            timeout: 5000,
+            ..Default::default()
+        };
 
-                // Calculate the round trip time
-                let connection_time = calc_connect_ms(pre_conn_timestamp, post_conn_timestamp);
+        let record = UdpProbers::default().probe(&src, closed, ping_options).await;
+        assert_eq!(record.result, ConnectResult::Refused);
+        assert!(!record.success);
+    }
 
-                conn_record.success = true;
-                conn_record.result = ConnectResult::Pong;
-                conn_record.time = connection_time;
-                // latencies.push(connection_time);
+    #[tokio::test]
+    async fn ipv6_destinations_are_probed_from_an_ipv6_source() {
+        let server = UdpSocket::bind("[::1]:0").await.unwrap();
+        let port = server.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buffer = [0u8; MAX_PACKET_SIZE];
+            while let Ok((len, src)) = server.recv_from(&mut buffer).await {
+                server.send_to(&buffer[..len], src).await.unwrap();
+            }
+        });
 
-                if ping_options.nk_peer && len > 0 {
-                    // let data_string = &String::from_utf8_lossy(&buffer[..len]);
+        let client = |ip_protocol| {
+            UdpClient::new(
+                vec![("::1".to_owned(), port)],
+                None,
+                None,
+                None,
+                LoggingOptions {
+                    quiet: true,
+                    ..Default::default()
+                },
+                PingOptions {
+                    repeat: 1,
+                    ..Default::default()
+                },
+                IpOptions {
+                    ip_protocol,
+                    ..Default::default()
+                },
+                DnsOptions::default(),
+            )
+        };
 
-                    // // Handle connection to a NetKraken peer
-                    // if let Some(mut m) = nk_msg_reader(data_string) {
-                    //     m.round_trip_time_utc = time_now_utc();
-                    //     m.round_trip_timestamp = time_now_us();
-                    //     m.round_trip_time_ms = connection_time;
+        for ip_protocol in [IpProtocol::V6, IpProtocol::All] {
+            let results = client(ip_protocol).connect().await.unwrap();
+            assert_eq!(results.len(), 1);
+            assert_eq!(results[0].destination, format!("[::1]:{port}"));
+            assert_eq!(results[0].received, 1);
+        }
 
-                    //     // TODO: Do something with nk message
-                    //     // println!("{:#?}", m);
-                    // }
-                }
+        // IPv6 destinations are left out when probing over IPv4.
+        let results = client(IpProtocol::V4).connect().await.unwrap();
+        assert!(results.iter().all(|result| result.sent == 0));
+    }
+
+    #[tokio::test]
+    async fn records_are_passed_to_the_callback() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = server.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buffer = [0u8; MAX_PACKET_SIZE];
+            while let Ok((len, src)) = server.recv_from(&mut buffer).await {
+                server.send_to(&buffer[..len], src).await.unwrap();
             }
-        }
-        Err(e) => {
-            let error_msg = e.to_string();
-            conn_record.result = io_error_switch_handler(e.into());
-            conn_record.error_msg = Some(error_msg);
-        }
+        });
+
+        let client = UdpClient::new(
+            vec![("127.0.0.1".to_owned(), port)],
+            None,
+            None,
+            None,
+            LoggingOptions {
+                quiet: true,
+                ..Default::default()
+            },
+            PingOptions {
+                repeat: 3,
+                interval: 0,
+                ..Default::default()
+            },
+            IpOptions::default(),
+            DnsOptions::default(),
+        );
+        let mut successes = 0;
+        let results = client
+            .run_with(|record| {
+                successes += u16::from(record.success);
+                async {}
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(successes, 3);
+        assert_eq!(results[0].received, 3);
     }
 
-    conn_record
+    #[tokio::test]
+    async fn one_socket_is_shared_across_cycles_and_closed_at_the_end() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = server.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buffer = [0u8; MAX_PACKET_SIZE];
+            while let Ok((len, src)) = server.recv_from(&mut buffer).await {
+                server.send_to(&buffer[..len], src).await.unwrap();
+            }
+        });
+
+        let client = UdpClient::new(
+            vec![("127.0.0.1".to_owned(), port)],
+            None,
+            None,
+            None,
+            LoggingOptions {
+                quiet: true,
+                ..Default::default()
+            },
+            PingOptions {
+                repeat: 4,
+                interval: 0,
+                ..Default::default()
+            },
+            IpOptions::default(),
+            DnsOptions::default(),
+        );
+        let mut records = client.runner.run_handle.subscribe();
+        let results = client.connect().await.unwrap();
+        client.runner.run_handle.end();
+
+        let mut sources = Vec::new();
+        while let Some(record) = records.recv().await {
+            sources.push(record.source.parse::<SocketAddr>().unwrap());
+        }
+        assert_eq!(results[0].received, 4);
+        assert_eq!(sources.len(), 4);
+        assert!(sources.iter().all(|source| *source == sources[0]));
+
+        // The socket and its receive task are dropped with the client,
+        // so the source port can be bound again.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(UdpSocket::bind(sources[0]).await.is_ok());
+    }
 }
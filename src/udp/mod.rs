@@ -1,2 +1,3 @@
 pub mod client;
+#[cfg(feature = "server")]
 pub mod server;
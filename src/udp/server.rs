@@ -2,13 +2,18 @@ use std::{net::SocketAddr, sync::Arc};
 
 use anyhow::Result;
 use tokio::net::UdpSocket;
+use tokio::signal::ctrl_c;
 use tokio::sync::mpsc;
+use tokio::time::{sleep, Duration};
 
 use crate::core::common::{ConnectMethod, ConnectResult, ListenOptions, LogLevel, LoggingOptions};
 use crate::core::konst::{BIND_ADDR_IPV4, BIND_PORT, MAX_PACKET_SIZE};
-use crate::util::handler::log_handler;
-use crate::util::message::{server_conn_success_msg, server_start_msg};
-use crate::util::parser::{nk_msg_reader, parse_ipaddr};
+use crate::util::csv::CsvWriter;
+use crate::util::fault::{choose_fault, corrupt, fault_rng, Fault};
+use crate::util::handler::{info_handler, log_handler, summary_handler};
+use crate::util::message::{server_conn_success_msg, server_sources_table_msg, server_start_msg};
+use crate::util::parser::{nk_msg_reader, parse_scoped_ipaddr, scoped_socket_addr};
+use crate::util::result::{server_record, SourceAccumulator};
 use crate::util::time::{calc_connect_ms, time_now_us, time_now_utc};
 
 pub struct UdpServer {
@@ -20,9 +25,9 @@ pub struct UdpServer {
 
 impl UdpServer {
     pub async fn listen(&self) -> Result<()> {
-        let listen_ip = parse_ipaddr(&self.listen_ip)?;
+        let (listen_ip, scope_id) = parse_scoped_ipaddr(&self.listen_ip)?;
 
-        let bind_addr = format!("{}:{}", listen_ip, self.listen_port);
+        let bind_addr = scoped_socket_addr(listen_ip, scope_id, self.listen_port);
         let socket = UdpSocket::bind(&bind_addr).await?;
 
         let reader = Arc::new(socket);
@@ -30,7 +35,7 @@ impl UdpServer {
         let (tx_chan, mut rx_chan) = mpsc::channel::<(Vec<u8>, SocketAddr)>(1);
 
         let start_msg = server_start_msg(ConnectMethod::UDP, &listen_ip, &self.listen_port);
-        println!("{}", start_msg);
+        info_handler(&start_msg, &self.logging_options);
 
         tokio::spawn(async move {
             while let Some((bytes, addr)) = rx_chan.recv().await {
@@ -39,9 +44,25 @@ impl UdpServer {
             Ok::<(), anyhow::Error>(())
         });
 
+        let mut csv_writer = match &self.logging_options.csv_file {
+            Some(csv_file) => Some(CsvWriter::append(csv_file)?),
+            None => None,
+        };
+        let mut source_accumulator = SourceAccumulator::default();
+        let mut fault_rng = fault_rng(&self.listen_options);
+
         loop {
             let mut buffer = vec![0u8; MAX_PACKET_SIZE];
-            let (len, addr) = match reader.recv_from(&mut buffer).await {
+            let received = tokio::select! {
+                received = reader.recv_from(&mut buffer) => received,
+                // Summarise the requests from each source when the server is stopped.
+                _ = ctrl_c() => {
+                    let sources_table = server_sources_table_msg(ConnectMethod::UDP, &source_accumulator.summaries());
+                    summary_handler(&sources_table, &self.logging_options);
+                    return Ok(());
+                }
+            };
+            let (len, addr) = match received {
                 Ok((len, addr)) => (len, addr),
                 Err(e) => {
                     // Received some kind of connection error
@@ -61,10 +82,8 @@ impl UdpServer {
             // Add echo handler
             let mut client_server_time = 0.0;
 
-            match self.listen_options.nk_peer && len > 0 {
-                false => {
-                    tx_chan.send((buffer.clone(), addr)).await?;
-                }
+            let mut response = match self.listen_options.nk_peer && len > 0 {
+                false => buffer.clone(),
                 true => {
                     let data_string = &String::from_utf8_lossy(&buffer);
 
@@ -77,11 +96,30 @@ impl UdpServer {
                             m.receive_timestamp = receive_time_stamp;
                             m.one_way_time_ms = connection_time;
                             m.nk_peer = true;
+                            m.reply_timestamp = time_now_us();
 
                             let json_message = serde_json::to_string(&m)?;
-                            tx_chan.send((json_message.as_bytes().to_vec(), addr)).await?;
+                            json_message.into_bytes()
                         }
-                        None => tx_chan.send((buffer.clone(), addr)).await?,
+                        None => buffer.clone(),
+                    }
+                }
+            };
+
+            let fault = choose_fault(&self.listen_options, &mut fault_rng);
+            if fault == Some(Fault::Corrupt) {
+                corrupt(&mut response, &mut fault_rng);
+            }
+            if fault != Some(Fault::Drop) {
+                match self.listen_options.delay {
+                    0 => tx_chan.send((response, addr)).await?,
+                    // Delay responses without holding up the receive loop.
+                    delay => {
+                        let tx_chan = tx_chan.clone();
+                        tokio::spawn(async move {
+                            sleep(Duration::from_millis(delay.into())).await;
+                            tx_chan.send((response, addr)).await
+                        });
                     }
                 }
             }
@@ -93,7 +131,24 @@ impl UdpServer {
                 local_addr,
                 client_server_time,
             );
+            let msg = match fault {
+                Some(fault) => format!("{msg} fault={fault}"),
+                None => msg,
+            };
             log_handler(LogLevel::INFO, &msg, &self.logging_options).await;
+
+            let record = server_record(
+                ConnectMethod::UDP,
+                peer_addr,
+                local_addr,
+                client_server_time,
+                false,
+                &self.logging_options,
+            );
+            source_accumulator.push(&record, len);
+            if let Some(csv_writer) = csv_writer.as_mut() {
+                csv_writer.write(&record)?;
+            }
         }
     }
 }
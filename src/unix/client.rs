@@ -0,0 +1,347 @@
+use anyhow::Result;
+use tokio::net::{UnixDatagram, UnixStream};
+use tokio::time::{timeout, Duration};
+use uuid::Uuid;
+
+use crate::core::common::{
+    ClientResult, ClientSummary, ConnectMethod, ConnectRecord, ConnectResult, LoggingOptions, PingOptions, ProbeSample,
+};
+use crate::core::konst::{MAX_PACKET_SIZE, PING_MSG, RUN_RECORDS, RUN_WINDOW};
+use crate::util::advice::AdviceAccumulator;
+use crate::util::csv::CsvWriter;
+use crate::util::handler::{
+    advice_handler, console_handler, info_handler, io_error_switch_handler, loop_handler, resource_warning_handler,
+    summary_handler, summary_log_handler, Cancel, StatsListener,
+};
+use crate::util::message::{interval_summary_table_msg, unix_header_msg, unix_summary_table_msg};
+use crate::util::record::RecordSampler;
+use crate::util::result::{assert_results, client_summary_result, label_results, sort_results, IntervalAccumulator};
+use crate::util::run::RunHandle;
+use crate::util::sink::RecordSinks;
+use crate::util::time::{calc_connect_ms, time_now_us};
+
+/// UNIX domain socket client. Stream sockets measure the connect
+/// time, datagram sockets measure the echo time of a payload.
+/// Stream sockets are not sent a payload, the daemons behind them
+/// (docker, haproxy) do not echo, so the headers and summaries
+/// name the time measured.
+#[derive(Debug)]
+pub struct UnixClient {
+    pub path: String,
+    pub protocol: ConnectMethod,
+    pub logging_options: LoggingOptions,
+    pub ping_options: PingOptions,
+    /// Queries the statistics of the run while it is in progress.
+    pub run_handle: RunHandle,
+}
+
+impl UnixClient {
+    pub fn new(
+        path: String,
+        protocol: ConnectMethod,
+        logging_options: LoggingOptions,
+        ping_options: PingOptions,
+    ) -> UnixClient {
+        UnixClient {
+            path,
+            protocol,
+            logging_options,
+            ping_options,
+            run_handle: RunHandle::new(RUN_WINDOW, RUN_RECORDS),
+        }
+    }
+
+    pub async fn connect(&self) -> Result<Vec<ClientResult>> {
+        let mut samples: Vec<ProbeSample> = Vec::new();
+
+        let mut count: u16 = 0;
+        let mut send_count: u16 = 0;
+        let mut record_sink = RecordSinks::create(&self.logging_options)?;
+        let mut record_sampler = RecordSampler::new(self.logging_options.sample);
+        let mut csv_writer = match &self.logging_options.csv_file {
+            Some(csv_file) => Some(CsvWriter::append(csv_file)?),
+            None => None,
+        };
+        let mut interval_accumulator =
+            IntervalAccumulator::new(self.logging_options.summary_interval, self.logging_options.watch);
+        let mut advice_accumulator = AdviceAccumulator::default();
+        let labels = self.logging_options.path_labels(&self.path);
+
+        let ping_header = unix_header_msg(&self.path, self.protocol);
+        info_handler(&ping_header, &self.logging_options);
+
+        // Ctrl-C cancels the client, interrupting the requests in flight,
+        // and the results collected so far are summarised.
+        let cancel = Cancel::on_ctrl_c();
+        // SIGQUIT prints the statistics of the run so far.
+        let _stats_listener = StatsListener::spawn(self.run_handle.clone());
+        let throttle = self.run_handle.start_run();
+
+        loop {
+            // The interval between requests is interrupted when cancelled.
+            let next = loop_handler(count, self.ping_options.repeat, self.ping_options.interval);
+            match cancel.run(next).await {
+                Some(false) => count += 1,
+                _ => break,
+            }
+
+            // The request in flight is dropped when cancelled.
+            let request = throttle.run(async {
+                match self.protocol {
+                    ConnectMethod::UNIXGRAM => connect_datagram(&self.path, self.ping_options).await,
+                    _ => connect_stream(&self.path, self.ping_options).await,
+                }
+            });
+            let Some(mut result) = cancel.run(request).await else {
+                break;
+            };
+            result.tags = self.logging_options.tags.clone();
+            result.label = labels.get(&result.destination).cloned();
+            samples.push(ProbeSample::new(&result));
+            interval_accumulator.push(&result);
+            advice_accumulator.push(&result);
+            self.run_handle.push(&result);
+
+            if record_sampler.sample() {
+                console_handler(&result, &self.logging_options).await;
+                if let Some(record_sink) = record_sink.as_mut() {
+                    record_sink.push(result.clone())?;
+                }
+                if let Some(csv_writer) = csv_writer.as_mut() {
+                    csv_writer.write(&result)?;
+                }
+            }
+
+            if let Some(mut interval_results) = interval_accumulator.take_due() {
+                label_results(&mut interval_results, &labels);
+                let interval_table = interval_summary_table_msg(
+                    self.logging_options.summary_interval,
+                    self.logging_options.watch,
+                    &interval_results,
+                    &self.logging_options.tags,
+                );
+                summary_handler(&interval_table, &self.logging_options);
+            }
+
+            send_count += 1;
+        }
+
+        let client_summary = ClientSummary { send_count, samples };
+        let mut client_results = vec![client_summary_result(&self.path, self.protocol, client_summary)];
+        sort_results(&mut client_results, &self.logging_options);
+        assert_results(&mut client_results, &self.logging_options);
+        label_results(&mut client_results, &labels);
+
+        let summary_table = unix_summary_table_msg(
+            &self.path,
+            self.protocol,
+            &client_results,
+            &self.logging_options.tags,
+            self.logging_options.histogram,
+        );
+        summary_handler(&summary_table, &self.logging_options);
+        resource_warning_handler(&self.run_handle, &self.logging_options);
+        advice_handler(&advice_accumulator, &self.logging_options);
+        summary_log_handler(&client_results, &self.logging_options);
+        if let Some(csv_writer) = csv_writer.as_mut() {
+            csv_writer.write_summary(&client_results)?;
+        }
+
+        if let Some(record_sink) = record_sink.as_mut() {
+            record_sink.flush()?;
+        }
+
+        Ok(client_results)
+    }
+}
+
+async fn connect_stream(path: &str, ping_options: PingOptions) -> ConnectRecord {
+    let mut conn_record = ConnectRecord {
+        result: ConnectResult::Unknown,
+        protocol: ConnectMethod::UNIX,
+        source: "unnamed".to_owned(),
+        destination: path.to_owned(),
+        time: -1.0,
+        success: false,
+        error_msg: None,
+        ..Default::default()
+    };
+
+    // record timestamp before connection
+    let pre_conn_timestamp = time_now_us();
+
+    let tick = Duration::from_millis(ping_options.timeout.into());
+    match timeout(tick, UnixStream::connect(path)).await {
+        Ok(Ok(_stream)) => {
+            let post_conn_timestamp = time_now_us();
+            conn_record.success = true;
+            conn_record.result = ConnectResult::Pong;
+            conn_record.time = calc_connect_ms(pre_conn_timestamp, post_conn_timestamp);
+        }
+        // Connection error
+        Ok(Err(e)) => {
+            conn_record.error_msg = Some(e.to_string());
+            conn_record.result = io_error_switch_handler(e);
+        }
+        // Timeout error
+        Err(e) => {
+            conn_record.error_msg = Some(e.to_string());
+            conn_record.result = io_error_switch_handler(e.into());
+        }
+    }
+    conn_record
+}
+
+async fn connect_datagram(path: &str, ping_options: PingOptions) -> ConnectRecord {
+    // A datagram socket must be bound to a path to receive a reply.
+    let bind_path = std::env::temp_dir().join(format!("nk-{}.sock", Uuid::new_v4()));
+
+    let mut conn_record = ConnectRecord {
+        result: ConnectResult::Unknown,
+        protocol: ConnectMethod::UNIXGRAM,
+        source: bind_path.display().to_string(),
+        destination: path.to_owned(),
+        time: -1.0,
+        success: false,
+        error_msg: None,
+        ..Default::default()
+    };
+
+    let socket = match UnixDatagram::bind(&bind_path) {
+        Ok(s) => s,
+        Err(e) => {
+            conn_record.result = ConnectResult::BindError;
+            conn_record.error_msg = Some(e.to_string());
+            return conn_record;
+        }
+    };
+
+    // record timestamp before connection
+    let pre_conn_timestamp = time_now_us();
+
+    let tick = Duration::from_millis(ping_options.timeout.into());
+    let mut buffer = vec![0u8; MAX_PACKET_SIZE];
+
+    let exchange = async {
+        socket.connect(path)?;
+        socket.send(PING_MSG.as_bytes()).await?;
+        socket.recv(&mut buffer).await
+    };
+
+    match timeout(tick, exchange).await {
+        Ok(Ok(_len)) => {
+            let post_conn_timestamp = time_now_us();
+            conn_record.success = true;
+            conn_record.result = ConnectResult::Pong;
+            conn_record.time = calc_connect_ms(pre_conn_timestamp, post_conn_timestamp);
+        }
+        // Connection error
+        Ok(Err(e)) => {
+            conn_record.error_msg = Some(e.to_string());
+            conn_record.result = io_error_switch_handler(e);
+        }
+        // Timeout error
+        Err(e) => {
+            conn_record.error_msg = Some(e.to_string());
+            conn_record.result = io_error_switch_handler(e.into());
+        }
+    }
+
+    // The socket file is in the temp directory, if it cannot
+    // be removed it will be cleaned up with the rest.
+    let _ = std::fs::remove_file(&bind_path);
+
+    conn_record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use tokio::net::UnixListener;
+
+    fn socket_path() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("nk-test-{}.sock", Uuid::new_v4()))
+    }
+
+    #[tokio::test]
+    async fn stream_probes_time_the_connect() {
+        let path = socket_path();
+        let listener = UnixListener::bind(&path).unwrap();
+        tokio::spawn(async move {
+            // Accepts without replying, as a daemon waiting for a request does.
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let _stream = stream;
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                });
+            }
+        });
+
+        let record = connect_stream(path.to_str().unwrap(), PingOptions::default()).await;
+        assert!(record.success);
+        assert_eq!(record.result, ConnectResult::Pong);
+        assert_eq!(record.protocol, ConnectMethod::UNIX);
+        assert!(record.time >= 0.0);
+
+        let _ = std::fs::remove_file(&path);
+        let record = connect_stream(path.to_str().unwrap(), PingOptions::default()).await;
+        assert!(!record.success);
+    }
+
+    #[tokio::test]
+    async fn datagram_probes_time_the_echo() {
+        let path = socket_path();
+        let server = UnixDatagram::bind(&path).unwrap();
+        tokio::spawn(async move {
+            let mut buffer = vec![0u8; MAX_PACKET_SIZE];
+            while let Ok((len, peer)) = server.recv_from(&mut buffer).await {
+                if let Some(peer) = peer.as_pathname() {
+                    let _ = server.send_to(&buffer[..len], peer).await;
+                }
+            }
+        });
+
+        let record = connect_datagram(path.to_str().unwrap(), PingOptions::default()).await;
+        assert!(record.success);
+        assert_eq!(record.protocol, ConnectMethod::UNIXGRAM);
+        assert!(!std::path::Path::new(&record.source).exists());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn labelled_paths_are_labelled() {
+        // A socket file nothing listens on refuses connections.
+        let path = socket_path();
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        let destination = path.to_str().unwrap().to_owned();
+
+        let client = UnixClient::new(
+            destination.to_owned(),
+            ConnectMethod::UNIX,
+            LoggingOptions {
+                quiet: true,
+                labels: BTreeMap::from([(format!("{destination}:0"), "docker".to_owned())]),
+                ..Default::default()
+            },
+            PingOptions {
+                repeat: 2,
+                interval: 0,
+                ..Default::default()
+            },
+        );
+        let mut records = client.run_handle.subscribe();
+        let results = client.connect().await.unwrap();
+        client.run_handle.end();
+
+        while let Some(record) = records.recv().await {
+            assert_eq!(record.result, ConnectResult::Refused);
+            assert_eq!(record.label.as_deref(), Some("docker"));
+        }
+        assert_eq!(results[0].display_destination(), "docker");
+        assert_eq!((results[0].sent, results[0].received), (2, 0));
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
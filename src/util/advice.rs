@@ -0,0 +1,265 @@
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+
+use crate::core::common::{Advice, ConnectMethod, ConnectRecord, ConnectResult};
+
+/// The replies of an address family, and the first
+/// destination of the family a probe failed to.
+#[derive(Debug, Default)]
+struct FamilyCounts {
+    received: u32,
+    failed_to: Option<String>,
+}
+
+/// Collects the results of a run, to recommend the likely causes of
+/// its failures and the commands to narrow them down at the end of it.
+#[derive(Debug, Default)]
+pub struct AdviceAccumulator {
+    protocol: Option<ConnectMethod>,
+    received: u32,
+    failed_to: Option<String>,
+    ipv4: FamilyCounts,
+    ipv6: FamilyCounts,
+    failures: BTreeMap<String, u32>,
+    sources: BTreeMap<IpAddr, (u32, u32)>, // probes, failures
+    lossy: Option<String>,
+}
+
+impl AdviceAccumulator {
+    pub fn push(&mut self, record: &ConnectRecord) {
+        self.protocol = Some(record.protocol);
+        // DNS queries are sent to the resolver in the source of their
+        // records, the destination is the name queried.
+        let (peer, source) = match record.protocol {
+            ConnectMethod::DNS => (&record.source, None),
+            _ => (&record.destination, Some(&record.source)),
+        };
+        match record.success {
+            true => self.received += 1,
+            false => {
+                self.failed_to.get_or_insert_with(|| peer.to_owned());
+                *self.failures.entry(record.result.to_string()).or_default() += 1;
+            }
+        }
+        if !record.success && record.result == ConnectResult::Timeout {
+            self.lossy.get_or_insert_with(|| record.destination.to_owned());
+        }
+
+        // Address families and sources are only compared for IP peers,
+        // UNIX socket paths and heartbeats do not have one.
+        let Some(peer_ip) = record_ip(peer) else {
+            return;
+        };
+        let family = match peer_ip.is_ipv4() {
+            true => &mut self.ipv4,
+            false => &mut self.ipv6,
+        };
+        match record.success {
+            true => family.received += 1,
+            false => _ = family.failed_to.get_or_insert_with(|| peer.to_owned()),
+        }
+
+        // Sources are only compared when they are configured, the
+        // wildcard source does not tell which address was used.
+        if let Some(src_ip) = source.and_then(|s| record_ip(s)).filter(|ip| !ip.is_unspecified()) {
+            let (probes, failures) = self.sources.entry(src_ip).or_default();
+            *probes += 1;
+            *failures += u32::from(!record.success);
+        }
+    }
+
+    /// Return the likely causes of the failures of the run, most
+    /// specific first, with a command to narrow each one down.
+    pub fn recommendations(&self) -> Vec<Advice> {
+        let Some(protocol) = self.protocol else {
+            return vec![];
+        };
+        let failed: u32 = self.failures.values().sum();
+        if failed == 0 {
+            return vec![];
+        }
+        let received = self.received;
+        let failures = |result: ConnectResult| self.failures.get(&result.to_string()).copied().unwrap_or_default();
+        let failed_to = self.failed_to.as_ref();
+        let failed_ip = failed_to.and_then(|d| record_ip(d));
+
+        let mut advice = Vec::new();
+        if failures(ConnectResult::ResourceExhausted) > 0 {
+            advice.push(Advice {
+                cause: "The local host ran out of open files, socket buffers or ephemeral ports".to_owned(),
+                command: "ulimit -n 65535, or lower --v4-concurrency and --v6-concurrency".to_owned(),
+            });
+        }
+        if failures(ConnectResult::BindError) > 0 {
+            advice.push(Advice {
+                cause: "The source address could not be bound, it may not be configured on this host".to_owned(),
+                command: "ip addr".to_owned(),
+            });
+        }
+        for (failing, working, name, flag) in [
+            (&self.ipv6, &self.ipv4, "IPv6", "v6"),
+            (&self.ipv4, &self.ipv6, "IPv4", "v4"),
+        ] {
+            if let (0, true, Some(destination)) = (failing.received, working.received > 0, &failing.failed_to) {
+                advice.push(Advice {
+                    cause: format!(
+                        "{name} failed everywhere while the other family worked, {name} is likely unrouted or filtered"
+                    ),
+                    command: format!(
+                        "nk -m icmp --ip-proto {flag} {}",
+                        record_ip(destination).map(|ip| ip.to_string()).unwrap_or_default()
+                    ),
+                });
+            }
+        }
+        if failures(ConnectResult::Refused) == failed {
+            let (listener, command) = match protocol {
+                ConnectMethod::UNIX | ConnectMethod::UNIXGRAM => ("the socket", "ss -lx"),
+                _ => (
+                    "the port or a firewall rejects the connections",
+                    "ss -ltn (on the server)",
+                ),
+            };
+            advice.push(Advice {
+                cause: format!("Every failure was refused, nothing listens on {listener}"),
+                command: command.to_owned(),
+            });
+        }
+        if let (0, true, Some(ip)) = (received, failures(ConnectResult::Timeout) == failed, failed_ip) {
+            advice.push(Advice {
+                cause: "Nothing replied, the host is down or a firewall silently drops the probes".to_owned(),
+                command: format!("nk -m icmp {ip}"),
+            });
+        }
+        let unreachable = failures(ConnectResult::NetworkUnreachable) + failures(ConnectResult::HostUnreachable);
+        if let (true, Some(ip)) = (unreachable > 0, failed_ip) {
+            advice.push(Advice {
+                cause: "The network or host was unreachable, a route is missing or a router rejects the probes"
+                    .to_owned(),
+                command: format!("traceroute {ip}"),
+            });
+        }
+        if failures(ConnectResult::TlsError) + failures(ConnectResult::CertError) > 0 {
+            advice.push(Advice {
+                cause: "TLS handshakes failed, check the certificate and TLS versions of the server".to_owned(),
+                command: format!("openssl s_client -connect {}", failed_to.cloned().unwrap_or_default()),
+            });
+        }
+        if let (true, Some(destination)) = (received > 0, &self.lossy) {
+            advice.push(Advice {
+                cause: "Probes were lost intermittently, likely congestion or a lossy link on the path".to_owned(),
+                command: format!("nk -m {protocol} {destination} -r 100 --histogram"),
+            });
+        }
+        let failing_sources: Vec<&IpAddr> = self
+            .sources
+            .iter()
+            .filter(|(_, (_, f))| *f > 0)
+            .map(|(ip, _)| ip)
+            .collect();
+        if let (&[source], true, Some(destination)) = (&failing_sources[..], self.sources.len() > 1, failed_to) {
+            let flag = if source.is_ipv4() { "--src-v4" } else { "--src-v6" };
+            advice.push(Advice {
+                cause: format!("Probes only failed from source {source}, check its routes and firewall rules"),
+                command: format!("nk -m {protocol} {flag} {source} {destination}"),
+            });
+        }
+        advice
+    }
+}
+
+/// Return the IP address of a `ip:port` or `ip` address of a record.
+fn record_ip(addr: &str) -> Option<IpAddr> {
+    addr.parse::<SocketAddr>()
+        .map(|socket| socket.ip())
+        .or_else(|_| addr.parse::<IpAddr>())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(result: ConnectResult, source: &str, destination: &str) -> ConnectRecord {
+        ConnectRecord {
+            result,
+            protocol: ConnectMethod::TCP,
+            source: source.to_owned(),
+            destination: destination.to_owned(),
+            time: 1.0,
+            success: result == ConnectResult::Pong,
+            error_msg: None,
+            ..Default::default()
+        }
+    }
+
+    fn commands(records: &[ConnectRecord]) -> Vec<String> {
+        let mut accumulator = AdviceAccumulator::default();
+        for record in records {
+            accumulator.push(record);
+        }
+        accumulator.recommendations().into_iter().map(|a| a.command).collect()
+    }
+
+    #[test]
+    fn no_advice_without_failures() {
+        assert!(commands(&[record(ConnectResult::Pong, "0.0.0.0:0", "192.0.2.1:443")]).is_empty());
+    }
+
+    #[test]
+    fn ipv6_failing_everywhere_is_reported() {
+        let records = [
+            record(ConnectResult::Pong, "0.0.0.0:0", "192.0.2.1:443"),
+            record(ConnectResult::Timeout, "[::]:0", "[2001:db8::1]:443"),
+            record(ConnectResult::Timeout, "[::]:0", "[2001:db8::1]:443"),
+        ];
+        assert_eq!(commands(&records)[0], "nk -m icmp --ip-proto v6 2001:db8::1");
+    }
+
+    #[test]
+    fn refused_and_silent_failures_are_told_apart() {
+        let refused = [record(ConnectResult::Refused, "0.0.0.0:0", "192.0.2.1:443")];
+        assert_eq!(commands(&refused), ["ss -ltn (on the server)"]);
+
+        let silent = [record(ConnectResult::Timeout, "0.0.0.0:0", "192.0.2.1:443")];
+        assert_eq!(commands(&silent), ["nk -m icmp 192.0.2.1"]);
+    }
+
+    #[test]
+    fn loss_from_one_source_is_reported() {
+        let records = [
+            record(ConnectResult::Pong, "192.0.2.10:5000", "192.0.2.1:443"),
+            record(ConnectResult::Pong, "192.0.2.11:5000", "192.0.2.1:443"),
+            record(ConnectResult::Timeout, "192.0.2.11:5001", "192.0.2.1:443"),
+        ];
+        assert_eq!(
+            commands(&records),
+            [
+                "nk -m tcp 192.0.2.1:443 -r 100 --histogram",
+                "nk -m tcp --src-v4 192.0.2.11 192.0.2.1:443",
+            ]
+        );
+    }
+
+    #[test]
+    fn resolvers_and_unix_sockets_are_advised_on() {
+        let query = |result| ConnectRecord {
+            protocol: ConnectMethod::DNS,
+            ..record(result, "192.0.2.53:53", "example.com")
+        };
+        assert_eq!(
+            commands(&[query(ConnectResult::Timeout), query(ConnectResult::Timeout)]),
+            ["nk -m icmp 192.0.2.53"]
+        );
+        assert_eq!(
+            commands(&[query(ConnectResult::Pong), query(ConnectResult::Timeout)]),
+            ["nk -m dns example.com -r 100 --histogram"]
+        );
+
+        let refused = ConnectRecord {
+            protocol: ConnectMethod::UNIX,
+            ..record(ConnectResult::Refused, "", "/run/nk.sock")
+        };
+        assert_eq!(commands(&[refused]), ["ss -lx"]);
+    }
+}
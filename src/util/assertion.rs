@@ -0,0 +1,335 @@
+use std::fmt::Display;
+
+use anyhow::{bail, Result};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::core::common::ClientResult;
+
+/// Summary statistics that can be used in an assertion.
+const VARIABLES: [&str; 13] = [
+    "min", "max", "avg", "p50", "p90", "p95", "p99", "jitter", "stddev", "loss", "sent", "received", "lost",
+];
+
+/// A pass/fail expression evaluated against the summary of each destination.
+/// `p95 < 80 && loss < 0.5`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Assertion {
+    source: String,
+    expr: Expr,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Operand, Op, Operand),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Operand {
+    Number(f64),
+    Variable(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl Assertion {
+    /// Return whether a destination's summary passes the assertion.
+    pub fn evaluate(&self, result: &ClientResult) -> bool {
+        self.expr.evaluate(result)
+    }
+}
+
+impl Display for Assertion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl TryFrom<String> for Assertion {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Assertion> {
+        parse_assertion(&s)
+    }
+}
+
+impl From<Assertion> for String {
+    fn from(assertion: Assertion) -> String {
+        assertion.source
+    }
+}
+
+impl Expr {
+    fn evaluate(&self, result: &ClientResult) -> bool {
+        match self {
+            Expr::Or(a, b) => a.evaluate(result) || b.evaluate(result),
+            Expr::And(a, b) => a.evaluate(result) && b.evaluate(result),
+            Expr::Not(a) => !a.evaluate(result),
+            Expr::Compare(a, op, b) => {
+                let (a, b) = (a.value(result), b.value(result));
+                match op {
+                    Op::Lt => a < b,
+                    Op::Le => a <= b,
+                    Op::Gt => a > b,
+                    Op::Ge => a >= b,
+                    Op::Eq => a == b,
+                    Op::Ne => a != b,
+                }
+            }
+        }
+    }
+}
+
+impl Operand {
+    fn value(&self, result: &ClientResult) -> f64 {
+        match self {
+            Operand::Number(n) => *n,
+            Operand::Variable(name) => match name.as_str() {
+                "min" => result.min,
+                "max" => result.max,
+                "avg" => result.avg,
+                "p50" => result.p50,
+                "p90" => result.p90,
+                "p95" => result.p95,
+                "p99" => result.p99,
+                "jitter" => result.jitter,
+                "stddev" => result.std_dev,
+                "loss" => result.loss_percent,
+                "sent" => result.sent.into(),
+                "received" => result.received.into(),
+                "lost" => result.lost.into(),
+                // Variables are checked when the assertion is parsed.
+                _ => f64::NAN,
+            },
+        }
+    }
+}
+
+/// Parse an assertion such as `p95 < 80 && loss < 0.5`.
+///
+/// Comparisons (`< <= > >= == !=`) of summary statistics and numbers
+/// can be combined with `&&`, `||`, `!` and parentheses.
+/// Latencies are in milliseconds and `loss` is a percentage.
+pub fn parse_assertion(s: &str) -> Result<Assertion> {
+    let tokens = tokenize(s)?;
+    let mut parser = Parser { tokens, position: 0 };
+    let expr = parser.or()?;
+    if parser.position < parser.tokens.len() {
+        bail!(
+            "assertion: `{s}` is invalid, unexpected {:?}",
+            parser.tokens[parser.position]
+        );
+    }
+    Ok(Assertion {
+        source: s.trim().to_owned(),
+        expr,
+    })
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = s.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let (token, len) = match (c, next) {
+            (c, _) if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ('&', Some('&')) => (Token::And, 2),
+            ('|', Some('|')) => (Token::Or, 2),
+            ('<', Some('=')) => (Token::Op(Op::Le), 2),
+            ('>', Some('=')) => (Token::Op(Op::Ge), 2),
+            ('=', Some('=')) => (Token::Op(Op::Eq), 2),
+            ('!', Some('=')) => (Token::Op(Op::Ne), 2),
+            ('<', _) => (Token::Op(Op::Lt), 1),
+            ('>', _) => (Token::Op(Op::Gt), 1),
+            ('!', _) => (Token::Not, 1),
+            ('(', _) => (Token::Open, 1),
+            (')', _) => (Token::Close, 1),
+            (c, _) if c.is_ascii_digit() || c == '.' => {
+                let len = chars[i..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_digit() || **c == '.')
+                    .count();
+                let number: String = chars[i..i + len].iter().collect();
+                match number.parse::<f64>() {
+                    Ok(n) => (Token::Number(n), len),
+                    Err(_) => bail!("assertion: `{number}` is not a number"),
+                }
+            }
+            (c, _) if c.is_ascii_alphabetic() => {
+                let len = chars[i..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+                    .count();
+                let ident: String = chars[i..i + len].iter().collect();
+                if !VARIABLES.contains(&ident.as_str()) {
+                    bail!(
+                        "assertion: `{ident}` is unknown, expected one of {}",
+                        VARIABLES.join(", ")
+                    );
+                }
+                (Token::Ident(ident), len)
+            }
+            (c, _) => bail!("assertion: `{c}` is unexpected"),
+        };
+        tokens.push(token);
+        i += len;
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser, `||` binds loosest and `!` tightest.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.position += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut expr = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.position += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.position += 1;
+                Ok(Expr::Not(Box::new(self.unary()?)))
+            }
+            Some(Token::Open) => {
+                self.position += 1;
+                let expr = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => bail!("assertion: expected `)`"),
+                }
+            }
+            _ => self.compare(),
+        }
+    }
+
+    fn compare(&mut self) -> Result<Expr> {
+        let a = self.operand()?;
+        let op = match self.next() {
+            Some(Token::Op(op)) => op,
+            _ => bail!("assertion: expected a comparison"),
+        };
+        let b = self.operand()?;
+        Ok(Expr::Compare(a, op, b))
+    }
+
+    fn operand(&mut self) -> Result<Operand> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Operand::Number(n)),
+            Some(Token::Ident(name)) => Ok(Operand::Variable(name)),
+            _ => bail!("assertion: expected a number or statistic"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::common::{ConnectMethod, LossBursts};
+
+    fn client_result() -> ClientResult {
+        ClientResult {
+            destination: "198.51.100.1:443".to_owned(),
+            protocol: ConnectMethod::TCP,
+            sent: 100,
+            received: 99,
+            lost: 1,
+            loss_percent: 1.0,
+            min: 10.0,
+            max: 120.0,
+            avg: 25.0,
+            p50: 20.0,
+            p90: 70.0,
+            p95: 75.0,
+            p99: 110.0,
+            jitter: 4.5,
+            std_dev: 12.0,
+            histogram: vec![],
+            loss_bursts: LossBursts::default(),
+            first_failure: None,
+            last_failure: None,
+            phases: vec![],
+            passed: None,
+            label: None,
+        }
+    }
+
+    #[test]
+    fn assertions_are_evaluated() {
+        let evaluate = |s: &str| parse_assertion(s).unwrap().evaluate(&client_result());
+
+        assert!(evaluate("p95 < 80"));
+        assert!(!evaluate("p95 < 80 && loss < 0.5"));
+        assert!(evaluate("p95 < 80 && loss < 0.5 || max <= 120"));
+        assert!(!evaluate("p95 < 80 && (loss < 0.5 || max < 120)"));
+        assert!(evaluate("jitter < 5 && stddev < 15 && p90 <= 70"));
+        assert!(evaluate("!(lost > 1) && received == 99 && sent != 0"));
+        assert!(evaluate("50 >= avg"));
+    }
+
+    #[test]
+    fn invalid_assertions_are_rejected() {
+        assert!(parse_assertion("p96 < 80").is_err());
+        assert!(parse_assertion("p95 < ").is_err());
+        assert!(parse_assertion("p95 80").is_err());
+        assert!(parse_assertion("(p95 < 80").is_err());
+        assert!(parse_assertion("p95 < 80 loss").is_err());
+        assert!(parse_assertion("p95 < 8.0.0").is_err());
+        assert!(parse_assertion("p95 = 80").is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+
+use anyhow::Result;
+
+use crate::core::common::{ClientResult, ConnectRecord};
+use crate::util::record::csv_field;
+use crate::util::time::time_now_utc;
+
+/// Appends connection records to a CSV file as they are received,
+/// followed by a summary row for each destination.
+pub struct CsvWriter<W: Write> {
+    writer: W,
+}
+
+impl CsvWriter<File> {
+    /// Open a CSV file for appending, writing the header if the file is new or empty.
+    pub fn append(filename: &str) -> Result<CsvWriter<File>> {
+        let file = OpenOptions::new().create(true).append(true).open(filename)?;
+        let header = file.metadata()?.len() == 0;
+        CsvWriter::new(file, header)
+    }
+}
+
+impl<W: Write> CsvWriter<W> {
+    pub fn new(writer: W, header: bool) -> Result<CsvWriter<W>> {
+        let mut csv_writer = CsvWriter { writer };
+        if header {
+            csv_writer.write_row(&[
+                "timestamp",
+                "protocol",
+                "source",
+                "destination",
+                "result",
+                "time",
+                "error_msg",
+                "sent",
+                "received",
+                "loss_percent",
+                "min",
+                "max",
+                "passed",
+            ])?;
+        }
+        Ok(csv_writer)
+    }
+
+    pub fn write(&mut self, record: &ConnectRecord) -> Result<()> {
+        self.write_row(&[
+            &time_now_utc(),
+            &record.protocol.to_string(),
+            &record.source,
+            &record.series(),
+            &record.result.to_string(),
+            &record.time.to_string(),
+            record.error_msg.as_deref().unwrap_or_default(),
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+        ])
+    }
+
+    /// Write a summary row for each destination.
+    /// The time of a summary row is the average connection time.
+    pub fn write_summary(&mut self, client_results: &[ClientResult]) -> Result<()> {
+        let timestamp = time_now_utc();
+        for result in client_results {
+            self.write_row(&[
+                &timestamp,
+                &result.protocol.to_string(),
+                "",
+                &result.destination,
+                "summary",
+                &result.avg.to_string(),
+                "",
+                &result.sent.to_string(),
+                &result.received.to_string(),
+                &result.loss_percent.to_string(),
+                &result.min.to_string(),
+                &result.max.to_string(),
+                &result.passed.map(|p| p.to_string()).unwrap_or_default(),
+            ])?;
+        }
+        Ok(())
+    }
+
+    /// Rows are written whole so a long running session
+    /// can be read while it is still in progress.
+    fn write_row(&mut self, fields: &[&str]) -> Result<()> {
+        let row = fields
+            .iter()
+            .map(|field| csv_field(field))
+            .collect::<Vec<String>>()
+            .join(",");
+        self.writer.write_all(format!("{row}\n").as_bytes())?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::common::{ConnectMethod, ConnectResult, LossBursts};
+
+    #[test]
+    fn records_and_summary_are_written() {
+        let record = ConnectRecord {
+            result: ConnectResult::Refused,
+            protocol: ConnectMethod::TCP,
+            source: "127.0.0.1:50000".to_owned(),
+            destination: "127.0.0.1:443".to_owned(),
+            time: -1.0,
+            success: false,
+            error_msg: Some("Connection refused, os error 111".to_owned()),
+            ..Default::default()
+        };
+        let summary = ClientResult {
+            destination: "127.0.0.1:443".to_owned(),
+            protocol: ConnectMethod::TCP,
+            sent: 1,
+            received: 0,
+            lost: 1,
+            loss_percent: 100.0,
+            min: 0.0,
+            max: 0.0,
+            avg: 0.0,
+            p50: 0.0,
+            p90: 0.0,
+            p95: 0.0,
+            p99: 0.0,
+            jitter: 0.0,
+            std_dev: 0.0,
+            histogram: vec![],
+            loss_bursts: LossBursts::default(),
+            first_failure: None,
+            last_failure: None,
+            phases: vec![],
+            passed: None,
+            label: None,
+        };
+
+        let mut buffer = Vec::new();
+        let mut csv_writer = CsvWriter::new(&mut buffer, true).unwrap();
+        csv_writer.write(&record).unwrap();
+        csv_writer.write_summary(&[summary]).unwrap();
+        let csv = String::from_utf8(buffer).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("timestamp,protocol,source,destination,result,time,error_msg"));
+        assert!(lines[1]
+            .ends_with(",tcp,127.0.0.1:50000,127.0.0.1:443,refused,-1,\"Connection refused, os error 111\",,,,,,"));
+        assert!(lines[2].ends_with(",tcp,,127.0.0.1:443,summary,0,,1,0,100,0,0,"));
+    }
+
+    #[test]
+    fn header_is_not_written_when_appending() {
+        let mut buffer = Vec::new();
+        CsvWriter::new(&mut buffer, false).unwrap();
+
+        assert!(buffer.is_empty());
+    }
+}
@@ -1,14 +1,69 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+use anyhow::{bail, Result};
+use futures::future::BoxFuture;
 use futures::StreamExt;
+use hickory_proto::op::{Edns, Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_proto::rr::rdata::opt::{ClientSubnet, EdnsOption};
+use hickory_proto::rr::rdata::svcb::{SvcParamKey, SvcParamValue, SVCB};
+use hickory_proto::rr::{Name, RData, RecordType};
+use time::OffsetDateTime;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::{timeout, Duration};
+use tokio_rustls::rustls::pki_types::ServerName;
+use uuid::Uuid;
 
-use crate::core::common::HostRecord;
-use crate::core::konst::BUFFER_SIZE;
+use crate::core::common::{DnsOptions, DnsRecord, HostRecord, HttpUrl, ServiceTarget};
+use crate::core::konst::{
+    BIND_ADDR_IPV4, BIND_ADDR_IPV6, BUFFER_SIZE, DNS_CACHE_SIZE, DNS_NEGATIVE_TTL, DNS_TIMEOUT, HTTPS_PORT,
+    KUBERNETES_CLUSTER_DOMAIN, KUBERNETES_NAMESPACE, MAX_DNS_PACKET_SIZE, MDNS_ADDR, NAT64_DISCOVERY_HOST, RESOLV_CONF,
+};
+use crate::util::nat64::{synthesize_ip, Nat64Prefix};
+use crate::util::parser::{http_status_line, normalize_host, parse_resolver, parse_scoped_ipaddr, parse_url};
+use crate::util::tls::tls_connector;
 
-pub async fn resolve_host(hosts: Vec<HostRecord>) -> Vec<HostRecord> {
+/// Resolve hosts with the resolution provider chosen for each host,
+/// the first resolver in the DNS options, or the system resolver.
+/// IP addresses are not resolved.
+/// With NAT64 enabled, IPv6 addresses are synthesized for IPv4 only hosts.
+pub async fn resolve_host(hosts: Vec<HostRecord>, dns_options: &DnsOptions) -> Vec<HostRecord> {
+    let client_subnet = dns_options.client_subnet();
+    let nat64_prefix = match dns_options.nat64 {
+        true => discover_nat64(dns_options).await,
+        false => None,
+    };
     let lookup_data: Vec<HostRecord> = futures::stream::iter(hosts)
         .map(|host| {
             async move {
                 //
-                HostRecord::new(&host.host, host.port).await
+                let mut host_record = match parse_scoped_ipaddr(&host.host).is_ok() {
+                    true => HostRecord::new(&host.host, host.port).await,
+                    false => {
+                        // Providers are validated when the options are built.
+                        let provider = dns_options
+                            .provider(&host.host)
+                            .and_then(|spec| resolution_provider(spec, client_subnet).ok());
+                        match provider {
+                            Some(provider) => provider_host_record(&host.host, host.port, provider.as_ref()).await,
+                            None => HostRecord::new(&host.host, host.port).await,
+                        }
+                    }
+                };
+                if let Some(prefix) = nat64_prefix {
+                    if host_record.ipv6_sockets.is_empty() {
+                        host_record.ipv6_sockets = host_record
+                            .ipv4_sockets
+                            .iter()
+                            .map(|socket| SocketAddr::new(synthesize_ip(socket.ip(), prefix), socket.port()))
+                            .collect();
+                    }
+                }
+                host_record
             }
         })
         .buffer_unordered(BUFFER_SIZE)
@@ -17,3 +72,906 @@ pub async fn resolve_host(hosts: Vec<HostRecord>) -> Vec<HostRecord> {
 
     lookup_data
 }
+
+/// Discover the NAT64 prefix of the network from the DNS64 synthesized
+/// addresses of `ipv4only.arpa` (RFC7050), using the resolver for the host.
+/// Returns `None` when the resolver does not synthesize addresses.
+pub async fn discover_nat64(dns_options: &DnsOptions) -> Option<Nat64Prefix> {
+    let spec = dns_options.provider(NAT64_DISCOVERY_HOST).cloned();
+    if let Some(prefix) = nat64_cache().lock().unwrap().get(&spec) {
+        return *prefix;
+    }
+    let provider = match &spec {
+        Some(spec) => resolution_provider(spec, None).ok()?,
+        None => Box::new(SystemProvider),
+    };
+    let prefix = provider
+        .lookup(NAT64_DISCOVERY_HOST)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .find_map(|ip| match ip {
+            IpAddr::V6(ipv6) => Nat64Prefix::from_synthesized(ipv6),
+            IpAddr::V4(_) => None,
+        });
+    nat64_cache().lock().unwrap().insert(spec, prefix);
+    prefix
+}
+
+/// The NAT64 prefix discovered with each resolver.
+fn nat64_cache() -> &'static Mutex<HashMap<Option<String>, Option<Nat64Prefix>>> {
+    static NAT64_CACHE: OnceLock<Mutex<HashMap<Option<String>, Option<Nat64Prefix>>>> = OnceLock::new();
+    NAT64_CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Build a HostRecord from the addresses returned by a provider.
+/// A failed lookup returns a HostRecord without sockets.
+pub async fn provider_host_record(host: &str, port: u16, provider: &dyn ResolutionProvider) -> HostRecord {
+    let mut ipv4_sockets = vec![];
+    let mut ipv6_sockets = vec![];
+    for ip in provider.lookup(host).await.unwrap_or_default() {
+        match ip {
+            IpAddr::V4(_) => ipv4_sockets.push(SocketAddr::new(ip, port)),
+            IpAddr::V6(_) => ipv6_sockets.push(SocketAddr::new(ip, port)),
+        }
+    }
+    HostRecord {
+        host: host.to_owned(),
+        port,
+        ipv4_sockets,
+        ipv6_sockets,
+    }
+}
+
+/// A source of name resolution.
+pub trait ResolutionProvider: Send + Sync {
+    /// Return the IPv4 and IPv6 addresses of a host.
+    fn lookup<'a>(&'a self, host: &'a str) -> BoxFuture<'a, Result<Vec<IpAddr>>>;
+}
+
+/// Build a resolution provider from its specification.
+///
+/// - `system` the operating system resolver
+/// - `IP` or `IP:port` a DNS resolver
+/// - `https://URL` a DNS over HTTPS (RFC8484) resolver
+/// - `mdns` multicast DNS (RFC6762) on the local network
+/// - `hosts:FILE` a static hosts file
+/// - `kubernetes` or `kubernetes:NAMESPACE` Kubernetes service names
+pub fn resolution_provider(spec: &str, client_subnet: Option<ClientSubnet>) -> Result<Box<dyn ResolutionProvider>> {
+    let provider: Box<dyn ResolutionProvider> = match spec.split_once(':') {
+        _ if spec == "system" => Box::new(SystemProvider),
+        _ if spec == "mdns" => Box::new(MdnsProvider),
+        _ if spec == "kubernetes" => Box::new(KubernetesProvider {
+            namespace: KUBERNETES_NAMESPACE.to_owned(),
+        }),
+        Some(("kubernetes", namespace)) if !namespace.is_empty() => Box::new(KubernetesProvider {
+            namespace: namespace.to_owned(),
+        }),
+        Some(("hosts", filename)) => Box::new(StaticHostsProvider::load(filename)?),
+        Some(("https", _)) => Box::new(DohProvider {
+            url: parse_url(spec)?,
+            client_subnet,
+        }),
+        _ => match parse_resolver(spec) {
+            Ok(resolver) => Box::new(DnsProvider {
+                resolver,
+                client_subnet,
+            }),
+            Err(_) => bail!(
+                "resolver: `{spec}` is invalid, expected system, IP[:port], https://URL, mdns, hosts:FILE or kubernetes[:NAMESPACE]"
+            ),
+        },
+    };
+    Ok(provider)
+}
+
+/// Validate a resolution provider specification.
+pub fn parse_provider(spec: &str) -> Result<String> {
+    resolution_provider(spec, None)?;
+    Ok(spec.to_owned())
+}
+
+/// The operating system resolver.
+pub struct SystemProvider;
+
+impl ResolutionProvider for SystemProvider {
+    fn lookup<'a>(&'a self, host: &'a str) -> BoxFuture<'a, Result<Vec<IpAddr>>> {
+        Box::pin(async move {
+            let sockets = tokio::net::lookup_host((host, 0)).await?;
+            Ok(sockets.map(|socket| socket.ip()).collect())
+        })
+    }
+}
+
+/// A DNS resolver queried over UDP. Responses are cached.
+pub struct DnsProvider {
+    pub resolver: SocketAddr,
+    pub client_subnet: Option<ClientSubnet>,
+}
+
+impl ResolutionProvider for DnsProvider {
+    fn lookup<'a>(&'a self, host: &'a str) -> BoxFuture<'a, Result<Vec<IpAddr>>> {
+        Box::pin(resolver_lookup(host, self.resolver, self.client_subnet))
+    }
+}
+
+/// A DNS over HTTPS (RFC8484) resolver. Queries are sent
+/// with the POST method on a new connection for each query.
+pub struct DohProvider {
+    pub url: HttpUrl,
+    pub client_subnet: Option<ClientSubnet>,
+}
+
+impl ResolutionProvider for DohProvider {
+    fn lookup<'a>(&'a self, host: &'a str) -> BoxFuture<'a, Result<Vec<IpAddr>>> {
+        Box::pin(async move {
+            let name = fqdn(host)?;
+            let mut ips = Vec::new();
+            for record_type in [RecordType::A, RecordType::AAAA] {
+                let (_, request) = query_message(&name, record_type, self.client_subnet);
+                let tick = Duration::from_millis(DNS_TIMEOUT.into());
+                let response = match timeout(tick, doh_query(&self.url, &request.to_vec()?)).await {
+                    Ok(response) => response?,
+                    Err(_) => bail!("DNS query to {} timed out", self.url),
+                };
+                ips.extend(answer_ips(&response));
+            }
+            Ok(ips)
+        })
+    }
+}
+
+/// Multicast DNS (RFC6762) one-shot queries on the local network.
+pub struct MdnsProvider;
+
+impl ResolutionProvider for MdnsProvider {
+    fn lookup<'a>(&'a self, host: &'a str) -> BoxFuture<'a, Result<Vec<IpAddr>>> {
+        Box::pin(async move {
+            let name = fqdn(host)?;
+            let mut ips = Vec::new();
+            for record_type in [RecordType::A, RecordType::AAAA] {
+                ips.extend(answer_ips(&mdns_query(&name, record_type).await?));
+            }
+            Ok(ips)
+        })
+    }
+}
+
+/// Hosts from a file in the `/etc/hosts` format.
+/// Names are matched without regard to case.
+pub struct StaticHostsProvider {
+    pub hosts: HashMap<String, Vec<IpAddr>>,
+}
+
+impl StaticHostsProvider {
+    pub fn load(filename: &str) -> Result<StaticHostsProvider> {
+        match std::fs::read_to_string(filename) {
+            Ok(contents) => Ok(StaticHostsProvider {
+                hosts: parse_hosts_file(&contents),
+            }),
+            Err(e) => bail!("hosts file: `{filename}` could not be read: {e}"),
+        }
+    }
+}
+
+impl ResolutionProvider for StaticHostsProvider {
+    fn lookup<'a>(&'a self, host: &'a str) -> BoxFuture<'a, Result<Vec<IpAddr>>> {
+        Box::pin(async move {
+            //
+            Ok(self.hosts.get(&normalize_host(host)).cloned().unwrap_or_default())
+        })
+    }
+}
+
+/// Kubernetes service names (`service` or `service.namespace`),
+/// expanded to the cluster domain and resolved with the system
+/// resolver, which is the cluster DNS service inside a pod.
+pub struct KubernetesProvider {
+    pub namespace: String,
+}
+
+impl ResolutionProvider for KubernetesProvider {
+    fn lookup<'a>(&'a self, host: &'a str) -> BoxFuture<'a, Result<Vec<IpAddr>>> {
+        Box::pin(async move {
+            let name = kubernetes_name(host, &self.namespace);
+            match system_resolver() {
+                Some(resolver) => resolver_lookup(&name, resolver, None).await,
+                None => SystemProvider.lookup(&name).await,
+            }
+        })
+    }
+}
+
+/// Expand a Kubernetes service name to its cluster domain name.
+/// Names already in the cluster domain are not changed.
+pub fn kubernetes_name(host: &str, namespace: &str) -> String {
+    let host = host.trim_end_matches('.');
+    let cluster_domain = format!(".svc.{KUBERNETES_CLUSTER_DOMAIN}");
+    if host.ends_with(&cluster_domain) {
+        return host.to_owned();
+    }
+    match host.split('.').count() {
+        1 => format!("{host}.{namespace}{cluster_domain}"),
+        2 => format!("{host}{cluster_domain}"),
+        _ => match host.ends_with(".svc") {
+            true => format!("{host}.{KUBERNETES_CLUSTER_DOMAIN}"),
+            false => host.to_owned(),
+        },
+    }
+}
+
+/// Parse a hosts file into the addresses of each name.
+/// Comments start with `#`, each line is an IP followed by its names.
+fn parse_hosts_file(contents: &str) -> HashMap<String, Vec<IpAddr>> {
+    let mut hosts: HashMap<String, Vec<IpAddr>> = HashMap::new();
+    for line in contents.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut fields = line.split_whitespace();
+        let ip = match fields.next().and_then(|ip| ip.parse::<IpAddr>().ok()) {
+            Some(ip) => ip,
+            None => continue,
+        };
+        for name in fields {
+            let ips = hosts.entry(normalize_host(name)).or_default();
+            if !ips.contains(&ip) {
+                ips.push(ip);
+            }
+        }
+    }
+    hosts
+}
+
+/// Query a resolver for the A and AAAA records of a host.
+/// The client subnet is sent as an EDNS Client Subnet option (RFC7871).
+pub async fn resolver_lookup(
+    host: &str,
+    resolver: SocketAddr,
+    client_subnet: Option<ClientSubnet>,
+) -> Result<Vec<IpAddr>> {
+    let name = fqdn(host)?;
+    let mut ips = Vec::new();
+    for record_type in [RecordType::A, RecordType::AAAA] {
+        let response = query(&name, record_type, resolver, client_subnet).await?;
+        ips.extend(answer_ips(&response));
+    }
+    Ok(ips)
+}
+
+/// Query a resolver for records of a host, bypassing the cache.
+/// Returns the answers of the queried types, the lowest TTL of the
+/// responses and the first response code that is not NOERROR.
+pub async fn resolver_answers(
+    host: &str,
+    record_types: &[RecordType],
+    resolver: SocketAddr,
+    client_subnet: Option<ClientSubnet>,
+) -> Result<(Vec<String>, u32, ResponseCode)> {
+    let name = fqdn(host)?;
+    let mut answers = Vec::new();
+    let mut ttl = u32::MAX;
+    let mut response_code = ResponseCode::NoError;
+    for record_type in record_types {
+        let response = exchange_query(&name, *record_type, resolver, client_subnet).await?;
+        answers.extend(typed_answers(&response, *record_type));
+        ttl = ttl.min(response_ttl(&response));
+        if response_code == ResponseCode::NoError {
+            response_code = response.response_code();
+        }
+    }
+    Ok((answers, ttl, response_code))
+}
+
+/// The mnemonic of a response code (`NOERROR`, `NXDOMAIN`, ...).
+pub fn response_code_name(code: ResponseCode) -> String {
+    match code {
+        ResponseCode::Unknown(value) => format!("RCODE{value}"),
+        code => format!("{code:?}").to_uppercase(),
+    }
+}
+
+/// Parse a DNS record type (`A`, `AAAA`, `MX`, ...).
+pub fn parse_record_type(s: &str) -> Result<RecordType> {
+    match RecordType::from_str(&s.to_uppercase()) {
+        Ok(RecordType::Unknown(_)) | Err(_) => bail!("record type: `{s}` is not a known DNS record type"),
+        Ok(record_type) => Ok(record_type),
+    }
+}
+
+/// Tracks the answers of a name across resolution probes.
+///
+/// With a failover time, old answers may be served until the TTL of
+/// the last answer seen before the failover expires. The first change
+/// after the failover is its propagation delay.
+pub struct AnswerMonitor {
+    failover_time: Option<OffsetDateTime>,
+    answers: Option<Vec<String>>,
+    expires: Option<OffsetDateTime>,
+    propagated: bool,
+}
+
+impl AnswerMonitor {
+    pub fn new(failover_time: Option<OffsetDateTime>) -> AnswerMonitor {
+        AnswerMonitor {
+            failover_time,
+            answers: None,
+            expires: None,
+            propagated: false,
+        }
+    }
+
+    /// Record the answers of a probe made at `now`.
+    pub fn observe(&mut self, mut answers: Vec<String>, ttl: u32, now: OffsetDateTime) -> DnsRecord {
+        answers.sort();
+        let changed = self.answers.as_ref().is_some_and(|previous| *previous != answers);
+        let mut record = DnsRecord {
+            answers: answers.clone(),
+            ttl,
+            changed,
+            ttl_exceeded: false,
+            propagation_delay: None,
+            ..Default::default()
+        };
+        let expires = now + time::Duration::seconds(ttl.into());
+        match self.failover_time {
+            Some(failover_time) if now >= failover_time && !self.propagated => {
+                let deadline = *self.expires.get_or_insert(expires);
+                record.ttl_exceeded = now > deadline;
+                if changed {
+                    self.propagated = true;
+                    record.propagation_delay = Some((now - failover_time).as_seconds_f64());
+                }
+            }
+            Some(failover_time) if now < failover_time => self.expires = Some(expires),
+            _ => {}
+        }
+        self.answers = Some(answers);
+        record
+    }
+}
+
+fn fqdn(host: &str) -> Result<Name> {
+    let mut name = Name::from_ascii(host)?;
+    name.set_fqdn(true);
+    Ok(name)
+}
+
+/// Return the addresses in the A and AAAA answers of a response.
+/// The answers of a record type in a response, skipping aliases.
+fn typed_answers(response: &Message, record_type: RecordType) -> Vec<String> {
+    response
+        .answers()
+        .iter()
+        .filter(|record| record.record_type() == record_type)
+        .filter_map(|record| record.data().map(|data| data.to_string()))
+        .collect()
+}
+
+fn answer_ips(response: &Message) -> Vec<IpAddr> {
+    response
+        .answers()
+        .iter()
+        .filter_map(|record| match record.data() {
+            Some(RData::A(a)) => Some(IpAddr::V4(a.0)),
+            Some(RData::AAAA(aaaa)) => Some(IpAddr::V6(aaaa.0)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns true when a host is a SRV service name (`_service._proto.name`).
+pub fn is_srv_name(host: &str) -> bool {
+    let mut labels = host.split('.');
+    matches!(
+        (labels.next(), labels.next(), labels.next()),
+        (Some(service), Some(proto), Some(_)) if service.len() > 1 && service.starts_with('_')
+            && proto.len() > 1 && proto.starts_with('_')
+    )
+}
+
+/// Expand a service into targets using its SRV records, or its
+/// HTTPS/SVCB records when `svcb` is set. Targets are ordered by
+/// priority, then by weight with the heaviest target first.
+pub async fn expand_service(host: &str, port: u16, dns_options: &DnsOptions, svcb: bool) -> Result<Vec<ServiceTarget>> {
+    let resolver = match dns_options.resolver_addrs().into_iter().next().or_else(system_resolver) {
+        Some(resolver) => resolver,
+        None => bail!("Service expansion requires a DNS resolver."),
+    };
+    let record_type = match (svcb, host.starts_with('_')) {
+        (false, _) => RecordType::SRV,
+        (true, true) => RecordType::SVCB,
+        (true, false) => RecordType::HTTPS,
+    };
+    let mut name = Name::from_ascii(host)?;
+    name.set_fqdn(true);
+
+    let response = query(&name, record_type, resolver, dns_options.client_subnet()).await?;
+    let mut targets: Vec<ServiceTarget> = response
+        .answers()
+        .iter()
+        .filter_map(|record| match record.data() {
+            Some(RData::SRV(srv)) => Some(ServiceTarget {
+                service: host.to_owned(),
+                host: target_host(srv.target()),
+                port: srv.port(),
+                priority: srv.priority(),
+                weight: srv.weight(),
+            }),
+            Some(RData::SVCB(svcb)) => Some(svcb_target(svcb, host, port)),
+            Some(RData::HTTPS(https)) => Some(svcb_target(&https.0, host, port)),
+            _ => None,
+        })
+        // A SRV target of `.` means the service is not available.
+        .filter(|target| !target.host.is_empty())
+        .collect();
+    if targets.is_empty() {
+        bail!("{host} has no {record_type} records");
+    }
+    sort_service_targets(&mut targets);
+    Ok(targets)
+}
+
+fn svcb_target(svcb: &SVCB, host: &str, port: u16) -> ServiceTarget {
+    let svc_port = svcb.svc_params().iter().find_map(|(key, value)| match (key, value) {
+        (SvcParamKey::Port, SvcParamValue::Port(port)) => Some(*port),
+        _ => None,
+    });
+    ServiceTarget {
+        service: host.to_owned(),
+        // A SVCB target of `.` is the owner name.
+        host: match svcb.target_name().is_root() {
+            true => host.trim_end_matches('.').to_owned(),
+            false => target_host(svcb.target_name()),
+        },
+        port: svc_port.unwrap_or(if port != 0 { port } else { HTTPS_PORT }),
+        priority: svcb.svc_priority(),
+        weight: 0,
+    }
+}
+
+fn target_host(target: &Name) -> String {
+    target.to_ascii().trim_end_matches('.').to_owned()
+}
+
+/// Order targets by priority, then by weight with the heaviest target first.
+pub fn sort_service_targets(targets: &mut [ServiceTarget]) {
+    targets.sort_by(|a, b| a.priority.cmp(&b.priority).then(b.weight.cmp(&a.weight)));
+}
+
+/// Return the first nameserver of the system resolver configuration.
+pub fn system_resolver() -> Option<SocketAddr> {
+    std::fs::read_to_string(RESOLV_CONF)
+        .ok()
+        .and_then(|contents| resolv_conf_nameserver(&contents))
+}
+
+fn resolv_conf_nameserver(contents: &str) -> Option<SocketAddr> {
+    contents
+        .lines()
+        .find_map(|line| match line.split_whitespace().collect::<Vec<&str>>()[..] {
+            ["nameserver", ip, ..] => parse_resolver(ip).ok(),
+            _ => None,
+        })
+}
+
+type CacheKey = (Name, RecordType, SocketAddr, Option<ClientSubnet>);
+
+/// A TTL aware cache of resolver responses.
+/// Responses without answers are cached for the negative TTL
+/// of the zone (RFC2308). When the cache is full, expired entries
+/// are removed first, then the entry closest to expiring.
+pub struct DnsCache {
+    capacity: usize,
+    entries: HashMap<CacheKey, (Message, Instant)>,
+}
+
+impl DnsCache {
+    pub fn new(capacity: usize) -> DnsCache {
+        DnsCache {
+            capacity,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, key: &CacheKey, now: Instant) -> Option<Message> {
+        match self.entries.get(key) {
+            Some((message, expires)) if *expires > now => Some(message.clone()),
+            Some(_) => {
+                self.entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&mut self, key: CacheKey, message: Message, now: Instant) {
+        let ttl = response_ttl(&message);
+        if ttl == 0 || self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            self.entries.retain(|_, (_, expires)| *expires > now);
+        }
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, expires))| *expires)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        let expires = now + Duration::from_secs(ttl.into());
+        self.entries.insert(key, (message, expires));
+    }
+}
+
+/// Return how long a response can be cached for in seconds.
+fn response_ttl(message: &Message) -> u32 {
+    match message.answers().iter().map(|r| r.ttl()).min() {
+        Some(ttl) => ttl,
+        None => message
+            .name_servers()
+            .iter()
+            .find_map(|r| match r.data() {
+                Some(RData::SOA(soa)) => Some(r.ttl().min(soa.minimum())),
+                _ => None,
+            })
+            .unwrap_or(DNS_NEGATIVE_TTL),
+    }
+}
+
+/// The resolver response cache shared by all lookups.
+fn dns_cache() -> &'static Mutex<DnsCache> {
+    static DNS_CACHE: OnceLock<Mutex<DnsCache>> = OnceLock::new();
+    DNS_CACHE.get_or_init(|| Mutex::new(DnsCache::new(DNS_CACHE_SIZE)))
+}
+
+/// Query a resolver, answering from the cache when possible.
+/// Failed queries are not cached.
+async fn query(
+    name: &Name,
+    record_type: RecordType,
+    resolver: SocketAddr,
+    client_subnet: Option<ClientSubnet>,
+) -> Result<Message> {
+    let key = (name.clone(), record_type, resolver, client_subnet);
+    if let Some(message) = dns_cache().lock().unwrap().get(&key, Instant::now()) {
+        return Ok(message);
+    }
+    let message = send_query(name, record_type, resolver, client_subnet).await?;
+    dns_cache().lock().unwrap().insert(key, message.clone(), Instant::now());
+    Ok(message)
+}
+
+/// Send a single recursive query to a resolver over UDP.
+/// A name that does not exist returns a response without answers.
+async fn send_query(
+    name: &Name,
+    record_type: RecordType,
+    resolver: SocketAddr,
+    client_subnet: Option<ClientSubnet>,
+) -> Result<Message> {
+    let response = exchange_query(name, record_type, resolver, client_subnet).await?;
+    match response.response_code() {
+        ResponseCode::NoError | ResponseCode::NXDomain => Ok(response),
+        code => bail!("DNS query to {resolver} failed: {code}"),
+    }
+}
+
+/// Send a query to a resolver and return its response, whatever the response code.
+async fn exchange_query(
+    name: &Name,
+    record_type: RecordType,
+    resolver: SocketAddr,
+    client_subnet: Option<ClientSubnet>,
+) -> Result<Message> {
+    let (id, request) = query_message(name, record_type, client_subnet);
+
+    let bind_addr = match resolver.is_ipv4() {
+        true => BIND_ADDR_IPV4,
+        false => BIND_ADDR_IPV6,
+    };
+    let socket = UdpSocket::bind((bind_addr, 0)).await?;
+    socket.connect(resolver).await?;
+    socket.send(&request.to_vec()?).await?;
+
+    let mut buffer = vec![0u8; MAX_DNS_PACKET_SIZE];
+    let response = loop {
+        let len = match timeout(Duration::from_millis(DNS_TIMEOUT.into()), socket.recv(&mut buffer)).await {
+            Ok(len) => len?,
+            Err(_) => bail!("DNS query to {resolver} timed out"),
+        };
+        let response = Message::from_vec(&buffer[..len])?;
+        // Ignore stray responses to other queries.
+        if response.id() == id {
+            break response;
+        }
+    };
+    Ok(response)
+}
+
+/// Build a recursive query, returning its ID and the query.
+fn query_message(name: &Name, record_type: RecordType, client_subnet: Option<ClientSubnet>) -> (u16, Message) {
+    let id = Uuid::new_v4().as_u128() as u16;
+    let mut request = Message::new();
+    request
+        .set_id(id)
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query)
+        .set_recursion_desired(true)
+        .add_query(Query::query(name.clone(), record_type));
+    if let Some(client_subnet) = client_subnet {
+        let mut edns = Edns::new();
+        edns.set_max_payload(MAX_DNS_PACKET_SIZE as u16);
+        edns.options_mut().insert(EdnsOption::Subnet(client_subnet));
+        request.set_edns(edns);
+    }
+    (id, request)
+}
+
+/// POST a query to a DNS over HTTPS resolver.
+/// HTTP/1.0 is used so the response is not chunked.
+async fn doh_query(url: &HttpUrl, request: &[u8]) -> Result<Message> {
+    let stream = TcpStream::connect((url.host.as_str(), url.port)).await?;
+    let head = format!(
+        "POST {} HTTP/1.0\r\nHost: {}\r\nAccept: application/dns-message\r\nContent-Type: application/dns-message\r\nContent-Length: {}\r\n\r\n",
+        url.path,
+        url.host,
+        request.len(),
+    );
+    let response = match url.tls {
+        true => {
+            let server_name = ServerName::try_from(url.host.to_owned())?;
+            let mut stream = tls_connector().connect(server_name, stream).await?;
+            stream.write_all(&[head.as_bytes(), request].concat()).await?;
+            read_to_close(&mut stream).await?
+        }
+        false => {
+            let mut stream = stream;
+            stream.write_all(&[head.as_bytes(), request].concat()).await?;
+            read_to_close(&mut stream).await?
+        }
+    };
+
+    let body = match response.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(i) => &response[i + 4..],
+        None => bail!("DNS query to {url} returned an invalid HTTP response"),
+    };
+    let status_line = String::from_utf8_lossy(response.split(|b| *b == b'\n').next().unwrap_or_default());
+    match http_status_line(&status_line) {
+        Some(200) => {}
+        Some(status) => bail!("DNS query to {url} failed: HTTP {status}"),
+        None => bail!("DNS query to {url} returned an invalid HTTP response"),
+    }
+
+    let response = Message::from_vec(body)?;
+    match response.response_code() {
+        ResponseCode::NoError | ResponseCode::NXDomain => Ok(response),
+        code => bail!("DNS query to {url} failed: {code}"),
+    }
+}
+
+/// Read a stream until it is closed.
+/// A TLS connection closed without a close_notify is not an error.
+async fn read_to_close<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Vec<u8>> {
+    let mut response = Vec::new();
+    match stream.read_to_end(&mut response).await {
+        Ok(_) => Ok(response),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(response),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Send a legacy unicast (RFC6762 section 6.7) query to the mDNS group.
+/// The first response to the query is returned, responders that do not
+/// know the name do not respond so the query times out.
+async fn mdns_query(name: &Name, record_type: RecordType) -> Result<Message> {
+    let (id, request) = query_message(name, record_type, None);
+    let socket = UdpSocket::bind((BIND_ADDR_IPV4, 0)).await?;
+    socket.send_to(&request.to_vec()?, MDNS_ADDR).await?;
+
+    let mut buffer = vec![0u8; MAX_DNS_PACKET_SIZE];
+    loop {
+        let len = match timeout(Duration::from_millis(DNS_TIMEOUT.into()), socket.recv(&mut buffer)).await {
+            Ok(len) => len?,
+            Err(_) => bail!("mDNS query for {name} timed out"),
+        };
+        // Ignore stray responses to other queries.
+        match Message::from_vec(&buffer[..len]) {
+            Ok(response) if response.id() == id => return Ok(response),
+            _ => continue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hickory_proto::rr::rdata::A;
+    use hickory_proto::rr::Record;
+
+    use crate::core::common::ServiceTarget;
+    use crate::util::dns::*;
+
+    fn cache_key(host: &str) -> CacheKey {
+        (
+            Name::from_ascii(host).unwrap(),
+            RecordType::A,
+            "192.0.2.53:53".parse().unwrap(),
+            None,
+        )
+    }
+
+    fn a_response(ttl: u32) -> Message {
+        let mut message = Message::new();
+        message.add_answer(Record::from_rdata(
+            Name::from_ascii("example.com.").unwrap(),
+            ttl,
+            RData::A(A::new(192, 0, 2, 1)),
+        ));
+        message
+    }
+
+    fn service_target(host: &str, priority: u16, weight: u16) -> ServiceTarget {
+        ServiceTarget {
+            service: "_sip._tcp.example.com".to_owned(),
+            host: host.to_owned(),
+            port: 5060,
+            priority,
+            weight,
+        }
+    }
+
+    #[test]
+    fn dns_cache_expires_entries() {
+        let mut cache = DnsCache::new(10);
+        let now = Instant::now();
+        cache.insert(cache_key("example.com."), a_response(60), now);
+
+        assert!(cache.get(&cache_key("example.com."), now).is_some());
+        assert!(cache
+            .get(&cache_key("example.com."), now + Duration::from_secs(61))
+            .is_none());
+    }
+
+    #[test]
+    fn dns_cache_caches_negative_responses() {
+        let mut cache = DnsCache::new(10);
+        let now = Instant::now();
+        cache.insert(cache_key("nxdomain.example.com."), Message::new(), now);
+
+        assert!(cache.get(&cache_key("nxdomain.example.com."), now).is_some());
+        assert!(cache
+            .get(
+                &cache_key("nxdomain.example.com."),
+                now + Duration::from_secs(DNS_NEGATIVE_TTL.into())
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn dns_cache_evicts_closest_to_expiring_when_full() {
+        let mut cache = DnsCache::new(2);
+        let now = Instant::now();
+        cache.insert(cache_key("a.example.com."), a_response(300), now);
+        cache.insert(cache_key("b.example.com."), a_response(30), now);
+        cache.insert(cache_key("c.example.com."), a_response(300), now);
+
+        assert!(cache.get(&cache_key("a.example.com."), now).is_some());
+        assert!(cache.get(&cache_key("b.example.com."), now).is_none());
+        assert!(cache.get(&cache_key("c.example.com."), now).is_some());
+    }
+
+    #[test]
+    fn is_srv_name_is_expected() {
+        assert!(is_srv_name("_sip._tcp.example.com"));
+        assert!(!is_srv_name("sip.example.com"));
+        assert!(!is_srv_name("_sip.example.com"));
+        assert!(!is_srv_name("_sip._tcp"));
+    }
+
+    #[test]
+    fn sort_service_targets_is_expected() {
+        let mut targets = vec![
+            service_target("backup", 20, 100),
+            service_target("light", 10, 10),
+            service_target("heavy", 10, 90),
+        ];
+        sort_service_targets(&mut targets);
+
+        let hosts: Vec<&str> = targets.iter().map(|t| t.host.as_str()).collect();
+        assert_eq!(hosts, vec!["heavy", "light", "backup"]);
+    }
+
+    #[test]
+    fn resolv_conf_nameserver_is_expected() {
+        let contents = "# generated\nsearch example.com\nnameserver 192.0.2.53\nnameserver 192.0.2.54\n";
+
+        assert_eq!(resolv_conf_nameserver(contents).unwrap().to_string(), "192.0.2.53:53");
+        assert!(resolv_conf_nameserver("search example.com").is_none());
+    }
+
+    #[test]
+    fn kubernetes_names_are_expanded() {
+        assert_eq!(kubernetes_name("api", "prod"), "api.prod.svc.cluster.local");
+        assert_eq!(kubernetes_name("api.staging", "prod"), "api.staging.svc.cluster.local");
+        assert_eq!(
+            kubernetes_name("api.staging.svc", "prod"),
+            "api.staging.svc.cluster.local"
+        );
+        assert_eq!(
+            kubernetes_name("api.staging.svc.cluster.local.", "prod"),
+            "api.staging.svc.cluster.local"
+        );
+        assert_eq!(kubernetes_name("www.example.com", "prod"), "www.example.com");
+    }
+
+    #[test]
+    fn parse_hosts_file_is_expected() {
+        let contents = "# static hosts\n192.0.2.1 web WWW.example.com # primary\n2001:db8::1 web\ninvalid host\n";
+        let hosts = parse_hosts_file(contents);
+
+        assert_eq!(
+            hosts["web"],
+            vec!["192.0.2.1".parse::<IpAddr>().unwrap(), "2001:db8::1".parse().unwrap()]
+        );
+        assert_eq!(hosts["www.example.com"], vec!["192.0.2.1".parse::<IpAddr>().unwrap()]);
+        assert!(!hosts.contains_key("host"));
+    }
+
+    #[test]
+    fn resolution_provider_specs_are_validated() {
+        for spec in [
+            "system",
+            "192.0.2.53",
+            "[2001:db8::53]:5353",
+            "https://dns.example/dns-query",
+            "mdns",
+            "kubernetes",
+            "kubernetes:prod",
+        ] {
+            assert!(resolution_provider(spec, None).is_ok(), "{spec}");
+        }
+        assert!(resolution_provider("dns.example", None).is_err());
+        assert!(resolution_provider("kubernetes:", None).is_err());
+        assert!(resolution_provider("hosts:/nonexistent/hosts", None).is_err());
+    }
+
+    #[test]
+    fn record_types_and_response_codes_are_expected() {
+        assert_eq!(parse_record_type("mx").unwrap(), RecordType::MX);
+        assert_eq!(parse_record_type("AAAA").unwrap(), RecordType::AAAA);
+        assert!(parse_record_type("bogus").is_err());
+
+        assert_eq!(response_code_name(ResponseCode::NoError), "NOERROR");
+        assert_eq!(response_code_name(ResponseCode::NXDomain), "NXDOMAIN");
+        assert_eq!(response_code_name(ResponseCode::ServFail), "SERVFAIL");
+    }
+
+    #[test]
+    fn answer_monitor_tracks_failover_propagation() {
+        // Monday 2024-01-01 00:00 UTC, the failover is made at 00:01.
+        let at = |seconds: i64| OffsetDateTime::from_unix_timestamp(1_704_067_200 + seconds).unwrap();
+        let answers = |ips: &[&str]| ips.iter().map(|ip| ip.to_string()).collect::<Vec<String>>();
+        let mut monitor = AnswerMonitor::new(Some(at(60)));
+
+        let record = monitor.observe(answers(&["192.0.2.2", "192.0.2.1"]), 30, at(50));
+        assert_eq!(record.answers, answers(&["192.0.2.1", "192.0.2.2"]));
+        assert!(!record.changed);
+
+        // Old answers may be served until 00:01:20.
+        let record = monitor.observe(answers(&["192.0.2.1", "192.0.2.2"]), 20, at(70));
+        assert!(!record.changed && !record.ttl_exceeded);
+        let record = monitor.observe(answers(&["192.0.2.1", "192.0.2.2"]), 10, at(90));
+        assert!(!record.changed && record.ttl_exceeded);
+
+        let record = monitor.observe(answers(&["198.51.100.1"]), 30, at(100));
+        assert!(record.changed && record.ttl_exceeded);
+        assert_eq!(record.propagation_delay, Some(40.0));
+
+        // Later changes are not part of the failover.
+        let record = monitor.observe(answers(&["198.51.100.2"]), 30, at(110));
+        assert!(record.changed && !record.ttl_exceeded);
+        assert_eq!(record.propagation_delay, None);
+    }
+}
@@ -0,0 +1,538 @@
+use std::fs;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConnection, StreamOwned};
+use uuid::Uuid;
+
+use crate::core::common::{HttpUrl, LoggingOptions};
+use crate::core::konst::{EXPORT_RETRY_INTERVAL, EXPORT_TIMEOUT};
+use crate::util::parser::http_status_line;
+use crate::util::time::time_now_us;
+use crate::util::tls::tls_client_config;
+
+enum Export {
+    Body(Vec<u8>, usize),
+    Flush(Sender<()>),
+}
+
+/// Counts of the records an exporter buffered on disk
+/// while its endpoint was unreachable.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExportStats {
+    pub buffered: usize,
+    pub sent: usize,
+    pub dropped: usize,
+}
+
+/// Posts request bodies to an HTTP endpoint. Requests are sent by
+/// a background thread so a slow endpoint does not delay probes,
+/// and a failed export is reported without stopping the client.
+/// With an export buffer, failed requests are stored on disk and
+/// sent again once the endpoint is reachable.
+pub struct HttpExporter {
+    name: &'static str,
+    sender: Sender<Export>,
+    stats: Arc<Mutex<ExportStats>>,
+}
+
+impl HttpExporter {
+    /// `name` identifies the exporter in error messages and
+    /// `headers` are added to each request.
+    pub fn new(
+        name: &'static str,
+        url: HttpUrl,
+        headers: Vec<(String, String)>,
+        logging_options: &LoggingOptions,
+    ) -> Result<HttpExporter> {
+        let stats = Arc::new(Mutex::new(ExportStats::default()));
+        let buffer = match &logging_options.export_buffer {
+            Some(dir) => Some(ExportBuffer::create(
+                dir,
+                name,
+                &url,
+                logging_options.export_buffer_size,
+                stats.clone(),
+            )?),
+            None => None,
+        };
+        Ok(HttpExporter::with_buffer(name, url, headers, buffer, stats))
+    }
+
+    fn with_buffer(
+        name: &'static str,
+        url: HttpUrl,
+        headers: Vec<(String, String)>,
+        buffer: Option<ExportBuffer>,
+        stats: Arc<Mutex<ExportStats>>,
+    ) -> HttpExporter {
+        let (sender, receiver) = channel();
+        thread::spawn(move || export_worker(name, url, headers, buffer, receiver));
+        HttpExporter { name, sender, stats }
+    }
+
+    /// Send a request body holding a number of records.
+    pub fn send(&self, body: Vec<u8>, records: usize) -> Result<()> {
+        if self.sender.send(Export::Body(body, records)).is_err() {
+            bail!("exporter has stopped");
+        }
+        Ok(())
+    }
+
+    pub fn stats(&self) -> ExportStats {
+        *self.stats.lock().unwrap()
+    }
+
+    /// Wait until the bodies sent so far have been posted.
+    pub fn flush(&self) -> Result<()> {
+        let (sender, receiver) = channel();
+        if self.sender.send(Export::Flush(sender)).is_err() || receiver.recv().is_err() {
+            bail!("exporter has stopped");
+        }
+        Ok(())
+    }
+}
+
+impl Drop for HttpExporter {
+    fn drop(&mut self) {
+        let stats = self.stats();
+        if stats.buffered > 0 {
+            eprintln!(
+                "{} export buffered {} records, of which {} were sent and {} dropped.",
+                self.name, stats.buffered, stats.sent, stats.dropped
+            );
+        }
+    }
+}
+
+fn export_worker(
+    name: &str,
+    url: HttpUrl,
+    headers: Vec<(String, String)>,
+    mut buffer: Option<ExportBuffer>,
+    receiver: Receiver<Export>,
+) {
+    let post = |body: &[u8]| http_post(&url, &headers, body);
+    let mut failing = false;
+    loop {
+        let export = match buffer.as_ref().filter(|buffer| !buffer.is_empty()) {
+            // Buffered requests are retried even when no new requests are sent.
+            Some(buffer) => match receiver.recv_timeout(buffer.retry_in()) {
+                Ok(export) => Some(export),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            None => match receiver.recv() {
+                Ok(export) => Some(export),
+                Err(_) => break,
+            },
+        };
+        let result = match (export, buffer.as_mut()) {
+            (Some(Export::Flush(done)), _) => {
+                let _ = done.send(());
+                continue;
+            }
+            (Some(Export::Body(body, _)), None) => post(&body).map(|_| 0),
+            (Some(Export::Body(body, records)), Some(buffer)) => buffer.export(Some((body, records)), post),
+            (None, Some(buffer)) => buffer.export(None, post),
+            (None, None) => continue,
+        };
+        // Only the first of a run of failures is reported.
+        match result {
+            Ok(sent) => {
+                if sent > 0 {
+                    eprintln!("{name} export to {url} resumed, {sent} buffered records sent.");
+                }
+                failing = false;
+            }
+            Err(e) if !failing => {
+                match &buffer {
+                    Some(buffer) => eprintln!(
+                        "{name} export to {url} failed: {e}, buffering in {}",
+                        buffer.dir.display()
+                    ),
+                    None => eprintln!("{name} export to {url} failed: {e}"),
+                }
+                failing = true;
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+/// Requests that could not be posted, stored on disk until the
+/// endpoint is reachable again and then sent in the order they
+/// were made. Each request is a separate file, so the exporters of
+/// several profiles or runs can share a directory, and the oldest
+/// requests are dropped once the buffer reaches its maximum size.
+struct ExportBuffer {
+    name: &'static str,
+    dir: PathBuf,
+    max_size: u64,
+    retry_interval: Duration,
+    failed: Option<Instant>,
+    full: bool,
+    last_time: u128,
+    stats: Arc<Mutex<ExportStats>>,
+}
+
+impl ExportBuffer {
+    /// Requests are buffered in a directory named after the
+    /// exporter and its endpoint, inside the export buffer directory.
+    fn create(
+        dir: &str,
+        name: &'static str,
+        url: &HttpUrl,
+        max_size: u64,
+        stats: Arc<Mutex<ExportStats>>,
+    ) -> Result<ExportBuffer> {
+        let endpoint: String = format!("{name}-{}-{}{}", url.host, url.port, url.path)
+            .chars()
+            .map(|c| match c.is_ascii_alphanumeric() {
+                true => c.to_ascii_lowercase(),
+                false => '-',
+            })
+            .collect();
+        let dir = Path::new(dir).join(endpoint);
+        if let Err(e) = fs::create_dir_all(&dir) {
+            bail!("export buffer: `{}` could not be created: {e}", dir.display());
+        }
+        Ok(ExportBuffer {
+            name,
+            dir,
+            max_size,
+            retry_interval: Duration::from_millis(EXPORT_RETRY_INTERVAL.into()),
+            failed: None,
+            full: false,
+            last_time: 0,
+            stats,
+        })
+    }
+
+    /// Post a request, and any buffered requests before it. Requests
+    /// are buffered while the endpoint is failing, and are only retried
+    /// once the retry interval has elapsed. Returns the number of
+    /// buffered records that were sent.
+    fn export<F>(&mut self, request: Option<(Vec<u8>, usize)>, post: F) -> Result<usize>
+    where
+        F: Fn(&[u8]) -> Result<()>,
+    {
+        let due = self.retry_in().is_zero();
+        let result = match due {
+            true => self.replay(&post).and_then(|sent| {
+                if let Some((body, _)) = &request {
+                    post(body)?;
+                }
+                Ok(sent)
+            }),
+            false => Err(anyhow!("endpoint is unreachable")),
+        };
+        match result {
+            Ok(sent) => {
+                self.failed = None;
+                self.full = false;
+                Ok(sent)
+            }
+            Err(e) => {
+                if due {
+                    self.failed = Some(Instant::now());
+                }
+                if let Some((body, records)) = request {
+                    self.push(&body, records)?;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Send the buffered requests, oldest first, until one fails.
+    /// A request is claimed by renaming it, so it is only sent by one
+    /// of the exporters sharing the directory.
+    fn replay<F>(&mut self, post: F) -> Result<usize>
+    where
+        F: Fn(&[u8]) -> Result<()>,
+    {
+        let mut sent = 0;
+        let result = self.requests().into_iter().try_for_each(|(path, records, _)| {
+            let claimed = path.with_extension("sending");
+            match fs::rename(&path, &claimed) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+            if let Err(e) = fs::read(&claimed)
+                .map_err(anyhow::Error::from)
+                .and_then(|body| post(&body))
+            {
+                let _ = fs::rename(&claimed, &path);
+                return Err(e);
+            }
+            let _ = fs::remove_file(&claimed);
+            sent += records;
+            Ok(())
+        });
+        self.stats.lock().unwrap().sent += sent;
+        result.map(|_| sent)
+    }
+
+    /// Store a request, dropping the oldest requests to keep the
+    /// buffer within its maximum size. A request larger than the
+    /// buffer is dropped itself.
+    fn push(&mut self, body: &[u8], records: usize) -> Result<()> {
+        let len = body.len() as u64;
+        let requests = self.requests();
+        let mut size: u64 = requests.iter().map(|(_, _, size)| size).sum();
+        let mut dropped = 0;
+        if len > self.max_size {
+            dropped += records;
+        } else {
+            for (path, records, request_size) in requests {
+                if size + len <= self.max_size {
+                    break;
+                }
+                if fs::remove_file(path).is_ok() {
+                    size -= request_size;
+                    dropped += records;
+                }
+            }
+            // Requests are named by time, so they are sent in order.
+            self.last_time = time_now_us().max(self.last_time + 1);
+            let path = self
+                .dir
+                .join(format!("{:020}-{records}-{}.request", self.last_time, Uuid::new_v4()));
+            // The request is written under a temporary name, so
+            // a partly written request is never sent.
+            let temp = path.with_extension("tmp");
+            fs::write(&temp, body)?;
+            fs::rename(&temp, &path)?;
+            self.stats.lock().unwrap().buffered += records;
+        }
+        if dropped > 0 {
+            self.stats.lock().unwrap().dropped += dropped;
+            if !self.full {
+                eprintln!(
+                    "{} export buffer {} is full, dropping the oldest records.",
+                    self.name,
+                    self.dir.display()
+                );
+                self.full = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the path, record count and size of the buffered requests, oldest first.
+    fn requests(&self) -> Vec<(PathBuf, usize, u64)> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let mut requests: Vec<(PathBuf, usize, u64)> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let path = entry.path();
+                if path.extension()? != "request" {
+                    return None;
+                }
+                let records = path.file_stem()?.to_str()?.split('-').nth(1)?.parse().ok()?;
+                let size = entry.metadata().ok()?.len();
+                Some((path, records, size))
+            })
+            .collect();
+        requests.sort();
+        requests
+    }
+
+    fn is_empty(&self) -> bool {
+        self.requests().is_empty()
+    }
+
+    /// Return the time until buffered requests can be retried.
+    fn retry_in(&self) -> Duration {
+        match self.failed {
+            Some(failed) => self.retry_interval.saturating_sub(failed.elapsed()),
+            None => Duration::ZERO,
+        }
+    }
+}
+
+/// POST a body to an HTTP endpoint.
+/// HTTP/1.0 is used so the response is not chunked.
+fn http_post(url: &HttpUrl, headers: &[(String, String)], body: &[u8]) -> Result<()> {
+    let timeout = Duration::from_millis(EXPORT_TIMEOUT.into());
+    let addr = match (url.host.as_str(), url.port).to_socket_addrs()?.next() {
+        Some(addr) => addr,
+        None => bail!("{} did not resolve to an IP address", url.host),
+    };
+    let stream = TcpStream::connect_timeout(&addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let mut head = format!(
+        "POST {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: netkraken/{}\r\nContent-Length: {}\r\n",
+        url.path,
+        url.host,
+        env!("CARGO_PKG_VERSION"),
+        body.len(),
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str("\r\n");
+    let request = [head.as_bytes(), body].concat();
+    let response = match url.tls {
+        true => {
+            let server_name = ServerName::try_from(url.host.to_owned())?;
+            let connection = ClientConnection::new(Arc::new(tls_client_config()), server_name)?;
+            let mut stream = StreamOwned::new(connection, stream);
+            stream.write_all(&request)?;
+            read_to_close(&mut stream)?
+        }
+        false => {
+            let mut stream = stream;
+            stream.write_all(&request)?;
+            read_to_close(&mut stream)?
+        }
+    };
+
+    let status_line = String::from_utf8_lossy(response.split(|b| *b == b'\n').next().unwrap_or_default());
+    match http_status_line(&status_line) {
+        Some(200..=299) => Ok(()),
+        Some(status) => bail!("HTTP {status}"),
+        None => bail!("invalid HTTP response"),
+    }
+}
+
+/// Read a stream until it is closed.
+/// A TLS connection closed without a close_notify is not an error.
+fn read_to_close<S: Read>(stream: &mut S) -> Result<Vec<u8>> {
+    let mut response = Vec::new();
+    match stream.read_to_end(&mut response) {
+        Ok(_) => Ok(response),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(response),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn buffer(max_size: u64) -> ExportBuffer {
+        let dir = std::env::temp_dir().join(format!("nk-export-{}", Uuid::new_v4()));
+        let url = HttpUrl {
+            tls: false,
+            host: "127.0.0.1".to_owned(),
+            port: 4318,
+            path: "/v1/traces".to_owned(),
+        };
+        let stats = Arc::new(Mutex::new(ExportStats::default()));
+        let mut buffer = ExportBuffer::create(&dir.display().to_string(), "OTLP", &url, max_size, stats).unwrap();
+        buffer.retry_interval = Duration::ZERO;
+        buffer
+    }
+
+    /// Accept `statuses.len()` requests, responding with each status
+    /// in turn and returning the body of each request.
+    fn endpoint(listener: TcpListener, statuses: Vec<u16>) -> thread::JoinHandle<Vec<String>> {
+        thread::spawn(move || {
+            statuses
+                .into_iter()
+                .map(|status| {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let mut request = Vec::new();
+                    let mut buffer = [0u8; 4096];
+                    let body = loop {
+                        let len = stream.read(&mut buffer).unwrap();
+                        request.extend_from_slice(&buffer[..len]);
+                        let text = String::from_utf8_lossy(&request).to_string();
+                        if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                            let length = head
+                                .lines()
+                                .find_map(|line| line.strip_prefix("Content-Length: "))
+                                .and_then(|length| length.parse::<usize>().ok())
+                                .unwrap();
+                            if body.len() == length {
+                                break body.to_owned();
+                            }
+                        }
+                    };
+                    stream
+                        .write_all(format!("HTTP/1.0 {status} Status\r\n\r\n").as_bytes())
+                        .unwrap();
+                    body
+                })
+                .collect()
+        })
+    }
+
+    #[test]
+    fn failed_exports_are_sent_once_the_endpoint_recovers() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = HttpUrl {
+            tls: false,
+            host: "127.0.0.1".to_owned(),
+            port: listener.local_addr().unwrap().port(),
+            path: "/".to_owned(),
+        };
+        let endpoint = endpoint(listener, vec![503, 200, 200]);
+        let buffer = buffer(1024);
+        let dir = buffer.dir.clone();
+        let stats = buffer.stats.clone();
+        let exporter = HttpExporter::with_buffer("OTLP", url, Vec::new(), Some(buffer), stats);
+
+        exporter.send(b"first".to_vec(), 1).unwrap();
+        exporter.flush().unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        // The buffered request is sent before the next request.
+        exporter.send(b"second".to_vec(), 2).unwrap();
+        exporter.flush().unwrap();
+
+        assert_eq!(endpoint.join().unwrap(), vec!["first", "first", "second"]);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        assert_eq!(
+            exporter.stats(),
+            ExportStats {
+                buffered: 1,
+                sent: 1,
+                dropped: 0
+            }
+        );
+        let _ = fs::remove_dir_all(dir.parent().unwrap());
+    }
+
+    #[test]
+    fn oldest_requests_are_dropped_when_the_buffer_is_full() {
+        let mut buffer = buffer(10);
+        buffer.push(b"1111", 1).unwrap();
+        buffer.push(b"2222", 2).unwrap();
+        buffer.push(b"3333", 3).unwrap();
+        // Larger than the buffer.
+        buffer.push(b"44444444444", 4).unwrap();
+
+        let records: Vec<usize> = buffer.requests().into_iter().map(|(_, records, _)| records).collect();
+        assert_eq!(records, vec![2, 3]);
+        assert_eq!(
+            *buffer.stats.lock().unwrap(),
+            ExportStats {
+                buffered: 6,
+                sent: 0,
+                dropped: 5
+            }
+        );
+
+        // Requests stay buffered while the endpoint is failing.
+        assert!(buffer.export(None, |_| bail!("HTTP 503")).is_err());
+        assert_eq!(buffer.requests().len(), 2);
+        assert_eq!(buffer.export(None, |_| Ok(())).unwrap(), 5);
+        assert!(buffer.is_empty());
+        let _ = fs::remove_dir_all(buffer.dir.parent().unwrap());
+    }
+}
@@ -0,0 +1,103 @@
+use std::fmt::Display;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::core::common::ListenOptions;
+
+/// A failure injected into a server response.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Fault {
+    Drop,
+    Corrupt,
+}
+
+impl Display for Fault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fault::Drop => write!(f, "drop"),
+            Fault::Corrupt => write!(f, "corrupt"),
+        }
+    }
+}
+
+/// Create the random source of injected failures.
+/// A seeded source injects the same failures on every run.
+pub fn fault_rng(listen_options: &ListenOptions) -> StdRng {
+    match listen_options.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    }
+}
+
+/// Choose the failure, if any, to inject into a response.
+/// The drop and corrupt percentages are exclusive of each other.
+pub fn choose_fault(listen_options: &ListenOptions, rng: &mut impl Rng) -> Option<Fault> {
+    let roll = rng.gen_range(0..100u16);
+    let drop = listen_options.drop as u16;
+    let corrupt = listen_options.corrupt as u16;
+    if roll < drop {
+        Some(Fault::Drop)
+    } else if roll < drop + corrupt {
+        Some(Fault::Corrupt)
+    } else {
+        None
+    }
+}
+
+/// Corrupt a response by inverting the bits of one random byte.
+pub fn corrupt(bytes: &mut [u8], rng: &mut impl Rng) {
+    if bytes.is_empty() {
+        return;
+    }
+    let i = rng.gen_range(0..bytes.len());
+    bytes[i] = !bytes[i];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn choose_fault_is_expected() {
+        let listen_options = |drop, corrupt| ListenOptions {
+            drop,
+            corrupt,
+            ..Default::default()
+        };
+
+        let mut rng = fault_rng(&ListenOptions::default());
+
+        assert_eq!(choose_fault(&listen_options(0, 0), &mut rng), None);
+        assert_eq!(choose_fault(&listen_options(100, 0), &mut rng), Some(Fault::Drop));
+        assert_eq!(choose_fault(&listen_options(0, 100), &mut rng), Some(Fault::Corrupt));
+    }
+
+    #[test]
+    fn seeded_faults_are_repeated() {
+        let listen_options = ListenOptions {
+            drop: 30,
+            corrupt: 30,
+            seed: Some(7),
+            ..Default::default()
+        };
+        let faults = || {
+            let mut rng = fault_rng(&listen_options);
+            let faults: Vec<_> = (0..50).map(|_| choose_fault(&listen_options, &mut rng)).collect();
+            let mut bytes = b"NetKraken".to_vec();
+            corrupt(&mut bytes, &mut rng);
+            (faults, bytes)
+        };
+
+        assert_eq!(faults(), faults());
+    }
+
+    #[test]
+    fn corrupt_changes_one_byte() {
+        let mut bytes = b"NetKraken".to_vec();
+        corrupt(&mut bytes, &mut fault_rng(&ListenOptions::default()));
+        let changed = bytes.iter().zip(b"NetKraken").filter(|(a, b)| a != b).count();
+
+        assert_eq!(changed, 1);
+    }
+}
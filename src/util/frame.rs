@@ -0,0 +1,58 @@
+use std::io::{Error, ErrorKind};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::core::konst::MAX_PACKET_SIZE;
+
+/// Prefix a message with its length as a big endian u32,
+/// so messages can be read back from a stream.
+pub fn frame(message: &[u8]) -> Vec<u8> {
+    let mut framed = (message.len() as u32).to_be_bytes().to_vec();
+    framed.extend_from_slice(message);
+    framed
+}
+
+/// Read a length prefixed message from a stream.
+/// Returns `None` if the stream ends before a message is received.
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> std::io::Result<Option<Vec<u8>>> {
+    let len = match reader.read_u32().await {
+        Ok(len) => len as usize,
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if len > MAX_PACKET_SIZE {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("message length {len} exceeds {MAX_PACKET_SIZE} bytes"),
+        ));
+    }
+    let mut message = vec![0u8; len];
+    reader.read_exact(&mut message).await?;
+    Ok(Some(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn frames_round_trip() {
+        let mut stream = [frame(b"NetKraken"), frame(b"")].concat();
+        let mut reader = stream.as_slice();
+
+        assert_eq!(read_frame(&mut reader).await.unwrap(), Some(b"NetKraken".to_vec()));
+        assert_eq!(read_frame(&mut reader).await.unwrap(), Some(vec![]));
+        assert_eq!(read_frame(&mut reader).await.unwrap(), None);
+
+        stream.truncate(6);
+        assert!(read_frame(&mut stream.as_slice()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn oversized_frames_are_rejected() {
+        let stream = u32::MAX.to_be_bytes();
+
+        let result = read_frame(&mut stream.as_slice()).await;
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
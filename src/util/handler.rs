@@ -1,11 +1,120 @@
+use std::future::Future;
+
+use tokio::signal;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
 use tokio::time::{sleep, Duration};
 use tracing::event;
 use tracing::Level;
 
+#[cfg(feature = "server")]
 use crate::core::common::LogLevel;
 use crate::core::common::LoggingOptions;
-use crate::core::common::{ConnectRecord, ConnectResult};
-use crate::core::konst::APP_NAME;
+use crate::core::common::{ClientResult, ConnectRecord, ConnectResult, OutputFormat};
+use crate::core::konst::{APP_NAME, SCHEMA_VERSION};
+use crate::util::advice::AdviceAccumulator;
+use crate::util::message::{
+    advice_table_msg, client_result_json_msg, client_result_msg, resource_exhaustion_msg, tags_msg,
+};
+use crate::util::run::RunHandle;
+
+/// A cancellation broadcast to the tasks of a client. Cancelling
+/// interrupts the requests in flight, rather than waiting for them
+/// to complete or time out, so the summary is printed straight away.
+#[derive(Clone)]
+pub struct Cancel {
+    receiver: watch::Receiver<bool>,
+}
+
+impl Cancel {
+    /// Return a cancellation that is cancelled by a Ctrl-C signal.
+    pub fn on_ctrl_c() -> Cancel {
+        let (sender, cancel) = Cancel::channel();
+        tokio::spawn(async move {
+            if signal::ctrl_c().await.is_ok() {
+                let _ = sender.send(true);
+            }
+        });
+        cancel
+    }
+
+    /// Return a cancellation that is cancelled by sending `true`.
+    pub fn channel() -> (watch::Sender<bool>, Cancel) {
+        let (sender, receiver) = watch::channel(false);
+        (sender, Cancel { receiver })
+    }
+
+    /// Return whether the cancellation was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Wait until cancelled. Without a signal handler this never completes.
+    pub async fn cancelled(&self) {
+        let mut receiver = self.receiver.clone();
+        if receiver.wait_for(|cancelled| *cancelled).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+
+    /// Run a future until it completes, returning None if cancelled first.
+    pub async fn run<F: Future>(&self, future: F) -> Option<F::Output> {
+        tokio::select! {
+            output = future => Some(output),
+            _ = self.cancelled() => None,
+        }
+    }
+}
+
+/// Prints the interim statistics of a run without stopping it when
+/// SIGQUIT is received, which a terminal sends with `Ctrl-\`, like `ping`.
+/// Statistics go to stderr so they do not mix with the chosen output
+/// format. The listener stops when it is dropped.
+pub struct StatsListener {
+    task: Option<JoinHandle<()>>,
+}
+
+impl StatsListener {
+    pub fn spawn(run_handle: RunHandle) -> StatsListener {
+        StatsListener::spawn_with(run_handle, |msg| eprintln!("{msg}"))
+    }
+
+    /// Spawn a listener that passes each line of the statistics to `print`.
+    fn spawn_with<F>(run_handle: RunHandle, print: F) -> StatsListener
+    where
+        F: Fn(String) + Send + 'static,
+    {
+        #[cfg(unix)]
+        {
+            use crate::util::message::run_totals_msg;
+            use tokio::signal::unix::{signal, SignalKind};
+            let task = match signal(SignalKind::quit()) {
+                Ok(mut quit) => Some(tokio::spawn(async move {
+                    while quit.recv().await.is_some() {
+                        for (destination, totals) in run_handle.totals() {
+                            print(run_totals_msg(&destination, &totals));
+                        }
+                    }
+                })),
+                Err(_) => None,
+            };
+            StatsListener { task }
+        }
+        #[cfg(not(unix))]
+        {
+            drop((run_handle, print));
+            StatsListener { task: None }
+        }
+    }
+}
+
+impl Drop for StatsListener {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
 
 /// Handler to manage loop iterations. On `true` the loop
 /// will break, on `false` it will continue.
@@ -31,6 +140,7 @@ pub async fn loop_handler(loop_count: u16, num_repeats: u16, sleep_interval: u16
     }
 }
 
+#[cfg(feature = "server")]
 pub async fn log_handler(log_level: LogLevel, message: &String, logging_options: &LoggingOptions) {
     if !logging_options.quiet {
         println!("{message}");
@@ -50,17 +160,57 @@ pub async fn log_handler(log_level: LogLevel, message: &String, logging_options:
 }
 
 pub fn io_error_switch_handler(error: std::io::Error) -> ConnectResult {
+    // Connecting without a free ephemeral port fails with EADDRNOTAVAIL.
+    if out_of_resources(&error) || error.kind() == std::io::ErrorKind::AddrNotAvailable {
+        return ConnectResult::ResourceExhausted;
+    }
     match error.kind() {
         std::io::ErrorKind::ConnectionRefused => ConnectResult::Refused,
         std::io::ErrorKind::ConnectionReset => ConnectResult::Reset,
         std::io::ErrorKind::TimedOut => ConnectResult::Timeout,
+        std::io::ErrorKind::NetworkUnreachable => ConnectResult::NetworkUnreachable,
+        std::io::ErrorKind::HostUnreachable => ConnectResult::HostUnreachable,
         _ => ConnectResult::Unknown,
     }
 }
 
+/// Classify an error creating or binding a source socket, separating
+/// the local host running out of resources from other bind errors.
+pub fn bind_error_switch_handler(error: &(dyn std::error::Error + 'static)) -> ConnectResult {
+    match error.downcast_ref::<std::io::Error>() {
+        Some(e) if out_of_resources(e) => ConnectResult::ResourceExhausted,
+        _ => ConnectResult::BindError,
+    }
+}
+
+/// Whether the local host ran out of file descriptors or socket buffers.
+fn out_of_resources(error: &std::io::Error) -> bool {
+    #[cfg(unix)]
+    if let Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS) = error.raw_os_error() {
+        return true;
+    }
+    error.kind() == std::io::ErrorKind::OutOfMemory
+}
+
+/// Print and log the result of a probe. The clients print each record
+/// with it, and embedding applications can pass it to `run_with` to
+/// print the records they consume the same way.
+pub async fn console_handler(record: &ConnectRecord, logging_options: &LoggingOptions) {
+    log_handler2(record, &client_result_msg(record), logging_options).await;
+}
+
 pub async fn log_handler2(record: &ConnectRecord, message: &String, logging_options: &LoggingOptions) {
+    let five_tuple = record.five_tuple();
+    let message = match (logging_options.verbose, &five_tuple) {
+        (true, Some(t)) => format!("{message} 5tuple={t}"),
+        _ => message.to_owned(),
+    };
+
     if !logging_options.quiet {
-        println!("{message}");
+        match logging_options.output {
+            OutputFormat::Text => println!("{message}"),
+            OutputFormat::Json => println!("{}", client_result_json_msg(record)),
+        }
     }
     if logging_options.syslog {
         match record.success {
@@ -69,14 +219,217 @@ pub async fn log_handler2(record: &ConnectRecord, message: &String, logging_opti
         };
     }
     if logging_options.json {
-        // json handler
+        event!(
+            target: APP_NAME,
+            Level::INFO,
+            schema_version = SCHEMA_VERSION,
+            result = %record.result,
+            protocol = %record.protocol,
+            source = %record.source,
+            destination = %record.destination,
+            src_ip = five_tuple.as_ref().map(|t| t.src_ip.to_string()),
+            src_port = five_tuple.as_ref().map(|t| t.src_port),
+            dst_ip = five_tuple.as_ref().map(|t| t.dst_ip.to_string()),
+            dst_port = five_tuple.as_ref().map(|t| t.dst_port),
+            time = record.time,
+            success = record.success,
+            error_msg = record.error_msg.as_deref().unwrap_or_default(),
+            tags = tags_msg(&record.tags),
+        );
+    }
+}
+
+/// Print an informational message, such as a banner, header or the
+/// addresses a host resolved to. Informational messages are only
+/// printed with text output, and are suppressed in plain mode.
+pub fn info_handler(message: &str, logging_options: &LoggingOptions) {
+    if info_output(logging_options) {
+        println!("{message}");
+    }
+}
+
+/// Print a result line of a probe that does not produce a record, such
+/// as a path MTU probe. Result lines are printed in plain mode, but only
+/// with text output as they have no JSON document.
+pub fn result_handler(message: &str, logging_options: &LoggingOptions) {
+    if result_output(logging_options) {
+        println!("{message}");
+    }
+}
+
+/// Print a summary, which is a table with text output or a JSON document
+/// with JSON output. Tables are suppressed in plain mode, leaving only
+/// the result lines.
+pub fn summary_handler(message: &str, logging_options: &LoggingOptions) {
+    if summary_output(logging_options) {
+        println!("{message}");
+    }
+}
+
+/// Warn when probes failed as the local host ran out of resources,
+/// so the failures are not mistaken for packet loss.
+pub fn resource_warning_handler(run_handle: &RunHandle, logging_options: &LoggingOptions) {
+    if let Some((exhausted, limit)) = run_handle.resource_exhaustion() {
+        let message = resource_exhaustion_msg(exhausted, limit);
+        info_handler(&message, logging_options);
+        if logging_options.syslog || logging_options.json {
+            event!(target: APP_NAME, Level::WARN, "{message}");
+        }
+    }
+}
+
+/// Print the likely causes of the failures of a run and the
+/// next commands to run, when asked to with `--advise`.
+pub fn advice_handler(advice: &AdviceAccumulator, logging_options: &LoggingOptions) {
+    if !logging_options.advise {
+        return;
+    }
+    let recommendations = advice.recommendations();
+    if !recommendations.is_empty() {
+        info_handler(&advice_table_msg(&recommendations), logging_options);
+    }
+}
+
+fn info_output(logging_options: &LoggingOptions) -> bool {
+    logging_options.output == OutputFormat::Text && !logging_options.plain
+}
+
+fn summary_output(logging_options: &LoggingOptions) -> bool {
+    logging_options.output == OutputFormat::Json || !logging_options.plain
+}
+
+fn result_output(logging_options: &LoggingOptions) -> bool {
+    logging_options.output == OutputFormat::Text && !logging_options.quiet
+}
+
+/// Log the client summary results when logging in JSON format.
+pub fn summary_log_handler(client_results: &[ClientResult], logging_options: &LoggingOptions) {
+    if !logging_options.json {
+        return;
+    }
+    for result in client_results {
+        event!(
+            target: APP_NAME,
+            Level::INFO,
+            schema_version = SCHEMA_VERSION,
+            summary = true,
+            destination = %result.destination,
+            protocol = %result.protocol,
+            sent = result.sent,
+            received = result.received,
+            lost = result.lost,
+            loss_percent = result.loss_percent,
+            min = result.min,
+            max = result.max,
+            avg = result.avg,
+            p50 = result.p50,
+            p90 = result.p90,
+            p95 = result.p95,
+            p99 = result.p99,
+            jitter = result.jitter,
+            std_dev = result.std_dev,
+            passed = result.passed,
+            loss_bursts = result.loss_bursts.bursts,
+            loss_longest_burst = result.loss_bursts.longest,
+            loss_mean_burst_length = result.loss_bursts.mean_length,
+            loss_p = result.loss_bursts.p,
+            loss_r = result.loss_bursts.r,
+            loss_pattern = %result.loss_bursts.pattern,
+            first_failure = result.first_failure.as_deref(),
+            last_failure = result.last_failure.as_deref(),
+            tags = tags_msg(&logging_options.tags),
+        );
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use crate::util::handler::*;
 
+    #[tokio::test]
+    async fn cancel_interrupts_running_futures() {
+        let (sender, cancel) = Cancel::channel();
+        assert_eq!(cancel.run(async { 1 }).await, Some(1));
+        assert!(!cancel.is_cancelled());
+
+        sender.send(true).unwrap();
+        assert!(cancel.is_cancelled());
+        assert_eq!(cancel.run(std::future::pending::<()>()).await, None);
+        drop(sender);
+        assert_eq!(cancel.run(std::future::pending::<()>()).await, None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn local_resource_errors_are_not_network_failures() {
+        let error = || std::io::Error::from_raw_os_error(libc::EMFILE);
+        assert_eq!(io_error_switch_handler(error()), ConnectResult::ResourceExhausted);
+        assert_eq!(bind_error_switch_handler(&error()), ConnectResult::ResourceExhausted);
+
+        let error = || std::io::Error::from_raw_os_error(libc::EADDRNOTAVAIL);
+        assert_eq!(io_error_switch_handler(error()), ConnectResult::ResourceExhausted);
+        // A source address that is not local is a bind error.
+        assert_eq!(bind_error_switch_handler(&error()), ConnectResult::BindError);
+
+        let error = std::io::Error::from_raw_os_error(libc::ECONNREFUSED);
+        assert_eq!(io_error_switch_handler(error), ConnectResult::Refused);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn sigquit_prints_the_statistics_so_far_without_stopping_the_run() {
+        use crate::core::common::ConnectMethod;
+        use crate::core::konst::{RUN_RECORDS, RUN_WINDOW};
+        use tokio::sync::mpsc;
+
+        let run_handle = RunHandle::new(RUN_WINDOW, RUN_RECORDS);
+        let (sender, mut printed) = mpsc::unbounded_channel();
+        let _listener = StatsListener::spawn_with(run_handle.clone(), move |msg| {
+            let _ = sender.send(msg);
+        });
+        let record = ConnectRecord {
+            result: ConnectResult::Pong,
+            protocol: ConnectMethod::TCP,
+            source: "127.0.0.1:50000".to_owned(),
+            destination: "127.0.0.1:443".to_owned(),
+            time: 2.0,
+            success: true,
+            ..Default::default()
+        };
+
+        for sent in 1..=2 {
+            run_handle.push(&record);
+            // This should not fail, the listener handles SIGQUIT.
+            assert_eq!(unsafe { libc::raise(libc::SIGQUIT) }, 0);
+            let msg = tokio::time::timeout(Duration::from_secs(1), printed.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(
+                msg.starts_with(&format!("127.0.0.1:443 sent={sent} received={sent}")),
+                "{msg}"
+            );
+        }
+    }
+
+    #[test]
+    fn plain_output_is_only_results() {
+        let mut logging_options = LoggingOptions::default();
+        assert!(info_output(&logging_options) && summary_output(&logging_options));
+
+        logging_options.plain = true;
+        assert!(!info_output(&logging_options) && !summary_output(&logging_options));
+        assert!(result_output(&logging_options));
+
+        logging_options.output = OutputFormat::Json;
+        assert!(!info_output(&logging_options) && summary_output(&logging_options));
+        // Result lines without a record would corrupt the JSON output.
+        assert!(!result_output(&logging_options));
+        logging_options.plain = false;
+        assert!(!info_output(&logging_options));
+    }
+
     #[tokio::test]
     async fn loop_handler_with_max_count_is_true() {
         let result = loop_handler(65535, 0, 1).await;
@@ -0,0 +1,139 @@
+use anyhow::Result;
+
+use crate::core::common::{ConnectRecord, LoggingOptions};
+use crate::util::export::HttpExporter;
+use crate::util::parser::parse_url;
+use crate::util::sink::RecordSink;
+use crate::util::time::time_now_us;
+
+/// Writes connection records to InfluxDB in line protocol, one
+/// point per record. The URL is the full write endpoint, eg:
+/// http://localhost:8086/api/v2/write?org=nk&bucket=probes
+pub struct InfluxWriter {
+    exporter: HttpExporter,
+    measurement: String,
+    tag_prefix: String,
+}
+
+impl InfluxWriter {
+    pub fn new(influx_url: &str, logging_options: &LoggingOptions) -> Result<InfluxWriter> {
+        let url = parse_url(influx_url)?;
+        let mut headers = vec![("Content-Type".to_owned(), "text/plain; charset=utf-8".to_owned())];
+        if let Some(token) = &logging_options.influx_token {
+            headers.push(("Authorization".to_owned(), format!("Token {token}")));
+        }
+        Ok(InfluxWriter {
+            exporter: HttpExporter::new("InfluxDB", url, headers, logging_options)?,
+            measurement: logging_options.influx_measurement.to_owned(),
+            tag_prefix: logging_options.influx_tag_prefix.to_owned(),
+        })
+    }
+
+    /// Return the line protocol point for a record.
+    fn line(&self, record: &ConnectRecord, timestamp: u128) -> String {
+        let prefix = &self.tag_prefix;
+        let mut line = escape(&self.measurement, &[',', ' ']);
+        let mut tags = vec![
+            ("protocol".to_owned(), record.protocol.to_string()),
+            ("source".to_owned(), record.source.to_owned()),
+            ("destination".to_owned(), record.destination.to_owned()),
+            ("result".to_owned(), record.result.to_string()),
+        ];
+        if let Some(label) = &record.label {
+            tags.push(("label".to_owned(), label.to_owned()));
+        }
+        tags.extend(
+            record
+                .tags
+                .iter()
+                .map(|(key, value)| (key.to_owned(), value.to_owned())),
+        );
+        // Line protocol does not allow empty tag values.
+        for (key, value) in tags.iter().filter(|(_, value)| !value.is_empty()) {
+            line.push_str(&format!(
+                ",{}={}",
+                escape(&format!("{prefix}{key}"), &[',', '=', ' ']),
+                escape(value, &[',', '=', ' '])
+            ));
+        }
+        line.push_str(&format!(" time_ms={},success={}", record.time, record.success));
+        if let Some(error_msg) = &record.error_msg {
+            line.push_str(&format!(",error=\"{}\"", escape(error_msg, &['"'])));
+        }
+        line.push_str(&format!(" {timestamp}"));
+        line
+    }
+}
+
+impl RecordSink for InfluxWriter {
+    fn write_batch(&mut self, records: &[ConnectRecord]) -> Result<()> {
+        // Records do not have a timestamp, so points are stamped when their
+        // batch is written. Each point is a nanosecond apart so points of
+        // the same series in a batch do not overwrite each other.
+        let start = time_now_us() * 1000;
+        let body: String = records
+            .iter()
+            .enumerate()
+            .map(|(i, record)| self.line(record, start + i as u128) + "\n")
+            .collect();
+        self.exporter.send(body.into_bytes(), records.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.exporter.flush()
+    }
+}
+
+/// Escape line protocol special characters, and backslashes, with a backslash.
+fn escape(s: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c == '\\' || special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::common::{ConnectMethod, ConnectResult};
+    use std::collections::BTreeMap;
+
+    fn connect_record() -> ConnectRecord {
+        ConnectRecord {
+            result: ConnectResult::Timeout,
+            protocol: ConnectMethod::TCP,
+            source: "127.0.0.1:50000".to_owned(),
+            destination: "127.0.0.1:443".to_owned(),
+            time: -1.0,
+            success: false,
+            error_msg: Some("deadline \"has\" elapsed".to_owned()),
+            tags: BTreeMap::from([
+                ("site".to_owned(), "syd 1".to_owned()),
+                ("rack".to_owned(), String::new()),
+            ]),
+            label: Some("web,a=b".to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn records_are_written_as_line_protocol() {
+        let logging_options = LoggingOptions {
+            influx_measurement: "probe results".to_owned(),
+            influx_tag_prefix: "nk_".to_owned(),
+            ..Default::default()
+        };
+        let writer = InfluxWriter::new("http://127.0.0.1:8086/write?db=nk", &logging_options).unwrap();
+
+        assert_eq!(
+            writer.line(&connect_record(), 1_700_000_000_000_000_000),
+            "probe\\ results,nk_protocol=tcp,nk_source=127.0.0.1:50000,nk_destination=127.0.0.1:443,\
+             nk_result=timeout,nk_label=web\\,a\\=b,nk_site=syd\\ 1 \
+             time_ms=-1,success=false,error=\"deadline \\\"has\\\" elapsed\" 1700000000000000000"
+        );
+    }
+}
@@ -1,12 +1,28 @@
-use std::net::{IpAddr, SocketAddr};
+use std::collections::BTreeMap;
+#[cfg(feature = "server")]
+use std::net::IpAddr;
+use std::net::SocketAddr;
 
+use tabled::builder::Builder;
 use tabled::settings::Panel;
 use tabled::settings::{object::Rows, Alignment, Margin, Modify, Span, Style};
 use tabled::Table;
+use time::OffsetDateTime;
 
-use crate::core::common::{ClientResult, ConnectMethod, ConnectRecord, ConnectResult, HostRecord};
+#[cfg(feature = "server")]
+use crate::core::common::SourceSummary;
+use crate::core::common::{
+    AddrKey, Advice, ClientResult, ConnectMethod, ConnectRecord, ConnectResult, DnsRecord, Escalation, HealthPolicy,
+    HostRecord, HostServices, Phase, PmtuRecord, ProbeHealth, RunDiff, ServiceTarget, SignificanceResult,
+};
+use crate::core::konst::{HISTOGRAM_BOUNDS, HISTOGRAM_WIDTH, SCHEMA_VERSION};
+use crate::util::record::VersionedRecord;
+use crate::util::report::{outage_duration_msg, timestamp_msg, SlaGroup};
+use crate::util::run::RunTotals;
+use crate::util::stats::median;
 
 /// Return server start message
+#[cfg(feature = "server")]
 pub fn server_start_msg(protocol: ConnectMethod, bind_addr: &IpAddr, bind_port: &u16) -> String {
     let addr = match bind_addr.is_ipv6() {
         true => format!("[{}]", bind_addr),
@@ -48,6 +64,28 @@ pub fn resolved_ips_msg(host_record: &HostRecord) -> String {
     )
 }
 
+/// Returns a service expansion message
+pub fn service_targets_msg(service: &str, targets: &[ServiceTarget]) -> String {
+    let target_desc = match targets.len() {
+        1 => "target",
+        _ => "targets",
+    };
+    let target_str = targets
+        .iter()
+        .map(|t| format!(" {}:{} priority={} weight={}", t.host, t.port, t.priority, t.weight))
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    format!(
+        "{} expands to {} {}\n\
+        {}\n",
+        service,
+        targets.len(),
+        target_desc,
+        target_str,
+    )
+}
+
 /// Return a ping header message
 pub fn ping_header_msg(destination: &String, port: u16, protocol: ConnectMethod) -> String {
     format!(
@@ -58,40 +96,163 @@ pub fn ping_header_msg(destination: &String, port: u16, protocol: ConnectMethod)
     )
 }
 
+/// Returns the time of each phase of a probe
+fn phases_msg(phases: &[(Phase, f64)]) -> String {
+    phases
+        .iter()
+        .map(|(phase, time)| format!("{phase}={time:.3}ms"))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
 /// Returns a client result message
 pub fn client_result_msg(record: &ConnectRecord) -> String {
     match record.result {
-        ConnectResult::Ping | ConnectResult::Pong => {
-            format!(
+        ConnectResult::Ping | ConnectResult::Pong | ConnectResult::Alive => {
+            let msg = format!(
                 "{} => proto={} src={} dst={} time={:.3}ms",
                 record.result,
                 record.protocol.to_string().to_uppercase(),
                 record.source,
-                record.destination,
+                record.display_destination(),
                 record.time,
-            )
+            );
+            let phases = record.phases();
+            let msg = match phases.is_empty() {
+                true => msg,
+                false => format!("{msg} {}", phases_msg(&phases)),
+            };
+            if record.warm {
+                return format!("{msg} conn=warm");
+            }
+            if let Some(peer) = &record.peer {
+                return format!(
+                    "{msg} one_way={:.3}ms return={:.3}ms offset={:.3}ms",
+                    peer.one_way_time, peer.return_time, peer.clock_offset
+                );
+            }
+            if let Some(mail) = &record.mail {
+                let starttls = match (mail.starttls_accepted, mail.starttls_offered) {
+                    (true, _) => "accepted",
+                    (false, true) => "offered",
+                    (false, false) => "none",
+                };
+                return format!("{msg} starttls={starttls}");
+            }
+            if let Some(telnet) = &record.telnet {
+                let rfc2217 = match telnet.com_port {
+                    Some(true) => "accepted",
+                    Some(false) => "refused",
+                    None => "none",
+                };
+                return format!("{msg} rfc2217={rfc2217}");
+            }
+            if let Some(http) = &record.http {
+                return format!("{msg} status={}", http.status);
+            }
+            if let Some(dns) = &record.dns {
+                let mut msg = format!(
+                    "{msg} type={} rcode={} count={} answers={} ttl={}s",
+                    dns.record_type,
+                    dns.response_code,
+                    dns.answers.len(),
+                    dns.answers.join(","),
+                    dns.ttl
+                );
+                if dns.changed {
+                    msg = format!("{msg} changed");
+                }
+                if let Some(delay) = dns.propagation_delay {
+                    msg = format!("{msg} propagation={delay:.1}s");
+                }
+                if dns.ttl_exceeded {
+                    msg = format!("{msg} ttl_exceeded");
+                }
+                return msg;
+            }
+            if let Some(tls) = &record.tls {
+                let expires = match tls.cert_expiry_days {
+                    Some(days) => format!("{days}d"),
+                    None => "unknown".to_owned(),
+                };
+                return format!(
+                    "{msg} tls={} cipher={} expires={expires}",
+                    tls.version, tls.cipher_suite
+                );
+            }
+            msg
         }
         ConnectResult::Refused
         | ConnectResult::Reset
         | ConnectResult::Timeout
         | ConnectResult::Unknown
-        | ConnectResult::BindError => {
-            format!(
+        | ConnectResult::BindError
+        | ConnectResult::ResourceExhausted
+        | ConnectResult::ProtocolError
+        | ConnectResult::TlsError
+        | ConnectResult::CertError
+        | ConnectResult::HttpError
+        | ConnectResult::NoAnswer
+        | ConnectResult::Corrupt
+        | ConnectResult::NetworkUnreachable
+        | ConnectResult::HostUnreachable
+        | ConnectResult::Prohibited
+        | ConnectResult::FragmentationNeeded => {
+            let msg = format!(
                 "{} => proto={} src={} dst={}",
                 record.result,
                 record.protocol.to_string().to_uppercase(),
                 record.source,
-                record.destination,
-            )
+                record.display_destination(),
+            );
+            match (record.warm, &record.http) {
+                (true, _) => format!("{msg} conn=warm"),
+                (false, Some(http)) if http.status > 0 => format!("{msg} status={}", http.status),
+                (false, _) => msg,
+            }
         }
     }
 }
 
+/// Returns the destinations of a client as `host:port` pairs.
+/// Long lists of destinations are summarised by their count.
+pub fn destinations_msg(destinations: &[(String, u16)]) -> String {
+    if destinations.len() > 3 {
+        return format!("{} destinations", destinations.len());
+    }
+    destinations
+        .iter()
+        .map(|(host, port)| format!("{host}:{port}"))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Return a ping header message for a path
+pub fn path_header_msg(path: &String, protocol: ConnectMethod) -> String {
+    format!("Connecting to {} via {}", path, protocol.to_string().to_uppercase())
+}
+
+/// Return the time measured by the probes of a UNIX socket.
+/// Stream sockets time the connect, datagram sockets the echo of a payload.
+fn unix_metric(protocol: ConnectMethod) -> &'static str {
+    match protocol {
+        ConnectMethod::UNIXGRAM => "echo time",
+        _ => "connect time",
+    }
+}
+
+/// Return a ping header message for a UNIX socket path
+pub fn unix_header_msg(path: &String, protocol: ConnectMethod) -> String {
+    format!("{} ({})", path_header_msg(path, protocol), unix_metric(protocol))
+}
+
 pub fn client_summary_table_msg(
     dst_host: &String,
     dst_port: u16,
     connect_method: ConnectMethod,
     client_results: &Vec<ClientResult>,
+    tags: &BTreeMap<String, String>,
+    histogram: bool,
 ) -> String {
     let header = format!(
         "--- Statistics for {} connection to {}:{} ---",
@@ -99,11 +260,322 @@ pub fn client_summary_table_msg(
         dst_host,
         dst_port,
     );
-    Table::new(client_results)
-        // table
+    summary_table(header, client_results, tags, histogram)
+}
+
+/// Returns a summary table for a single interval, or for the
+/// rolling window of probes when watching.
+pub fn interval_summary_table_msg(
+    interval: u16,
+    watch: u16,
+    client_results: &Vec<ClientResult>,
+    tags: &BTreeMap<String, String>,
+) -> String {
+    let header = match watch {
+        0 => format!("--- Statistics for the last {} seconds ---", interval),
+        _ => format!("--- Statistics for the last {} probes ---", watch),
+    };
+    summary_table(header, client_results, tags, false)
+}
+
+/// Returns a summary table for a UNIX socket path
+pub fn path_summary_table_msg(
+    path: &String,
+    connect_method: ConnectMethod,
+    client_results: &Vec<ClientResult>,
+    tags: &BTreeMap<String, String>,
+    histogram: bool,
+) -> String {
+    let header = format!(
+        "--- Statistics for {} connection to {} ---",
+        connect_method.to_string().to_uppercase(),
+        path,
+    );
+    summary_table(header, client_results, tags, histogram)
+}
+
+/// Returns a summary table for a UNIX socket path, naming the time measured
+pub fn unix_summary_table_msg(
+    path: &String,
+    connect_method: ConnectMethod,
+    client_results: &Vec<ClientResult>,
+    tags: &BTreeMap<String, String>,
+    histogram: bool,
+) -> String {
+    let header = format!(
+        "--- Statistics for {} {} to {} ---",
+        connect_method.to_string().to_uppercase(),
+        unix_metric(connect_method),
+        path,
+    );
+    summary_table(header, client_results, tags, histogram)
+}
+
+/// Returns a summary table for the records of a record file
+pub fn replay_summary_table_msg(input: &str, client_results: &Vec<ClientResult>, histogram: bool) -> String {
+    let header = format!("--- Statistics for records in {input} ---");
+    summary_table(header, client_results, &BTreeMap::new(), histogram)
+}
+
+/// Returns a client result as a JSON object
+pub fn client_result_json_msg(record: &ConnectRecord) -> String {
+    serde_json::json!(VersionedRecord {
+        schema_version: SCHEMA_VERSION,
+        record: record.clone(),
+    })
+    .to_string()
+}
+
+/// Returns a client summary as a JSON document
+pub fn client_summary_json_msg(
+    destination: &str,
+    connect_method: ConnectMethod,
+    client_results: &Vec<ClientResult>,
+    tags: &BTreeMap<String, String>,
+) -> String {
+    serde_json::json!({
+        "schema_version": SCHEMA_VERSION,
+        "summary": true,
+        "destination": destination,
+        "protocol": connect_method,
+        "results": client_results,
+        "tags": tags,
+    })
+    .to_string()
+}
+
+/// Returns a client summary for a single interval as a JSON document
+pub fn interval_summary_json_msg(
+    interval: u16,
+    watch: u16,
+    client_results: &Vec<ClientResult>,
+    tags: &BTreeMap<String, String>,
+) -> String {
+    serde_json::json!({
+        "schema_version": SCHEMA_VERSION,
+        "summary": true,
+        "interval": interval,
+        "window": watch,
+        "results": client_results,
+        "tags": tags,
+    })
+    .to_string()
+}
+
+fn summary_table(
+    header: String,
+    client_results: &Vec<ClientResult>,
+    tags: &BTreeMap<String, String>,
+    histogram: bool,
+) -> String {
+    let mut table = Table::new(client_results);
+    table
         .with(Style::ascii())
         .with(Margin::new(0, 0, 1, 1))
         .with(Panel::header(header))
+        .with(
+            Modify::new(Rows::first())
+                .with(Span::column(9))
+                .with(Alignment::center()),
+        );
+    if !tags.is_empty() {
+        table.with(Panel::footer(format!("tags: {}", tags_msg(tags))));
+    }
+    let mut table = table.to_string();
+    if client_results.iter().any(|r| r.received > 0) {
+        table = format!("{table}\n{}", distribution_table(client_results));
+    }
+    if histogram && client_results.iter().any(|r| r.received > 0) {
+        table = format!("{table}\n{}", histogram_table(client_results));
+    }
+    if client_results.iter().any(|r| !r.phases.is_empty()) {
+        table = format!("{table}\n{}", phases_table(client_results));
+    }
+    if client_results.iter().any(|r| r.loss_bursts.bursts > 0) {
+        table = format!("{table}\n{}", loss_bursts_table(client_results));
+    }
+    if client_results.iter().any(|r| r.passed.is_some()) {
+        table = format!("{table}\n{}", assertion_table(client_results));
+    }
+    table
+}
+
+/// Returns a table of the jitter, deviation and percentiles of the
+/// times to each destination with replies
+fn distribution_table(client_results: &[ClientResult]) -> String {
+    let mut builder = Builder::default();
+    builder.set_header([
+        "Destination",
+        "Jitter (ms)",
+        "Std Dev (ms)",
+        "P50 (ms)",
+        "P90 (ms)",
+        "P95 (ms)",
+        "P99 (ms)",
+    ]);
+    for result in client_results.iter().filter(|r| r.received > 0) {
+        builder.push_record([
+            result.display_destination().to_owned(),
+            format!("{:.3}", result.jitter),
+            format!("{:.3}", result.std_dev),
+            format!("{:.3}", result.p50),
+            format!("{:.3}", result.p90),
+            format!("{:.3}", result.p95),
+            format!("{:.3}", result.p99),
+        ]);
+    }
+    builder
+        .build()
+        .with(Style::ascii())
+        .with(Margin::new(0, 0, 0, 1))
+        .with(Panel::header("--- Latency Distribution ---"))
+        .with(
+            Modify::new(Rows::first())
+                .with(Span::column(7))
+                .with(Alignment::center()),
+        )
+        .to_string()
+}
+
+/// Returns a table of the replies from each destination in each latency
+/// bucket, with a bar scaled to the largest bucket of the destination.
+/// Empty buckets below the fastest and above the slowest reply are left out.
+fn histogram_table(client_results: &[ClientResult]) -> String {
+    let mut builder = Builder::default();
+    builder.set_header(["Destination", "Latency (ms)", "Replies", ""]);
+    for result in client_results.iter().filter(|r| r.received > 0) {
+        let first = result.histogram.iter().position(|count| *count > 0).unwrap_or_default();
+        let last = result
+            .histogram
+            .iter()
+            .rposition(|count| *count > 0)
+            .unwrap_or_default();
+        let largest = result.histogram.iter().max().copied().unwrap_or_default().max(1);
+        for (bucket, count) in result.histogram.iter().enumerate().take(last + 1).skip(first) {
+            let bucket_msg = match (
+                bucket.checked_sub(1).map(|b| HISTOGRAM_BOUNDS[b]),
+                HISTOGRAM_BOUNDS.get(bucket),
+            ) {
+                (None, Some(upper)) => format!("< {upper}"),
+                (Some(lower), Some(upper)) => format!("{lower}-{upper}"),
+                (Some(lower), None) => format!(">= {lower}"),
+                (None, None) => String::new(),
+            };
+            builder.push_record([
+                match bucket == first {
+                    true => result.display_destination().to_owned(),
+                    false => String::new(),
+                },
+                bucket_msg,
+                count.to_string(),
+                "#".repeat((*count as usize * HISTOGRAM_WIDTH).div_ceil(largest as usize)),
+            ]);
+        }
+    }
+    builder
+        .build()
+        .with(Style::ascii())
+        .with(Margin::new(0, 0, 0, 1))
+        .with(Panel::header("--- Latency Histogram ---"))
+        .with(
+            Modify::new(Rows::first())
+                .with(Span::column(4))
+                .with(Alignment::center()),
+        )
+        .to_string()
+}
+
+/// Returns a table of whether each destination passed the assertion
+fn assertion_table(client_results: &[ClientResult]) -> String {
+    let mut builder = Builder::default();
+    builder.set_header(["Destination", "P50 (ms)", "P95 (ms)", "P99 (ms)", "Result"]);
+    for result in client_results {
+        builder.push_record([
+            result.display_destination().to_owned(),
+            format!("{:.3}", result.p50),
+            format!("{:.3}", result.p95),
+            format!("{:.3}", result.p99),
+            match result.passed {
+                Some(true) => "pass".to_owned(),
+                Some(false) => "fail".to_owned(),
+                None => "-".to_owned(),
+            },
+        ]);
+    }
+    builder
+        .build()
+        .with(Style::ascii())
+        .with(Margin::new(0, 0, 0, 1))
+        .with(Panel::header("--- Assertion ---"))
+        .with(
+            Modify::new(Rows::first())
+                .with(Span::column(5))
+                .with(Alignment::center()),
+        )
+        .to_string()
+}
+
+/// Returns a table of the average time of each phase by destination
+fn phases_table(client_results: &[ClientResult]) -> String {
+    let columns = [Phase::Connect, Phase::Tls, Phase::App];
+    let mut builder = Builder::default();
+    builder.set_header(["Destination", "Connect (ms)", "TLS (ms)", "App (ms)"]);
+    for result in client_results.iter().filter(|r| !r.phases.is_empty()) {
+        let mut record = vec![result.display_destination().to_owned()];
+        for column in columns {
+            record.push(match result.phases.iter().find(|(phase, _)| *phase == column) {
+                Some((_, time)) => format!("{time:.3}"),
+                None => "-".to_owned(),
+            });
+        }
+        builder.push_record(record);
+    }
+    builder
+        .build()
+        .with(Style::ascii())
+        .with(Margin::new(0, 0, 0, 1))
+        .with(Panel::header("--- Latency Breakdown ---"))
+        .with(
+            Modify::new(Rows::first())
+                .with(Span::column(4))
+                .with(Alignment::center()),
+        )
+        .to_string()
+}
+
+/// Returns a table of loss burst statistics for destinations with loss
+fn loss_bursts_table(client_results: &[ClientResult]) -> String {
+    let mut builder = Builder::default();
+    builder.set_header([
+        "Destination",
+        "Bursts",
+        "Longest",
+        "Mean Length",
+        "p",
+        "r",
+        "Pattern",
+        "First Failure",
+        "Last Failure",
+    ]);
+    for result in client_results.iter().filter(|r| r.loss_bursts.bursts > 0) {
+        let loss_bursts = &result.loss_bursts;
+        builder.push_record([
+            result.display_destination().to_owned(),
+            loss_bursts.bursts.to_string(),
+            loss_bursts.longest.to_string(),
+            format!("{:.2}", loss_bursts.mean_length),
+            format!("{:.3}", loss_bursts.p),
+            format!("{:.3}", loss_bursts.r),
+            loss_bursts.pattern.to_string(),
+            result.first_failure.to_owned().unwrap_or("-".to_owned()),
+            result.last_failure.to_owned().unwrap_or("-".to_owned()),
+        ]);
+    }
+    builder
+        .build()
+        .with(Style::ascii())
+        .with(Margin::new(0, 0, 0, 1))
+        .with(Panel::header("--- Loss Bursts ---"))
         .with(
             Modify::new(Rows::first())
                 .with(Span::column(9))
@@ -112,7 +584,589 @@ pub fn client_summary_table_msg(
         .to_string()
 }
 
+/// Returns a table comparing the latency samples of two destinations
+pub fn compare_table_msg(
+    a: &str,
+    a_samples: &[f64],
+    b: &str,
+    b_samples: &[f64],
+    results: &Vec<SignificanceResult>,
+) -> String {
+    let header = format!(
+        "--- Comparison of {} (n={}, median={:.3}ms) and {} (n={}, median={:.3}ms) ---",
+        a,
+        a_samples.len(),
+        median(a_samples),
+        b,
+        b_samples.len(),
+        median(b_samples),
+    );
+    Table::new(results)
+        .with(Style::ascii())
+        .with(Margin::new(0, 0, 1, 1))
+        .with(Panel::header(header))
+        .with(
+            Modify::new(Rows::first())
+                .with(Span::column(4))
+                .with(Alignment::center()),
+        )
+        .to_string()
+}
+
+/// Returns a table comparing the answers and latency of each resolver view
+pub fn resolver_views_table_msg(dst_host: &String, dst_port: u16, views: &[(String, Vec<ClientResult>)]) -> String {
+    let answers = |results: &Vec<ClientResult>| {
+        let mut answers: Vec<String> = results.iter().map(|r| r.destination.to_owned()).collect();
+        answers.sort();
+        answers
+    };
+    let first_answers = views.first().map(|(_, results)| answers(results)).unwrap_or_default();
+
+    let mut builder = Builder::default();
+    builder.set_header(["Resolver", "Answers", "Same Answers", "Sent", "Lost", "Avg (ms)"]);
+    for (resolver, results) in views {
+        let view_answers = answers(results);
+        let received: Vec<&ClientResult> = results.iter().filter(|r| r.received > 0).collect();
+        let avg = match received.is_empty() {
+            true => 0.0,
+            false => received.iter().map(|r| r.avg).sum::<f64>() / received.len() as f64,
+        };
+        builder.push_record([
+            resolver.to_owned(),
+            view_answers.join("\n"),
+            match view_answers == first_answers {
+                true => "yes".to_owned(),
+                false => "no".to_owned(),
+            },
+            results.iter().map(|r| r.sent as u32).sum::<u32>().to_string(),
+            results.iter().map(|r| r.lost as u32).sum::<u32>().to_string(),
+            format!("{:.3}", avg),
+        ]);
+    }
+    builder
+        .build()
+        .with(Style::ascii())
+        .with(Margin::new(0, 0, 1, 1))
+        .with(Panel::header(format!(
+            "--- Resolver views for {}:{} ---",
+            dst_host, dst_port
+        )))
+        .with(
+            Modify::new(Rows::first())
+                .with(Span::column(6))
+                .with(Alignment::center()),
+        )
+        .to_string()
+}
+
+/// Returns a table of the loss and latency of each destination in two
+/// runs. A destination that regressed is marked with `!`.
+pub fn run_diff_table_msg(before: &str, after: &str, diffs: &[RunDiff]) -> String {
+    let change = |diff: &RunDiff, stat: fn(&ClientResult) -> f64, loss: bool| match (&diff.before, &diff.after) {
+        (Some(b), Some(a)) if loss => format!("{:.2} -> {:.2} ({:+.2})", stat(b), stat(a), stat(a) - stat(b)),
+        (Some(b), Some(a)) if b.received > 0 && a.received > 0 => format!(
+            "{:.3} -> {:.3} ({:+.1}%)",
+            stat(b),
+            stat(a),
+            (stat(a) - stat(b)) / stat(b) * 100.0
+        ),
+        _ => "-".to_owned(),
+    };
+    let mut builder = Builder::default();
+    builder.set_header(["Destination", "Loss (%)", "Avg (ms)", "P95 (ms)", "Regressed"]);
+    for diff in diffs {
+        builder.push_record([
+            diff.destination.to_owned(),
+            change(diff, |r| r.loss_percent, true),
+            change(diff, |r| r.avg, false),
+            change(diff, |r| r.p95, false),
+            match (&diff.before, &diff.after, diff.regressed) {
+                (_, _, true) => "!".to_owned(),
+                (None, _, _) => format!("only in {after}"),
+                (_, None, _) => format!("only in {before}"),
+                _ => String::new(),
+            },
+        ]);
+    }
+    builder
+        .build()
+        .with(Style::ascii())
+        .with(Margin::new(0, 0, 1, 1))
+        .with(Panel::header(format!("--- Changes from {before} to {after} ---")))
+        .with(
+            Modify::new(Rows::first())
+                .with(Span::column(5))
+                .with(Alignment::center()),
+        )
+        .to_string()
+}
+
+/// Returns a matrix of the loss and average latency of each destination
+/// from each vantage point. A destination only unreachable from one
+/// vantage point is marked with `!`.
+pub fn vantage_matrix_table_msg(vantages: &[(String, Vec<ClientResult>)]) -> String {
+    let mut destinations: Vec<&String> = vantages
+        .iter()
+        .flat_map(|(_, results)| results.iter().map(|r| &r.destination))
+        .collect();
+    destinations.sort_by_cached_key(|destination| AddrKey::new(destination));
+    destinations.dedup();
+
+    let mut header = vec!["Destination".to_owned()];
+    header.extend(vantages.iter().map(|(vantage, _)| vantage.to_owned()));
+    header.push("Uniquely Unreachable".to_owned());
+
+    let mut builder = Builder::default();
+    builder.set_header(header.clone());
+    for destination in destinations {
+        let results: Vec<Option<&ClientResult>> = vantages
+            .iter()
+            .map(|(_, results)| results.iter().find(|r| &r.destination == destination))
+            .collect();
+        let unreachable: Vec<usize> = (0..results.len())
+            .filter(|i| matches!(results[*i], Some(r) if r.received == 0))
+            .collect();
+        let probed = results.iter().flatten().count();
+        let unique = match unreachable[..] {
+            [i] if probed > 1 => Some(i),
+            _ => None,
+        };
+
+        let mut row = vec![destination.to_owned()];
+        for (i, result) in results.iter().enumerate() {
+            row.push(match result {
+                Some(r) if r.received == 0 => format!("{:.2}%", r.loss_percent),
+                Some(r) => format!("{:.2}% {:.3}ms", r.loss_percent, r.avg),
+                None => "-".to_owned(),
+            });
+            if unique == Some(i) {
+                row[i + 1] = format!("{} !", row[i + 1]);
+            }
+        }
+        row.push(unique.map(|i| vantages[i].0.to_owned()).unwrap_or_default());
+        builder.push_record(row);
+    }
+    builder
+        .build()
+        .with(Style::ascii())
+        .with(Margin::new(0, 0, 1, 1))
+        .with(Panel::header("--- Loss and average latency by vantage point ---"))
+        .with(
+            Modify::new(Rows::first())
+                .with(Span::column(header.len()))
+                .with(Alignment::center()),
+        )
+        .to_string()
+}
+
+/// Returns the availability, latency percentile and outage tables of each group of an SLA report
+pub fn sla_report_table_msg(from: OffsetDateTime, to: OffsetDateTime, groups: &[SlaGroup]) -> String {
+    let period = format!("{} to {}", timestamp_msg(from), timestamp_msg(to));
+    let mut tables = Vec::new();
+    for group in groups {
+        let mut builder = Builder::default();
+        builder.set_header([
+            "Destination",
+            "Protocol",
+            "Sent",
+            "Received",
+            "Availability",
+            "p50 (ms)",
+            "p90 (ms)",
+            "p95 (ms)",
+            "p99 (ms)",
+            "Max (ms)",
+        ]);
+        for result in &group.results {
+            builder.push_record([
+                result.destination.to_owned(),
+                result.protocol.to_owned(),
+                result.sent.to_string(),
+                result.received.to_string(),
+                format!("{:.3}%", result.availability),
+                format!("{:.3}", result.p50),
+                format!("{:.3}", result.p90),
+                format!("{:.3}", result.p95),
+                format!("{:.3}", result.p99),
+                format!("{:.3}", result.max),
+            ]);
+        }
+        tables.push(
+            builder
+                .build()
+                .with(Style::ascii())
+                .with(Margin::new(0, 0, 1, 1))
+                .with(Panel::header(format!("--- SLA report: {} ({period}) ---", group.name)))
+                .with(
+                    Modify::new(Rows::first())
+                        .with(Span::column(10))
+                        .with(Alignment::center()),
+                )
+                .to_string(),
+        );
+
+        if group.outages.is_empty() {
+            continue;
+        }
+        let mut builder = Builder::default();
+        builder.set_header(["Destination", "Start", "End", "Duration", "Failed Probes"]);
+        for outage in &group.outages {
+            builder.push_record([
+                outage.destination.to_owned(),
+                timestamp_msg(outage.start),
+                outage.end.map(timestamp_msg).unwrap_or_else(|| "ongoing".to_owned()),
+                outage_duration_msg(outage, to),
+                outage.failures.to_string(),
+            ]);
+        }
+        tables.push(
+            builder
+                .build()
+                .with(Style::ascii())
+                .with(Margin::new(0, 0, 1, 1))
+                .with(Panel::header(format!("--- Outages: {} ---", group.name)))
+                .with(
+                    Modify::new(Rows::first())
+                        .with(Span::column(5))
+                        .with(Alignment::center()),
+                )
+                .to_string(),
+        );
+    }
+    match tables.is_empty() {
+        true => format!("No results found from {period}."),
+        false => tables.join("\n"),
+    }
+}
+
+/// Returns the health of a target and the result of each of its probes
+pub fn health_table_msg(name: &str, policy: HealthPolicy, healthy: bool, probes: &[ProbeHealth]) -> String {
+    let status = match healthy {
+        true => "HEALTHY",
+        false => "UNHEALTHY",
+    };
+    let mut builder = Builder::default();
+    builder.set_header(["Profile", "Protocol", "Destination", "Sent", "Received", "Status"]);
+    for probe in probes {
+        builder.push_record([
+            probe.profile.to_owned(),
+            probe.protocol.to_string().to_uppercase(),
+            probe.destinations.join(", "),
+            probe.sent.to_string(),
+            probe.received.to_string(),
+            match (&probe.error, probe.passed) {
+                (Some(error), _) => format!("error: {error}"),
+                (None, true) => "pass".to_owned(),
+                (None, false) => "fail".to_owned(),
+            },
+        ]);
+    }
+    builder
+        .build()
+        .with(Style::ascii())
+        .with(Margin::new(0, 0, 1, 1))
+        .with(Panel::header(format!("--- {name}: {status} ({policy}) ---")))
+        .with(
+            Modify::new(Rows::first())
+                .with(Span::column(6))
+                .with(Alignment::center()),
+        )
+        .to_string()
+}
+
+/// Returns a table of the requests a server received from each source
+#[cfg(feature = "server")]
+pub fn server_sources_table_msg(protocol: ConnectMethod, summaries: &[SourceSummary]) -> String {
+    let mut builder = Builder::default();
+    builder.set_header(["Source", "Requests", "Bytes", "Avg One Way (ms)"]);
+    for summary in summaries {
+        builder.push_record([
+            summary.source.to_owned(),
+            summary.requests.to_string(),
+            summary.bytes.to_string(),
+            format!("{:.3}", summary.avg_one_way),
+        ]);
+    }
+    builder
+        .build()
+        .with(Style::ascii())
+        .with(Margin::new(0, 0, 1, 1))
+        .with(Panel::header(format!(
+            "--- {} server sources ---",
+            protocol.to_string().to_uppercase()
+        )))
+        .with(
+            Modify::new(Rows::first())
+                .with(Span::column(4))
+                .with(Alignment::center()),
+        )
+        .to_string()
+}
+
+/// Returns a table of the open ports and services of each host probed on several ports
+pub fn service_summary_table_msg(protocol: ConnectMethod, hosts: &[HostServices]) -> String {
+    let ports = |ports: &[u16]| match ports.is_empty() {
+        true => "-".to_owned(),
+        false => ports
+            .iter()
+            .map(|port| port.to_string())
+            .collect::<Vec<String>>()
+            .join(", "),
+    };
+    let mut builder = Builder::default();
+    builder.set_header(["Host", "Open", "Closed", "Services"]);
+    for host in hosts {
+        builder.push_record([
+            host.host.to_owned(),
+            ports(&host.open),
+            ports(&host.closed),
+            match host.services.is_empty() {
+                true => "-".to_owned(),
+                false => host
+                    .services
+                    .iter()
+                    .map(|(port, service)| format!("{port}: {service}"))
+                    .collect::<Vec<String>>()
+                    .join("\n"),
+            },
+        ]);
+    }
+    builder
+        .build()
+        .with(Style::ascii())
+        .with(Margin::new(0, 0, 1, 1))
+        .with(Panel::header(format!(
+            "--- {} service summary ---",
+            protocol.to_string().to_uppercase()
+        )))
+        .with(
+            Modify::new(Rows::first())
+                .with(Span::column(4))
+                .with(Alignment::center()),
+        )
+        .to_string()
+}
+
+/// Returns the likely causes of the failures of a run, and the commands to narrow them down
+pub fn advice_table_msg(advice: &[Advice]) -> String {
+    let mut builder = Builder::default();
+    builder.set_header(["Likely Cause", "Next Command"]);
+    for a in advice {
+        builder.push_record([a.cause.to_owned(), a.command.to_owned()]);
+    }
+    builder
+        .build()
+        .with(Style::ascii())
+        .with(Margin::new(0, 0, 1, 1))
+        .with(Panel::header("--- Recommendations ---"))
+        .with(
+            Modify::new(Rows::first())
+                .with(Span::column(2))
+                .with(Alignment::center()),
+        )
+        .to_string()
+}
+
+/// Returns a table of the answer changes seen by a resolution probe
+pub fn dns_changes_table_msg(host: &str, changes: &[(String, DnsRecord)]) -> String {
+    let mut builder = Builder::default();
+    builder.set_header(["Time", "Answers", "TTL (s)", "Propagation (s)", "TTL Exceeded"]);
+    for (time, record) in changes {
+        builder.push_record([
+            time.to_owned(),
+            match record.answers.is_empty() {
+                true => "-".to_owned(),
+                false => record.answers.join(", "),
+            },
+            record.ttl.to_string(),
+            record
+                .propagation_delay
+                .map(|delay| format!("{delay:.1}"))
+                .unwrap_or("-".to_owned()),
+            record.ttl_exceeded.to_string(),
+        ]);
+    }
+    builder
+        .build()
+        .with(Style::ascii())
+        .with(Margin::new(0, 0, 1, 1))
+        .with(Panel::header(format!("--- Answer changes for {host} ---")))
+        .with(
+            Modify::new(Rows::first())
+                .with(Span::column(5))
+                .with(Alignment::center()),
+        )
+        .to_string()
+}
+
+/// Returns the evidence collected when a destination started failing
+pub fn escalation_table_msg(escalation: &Escalation) -> String {
+    let mut builder = Builder::default();
+    builder.set_header(["Check", "Result"]);
+    let addresses = match escalation.addresses.is_empty() {
+        true => "did not resolve".to_owned(),
+        false if !escalation.addresses.contains(&escalation.destination) => {
+            format!("{} (destination no longer resolves)", escalation.addresses.join(", "))
+        }
+        false => escalation.addresses.join(", "),
+    };
+    builder.push_record([format!("DNS {}", escalation.host), addresses]);
+    builder.push_record(["TLS".to_owned(), escalation_probe_msg(&escalation.tls)]);
+    builder.push_record([
+        "Gateway".to_owned(),
+        match &escalation.gateway {
+            Some(gateway) => format!("{} {}", gateway.destination, escalation_probe_msg(gateway)),
+            None => "not found".to_owned(),
+        },
+    ]);
+    for unsupported in &escalation.unsupported {
+        builder.push_record(["Unsupported".to_owned(), unsupported.to_owned()]);
+    }
+    let table = builder
+        .build()
+        .with(Style::ascii())
+        .with(Margin::new(0, 0, 1, 1))
+        .with(Panel::header(format!(
+            "--- Escalation for {} ({}) at {} ---",
+            escalation.destination, escalation.result, escalation.time
+        )))
+        .with(
+            Modify::new(Rows::first())
+                .with(Span::column(2))
+                .with(Alignment::center()),
+        )
+        .to_string();
+    if escalation.path.is_empty() {
+        return table;
+    }
+
+    let mut builder = Builder::default();
+    builder.set_header(["Hop", "Router", "Time (ms)"]);
+    for hop in &escalation.path {
+        builder.push_record([
+            hop.ttl.to_string(),
+            hop.router.map(|ip| ip.to_string()).unwrap_or("*".to_owned()),
+            match hop.time >= 0.0 {
+                true => format!("{:.3}", hop.time),
+                false => "-".to_owned(),
+            },
+        ]);
+    }
+    let path = builder
+        .build()
+        .with(Style::ascii())
+        .with(Margin::new(0, 0, 0, 1))
+        .with(Panel::header("--- Path ---"))
+        .with(
+            Modify::new(Rows::first())
+                .with(Span::column(3))
+                .with(Alignment::center()),
+        )
+        .to_string();
+    format!("{table}\n{path}")
+}
+
+/// Returns the result of a probe made while escalating
+fn escalation_probe_msg(record: &ConnectRecord) -> String {
+    match (record.success, &record.tls) {
+        (true, Some(tls)) => format!("{} {} time={:.3}ms", record.result, tls.version, record.time),
+        (true, None) => format!("{} time={:.3}ms", record.result, record.time),
+        (false, _) => match &record.error_msg {
+            Some(error_msg) => format!("{} {error_msg}", record.result),
+            None => record.result.to_string(),
+        },
+    }
+}
+
+/// Returns the evidence collected when a destination started failing as a JSON document
+pub fn escalation_json_msg(escalation: &Escalation) -> String {
+    serde_json::json!({
+        "schema_version": SCHEMA_VERSION,
+        "escalation": escalation,
+    })
+    .to_string()
+}
+
+/// Returns the path MTU discovered to each address of a host
+pub fn pmtu_table_msg(host: &str, records: &[PmtuRecord]) -> String {
+    let mut builder = Builder::default();
+    builder.set_header([
+        "Destination",
+        "Path MTU",
+        "Interface MTU",
+        "Fragmentation At",
+        "Reported MTU",
+    ]);
+    for record in records {
+        builder.push_record([
+            record.destination.to_owned(),
+            record.path_mtu.map(|mtu| mtu.to_string()).unwrap_or("-".to_owned()),
+            record.interface_mtu.to_string(),
+            record
+                .fragmentation_at
+                .map(|ip| ip.to_string())
+                .unwrap_or("-".to_owned()),
+            record.reported_mtu.map(|mtu| mtu.to_string()).unwrap_or("-".to_owned()),
+        ]);
+    }
+    builder
+        .build()
+        .with(Style::ascii())
+        .with(Margin::new(0, 0, 1, 1))
+        .with(Panel::header(format!("--- Path MTU to {host} ---")))
+        .with(
+            Modify::new(Rows::first())
+                .with(Span::column(5))
+                .with(Alignment::center()),
+        )
+        .to_string()
+}
+
+/// Returns a destination as `host:port`, with IPv6 addresses in brackets
+pub fn destination_msg(host: &str, port: u16) -> String {
+    match host.contains(':') {
+        true => format!("[{host}]:{port}"),
+        false => format!("{host}:{port}"),
+    }
+}
+
+/// Returns the interim statistics of a destination during a run
+pub fn run_totals_msg(destination: &str, totals: &RunTotals) -> String {
+    format!(
+        "{destination} sent={} received={} loss={:.2}% min={:.3}ms avg={:.3}ms max={:.3}ms",
+        totals.sent,
+        totals.received,
+        totals.loss_percent(),
+        totals.min,
+        totals.avg(),
+        totals.max,
+    )
+}
+
+/// Returns a warning that probes failed as the local host ran out of resources
+pub fn resource_exhaustion_msg(exhausted: usize, limit: usize) -> String {
+    format!(
+        "warning: {exhausted} probes failed as the local host ran out of resources \
+        (open files, socket buffers or ephemeral ports), these are not packet loss. \
+        Probes in flight were limited to {limit}."
+    )
+}
+
+/// Returns the value of the probe header of HTTP requests, identifying the run and agent
+pub fn probe_identity_msg(run_id: &str, agent_name: &str) -> String {
+    format!("run={run_id}; agent={agent_name}")
+}
+
+/// Returns tags as a `key=value` list
+pub fn tags_msg(tags: &BTreeMap<String, String>) -> String {
+    tags.iter()
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
 /// Returns a server connection summary message
+#[cfg(feature = "server")]
 pub fn server_conn_success_msg(
     result: ConnectResult,
     protocol: ConnectMethod,
@@ -145,12 +1199,96 @@ pub fn server_conn_success_msg(
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
     use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 
-    use crate::core::common::HostRecord;
+    use crate::core::common::{HostRecord, LossBursts, LossPattern, PeerRecord, Phase, ServiceTarget};
     use crate::core::konst::CLI_HEADER_MSG;
     use crate::util::message::*;
 
+    #[test]
+    fn client_result_msg_with_peer_is_expected() {
+        let record = ConnectRecord {
+            result: ConnectResult::Pong,
+            protocol: ConnectMethod::UDP,
+            source: "192.0.2.1:50000".to_owned(),
+            destination: "192.0.2.2:13337".to_owned(),
+            time: 1.5,
+            success: true,
+            error_msg: None,
+            peer: Some(PeerRecord {
+                uuid: "d6c1b9a1".to_owned(),
+                one_way_time: 0.75,
+                round_trip_time: 1.5,
+                receive_time_utc: "2024-01-01 0:00:00.0 +00:00:00".to_owned(),
+                return_time: 0.25,
+                clock_offset: 0.25,
+            }),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            client_result_msg(&record),
+            "pong => proto=UDP src=192.0.2.1:50000 dst=192.0.2.2:13337 time=1.500ms one_way=0.750ms return=0.250ms offset=0.250ms"
+        );
+    }
+
+    #[test]
+    fn vantage_matrix_marks_uniquely_unreachable_destinations() {
+        let result = |destination: &str, received: u16| ClientResult {
+            destination: destination.to_owned(),
+            protocol: ConnectMethod::TCP,
+            sent: 2,
+            received,
+            lost: 2 - received,
+            loss_percent: (2 - received) as f64 * 50.0,
+            min: 1.0,
+            max: 1.0,
+            avg: 1.0,
+            p50: 1.0,
+            p90: 1.0,
+            p95: 1.0,
+            p99: 1.0,
+            jitter: 0.0,
+            std_dev: 0.0,
+            histogram: vec![],
+            loss_bursts: LossBursts::default(),
+            first_failure: None,
+            last_failure: None,
+            phases: vec![],
+            passed: None,
+            label: None,
+        };
+        let vantages = vec![
+            (
+                "syd".to_owned(),
+                vec![result("192.0.2.1:443", 2), result("192.0.2.2:443", 0)],
+            ),
+            (
+                "lon".to_owned(),
+                vec![result("192.0.2.1:443", 0), result("192.0.2.2:443", 0)],
+            ),
+            ("nyc".to_owned(), vec![result("192.0.2.1:443", 1)]),
+        ];
+
+        let table = vantage_matrix_table_msg(&vantages);
+        let rows: Vec<&str> = table.lines().filter(|l| l.starts_with("| 192.0.2.")).collect();
+        assert_eq!(
+            rows[0],
+            "| 192.0.2.1:443 | 0.00% 1.000ms | 100.00% ! | 50.00% 1.000ms | lon                  |"
+        );
+        assert_eq!(
+            rows[1],
+            "| 192.0.2.2:443 | 100.00%       | 100.00%   | -              |                      |"
+        );
+    }
+
+    #[test]
+    fn destination_msg_is_expected() {
+        assert_eq!(destination_msg("host1", 443), "host1:443");
+        assert_eq!(destination_msg("2001:db8::1", 443), "[2001:db8::1]:443");
+    }
+
     #[test]
     fn resolved_ips_msg_with_no_ips_is_expected() {
         let host_record = HostRecord {
@@ -210,6 +1348,27 @@ mod tests {
         assert_eq!(msg, "Connecting to 198.51.100.1:443 via TCP");
     }
 
+    #[test]
+    fn path_header_msg_is_expected() {
+        let msg = path_header_msg(&"/var/run/docker.sock".to_owned(), ConnectMethod::UNIX);
+
+        assert_eq!(msg, "Connecting to /var/run/docker.sock via UNIX");
+    }
+
+    #[test]
+    fn unix_header_msg_names_the_time_measured() {
+        let path = "/var/run/docker.sock".to_owned();
+
+        assert_eq!(
+            unix_header_msg(&path, ConnectMethod::UNIX),
+            "Connecting to /var/run/docker.sock via UNIX (connect time)"
+        );
+        assert_eq!(
+            unix_header_msg(&path, ConnectMethod::UNIXGRAM),
+            "Connecting to /var/run/docker.sock via UNIXGRAM (echo time)"
+        );
+    }
+
     #[test]
     fn cli_header_msg_is_expected() {
         assert_eq!(
@@ -218,6 +1377,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "server")]
     #[test]
     fn server_start_msg_is_expected() {
         let listen_ip: IpAddr = "127.0.0.1".parse::<IpAddr>().unwrap();
@@ -242,6 +1402,19 @@ mod tests {
             min: 234.0,
             max: 254.0,
             avg: 243.0,
+            p50: 241.0,
+            p90: 254.0,
+            p95: 254.0,
+            p99: 254.0,
+            jitter: 10.0,
+            std_dev: 7.5,
+            histogram: vec![],
+            loss_bursts: LossBursts::default(),
+            first_failure: None,
+            last_failure: None,
+            phases: vec![],
+            passed: None,
+            label: None,
         };
 
         let summary_table = client_summary_table_msg(
@@ -249,6 +1422,8 @@ mod tests {
             443,
             ConnectMethod::TCP,
             &vec![client_results],
+            &BTreeMap::new(),
+            false,
         );
 
         let expected = "                                                                                                \n\
@@ -258,11 +1433,258 @@ mod tests {
         | Destination  | Protocol | Sent | Received | Lost | Loss (%) | Min (ms) | Max (ms) | Avg (ms) |\n\
         +--------------+----------+------+----------+------+----------+----------+----------+----------+\n\
         | 198.51.100.1 | TCP      | 4    | 4        | 0    | 0.00     | 234.000  | 254.000  | 243.000  |\n\
-        +--------------+----------+------+----------+------+----------+----------+----------+----------+\n                                                                                                ";
+        +--------------+----------+------+----------+------+----------+----------+----------+----------+\n                                                                                                \n\
+        +--------------+-------------+--------------+----------+----------+----------+----------+\n\
+        |                             --- Latency Distribution ---                              |\n\
+        +--------------+-------------+--------------+----------+----------+----------+----------+\n\
+        | Destination  | Jitter (ms) | Std Dev (ms) | P50 (ms) | P90 (ms) | P95 (ms) | P99 (ms) |\n\
+        +--------------+-------------+--------------+----------+----------+----------+----------+\n\
+        | 198.51.100.1 | 10.000      | 7.500        | 241.000  | 254.000  | 254.000  | 254.000  |\n\
+        +--------------+-------------+--------------+----------+----------+----------+----------+\n                                                                                         ";
 
         assert_eq!(summary_table, expected);
     }
 
+    #[test]
+    fn client_summary_table_msg_with_histogram_has_buckets() {
+        let client_results = ClientResult {
+            destination: "198.51.100.1".to_owned(),
+            protocol: ConnectMethod::TCP,
+            sent: 5,
+            received: 5,
+            lost: 0,
+            loss_percent: 0.0,
+            min: 1.5,
+            max: 12.0,
+            avg: 4.0,
+            p50: 2.0,
+            p90: 12.0,
+            p95: 12.0,
+            p99: 12.0,
+            jitter: 0.0,
+            std_dev: 0.0,
+            histogram: vec![0, 4, 0, 1, 0, 0, 0, 0, 0, 0],
+            loss_bursts: LossBursts::default(),
+            first_failure: None,
+            last_failure: None,
+            phases: vec![],
+            passed: None,
+            label: None,
+        };
+        let client_results = vec![client_results];
+        let summary_table = |histogram: bool| {
+            client_summary_table_msg(
+                &"stuff.things".to_string(),
+                443,
+                ConnectMethod::TCP,
+                &client_results,
+                &BTreeMap::new(),
+                histogram,
+            )
+        };
+
+        assert!(!summary_table(false).contains("--- Latency Histogram ---"));
+        let summary_table = summary_table(true);
+        assert!(summary_table.contains("--- Latency Histogram ---"));
+        assert!(summary_table.contains(&format!(
+            "| 198.51.100.1 | 1-5          | 4       | {} |",
+            "#".repeat(40)
+        )));
+        assert!(summary_table.contains("|              | 5-10         | 0       |"));
+        assert!(summary_table.contains(&format!(
+            "|              | 10-20        | 1       | {:<40} |",
+            "#".repeat(10)
+        )));
+        assert!(!summary_table.contains("< 1"));
+    }
+
+    #[test]
+    fn client_summary_json_msg_is_expected() {
+        let client_results = ClientResult {
+            destination: "198.51.100.1:443".to_owned(),
+            protocol: ConnectMethod::TCP,
+            sent: 4,
+            received: 3,
+            lost: 1,
+            loss_percent: 25.0,
+            min: 234.0,
+            max: 254.0,
+            avg: 243.0,
+            p50: 0.0,
+            p90: 0.0,
+            p95: 0.0,
+            p99: 0.0,
+            jitter: 0.0,
+            std_dev: 0.0,
+            histogram: vec![],
+            loss_bursts: LossBursts::default(),
+            first_failure: None,
+            last_failure: None,
+            phases: vec![],
+            passed: None,
+            label: None,
+        };
+        let tags = BTreeMap::from([("site".to_owned(), "syd".to_owned())]);
+
+        let summary = client_summary_json_msg("stuff.things:443", ConnectMethod::TCP, &vec![client_results], &tags);
+        let summary: serde_json::Value = serde_json::from_str(&summary).unwrap();
+
+        assert_eq!(summary["summary"], true);
+        assert_eq!(summary["destination"], "stuff.things:443");
+        assert_eq!(summary["results"][0]["destination"], "198.51.100.1:443");
+        assert_eq!(summary["results"][0]["lost"], 1);
+        assert_eq!(summary["tags"]["site"], "syd");
+    }
+
+    #[test]
+    fn client_summary_table_msg_with_tags_is_expected() {
+        let client_results = ClientResult {
+            destination: "198.51.100.1".to_owned(),
+            protocol: ConnectMethod::TCP,
+            sent: 4,
+            received: 4,
+            lost: 0,
+            loss_percent: 0.0,
+            min: 234.0,
+            max: 254.0,
+            avg: 243.0,
+            p50: 0.0,
+            p90: 0.0,
+            p95: 0.0,
+            p99: 0.0,
+            jitter: 0.0,
+            std_dev: 0.0,
+            histogram: vec![],
+            loss_bursts: LossBursts::default(),
+            first_failure: None,
+            last_failure: None,
+            phases: vec![],
+            passed: None,
+            label: None,
+        };
+        let tags = BTreeMap::from([
+            ("site".to_owned(), "nyc".to_owned()),
+            ("circuit".to_owned(), "ABC123".to_owned()),
+        ]);
+
+        let summary_table = client_summary_table_msg(
+            &"stuff.things".to_string(),
+            443,
+            ConnectMethod::TCP,
+            &vec![client_results],
+            &tags,
+            false,
+        );
+
+        assert!(summary_table.contains("| tags: circuit=ABC123,site=nyc "));
+    }
+
+    #[test]
+    fn client_summary_table_msg_with_loss_has_loss_bursts() {
+        let client_results = ClientResult {
+            destination: "198.51.100.1".to_owned(),
+            protocol: ConnectMethod::TCP,
+            sent: 4,
+            received: 2,
+            lost: 2,
+            loss_percent: 50.0,
+            min: 234.0,
+            max: 254.0,
+            avg: 243.0,
+            p50: 0.0,
+            p90: 0.0,
+            p95: 0.0,
+            p99: 0.0,
+            jitter: 0.0,
+            std_dev: 0.0,
+            histogram: vec![],
+            loss_bursts: LossBursts {
+                bursts: 1,
+                longest: 2,
+                mean_length: 2.0,
+                p: 1.0,
+                r: 0.5,
+                pattern: LossPattern::Bursty,
+            },
+            first_failure: Some("2024-01-01 0:00:01.0 +00:00:00".to_owned()),
+            last_failure: Some("2024-01-01 0:00:02.0 +00:00:00".to_owned()),
+            phases: vec![],
+            passed: None,
+            label: None,
+        };
+
+        let summary_table = client_summary_table_msg(
+            &"stuff.things".to_string(),
+            443,
+            ConnectMethod::TCP,
+            &vec![client_results],
+            &BTreeMap::new(),
+            false,
+        );
+
+        assert!(summary_table.contains("--- Loss Bursts ---"));
+        assert!(summary_table.contains("| 198.51.100.1 | 1      | 2       | 2.00        | 1.000 | 0.500 | bursty  |"));
+        assert!(summary_table.contains("| 2024-01-01 0:00:01.0 +00:00:00 | 2024-01-01 0:00:02.0 +00:00:00 |"));
+    }
+
+    #[test]
+    fn client_summary_table_msg_with_phases_has_breakdown() {
+        let client_results = ClientResult {
+            destination: "198.51.100.1:25".to_owned(),
+            protocol: ConnectMethod::SMTP,
+            sent: 1,
+            received: 1,
+            lost: 0,
+            loss_percent: 0.0,
+            min: 3.5,
+            max: 3.5,
+            avg: 3.5,
+            p50: 0.0,
+            p90: 0.0,
+            p95: 0.0,
+            p99: 0.0,
+            jitter: 0.0,
+            std_dev: 0.0,
+            histogram: vec![],
+            loss_bursts: LossBursts::default(),
+            first_failure: None,
+            last_failure: None,
+            phases: vec![(Phase::Connect, 1.0), (Phase::App, 2.5)],
+            passed: None,
+            label: None,
+        };
+
+        let summary_table = client_summary_table_msg(
+            &"mail.things".to_string(),
+            25,
+            ConnectMethod::SMTP,
+            &vec![client_results],
+            &BTreeMap::new(),
+            false,
+        );
+
+        assert!(summary_table.contains("--- Latency Breakdown ---"));
+        assert!(summary_table.contains("| 198.51.100.1:25 | 1.000        | -        | 2.500    |"));
+    }
+
+    #[test]
+    fn service_targets_msg_is_expected() {
+        let targets = vec![ServiceTarget {
+            service: "_sip._tcp.example.com".to_owned(),
+            host: "sip.example.com".to_owned(),
+            port: 5060,
+            priority: 10,
+            weight: 60,
+        }];
+        let msg = service_targets_msg("_sip._tcp.example.com", &targets);
+
+        assert_eq!(
+            msg,
+            "_sip._tcp.example.com expands to 1 target\n sip.example.com:5060 priority=10 weight=60\n"
+        );
+    }
+
+    #[cfg(feature = "server")]
     #[test]
     fn server_conn_success_msg_with_time_is_expected() {
         let msg = server_conn_success_msg(
@@ -279,6 +1701,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "server")]
     #[test]
     fn server_conn_success_msg_without_time_is_expected() {
         let msg = server_conn_success_msg(
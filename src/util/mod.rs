@@ -1,7 +1,35 @@
+pub mod advice;
+pub mod assertion;
+pub mod csv;
 pub mod dns;
+#[cfg(feature = "export")]
+pub mod export;
+#[cfg(feature = "server")]
+pub mod fault;
+pub mod frame;
 pub mod handler;
+#[cfg(feature = "export")]
+pub mod influx;
 pub mod message;
+pub mod nat64;
+#[cfg(feature = "export")]
+pub mod otlp;
 pub mod parser;
+pub mod platform;
+pub mod probe;
+pub mod record;
+pub mod recverr;
+pub mod report;
 pub mod result;
+pub mod rotate;
+pub mod rules;
+pub mod run;
+pub mod schedule;
+pub mod sink;
+pub mod stats;
+pub mod stream;
+#[cfg(feature = "export")]
+pub mod syslog;
 pub mod time;
+pub mod tls;
 pub mod validate;
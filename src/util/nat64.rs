@@ -0,0 +1,182 @@
+use std::fmt::Display;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::core::common::ClientResult;
+
+/// The IPv4 addresses of `ipv4only.arpa` (RFC7050).
+const WELL_KNOWN_IPV4: [Ipv4Addr; 2] = [Ipv4Addr::new(192, 0, 0, 170), Ipv4Addr::new(192, 0, 0, 171)];
+
+/// Valid prefix lengths (RFC6052), longest first.
+const PREFIX_LENGTHS: [u8; 6] = [96, 64, 56, 48, 40, 32];
+
+/// A NAT64 prefix used to embed IPv4 addresses in IPv6 addresses (RFC6052).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Nat64Prefix {
+    pub prefix: Ipv6Addr,
+    pub len: u8,
+}
+
+impl Nat64Prefix {
+    /// Discover the prefix from a DNS64 synthesized address of `ipv4only.arpa`.
+    pub fn from_synthesized(addr: Ipv6Addr) -> Option<Nat64Prefix> {
+        PREFIX_LENGTHS.into_iter().find_map(|len| {
+            let prefix = Nat64Prefix {
+                prefix: mask(addr, len),
+                len,
+            };
+            match prefix.extract(addr) {
+                Some(ip) if WELL_KNOWN_IPV4.contains(&ip) => Some(prefix),
+                _ => None,
+            }
+        })
+    }
+
+    /// Embed an IPv4 address in the prefix.
+    pub fn synthesize(&self, ip: Ipv4Addr) -> Ipv6Addr {
+        let mut octets = self.prefix.octets();
+        for (position, octet) in positions(self.len).zip(ip.octets()) {
+            octets[position] = octet;
+        }
+        Ipv6Addr::from(octets)
+    }
+
+    /// Return the IPv4 address embedded in an address within the prefix.
+    pub fn extract(&self, ip: Ipv6Addr) -> Option<Ipv4Addr> {
+        let octets = ip.octets();
+        // Bits 64 to 71 must be zero for prefixes shorter than 96 bits.
+        if mask(ip, self.len) != self.prefix || (self.len < 96 && octets[8] != 0) {
+            return None;
+        }
+        let mut ipv4 = [0u8; 4];
+        for (octet, position) in ipv4.iter_mut().zip(positions(self.len)) {
+            *octet = octets[position];
+        }
+        Some(Ipv4Addr::from(ipv4))
+    }
+}
+
+impl Display for Nat64Prefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.prefix, self.len)
+    }
+}
+
+/// The positions of the IPv4 octets for a prefix length, skipping octet 8.
+fn positions(len: u8) -> impl Iterator<Item = usize> {
+    (usize::from(len) / 8..16).filter(|i| *i != 8).take(4)
+}
+
+fn mask(ip: Ipv6Addr, len: u8) -> Ipv6Addr {
+    Ipv6Addr::from(u128::from(ip) & (u128::MAX << (128 - u32::from(len))))
+}
+
+/// Return true when a destination only responded via NAT64,
+/// a synthesized destination received a response and no native
+/// IPv4 or IPv6 destination did.
+pub fn nat64_only(client_results: &[ClientResult], prefix: Nat64Prefix) -> bool {
+    let is_synthesized = |result: &ClientResult| match result.destination.parse::<SocketAddr>() {
+        Ok(SocketAddr::V6(socket)) => prefix.extract(*socket.ip()).is_some(),
+        _ => false,
+    };
+    let (synthesized, native): (Vec<&ClientResult>, Vec<&ClientResult>) =
+        client_results.iter().partition(|result| is_synthesized(result));
+    synthesized.iter().any(|result| result.received > 0) && native.iter().all(|result| result.received == 0)
+}
+
+/// Synthesize an IPv6 address for an IPv4 address, other addresses are unchanged.
+pub fn synthesize_ip(ip: IpAddr, prefix: Nat64Prefix) -> IpAddr {
+    match ip {
+        IpAddr::V4(ipv4) => IpAddr::V6(prefix.synthesize(ipv4)),
+        IpAddr::V6(_) => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::common::{ConnectMethod, LossBursts};
+
+    fn client_result(destination: &str, received: u16) -> ClientResult {
+        ClientResult {
+            destination: destination.to_owned(),
+            protocol: ConnectMethod::TCP,
+            sent: 1,
+            received,
+            lost: 1 - received,
+            loss_percent: 100.0 - f64::from(received) * 100.0,
+            min: 0.0,
+            max: 0.0,
+            avg: 0.0,
+            p50: 0.0,
+            p90: 0.0,
+            p95: 0.0,
+            p99: 0.0,
+            jitter: 0.0,
+            std_dev: 0.0,
+            histogram: vec![],
+            loss_bursts: LossBursts::default(),
+            first_failure: None,
+            last_failure: None,
+            phases: vec![],
+            passed: None,
+            label: None,
+        }
+    }
+
+    #[test]
+    fn prefixes_are_discovered_from_synthesized_addresses() {
+        let well_known = Nat64Prefix::from_synthesized("64:ff9b::c000:aa".parse().unwrap()).unwrap();
+        assert_eq!(well_known.to_string(), "64:ff9b::/96");
+
+        let prefix = Nat64Prefix::from_synthesized("2001:db8:c000:aa::".parse().unwrap()).unwrap();
+        assert_eq!(prefix.to_string(), "2001:db8::/32");
+
+        let prefix = Nat64Prefix::from_synthesized("2001:db8:100:c000:0:aa00::".parse().unwrap()).unwrap();
+        assert_eq!(prefix.to_string(), "2001:db8:100::/48");
+
+        assert!(Nat64Prefix::from_synthesized("2001:db8::1".parse().unwrap()).is_none());
+    }
+
+    #[test]
+    fn addresses_are_synthesized_and_extracted() {
+        let ip = Ipv4Addr::new(198, 51, 100, 1);
+        let prefix = Nat64Prefix {
+            prefix: "64:ff9b::".parse().unwrap(),
+            len: 96,
+        };
+        assert_eq!(prefix.synthesize(ip).to_string(), "64:ff9b::c633:6401");
+
+        let prefix = Nat64Prefix {
+            prefix: "2001:db8:100::".parse().unwrap(),
+            len: 56,
+        };
+        let synthesized = prefix.synthesize(ip);
+        assert_eq!(synthesized.to_string(), "2001:db8:100:c6:33:6401::");
+        assert_eq!(prefix.extract(synthesized), Some(ip));
+        assert_eq!(prefix.extract("2001:db8:200::1".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn nat64_only_is_expected() {
+        let prefix = Nat64Prefix {
+            prefix: "64:ff9b::".parse().unwrap(),
+            len: 96,
+        };
+
+        assert!(nat64_only(
+            &[
+                client_result("198.51.100.1:443", 0),
+                client_result("[64:ff9b::c633:6401]:443", 1)
+            ],
+            prefix
+        ));
+        assert!(!nat64_only(
+            &[
+                client_result("198.51.100.1:443", 1),
+                client_result("[64:ff9b::c633:6401]:443", 1)
+            ],
+            prefix
+        ));
+        assert!(!nat64_only(&[client_result("[64:ff9b::c633:6401]:443", 0)], prefix));
+    }
+}
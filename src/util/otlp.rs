@@ -0,0 +1,310 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::core::common::{ConnectRecord, LoggingOptions};
+use crate::core::konst::{OTLP_METRICS_PATH, OTLP_SERVICE_NAME, OTLP_TRACES_PATH};
+use crate::util::export::HttpExporter;
+use crate::util::parser::parse_url;
+use crate::util::sink::RecordSink;
+use crate::util::stats::ExponentialHistogram;
+use crate::util::time::time_now_us;
+
+/// Exports connection records to an OpenTelemetry collector as
+/// OTLP/HTTP JSON spans, one span per record. With histograms, the
+/// latency of each destination is also exported as a cumulative
+/// exponential histogram after each batch, which Prometheus stores
+/// as a native histogram.
+pub struct OtlpExporter {
+    exporter: HttpExporter,
+    resource: Value,
+    metrics: Option<HttpExporter>,
+    histograms: BTreeMap<String, (Vec<Value>, ExponentialHistogram)>,
+    start: u128,
+}
+
+impl OtlpExporter {
+    /// The traces path is used when the endpoint does not have a path,
+    /// and metrics are sent to the metrics path next to the traces path.
+    pub fn new(endpoint: &str, logging_options: &LoggingOptions) -> Result<OtlpExporter> {
+        let mut url = parse_url(endpoint)?;
+        if url.path == "/" {
+            url.path = OTLP_TRACES_PATH.to_owned();
+        }
+        let mut metrics_url = url.clone();
+        metrics_url.path = match url.path.strip_suffix("/traces") {
+            Some(base) => format!("{base}/metrics"),
+            None => OTLP_METRICS_PATH.to_owned(),
+        };
+        let hostname = hostname::get()
+            .map(|h| h.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let resource = json!({
+            "attributes": [
+                string_attribute("service.name", OTLP_SERVICE_NAME),
+                string_attribute("service.version", env!("CARGO_PKG_VERSION")),
+                string_attribute("host.name", &hostname),
+            ],
+        });
+        let headers = vec![("Content-Type".to_owned(), "application/json".to_owned())];
+        Ok(OtlpExporter {
+            metrics: match logging_options.otlp_histograms {
+                true => Some(HttpExporter::new(
+                    "OTLP metrics",
+                    metrics_url,
+                    headers.clone(),
+                    logging_options,
+                )?),
+                false => None,
+            },
+            exporter: HttpExporter::new("OTLP", url, headers, logging_options)?,
+            resource,
+            histograms: BTreeMap::new(),
+            start: time_now_us() * 1000,
+        })
+    }
+
+    fn export_histograms(&mut self, records: &[ConnectRecord], time: u128) -> Result<()> {
+        let Some(metrics) = &self.metrics else {
+            return Ok(());
+        };
+        for record in records.iter().filter(|r| r.success && r.time > 0.0) {
+            let (_, histogram) = self
+                .histograms
+                .entry(record.series())
+                .or_insert_with(|| (record_attributes(record), ExponentialHistogram::default()));
+            histogram.record(record.time);
+        }
+        if self.histograms.is_empty() {
+            return Ok(());
+        }
+        let data_points: Vec<Value> = self
+            .histograms
+            .values()
+            .map(|(attributes, histogram)| otlp_data_point(attributes, histogram, self.start, time))
+            .collect();
+        let body = json!({
+            "resourceMetrics": [{
+                "resource": self.resource,
+                "scopeMetrics": [{
+                    "scope": { "name": OTLP_SERVICE_NAME, "version": env!("CARGO_PKG_VERSION") },
+                    "metrics": [{
+                        "name": "nk.probe.duration",
+                        "description": "Latency of successful probes",
+                        "unit": "ms",
+                        "exponentialHistogram": {
+                            // AGGREGATION_TEMPORALITY_CUMULATIVE
+                            "aggregationTemporality": 2,
+                            "dataPoints": data_points,
+                        },
+                    }],
+                }],
+            }],
+        });
+        metrics.send(body.to_string().into_bytes(), 0)
+    }
+}
+
+impl RecordSink for OtlpExporter {
+    fn write_batch(&mut self, records: &[ConnectRecord]) -> Result<()> {
+        // Records do not have a timestamp, so spans end when their batch is written.
+        let end = time_now_us() * 1000;
+        let spans: Vec<Value> = records.iter().map(|record| otlp_span(record, end)).collect();
+        let body = json!({
+            "resourceSpans": [{
+                "resource": self.resource,
+                "scopeSpans": [{
+                    "scope": { "name": OTLP_SERVICE_NAME, "version": env!("CARGO_PKG_VERSION") },
+                    "spans": spans,
+                }],
+            }],
+        });
+        self.exporter.send(body.to_string().into_bytes(), records.len())?;
+        self.export_histograms(records, end)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Some(metrics) = &self.metrics {
+            metrics.flush()?;
+        }
+        self.exporter.flush()
+    }
+}
+
+/// Return a span for a connection record. Failed probes have an error status.
+fn otlp_span(record: &ConnectRecord, end: u128) -> Value {
+    let duration = match record.time > 0.0 {
+        true => (record.time * 1_000_000.0) as u128,
+        false => 0,
+    };
+    let mut attributes = vec![
+        string_attribute("nk.source", &record.source),
+        string_attribute("nk.result", &record.result.to_string()),
+        json!({ "key": "nk.time_ms", "value": { "doubleValue": record.time } }),
+    ];
+    attributes.extend(record_attributes(record));
+    let status = match record.success {
+        true => json!({ "code": 1 }),
+        false => json!({ "code": 2, "message": record.error_msg.to_owned().unwrap_or_default() }),
+    };
+    json!({
+        "traceId": Uuid::new_v4().simple().to_string(),
+        "spanId": Uuid::new_v4().simple().to_string()[..16],
+        "name": format!("{} probe", record.protocol),
+        // SPAN_KIND_CLIENT
+        "kind": 3,
+        "startTimeUnixNano": end.saturating_sub(duration).to_string(),
+        "endTimeUnixNano": end.to_string(),
+        "attributes": attributes,
+        "status": status,
+    })
+}
+
+/// Return the attributes identifying the destination of a record.
+fn record_attributes(record: &ConnectRecord) -> Vec<Value> {
+    let mut attributes = vec![
+        string_attribute("nk.protocol", &record.protocol.to_string()),
+        string_attribute("nk.destination", &record.destination),
+    ];
+    if record.warm {
+        attributes.push(json!({ "key": "nk.warm", "value": { "boolValue": true } }));
+    }
+    if let Some(label) = &record.label {
+        attributes.push(string_attribute("nk.label", label));
+    }
+    for (key, value) in &record.tags {
+        attributes.push(string_attribute(&format!("nk.tag.{key}"), value));
+    }
+    attributes
+}
+
+/// Return an exponential histogram data point. Counts are
+/// 64-bit integers, so they are strings in OTLP JSON.
+fn otlp_data_point(attributes: &[Value], histogram: &ExponentialHistogram, start: u128, time: u128) -> Value {
+    let bucket_counts: Vec<String> = histogram.bucket_counts().iter().map(|c| c.to_string()).collect();
+    json!({
+        "attributes": attributes,
+        "startTimeUnixNano": start.to_string(),
+        "timeUnixNano": time.to_string(),
+        "count": histogram.count.to_string(),
+        "sum": histogram.sum,
+        "min": histogram.min,
+        "max": histogram.max,
+        "scale": histogram.scale,
+        "zeroCount": histogram.zero_count.to_string(),
+        "positive": { "offset": histogram.offset(), "bucketCounts": bucket_counts },
+    })
+}
+
+fn string_attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::common::{ConnectMethod, ConnectResult};
+    use std::collections::BTreeMap;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn connect_record() -> ConnectRecord {
+        ConnectRecord {
+            result: ConnectResult::Timeout,
+            protocol: ConnectMethod::TCP,
+            source: "127.0.0.1:50000".to_owned(),
+            destination: "127.0.0.1:443".to_owned(),
+            time: -1.0,
+            success: false,
+            error_msg: Some("deadline has elapsed".to_owned()),
+            tags: BTreeMap::from([("site".to_owned(), "syd".to_owned())]),
+            ..Default::default()
+        }
+    }
+
+    /// Accept `requests` requests, returning the head and body of each.
+    fn collector(listener: TcpListener, requests: usize) -> thread::JoinHandle<Vec<(String, Value)>> {
+        thread::spawn(move || {
+            (0..requests)
+                .map(|_| {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let mut request = Vec::new();
+                    let mut buffer = [0u8; 4096];
+                    // The body is complete once it parses.
+                    let body = loop {
+                        let len = stream.read(&mut buffer).unwrap();
+                        request.extend_from_slice(&buffer[..len]);
+                        let text = String::from_utf8_lossy(&request).to_string();
+                        if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                            if let Ok(body) = serde_json::from_str::<Value>(body) {
+                                break (head.to_owned(), body);
+                            }
+                        }
+                    };
+                    stream.write_all(b"HTTP/1.0 200 OK\r\n\r\n{}").unwrap();
+                    body
+                })
+                .collect()
+        })
+    }
+
+    #[test]
+    fn records_are_exported_as_spans() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let collector = collector(listener, 1);
+
+        let mut exporter = OtlpExporter::new(&endpoint, &LoggingOptions::default()).unwrap();
+        exporter.write_batch(&[connect_record()]).unwrap();
+        exporter.flush().unwrap();
+
+        let (head, body) = &collector.join().unwrap()[0];
+        assert!(head.starts_with("POST /v1/traces HTTP/1.0"));
+        let span = &body["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(span["name"], "tcp probe");
+        assert_eq!(span["status"]["code"], 2);
+        assert_eq!(span["status"]["message"], "deadline has elapsed");
+        assert_eq!(span["startTimeUnixNano"], span["endTimeUnixNano"]);
+        let attributes = span["attributes"].as_array().unwrap();
+        assert!(attributes.contains(&string_attribute("nk.destination", "127.0.0.1:443")));
+        assert!(attributes.contains(&string_attribute("nk.tag.site", "syd")));
+    }
+
+    #[test]
+    fn latencies_are_exported_as_exponential_histograms() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/otlp/v1/traces", listener.local_addr().unwrap());
+        let collector = collector(listener, 2);
+
+        let mut success = connect_record();
+        success.success = true;
+        success.time = 2.0;
+        let logging_options = LoggingOptions {
+            otlp_histograms: true,
+            ..Default::default()
+        };
+        let mut exporter = OtlpExporter::new(&endpoint, &logging_options).unwrap();
+        exporter
+            .write_batch(&[success.clone(), success, connect_record()])
+            .unwrap();
+        exporter.flush().unwrap();
+
+        let requests = collector.join().unwrap();
+        let (_, body) = requests
+            .iter()
+            .find(|(head, _)| head.starts_with("POST /otlp/v1/metrics HTTP/1.0"))
+            .unwrap();
+        let metric = &body["resourceMetrics"][0]["scopeMetrics"][0]["metrics"][0];
+        assert_eq!(metric["name"], "nk.probe.duration");
+        let data_point = &metric["exponentialHistogram"]["dataPoints"][0];
+        assert_eq!(data_point["count"], "2");
+        assert_eq!(data_point["sum"], 4.0);
+        assert_eq!(data_point["positive"]["bucketCounts"], json!(["2"]));
+        // 2ms is the upper bound of its bucket.
+        let scale = data_point["scale"].as_i64().unwrap();
+        assert_eq!(data_point["positive"]["offset"].as_i64().unwrap(), (1 << scale) - 1);
+    }
+}
@@ -1,8 +1,14 @@
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr, SocketAddrV6};
 
 use anyhow::{bail, Result};
+use clap::ValueEnum;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
 
-use crate::core::common::NetKrakenMessage;
+use crate::core::common::{ConnectMethod, CsvTarget, HttpUrl, NetKrakenMessage, SyslogTarget};
+use crate::core::konst::{
+    DNS_PORT, SYSLOG_PORT, TELNET_DO, TELNET_DONT, TELNET_IAC, TELNET_SB, TELNET_SE, TELNET_WILL, TELNET_WONT,
+};
 
 /// Parse into a std::net::IPv4 or std::net::IPv6 address from a string
 pub fn parse_ipaddr(s: &str) -> Result<IpAddr> {
@@ -16,6 +22,343 @@ pub fn parse_ipaddr(s: &str) -> Result<IpAddr> {
     }
 }
 
+/// Parse an IP address with an optional IPv6 zone ID (`fe80::1%eth0`).
+/// The zone can be an interface name or an interface index and is
+/// returned as the scope ID. Addresses without a zone have a scope ID of 0.
+pub fn parse_scoped_ipaddr(s: &str) -> Result<(IpAddr, u32)> {
+    let s = s.trim_start_matches('[').trim_end_matches(']');
+    let (addr, zone) = match s.split_once('%') {
+        Some((addr, zone)) => (addr, Some(zone)),
+        None => (s, None),
+    };
+    let ip = parse_ipaddr(addr)?;
+
+    let scope_id = match zone {
+        None => 0,
+        Some(_) if ip.is_ipv4() => bail!("address: `{s}` is invalid, zone IDs are only valid for IPv6"),
+        Some(zone) => match zone.parse::<u32>() {
+            Ok(index) => index,
+            Err(_) => interface_index(zone)?,
+        },
+    };
+    Ok((ip, scope_id))
+}
+
+/// Build a socket address, keeping the scope ID of IPv6 addresses.
+pub fn scoped_socket_addr(ip: IpAddr, scope_id: u32, port: u16) -> SocketAddr {
+    match ip {
+        IpAddr::V4(_) => SocketAddr::new(ip, port),
+        IpAddr::V6(ip) => SocketAddr::V6(SocketAddrV6::new(ip, port, 0, scope_id)),
+    }
+}
+
+/// Return the index of a network interface from its name.
+#[cfg(unix)]
+fn interface_index(name: &str) -> Result<u32> {
+    let c_name = std::ffi::CString::new(name)?;
+    // SAFETY: `c_name` is a valid nul terminated string that
+    // outlives the call, `if_nametoindex` does not retain it.
+    match unsafe { libc::if_nametoindex(c_name.as_ptr()) } {
+        0 => bail!("zone ID: `{name}` is not a local interface"),
+        index => Ok(index),
+    }
+}
+
+/// Return the index of a network interface from its name.
+#[cfg(not(unix))]
+fn interface_index(name: &str) -> Result<u32> {
+    bail!("zone ID: `{name}` is invalid, use the interface index on this platform")
+}
+
+/// Parse a `key=value` tag
+pub fn parse_tag(s: &str) -> Result<(String, String)> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => Ok((key.trim().to_owned(), value.trim().to_owned())),
+        _ => bail!("tag: `{s}` is invalid, expected key=value"),
+    }
+}
+
+/// Parse an HTTP header name, which is a token (RFC9110).
+/// An empty name is allowed, for a header that is disabled.
+pub fn parse_header_name(s: &str) -> Result<String> {
+    let token = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+    match s.chars().all(token) {
+        true => Ok(s.to_owned()),
+        false => bail!("header: `{s}` is not a valid header name"),
+    }
+}
+
+/// Parse a labelled `label=host:port` destination.
+pub fn parse_target(s: &str) -> Result<(String, Destination)> {
+    match s.split_once('=') {
+        Some((label, destination)) if !label.trim().is_empty() => match parse_destination(destination.trim()) {
+            Ok((host, port)) if port > 0 => Ok((label.trim().to_owned(), (host, port))),
+            _ => bail!("target: `{s}` is invalid, expected label=host:port"),
+        },
+        _ => bail!("target: `{s}` is invalid, expected label=host:port"),
+    }
+}
+
+/// Parse a size in bytes, with an optional K, M or G (1024 based) suffix.
+pub fn parse_size(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let (number, multiplier) = match s.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&s[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&s[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+    match number.parse::<u64>().ok().and_then(|n| n.checked_mul(multiplier)) {
+        Some(size) => Ok(size),
+        None => bail!("size: `{s}` is invalid, expected bytes or a K, M or G suffix"),
+    }
+}
+
+/// Parse a DNS resolver address.
+/// The port defaults to 53 when it is not specified.
+pub fn parse_resolver(s: &str) -> Result<SocketAddr> {
+    if let Ok(addr) = s.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    match s.parse::<IpAddr>() {
+        Ok(ip) => Ok(SocketAddr::new(ip, DNS_PORT)),
+        Err(_) => bail!("resolver: `{s}` is invalid, expected IP or IP:port"),
+    }
+}
+
+/// Parse an RFC3339 timestamp (`2024-01-01T00:00:00Z`)
+pub fn parse_rfc3339(s: &str) -> Result<OffsetDateTime> {
+    match OffsetDateTime::parse(s, &Rfc3339) {
+        Ok(time) => Ok(time),
+        Err(_) => bail!("time: `{s}` is invalid, expected RFC3339 (2024-01-01T00:00:00Z)"),
+    }
+}
+
+/// Parse a `1/N` sample rate into `N`
+pub fn parse_sample(s: &str) -> Result<u16> {
+    let rate = match s.split_once('/') {
+        Some(("1", n)) => n,
+        Some(_) => bail!("sample: `{s}` is invalid, expected 1/N"),
+        None => s,
+    };
+    match rate.trim().parse::<u16>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => bail!("sample: `{s}` is invalid, expected 1/N"),
+    }
+}
+
+/// Parse destination arguments into host and port pairs.
+/// A single host may be followed by its port (`host port`),
+/// otherwise each destination is `host:port` or `[ipv6]:port`.
+/// Destinations without a port have a port of 0.
+pub fn parse_destinations(args: &[String]) -> Result<Vec<(String, u16)>> {
+    if let [host, port] = args {
+        if let Ok(port) = port.parse::<u16>() {
+            return Ok(vec![(host.to_owned(), port)]);
+        }
+    }
+    args.iter().map(|s| parse_destination(s)).collect()
+}
+
+/// Normalize a destination host, so the same host written
+/// differently is detected as a duplicate. Host names are
+/// case insensitive and may be fully qualified with a trailing dot.
+pub fn normalize_host(host: &str) -> String {
+    match host.parse::<IpAddr>() {
+        Ok(ip) => ip.to_string(),
+        Err(_) => host.trim_end_matches('.').to_lowercase(),
+    }
+}
+
+/// A `(host, port)` destination.
+pub type Destination = (String, u16);
+
+/// Remove duplicate destinations, keeping the first of each.
+/// Returns the unique destinations and the duplicates that were removed.
+pub fn dedup_destinations(destinations: Vec<Destination>) -> (Vec<Destination>, Vec<Destination>) {
+    let mut seen: Vec<Destination> = Vec::new();
+    let (mut unique, mut duplicates) = (Vec::new(), Vec::new());
+    for (host, port) in destinations {
+        let normalized = (normalize_host(&host), port);
+        match seen.contains(&normalized) {
+            true => duplicates.push((host, port)),
+            false => {
+                seen.push(normalized);
+                unique.push((host, port));
+            }
+        }
+    }
+    (unique, duplicates)
+}
+
+/// Parse a `host:port` destination.
+pub fn parse_destination(s: &str) -> Result<(String, u16)> {
+    let (host, port) = match s.strip_prefix('[').and_then(|s| s.split_once("]:")) {
+        Some((host, port)) => (host, port),
+        // A bare IPv6 address or a path does not have a port.
+        None => match s.split_once(':') {
+            Some((host, port)) if !port.contains(':') => (host, port),
+            _ => return Ok((s.to_owned(), 0)),
+        },
+    };
+    match port.parse::<u16>() {
+        Ok(port) if !host.is_empty() => Ok((host.to_owned(), port)),
+        _ => bail!("destination: `{s}` is invalid, expected host:port"),
+    }
+}
+
+/// Parse the `host,port,protocol,label,timeout` rows of a CSV targets file.
+/// Trailing columns may be left out, and a header row, blank lines and
+/// lines starting with `#` are ignored. Every invalid row is reported
+/// with its line number.
+pub fn parse_csv_targets(contents: &str) -> Result<Vec<CsvTarget>> {
+    let mut targets = Vec::new();
+    let mut errors = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || (targets.is_empty() && line.starts_with("host,")) {
+            continue;
+        }
+        match parse_csv_target(line) {
+            Ok(target) => targets.push(target),
+            Err(e) => errors.push(format!("line {}: {e}", i + 1)),
+        }
+    }
+    if !errors.is_empty() {
+        bail!("{}", errors.join("\n"));
+    }
+    Ok(targets)
+}
+
+fn parse_csv_target(line: &str) -> Result<CsvTarget> {
+    let fields = csv_fields(line);
+    if fields.len() > 5 {
+        bail!("expected host,port,protocol,label,timeout");
+    }
+    let field = |i: usize| fields.get(i).map(|f| f.trim()).unwrap_or_default();
+    let host = match field(0) {
+        "" => bail!("host is required"),
+        host => host.trim_start_matches('[').trim_end_matches(']').to_owned(),
+    };
+    let port = match field(1) {
+        "" => 0,
+        port => match port.parse::<u16>() {
+            Ok(port) => port,
+            Err(_) => bail!("port `{port}` is invalid"),
+        },
+    };
+    let protocol = match field(2) {
+        "" => ConnectMethod::default(),
+        protocol => match ConnectMethod::from_str(protocol, true) {
+            Ok(protocol) => protocol,
+            Err(_) => bail!("protocol `{protocol}` is invalid"),
+        },
+    };
+    let label = match field(3) {
+        "" => None,
+        label => Some(label.to_owned()),
+    };
+    let timeout = match field(4) {
+        "" => None,
+        timeout => match timeout.parse::<u16>() {
+            Ok(timeout) if timeout > 0 => Some(timeout),
+            _ => bail!("timeout `{timeout}` is invalid"),
+        },
+    };
+    Ok(CsvTarget {
+        host,
+        port,
+        protocol,
+        label,
+        timeout,
+    })
+}
+
+/// Split a CSV line into its fields. Fields may be quoted,
+/// with a doubled quote for a quote in the field.
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            ('"', _) => quoted = !quoted,
+            (',', false) => fields.push(String::new()),
+            (c, _) => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+/// Parse an `http://` or `https://` URL.
+/// The port defaults to 80 for HTTP and 443 for HTTPS, the path to `/`.
+pub fn parse_url(s: &str) -> Result<HttpUrl> {
+    let (tls, rest) = match (s.strip_prefix("http://"), s.strip_prefix("https://")) {
+        (Some(rest), _) => (false, rest),
+        (_, Some(rest)) => (true, rest),
+        _ => bail!("url: `{s}` is invalid, expected http:// or https://"),
+    };
+    let (authority, path) = match rest.find(['/', '?']) {
+        Some(i) => (&rest[..i], rest[i..].to_owned()),
+        None => (rest, "/".to_owned()),
+    };
+    let path = match path.starts_with('?') {
+        true => format!("/{path}"),
+        false => path,
+    };
+    let default_port = match tls {
+        true => 443,
+        false => 80,
+    };
+    let (host, port) = match parse_destination(authority) {
+        Ok((host, 0)) => (
+            host.trim_start_matches('[').trim_end_matches(']').to_owned(),
+            default_port,
+        ),
+        Ok((host, port)) => (host, port),
+        Err(_) => bail!("url: `{s}` is invalid, expected a port number"),
+    };
+    if host.is_empty() || host.contains('@') {
+        bail!("url: `{s}` is invalid, expected a host");
+    }
+    Ok(HttpUrl { tls, host, port, path })
+}
+
+/// Parse a `udp://host[:port]`, `tcp://host[:port]` or `unix:///path` syslog target.
+/// The port defaults to 514 when it is not specified.
+pub fn parse_syslog_target(s: &str) -> Result<SyslogTarget> {
+    if let Some(path) = s.strip_prefix("unix://") {
+        if path.is_empty() {
+            bail!("syslog target: `{s}` is invalid, expected a socket path");
+        }
+        return Ok(SyslogTarget::Unix(path.to_owned()));
+    }
+    let (tcp, authority) = match (s.strip_prefix("udp://"), s.strip_prefix("tcp://")) {
+        (Some(authority), _) => (false, authority),
+        (_, Some(authority)) => (true, authority),
+        _ => bail!("syslog target: `{s}` is invalid, expected udp://, tcp:// or unix://"),
+    };
+    let (host, port) = match parse_destination(authority) {
+        Ok((host, 0)) => (
+            host.trim_start_matches('[').trim_end_matches(']').to_owned(),
+            SYSLOG_PORT,
+        ),
+        Ok((host, port)) => (host, port),
+        Err(_) => bail!("syslog target: `{s}` is invalid, expected a port number"),
+    };
+    if host.is_empty() {
+        bail!("syslog target: `{s}` is invalid, expected a host");
+    }
+    Ok(match tcp {
+        true => SyslogTarget::Tcp(host, port),
+        false => SyslogTarget::Udp(host, port),
+    })
+}
+
 /// Attempt to read in a NetKrakenMessage from a string
 /// If the string cannot be read into a NetKrakenMessage then
 /// it will be assumed that the peer is not a NetKraken host
@@ -29,32 +372,379 @@ pub fn nk_msg_reader(s: &str) -> Option<NetKrakenMessage> {
     Some(data)
 }
 
+/// Split an SMTP reply line into its reply code, whether it is
+/// the final line of the reply, and the reply text.
+/// `250-SIZE 1000` => (250, false, "SIZE 1000")
+/// `250 OK` => (250, true, "OK")
+pub fn smtp_reply_line(s: &str) -> Option<(u16, bool, &str)> {
+    let code = s.get(..3)?.parse::<u16>().ok()?;
+    let last = s.as_bytes().get(3) != Some(&b'-');
+    let text = s.get(4..).unwrap_or_default().trim_end();
+    Some((code, last, text))
+}
+
+/// Return the status code of an HTTP status line.
+/// `HTTP/1.1 200 OK` => 200
+pub fn http_status_line(s: &str) -> Option<u16> {
+    let mut parts = s.split_whitespace();
+    if !parts.next()?.starts_with("HTTP/") {
+        return None;
+    }
+    match parts.next()?.parse::<u16>().ok()? {
+        code @ 100..=599 => Some(code),
+        _ => None,
+    }
+}
+
+/// Return the capabilities from an IMAP `* CAPABILITY` response
+/// or a greeting with a `[CAPABILITY ...]` response code.
+pub fn imap_capabilities(s: &str) -> Vec<String> {
+    let upper = s.trim_end().to_ascii_uppercase();
+    let caps = match upper.find("[CAPABILITY ") {
+        Some(i) => upper[i + 12..].split(']').next().unwrap_or_default(),
+        None => upper.strip_prefix("* CAPABILITY ").unwrap_or_default(),
+    };
+    caps.split_whitespace().map(|c| c.to_owned()).collect()
+}
+
+/// Return the telnet negotiation commands (command, option) found in
+/// a buffer, and the length of the buffer parsed. A command split at
+/// the end of the buffer is not parsed, it is completed by the next read.
+/// Sub-negotiations are returned with the `SB` command.
+/// Escaped `IAC IAC` data bytes are ignored.
+pub fn telnet_commands(buffer: &[u8]) -> (Vec<(u8, u8)>, usize) {
+    let mut commands = Vec::new();
+    let mut i = 0;
+    while i < buffer.len() {
+        if buffer[i] != TELNET_IAC {
+            i += 1;
+            continue;
+        }
+        match buffer.get(i + 1) {
+            None => break,
+            Some(&command @ (TELNET_WILL | TELNET_WONT | TELNET_DO | TELNET_DONT | TELNET_SB)) => {
+                match buffer.get(i + 2) {
+                    Some(&option) => {
+                        commands.push((command, option));
+                        i += 3;
+                    }
+                    None => break,
+                }
+            }
+            // Escaped data byte, end of sub-negotiation or
+            // a command without an option.
+            Some(_) => i += 2,
+        }
+    }
+    (commands, i)
+}
+
+/// Return a human readable telnet negotiation command.
+/// `(253, 1)` => "DO ECHO"
+pub fn telnet_command_name(command: u8, option: u8) -> String {
+    let command = match command {
+        TELNET_WILL => "WILL",
+        TELNET_WONT => "WONT",
+        TELNET_DO => "DO",
+        TELNET_DONT => "DONT",
+        TELNET_SB => "SB",
+        TELNET_SE => "SE",
+        _ => "CMD",
+    };
+    let option = match option {
+        0 => "BINARY".to_owned(),
+        1 => "ECHO".to_owned(),
+        3 => "SUPPRESS-GO-AHEAD".to_owned(),
+        24 => "TERMINAL-TYPE".to_owned(),
+        31 => "NAWS".to_owned(),
+        44 => "COM-PORT-OPTION".to_owned(),
+        o => o.to_string(),
+    };
+    format!("{command} {option}")
+}
+
 #[cfg(test)]
 mod tests {
-    use std::net::{Ipv4Addr, Ipv6Addr};
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
-    use crate::core::common::NetKrakenMessage;
-    use crate::util::parser::{nk_msg_reader, parse_ipaddr};
+    use crate::core::common::{ConnectMethod, CsvTarget, HttpUrl, NetKrakenMessage, SyslogTarget};
+    use crate::util::parser::{
+        dedup_destinations, http_status_line, imap_capabilities, nk_msg_reader, parse_csv_targets, parse_destinations,
+        parse_header_name, parse_ipaddr, parse_resolver, parse_rfc3339, parse_sample, parse_scoped_ipaddr, parse_size,
+        parse_syslog_target, parse_tag, parse_target, parse_url, scoped_socket_addr, smtp_reply_line,
+        telnet_command_name, telnet_commands,
+    };
 
     const IPV4_ADDR: &str = "198.51.100.1";
     const IPV6_ADDR: &str = "2001:0DB8::1";
 
     #[test]
     fn parse_ipaddr_with_ipv4_addr() {
-        let result = parse_ipaddr(&IPV4_ADDR.to_owned()).unwrap();
+        let result = parse_ipaddr(IPV4_ADDR).unwrap();
         assert_eq!(result, Ipv4Addr::new(198, 51, 100, 1));
     }
 
     #[test]
     fn parse_ipaddr_with_ipv6_addr() {
-        let result = parse_ipaddr(&IPV6_ADDR.to_owned()).unwrap();
+        let result = parse_ipaddr(IPV6_ADDR).unwrap();
         assert_eq!(result, Ipv6Addr::new(0x2001, 0x0DB8, 0, 0, 0, 0, 0, 1));
     }
 
     #[test]
     #[should_panic]
     fn parse_ipaddr_with_invalid_param() {
-        parse_ipaddr(&"blah".to_string()).unwrap();
+        parse_ipaddr("blah").unwrap();
+    }
+
+    #[test]
+    fn parse_scoped_ipaddr_without_zone() {
+        let (ip, scope_id) = parse_scoped_ipaddr(IPV6_ADDR).unwrap();
+        assert_eq!(ip, Ipv6Addr::new(0x2001, 0x0DB8, 0, 0, 0, 0, 0, 1));
+        assert_eq!(scope_id, 0);
+    }
+
+    #[test]
+    fn parse_scoped_ipaddr_with_zone_index() {
+        let (ip, scope_id) = parse_scoped_ipaddr("[fe80::1%3]").unwrap();
+        assert_eq!(ip, Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1));
+        assert_eq!(scope_id, 3);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn parse_scoped_ipaddr_with_zone_name() {
+        let (_, scope_id) = parse_scoped_ipaddr("fe80::1%lo").unwrap();
+        assert!(scope_id > 0);
+    }
+
+    #[test]
+    fn parse_scoped_ipaddr_with_invalid_zone() {
+        assert!(parse_scoped_ipaddr("fe80::1%blahblehblow0").is_err());
+        assert!(parse_scoped_ipaddr("198.51.100.1%1").is_err());
+    }
+
+    #[test]
+    fn scoped_socket_addr_is_expected() {
+        let ipv6: IpAddr = "fe80::1".parse().unwrap();
+        assert_eq!(scoped_socket_addr(ipv6, 3, 443).to_string(), "[fe80::1%3]:443");
+
+        let ipv4: IpAddr = IPV4_ADDR.parse().unwrap();
+        assert_eq!(scoped_socket_addr(ipv4, 3, 443).to_string(), "198.51.100.1:443");
+    }
+
+    #[test]
+    fn parse_tag_is_expected() {
+        assert_eq!(parse_tag("site=nyc").unwrap(), ("site".to_owned(), "nyc".to_owned()));
+        assert_eq!(parse_tag("note=a=b").unwrap(), ("note".to_owned(), "a=b".to_owned()));
+    }
+
+    #[test]
+    fn parse_tag_with_invalid_tag() {
+        assert!(parse_tag("site").is_err());
+        assert!(parse_tag("=nyc").is_err());
+    }
+
+    #[test]
+    fn parse_csv_targets_is_expected() {
+        let contents = "host,port,protocol,label,timeout\n\
+            # core\n\
+            10.1.1.1,443,tcp,\"core, fw\",500\n\
+            [2001:db8::1],53,udp\n\
+            gw.example.com,,icmp\n";
+        let targets = parse_csv_targets(contents).unwrap();
+        assert_eq!(targets.len(), 3);
+        assert_eq!(
+            targets[0],
+            CsvTarget {
+                host: "10.1.1.1".to_owned(),
+                port: 443,
+                protocol: ConnectMethod::TCP,
+                label: Some("core, fw".to_owned()),
+                timeout: Some(500),
+            }
+        );
+        assert_eq!(
+            (targets[1].host.as_str(), targets[1].protocol),
+            ("2001:db8::1", ConnectMethod::UDP)
+        );
+        assert_eq!((targets[2].port, targets[2].label.as_ref()), (0, None));
+
+        let errors = parse_csv_targets("host1,https\nhost2,443,tcp\nhost3,443,ftp,,0\n,443")
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            errors,
+            "line 1: port `https` is invalid\nline 3: protocol `ftp` is invalid\nline 4: host is required"
+        );
+    }
+
+    #[test]
+    fn parse_header_name_is_expected() {
+        assert_eq!(parse_header_name("X-NetKraken-Probe").unwrap(), "X-NetKraken-Probe");
+        assert_eq!(parse_header_name("").unwrap(), "");
+        assert!(parse_header_name("X Probe").is_err());
+        assert!(parse_header_name("X-Probe:").is_err());
+    }
+
+    #[test]
+    fn parse_target_is_expected() {
+        assert_eq!(
+            parse_target("core-fw=10.1.1.1:443").unwrap(),
+            ("core-fw".to_owned(), ("10.1.1.1".to_owned(), 443))
+        );
+        assert_eq!(
+            parse_target("dns=[2001:db8::53]:53").unwrap(),
+            ("dns".to_owned(), ("2001:db8::53".to_owned(), 53))
+        );
+        assert!(parse_target("10.1.1.1:443").is_err());
+        assert!(parse_target("core-fw=10.1.1.1").is_err());
+    }
+
+    #[test]
+    fn parse_resolver_is_expected() {
+        assert_eq!(parse_resolver("192.0.2.53").unwrap().to_string(), "192.0.2.53:53");
+        assert_eq!(
+            parse_resolver("192.0.2.53:5353").unwrap().to_string(),
+            "192.0.2.53:5353"
+        );
+        assert_eq!(parse_resolver("2001:db8::53").unwrap().to_string(), "[2001:db8::53]:53");
+        assert_eq!(
+            parse_resolver("[2001:db8::53]:5353").unwrap().to_string(),
+            "[2001:db8::53]:5353"
+        );
+        assert!(parse_resolver("dns.example").is_err());
+    }
+
+    #[test]
+    fn parse_destinations_is_expected() {
+        let args = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+
+        assert_eq!(
+            parse_destinations(&args(&["host1", "443"])).unwrap(),
+            vec![("host1".to_owned(), 443)]
+        );
+        assert_eq!(
+            parse_destinations(&args(&["host1:443", "[2001:db8::1]:8080", "2001:db8::2", "host1:443"])).unwrap(),
+            vec![
+                ("host1".to_owned(), 443),
+                ("2001:db8::1".to_owned(), 8080),
+                ("2001:db8::2".to_owned(), 0),
+                ("host1".to_owned(), 443)
+            ]
+        );
+        assert!(parse_destinations(&args(&["host1:443", "host2:https"])).is_err());
+    }
+
+    #[test]
+    fn http_status_line_is_expected() {
+        assert_eq!(http_status_line("HTTP/1.1 200 OK\r\n"), Some(200));
+        assert_eq!(http_status_line("HTTP/1.0 503"), Some(503));
+        assert_eq!(http_status_line("HTTP/1.1 999 Nope"), None);
+        assert_eq!(http_status_line("SSH-2.0-OpenSSH_9.6"), None);
+    }
+
+    #[test]
+    fn parse_url_is_expected() {
+        let url = |tls, host: &str, port, path: &str| HttpUrl {
+            tls,
+            host: host.to_owned(),
+            port,
+            path: path.to_owned(),
+        };
+
+        assert_eq!(parse_url("http://host1").unwrap(), url(false, "host1", 80, "/"));
+        assert_eq!(
+            parse_url("https://host1:8443/health?full=1").unwrap(),
+            url(true, "host1", 8443, "/health?full=1")
+        );
+        assert_eq!(
+            parse_url("https://[2001:db8::1]/").unwrap(),
+            url(true, "2001:db8::1", 443, "/")
+        );
+        assert_eq!(
+            parse_url("http://[2001:db8::1]:8080?q").unwrap(),
+            url(false, "2001:db8::1", 8080, "/?q")
+        );
+        assert!(parse_url("ftp://host1").is_err());
+        assert!(parse_url("http://host1:http/").is_err());
+        assert!(parse_url("http://user@host1/").is_err());
+        assert!(parse_url("https:///").is_err());
+    }
+
+    #[test]
+    fn parse_size_is_expected() {
+        assert_eq!(parse_size("0").unwrap(), 0);
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("10K").unwrap(), 10 * 1024);
+        assert_eq!(parse_size("10m").unwrap(), 10 * 1024 * 1024);
+        assert_eq!(parse_size("1G").unwrap(), 1024 * 1024 * 1024);
+        assert!(parse_size("10MB").is_err());
+        assert!(parse_size("M").is_err());
+    }
+
+    #[test]
+    fn parse_syslog_target_is_expected() {
+        assert_eq!(
+            parse_syslog_target("udp://host1").unwrap(),
+            SyslogTarget::Udp("host1".to_owned(), 514)
+        );
+        assert_eq!(
+            parse_syslog_target("tcp://[2001:db8::1]:6514").unwrap(),
+            SyslogTarget::Tcp("2001:db8::1".to_owned(), 6514)
+        );
+        assert_eq!(
+            parse_syslog_target("unix:///dev/log").unwrap(),
+            SyslogTarget::Unix("/dev/log".to_owned())
+        );
+        assert!(parse_syslog_target("host1:514").is_err());
+        assert!(parse_syslog_target("udp://host1:syslog").is_err());
+        assert!(parse_syslog_target("unix://").is_err());
+    }
+
+    #[test]
+    fn dedup_destinations_removes_normalized_duplicates() {
+        let destinations = vec![
+            ("Host1.example".to_owned(), 443),
+            ("host1.example.".to_owned(), 443),
+            ("host1.example".to_owned(), 8443),
+            ("2001:db8::1".to_owned(), 443),
+            ("2001:0db8:0:0::1".to_owned(), 443),
+        ];
+
+        let (unique, duplicates) = dedup_destinations(destinations);
+        assert_eq!(
+            unique,
+            vec![
+                ("Host1.example".to_owned(), 443),
+                ("host1.example".to_owned(), 8443),
+                ("2001:db8::1".to_owned(), 443)
+            ]
+        );
+        assert_eq!(
+            duplicates,
+            vec![("host1.example.".to_owned(), 443), ("2001:0db8:0:0::1".to_owned(), 443)]
+        );
+    }
+
+    #[test]
+    fn parse_rfc3339_is_expected() {
+        assert_eq!(
+            parse_rfc3339("2024-01-01T00:00:00Z").unwrap().unix_timestamp(),
+            1_704_067_200
+        );
+        assert_eq!(
+            parse_rfc3339("2024-01-01T01:00:00+01:00").unwrap().unix_timestamp(),
+            1_704_067_200
+        );
+        assert!(parse_rfc3339("2024-01-01 00:00").is_err());
+    }
+
+    #[test]
+    fn parse_sample_is_expected() {
+        assert_eq!(parse_sample("1/10").unwrap(), 10);
+        assert_eq!(parse_sample("10").unwrap(), 10);
+        assert!(parse_sample("2/10").is_err());
+        assert!(parse_sample("1/0").is_err());
     }
 
     #[test]
@@ -67,4 +757,69 @@ mod tests {
     fn parse_nk_message_none() {
         assert!(nk_msg_reader("msg").is_none());
     }
+
+    #[test]
+    fn smtp_reply_line_with_continuation() {
+        assert_eq!(smtp_reply_line("250-STARTTLS"), Some((250, false, "STARTTLS")));
+    }
+
+    #[test]
+    fn smtp_reply_line_with_last_line() {
+        assert_eq!(
+            smtp_reply_line("220 mx.stuff.things ESMTP\r\n"),
+            Some((220, true, "mx.stuff.things ESMTP"))
+        );
+        assert_eq!(smtp_reply_line("250"), Some((250, true, "")));
+    }
+
+    #[test]
+    fn smtp_reply_line_with_invalid_line() {
+        assert!(smtp_reply_line("blah").is_none());
+        assert!(smtp_reply_line("25").is_none());
+    }
+
+    #[test]
+    fn imap_capabilities_from_capability_response() {
+        let caps = imap_capabilities("* CAPABILITY IMAP4rev1 starttls AUTH=PLAIN\r\n");
+        assert_eq!(caps, vec!["IMAP4REV1", "STARTTLS", "AUTH=PLAIN"]);
+    }
+
+    #[test]
+    fn imap_capabilities_from_greeting() {
+        let caps = imap_capabilities("* OK [CAPABILITY IMAP4rev1 STARTTLS] Dovecot ready.");
+        assert_eq!(caps, vec!["IMAP4REV1", "STARTTLS"]);
+    }
+
+    #[test]
+    fn imap_capabilities_without_capabilities() {
+        assert!(imap_capabilities("* OK Dovecot ready.").is_empty());
+    }
+
+    #[test]
+    fn telnet_commands_with_negotiation() {
+        let buffer = [255, 253, 44, 255, 251, 1, b'h', b'i', 255, 250, 44, 10, 1, 255, 240];
+        assert_eq!(
+            telnet_commands(&buffer),
+            (vec![(253, 44), (251, 1), (250, 44)], buffer.len())
+        );
+    }
+
+    #[test]
+    fn telnet_commands_with_escaped_iac() {
+        let buffer = [b'a', 255, 255, 253, 1];
+        assert_eq!(telnet_commands(&buffer), (vec![], buffer.len()));
+    }
+
+    #[test]
+    fn telnet_commands_with_truncated_command() {
+        assert_eq!(telnet_commands(&[b'a', 255, 253]), (vec![], 1));
+        assert_eq!(telnet_commands(&[255]), (vec![], 0));
+        assert_eq!(telnet_commands(b"login"), (vec![], 5));
+    }
+
+    #[test]
+    fn telnet_command_name_is_expected() {
+        assert_eq!(telnet_command_name(253, 44), "DO COM-PORT-OPTION");
+        assert_eq!(telnet_command_name(252, 99), "WONT 99");
+    }
 }
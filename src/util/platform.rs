@@ -0,0 +1,62 @@
+use std::fmt::Display;
+
+/// Socket and system features that are only available on some
+/// platforms. Features are checked before they are used, so the
+/// same options run on every platform, and the checks that cannot
+/// be made are reported as unsupported instead of failing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlatformFeature {
+    /// ICMP errors read from a socket error queue (IP_RECVERR).
+    ErrorQueue,
+    /// ICMP echo requests sent with the DF bit set (IP_PMTUDISC_PROBE).
+    PathMtu,
+    /// The kernel routing table, read for the default gateway,
+    /// from `/proc` on Linux and with `route` on the BSDs.
+    RouteTable,
+    /// UNIX domain sockets.
+    UnixSocket,
+}
+
+impl PlatformFeature {
+    /// Return whether the feature is supported on this platform.
+    pub fn supported(self) -> bool {
+        match self {
+            PlatformFeature::ErrorQueue | PlatformFeature::PathMtu => cfg!(target_os = "linux"),
+            PlatformFeature::RouteTable => {
+                cfg!(any(target_os = "linux", target_os = "freebsd", target_os = "openbsd"))
+            }
+            PlatformFeature::UnixSocket => cfg!(unix),
+        }
+    }
+
+    /// Return the message for a feature that is not supported on this platform.
+    pub fn unsupported_msg(self) -> String {
+        format!("{self}: unsupported on this OS ({})", std::env::consts::OS)
+    }
+}
+
+impl Display for PlatformFeature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlatformFeature::ErrorQueue => write!(f, "Socket error queues"),
+            PlatformFeature::PathMtu => write!(f, "Path MTU discovery"),
+            PlatformFeature::RouteTable => write!(f, "Routing tables"),
+            PlatformFeature::UnixSocket => write!(f, "UNIX sockets"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsupported_features_name_the_os() {
+        assert_eq!(
+            PlatformFeature::PathMtu.unsupported_msg(),
+            format!("Path MTU discovery: unsupported on this OS ({})", std::env::consts::OS)
+        );
+        assert_eq!(PlatformFeature::UnixSocket.supported(), cfg!(unix));
+        assert_eq!(PlatformFeature::ErrorQueue.supported(), cfg!(target_os = "linux"));
+    }
+}
@@ -0,0 +1,606 @@
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+
+use anyhow::{bail, Result};
+use futures::future::BoxFuture;
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+
+use crate::core::common::{
+    ClientResult, ClientSummary, ConnectMethod, ConnectRecord, DnsOptions, HostRecord, HostResults, IpOptions, IpPort,
+    IpProtocol, LoggingOptions, OutputFormat, PingOptions, ProbeSample, SourceFallback,
+};
+use crate::core::konst::{BIND_ADDR_IPV4, BIND_ADDR_IPV6, BIND_PORT, BUFFER_SIZE, RUN_RECORDS, RUN_WINDOW};
+use crate::util::advice::AdviceAccumulator;
+use crate::util::csv::CsvWriter;
+use crate::util::dns::resolve_host;
+use crate::util::handler::{
+    advice_handler, console_handler, info_handler, loop_handler, resource_warning_handler, summary_handler,
+    summary_log_handler, Cancel, StatsListener,
+};
+use crate::util::message::{
+    client_summary_json_msg, destinations_msg, interval_summary_json_msg, interval_summary_table_msg, path_header_msg,
+    path_summary_table_msg, resolved_ips_msg, service_summary_table_msg,
+};
+use crate::util::parser::{parse_ipaddr, parse_scoped_ipaddr};
+use crate::util::record::RecordSampler;
+use crate::util::result::{
+    assert_results, client_summary_result, get_results_map, label_results, sort_results, IntervalAccumulator,
+    PhaseAccumulator, ServiceAccumulator,
+};
+use crate::util::run::RunHandle;
+use crate::util::schedule::{schedule_sockets, Throttle};
+use crate::util::sink::RecordSinks;
+
+/// A protocol client, which only sends a single probe to a socket. The
+/// `ProbeRunner` resolves the destinations, schedules the probes, and
+/// handles cancellation, logging and summaries. Clients that do more
+/// than send a single probe override the hooks, which do nothing by default.
+pub trait ProbeClient: Sync {
+    /// The protocol of the probes, shown in results and summaries.
+    fn protocol(&self) -> ConnectMethod;
+
+    /// Send a single probe to a socket of `host`, binding to the source
+    /// for the family of the socket.
+    fn probe_once(
+        &self,
+        src: IpPort,
+        host: &str,
+        dst_socket: SocketAddr,
+        ping_options: PingOptions,
+    ) -> impl Future<Output = ConnectRecord> + Send;
+
+    /// Send the probes of a round to a socket of `host`. A round sends
+    /// a single probe, unless the client also makes other requests,
+    /// such as over a warm connection.
+    fn probe(
+        &self,
+        src: IpPort,
+        host: &str,
+        dst_socket: SocketAddr,
+        ping_options: PingOptions,
+    ) -> impl Future<Output = Vec<ConnectRecord>> + Send {
+        async move { vec![self.probe_once(src, host, dst_socket, ping_options).await] }
+    }
+
+    /// Whether rounds are started on their interval without waiting
+    /// for the probes of the previous round to complete.
+    fn pipelined(&self) -> bool {
+        false
+    }
+
+    /// Inspect the record of each probe to `host` as the run handles it.
+    fn inspect(&self, _src: &IpPort, _host: &str, _record: &ConnectRecord) {}
+
+    /// Report what the client collected after the summary of the run,
+    /// and release what it holds for the run.
+    fn finish(&self) -> impl Future<Output = Result<()>> + Send {
+        async { Ok(()) }
+    }
+}
+
+/// Runs the probes of a `ProbeClient` to its destinations.
+#[derive(Debug)]
+pub struct ProbeRunner {
+    pub destinations: Vec<(String, u16)>,
+    pub src_ipv4: Option<IpAddr>,
+    pub src_ipv6: Option<IpAddr>,
+    pub src_ipv6_scope_id: u32,
+    pub src_port: u16,
+    pub logging_options: LoggingOptions,
+    pub ping_options: PingOptions,
+    pub ip_options: IpOptions,
+    pub dns_options: DnsOptions,
+    /// Queries the statistics of the run while it is in progress.
+    pub run_handle: RunHandle,
+}
+
+impl ProbeRunner {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        destinations: Vec<(String, u16)>,
+        src_ipv4: Option<String>,
+        src_ipv6: Option<String>,
+        src_port: Option<u16>,
+        logging_options: LoggingOptions,
+        ping_options: PingOptions,
+        ip_options: IpOptions,
+        dns_options: DnsOptions,
+    ) -> ProbeRunner {
+        let src_ipv4 = match src_ipv4 {
+            Some(x) => parse_ipaddr(&x).ok(),
+            None => parse_ipaddr(BIND_ADDR_IPV4).ok(),
+        };
+
+        let (src_ipv6, src_ipv6_scope_id) = match src_ipv6 {
+            Some(x) => parse_scoped_ipaddr(&x).ok().unzip(),
+            None => parse_scoped_ipaddr(BIND_ADDR_IPV6).ok().unzip(),
+        };
+        let src_ipv6_scope_id = src_ipv6_scope_id.unwrap_or_default();
+
+        let src_port = src_port.unwrap_or(BIND_PORT);
+
+        ProbeRunner {
+            destinations,
+            src_ipv4,
+            src_ipv6,
+            src_ipv6_scope_id,
+            src_port,
+            logging_options,
+            ping_options,
+            ip_options,
+            dns_options,
+            run_handle: RunHandle::new(RUN_WINDOW, RUN_RECORDS),
+        }
+    }
+
+    pub async fn run<C: ProbeClient>(&self, client: &C) -> Result<Vec<ClientResult>> {
+        let protocol = client.protocol();
+        let src_ip_port = IpPort {
+            // These should never be None at this point as they are set in the ProbeRunner::new() constructor.
+            ipv4: self.src_ipv4.unwrap(),
+            ipv6: self.src_ipv6.unwrap(),
+            ipv6_scope_id: self.src_ipv6_scope_id,
+            port: self.src_port,
+            fallback: SourceFallback::new(self.ip_options.source_fallback),
+        };
+
+        // Resolve the destination hosts to IPv4 and IPv6 addresses.
+        let mut hosts = Vec::new();
+        for (dst_ip, dst_port) in &self.destinations {
+            hosts.push(HostRecord::new(dst_ip, *dst_port).await);
+        }
+        let resolved_hosts = resolve_host(hosts, &self.dns_options).await;
+
+        // Check if the host resolved to an IPv4 or IPv6 addresses.
+        // If not, return an error.
+        for record in &resolved_hosts {
+            match record.ipv4_sockets.is_empty() && record.ipv6_sockets.is_empty() {
+                true => bail!("{} did not resolve to an IP address", record.host),
+                false => {
+                    info_handler(&resolved_ips_msg(record), &self.logging_options);
+                }
+            }
+        }
+
+        // Filter the resolved hosts based on the IP protocol.
+        let mut filtered_hosts = Vec::new();
+        for record in &resolved_hosts {
+            let mut record = record.clone();
+            match &self.ip_options.ip_protocol {
+                IpProtocol::All => {
+                    filtered_hosts.push(record);
+                }
+                IpProtocol::V4 => {
+                    record.ipv6_sockets.clear();
+                    filtered_hosts.push(record);
+                }
+                IpProtocol::V6 => {
+                    record.ipv4_sockets.clear();
+                    filtered_hosts.push(record);
+                }
+            }
+        }
+
+        let mut results_map = get_results_map(&filtered_hosts);
+        let socket_labels = self.logging_options.socket_labels(&filtered_hosts);
+
+        let mut count: u16 = 0;
+        let mut send_count: u16 = 0;
+        let mut record_sink = RecordSinks::create(&self.logging_options)?;
+        let mut record_sampler = RecordSampler::new(self.logging_options.sample);
+        let mut csv_writer = match &self.logging_options.csv_file {
+            Some(csv_file) => Some(CsvWriter::append(csv_file)?),
+            None => None,
+        };
+        let mut interval_accumulator =
+            IntervalAccumulator::new(self.logging_options.summary_interval, self.logging_options.watch);
+        let mut advice_accumulator = AdviceAccumulator::default();
+        let mut phase_accumulator = PhaseAccumulator::default();
+        // Hosts probed on several ports are summarised as a service inventory.
+        let mut service_accumulator = ServiceAccumulator::default();
+
+        let destinations = destinations_msg(&self.destinations);
+        let ping_header = path_header_msg(&destinations, protocol);
+        info_handler(&ping_header, &self.logging_options);
+
+        // Ctrl-C cancels the client, interrupting the requests in flight,
+        // and the results collected so far are summarised.
+        let cancel = Cancel::on_ctrl_c();
+        // SIGQUIT prints the statistics of the run so far.
+        let _stats_listener = StatsListener::spawn(self.run_handle.clone());
+        let throttle = self.run_handle.start_run();
+
+        // The next round is started when the interval between requests
+        // ends, which is interrupted when cancelled. Rounds of a pipelined
+        // client are started without waiting for the previous round, and
+        // report their results when their probes have completed.
+        let pipelined = client.pipelined();
+        let next_round = |count: u16| -> BoxFuture<'_, Option<bool>> {
+            Box::pin(cancel.run(loop_handler(
+                count,
+                self.ping_options.repeat,
+                self.ping_options.interval,
+            )))
+        };
+        let mut next = Some(next_round(count));
+        let mut rounds: FuturesUnordered<BoxFuture<'_, Option<Vec<HostResults>>>> = FuturesUnordered::new();
+
+        loop {
+            let ready = next.is_some() && (pipelined || rounds.is_empty());
+            tokio::select! {
+                start = async { next.as_mut().unwrap().await }, if ready => {
+                    next = None;
+                    if start == Some(false) {
+                        count += 1;
+                        let round = futures::stream::iter(resolved_hosts.clone())
+                            .map(|host_record| {
+                                let src_ip_port = src_ip_port.clone();
+                                let throttle = &throttle;
+                                async move {
+                                    //
+                                    process_host(
+                                        client,
+                                        src_ip_port,
+                                        host_record,
+                                        self.ping_options,
+                                        self.ip_options,
+                                        throttle,
+                                    )
+                                    .await
+                                }
+                            })
+                            .buffer_unordered(BUFFER_SIZE)
+                            .collect::<Vec<HostResults>>();
+                        // The requests in flight are dropped when cancelled.
+                        rounds.push(Box::pin(cancel.run(round)));
+                        next = Some(next_round(count));
+                    }
+                }
+                Some(round) = rounds.next() => {
+                    // Rounds cancelled in flight are not counted as sent,
+                    // so they are not reported as lost.
+                    let Some(host_results) = round else {
+                        continue;
+                    };
+
+                    for host in host_results {
+                        for mut result in host.results {
+                            result.tags = self.logging_options.tags.clone();
+                            result.label = socket_labels.get(&result.destination).cloned();
+                            results_map
+                                // This should never fail
+                                .get_mut(&host.host)
+                                .unwrap()
+                                // Warm requests are summarised as their own series.
+                                .entry(result.series())
+                                .or_default()
+                                .push(ProbeSample::new(&result));
+                            interval_accumulator.push(&result);
+                            advice_accumulator.push(&result);
+                            self.run_handle.push(&result);
+                            phase_accumulator.push(&result);
+                            service_accumulator.push(&result);
+                            client.inspect(&src_ip_port, &host.host, &result);
+
+                            if record_sampler.sample() {
+                                console_handler(&result, &self.logging_options).await;
+                                if let Some(record_sink) = record_sink.as_mut() {
+                                    record_sink.push(result.clone())?;
+                                }
+                                if let Some(csv_writer) = csv_writer.as_mut() {
+                                    csv_writer.write(&result)?;
+                                }
+                            }
+                        }
+                    }
+
+                    if let Some(mut interval_results) = interval_accumulator.take_due() {
+                        label_results(&mut interval_results, &socket_labels);
+                        let interval_summary = match self.logging_options.output {
+                            OutputFormat::Text => interval_summary_table_msg(
+                                self.logging_options.summary_interval,
+                                self.logging_options.watch,
+                                &interval_results,
+                                &self.logging_options.tags,
+                            ),
+                            OutputFormat::Json => interval_summary_json_msg(
+                                self.logging_options.summary_interval,
+                                self.logging_options.watch,
+                                &interval_results,
+                                &self.logging_options.tags,
+                            ),
+                        };
+                        summary_handler(&interval_summary, &self.logging_options);
+                    }
+
+                    send_count += 1;
+                }
+                else => break,
+            }
+        }
+
+        let mut client_results: Vec<ClientResult> = Vec::new();
+        for (_, addrs) in results_map {
+            for (addr, samples) in addrs {
+                let client_summary = ClientSummary { send_count, samples };
+                let mut summary_msg = client_summary_result(&addr, protocol, client_summary);
+                summary_msg.phases = phase_accumulator.averages(&addr);
+                client_results.push(summary_msg)
+            }
+        }
+        sort_results(&mut client_results, &self.logging_options);
+        assert_results(&mut client_results, &self.logging_options);
+        label_results(&mut client_results, &socket_labels);
+
+        let summary = match self.logging_options.output {
+            OutputFormat::Text => path_summary_table_msg(
+                &destinations,
+                protocol,
+                &client_results,
+                &self.logging_options.tags,
+                self.logging_options.histogram,
+            ),
+            OutputFormat::Json => {
+                client_summary_json_msg(&destinations, protocol, &client_results, &self.logging_options.tags)
+            }
+        };
+        summary_handler(&summary, &self.logging_options);
+        resource_warning_handler(&self.run_handle, &self.logging_options);
+        advice_handler(&advice_accumulator, &self.logging_options);
+        let services = service_accumulator.summaries();
+        if !services.is_empty() {
+            summary_handler(&service_summary_table_msg(protocol, &services), &self.logging_options);
+        }
+        summary_log_handler(&client_results, &self.logging_options);
+        client.finish().await?;
+        if let Some(csv_writer) = csv_writer.as_mut() {
+            csv_writer.write_summary(&client_results)?;
+        }
+
+        if let Some(record_sink) = record_sink.as_mut() {
+            record_sink.flush()?;
+        }
+
+        Ok(client_results)
+    }
+}
+
+async fn process_host<C: ProbeClient>(
+    client: &C,
+    src_ip_port: IpPort,
+    host_record: HostRecord,
+    ping_options: PingOptions,
+    ip_options: IpOptions,
+    throttle: &Throttle,
+) -> HostResults {
+    // Create a vector of sockets based on the IP protocol.
+    let sockets = match ip_options.ip_protocol {
+        IpProtocol::All => [host_record.ipv4_sockets, host_record.ipv6_sockets].concat(),
+        IpProtocol::V4 => host_record.ipv4_sockets,
+        IpProtocol::V6 => host_record.ipv6_sockets,
+    };
+
+    let host = host_record.host.as_str();
+    let results: Vec<Vec<ConnectRecord>> = schedule_sockets(sockets, ip_options, throttle, |dst_socket| {
+        let src_ip_port = src_ip_port.clone();
+        async move {
+            //
+            client.probe(src_ip_port, host, dst_socket, ping_options).await
+        }
+    })
+    .await;
+    let results = results.into_iter().flatten().collect();
+
+    HostResults {
+        host: host_record.host,
+        results,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::common::ConnectResult;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use tokio::time::{Duration, Instant};
+
+    /// Replies to every probe after 1ms.
+    struct ReplyProbe;
+
+    impl ProbeClient for ReplyProbe {
+        fn protocol(&self) -> ConnectMethod {
+            ConnectMethod::TELNET
+        }
+
+        async fn probe_once(
+            &self,
+            src: IpPort,
+            _host: &str,
+            dst_socket: SocketAddr,
+            _ping_options: PingOptions,
+        ) -> ConnectRecord {
+            ConnectRecord {
+                result: ConnectResult::Pong,
+                protocol: ConnectMethod::TELNET,
+                source: src.bind_addr(&dst_socket).to_string(),
+                destination: dst_socket.to_string(),
+                time: 1.0,
+                success: true,
+                error_msg: None,
+                ..Default::default()
+            }
+        }
+    }
+
+    /// Runs out of local resources on every probe.
+    struct ExhaustedProbe;
+
+    impl ProbeClient for ExhaustedProbe {
+        fn protocol(&self) -> ConnectMethod {
+            ConnectMethod::TELNET
+        }
+
+        async fn probe_once(
+            &self,
+            src: IpPort,
+            _host: &str,
+            dst_socket: SocketAddr,
+            _ping_options: PingOptions,
+        ) -> ConnectRecord {
+            ConnectRecord {
+                result: ConnectResult::ResourceExhausted,
+                protocol: ConnectMethod::TELNET,
+                source: src.bind_addr(&dst_socket).to_string(),
+                destination: dst_socket.to_string(),
+                time: -1.0,
+                success: false,
+                error_msg: None,
+                ..Default::default()
+            }
+        }
+    }
+
+    fn runner() -> ProbeRunner {
+        ProbeRunner::new(
+            vec![("127.0.0.1".to_owned(), 23)],
+            None,
+            None,
+            None,
+            LoggingOptions {
+                quiet: true,
+                ..Default::default()
+            },
+            PingOptions {
+                repeat: 3,
+                interval: 0,
+                ..Default::default()
+            },
+            IpOptions::default(),
+            DnsOptions::default(),
+        )
+    }
+
+    #[tokio::test]
+    async fn runner_summarises_the_probes_of_a_client() {
+        let runner = runner();
+        let results = runner.run(&ReplyProbe).await.unwrap();
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].destination, "127.0.0.1:23");
+        assert_eq!(results[0].protocol, ConnectMethod::TELNET);
+        assert_eq!((results[0].sent, results[0].received), (3, 3));
+        assert_eq!(runner.run_handle.stats().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn each_run_starts_without_a_throttle() {
+        let runner = runner();
+        runner.run(&ExhaustedProbe).await.unwrap();
+        assert_eq!(runner.run_handle.resource_exhaustion(), Some((3, 1)));
+
+        let results = runner.run(&ReplyProbe).await.unwrap();
+        assert_eq!(runner.run_handle.resource_exhaustion(), None);
+        assert_eq!(results[0].received, 3);
+    }
+
+    /// Replies to every probe after 100ms, with a warm request in each
+    /// round, and keeps what the runner passes to the hooks.
+    #[derive(Default)]
+    struct HookProbe {
+        pipelined: bool,
+        inspected: Mutex<Vec<String>>,
+        finished: AtomicUsize,
+    }
+
+    impl ProbeClient for HookProbe {
+        fn protocol(&self) -> ConnectMethod {
+            ConnectMethod::TCP
+        }
+
+        async fn probe_once(
+            &self,
+            src: IpPort,
+            host: &str,
+            dst_socket: SocketAddr,
+            ping_options: PingOptions,
+        ) -> ConnectRecord {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            ReplyProbe.probe_once(src, host, dst_socket, ping_options).await
+        }
+
+        async fn probe(
+            &self,
+            src: IpPort,
+            host: &str,
+            dst_socket: SocketAddr,
+            ping_options: PingOptions,
+        ) -> Vec<ConnectRecord> {
+            let record = self.probe_once(src, host, dst_socket, ping_options).await;
+            let warm = ConnectRecord {
+                warm: true,
+                ..record.clone()
+            };
+            vec![record, warm]
+        }
+
+        fn pipelined(&self) -> bool {
+            self.pipelined
+        }
+
+        fn inspect(&self, _src: &IpPort, host: &str, record: &ConnectRecord) {
+            self.inspected
+                .lock()
+                .unwrap()
+                .push(format!("{host} {}", record.series()));
+        }
+
+        async fn finish(&self) -> Result<()> {
+            self.finished.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn runner_passes_every_record_to_the_hooks() {
+        let runner = runner();
+        let client = HookProbe::default();
+        let results = runner.run(&client).await.unwrap();
+
+        // Warm requests are summarised as their own series.
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|result| result.received == 3));
+        let inspected = client.inspected.lock().unwrap();
+        assert_eq!(inspected.len(), 6);
+        assert_eq!(inspected[1], "127.0.0.1 127.0.0.1:23 (warm)");
+        assert_eq!(client.finished.load(Ordering::SeqCst), 1);
+    }
+
+    // Time is paused, and only advances when every task is waiting on a timer.
+    #[tokio::test(start_paused = true)]
+    async fn pipelined_rounds_do_not_wait_for_the_previous_round() {
+        let runner = ProbeRunner {
+            ping_options: PingOptions {
+                repeat: 3,
+                interval: 10,
+                ..Default::default()
+            },
+            ..runner()
+        };
+
+        // Each probe takes 100ms. Rounds either follow each other with
+        // 10ms between them, or are started 10ms after the previous round.
+        for (pipelined, expected) in [(false, 320), (true, 120)] {
+            let start = Instant::now();
+            let client = HookProbe {
+                pipelined,
+                ..Default::default()
+            };
+            let results = runner.run(&client).await.unwrap();
+
+            assert_eq!((results[0].sent, results[0].received), (3, 3));
+            assert_eq!(start.elapsed(), Duration::from_millis(expected));
+        }
+    }
+}
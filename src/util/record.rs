@@ -0,0 +1,471 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Read, Write};
+
+use anyhow::{bail, Result};
+
+use crate::core::common::{
+    AddrKey, ClientResult, ClientSummary, ConnectMethod, ConnectRecord, ProbeSample, RecordFormat,
+};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::core::konst::{RECORD_FILE_MAGIC, SCHEMA_VERSION};
+use crate::util::message::tags_msg;
+use crate::util::result::{client_summary_result, PhaseAccumulator};
+
+/// A connection record stamped with the schema version it was written with.
+/// JSON records without a schema version are from the first schema version.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VersionedRecord {
+    #[serde(default = "first_schema_version")]
+    pub schema_version: u16,
+    #[serde(flatten)]
+    pub record: ConnectRecord,
+}
+
+fn first_schema_version() -> u16 {
+    1
+}
+
+/// Writes connection records to a compact binary file.
+///
+/// The file starts with a magic number and the schema version,
+/// followed by length prefixed MessagePack encoded records.
+pub struct RecordWriter<W: Write> {
+    writer: BufWriter<W>,
+}
+
+impl RecordWriter<File> {
+    /// Create a record file, truncating it if it already exists.
+    pub fn create(filename: &str) -> Result<RecordWriter<File>> {
+        RecordWriter::new(File::create(filename)?)
+    }
+}
+
+impl<W: Write> RecordWriter<W> {
+    pub fn new(writer: W) -> Result<RecordWriter<W>> {
+        let mut writer = BufWriter::new(writer);
+        writer.write_all(RECORD_FILE_MAGIC)?;
+        writer.write_all(&SCHEMA_VERSION.to_le_bytes())?;
+        Ok(RecordWriter { writer })
+    }
+
+    pub fn write(&mut self, record: &ConnectRecord) -> Result<()> {
+        let bytes = rmp_serde::to_vec(record)?;
+        self.writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&bytes)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Selects every Nth record for output.
+/// The first record is always selected.
+pub struct RecordSampler {
+    rate: u16,
+    count: u64,
+}
+
+impl RecordSampler {
+    pub fn new(rate: u16) -> RecordSampler {
+        RecordSampler {
+            rate: rate.max(1),
+            count: 0,
+        }
+    }
+
+    pub fn sample(&mut self) -> bool {
+        let selected = self.count.is_multiple_of(self.rate as u64);
+        self.count += 1;
+        selected
+    }
+}
+
+/// Reads connection records from a binary record file.
+pub struct RecordReader<R: Read> {
+    reader: BufReader<R>,
+    version: u16,
+}
+
+impl RecordReader<File> {
+    pub fn open(filename: &str) -> Result<RecordReader<File>> {
+        RecordReader::new(File::open(filename)?)
+    }
+}
+
+impl<R: Read> RecordReader<R> {
+    pub fn new(reader: R) -> Result<RecordReader<R>> {
+        let mut reader = BufReader::new(reader);
+        let mut magic = [0u8; 4];
+        let mut version = [0u8; 2];
+        if reader.read_exact(&mut magic).is_err() || &magic != RECORD_FILE_MAGIC {
+            bail!("not a NetKraken record file");
+        }
+        reader.read_exact(&mut version)?;
+        let version = u16::from_le_bytes(version);
+        if version == 0 || version > SCHEMA_VERSION {
+            bail!("unsupported record file schema version: {version}");
+        }
+        Ok(RecordReader { reader, version })
+    }
+}
+
+impl<R: Read> Iterator for RecordReader<R> {
+    type Item = Result<ConnectRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut len = [0u8; 4];
+        match self.reader.read_exact(&mut len) {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return None,
+            Err(e) => return Some(Err(e.into())),
+        }
+        let mut bytes = vec![0u8; u32::from_le_bytes(len) as usize];
+        if let Err(e) = self.reader.read_exact(&mut bytes) {
+            return Some(Err(e.into()));
+        }
+        Some(decode_record(self.version, &bytes))
+    }
+}
+
+/// Decode a record written with a schema version.
+/// Records from older schema versions are upgraded to
+/// the current schema here when the schema changes.
+fn decode_record(_version: u16, bytes: &[u8]) -> Result<ConnectRecord> {
+    Ok(rmp_serde::from_slice(bytes)?)
+}
+
+/// Upgrade a record file, or JSON records written by `nk convert`,
+/// to the current schema version. Returns the number of records migrated.
+pub fn migrate_records(input: &str, output: &str) -> Result<usize> {
+    let mut reader = BufReader::new(File::open(input)?);
+    let is_record_file = reader.fill_buf()?.starts_with(RECORD_FILE_MAGIC);
+    let mut count = 0;
+    match is_record_file {
+        true => {
+            let mut writer = RecordWriter::create(output)?;
+            for record in RecordReader::new(reader)? {
+                writer.write(&record?)?;
+                count += 1;
+            }
+            writer.flush()?;
+        }
+        false => count = migrate_json_records(reader, &mut BufWriter::new(File::create(output)?))?,
+    }
+    Ok(count)
+}
+
+/// Upgrade JSON records (one record per line) to the current schema version.
+pub fn migrate_json_records<R: BufRead, W: Write>(reader: R, writer: &mut W) -> Result<usize> {
+    let mut count = 0;
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut record: VersionedRecord = serde_json::from_str(&line)?;
+        if record.schema_version == 0 || record.schema_version > SCHEMA_VERSION {
+            bail!("unsupported record schema version: {}", record.schema_version);
+        }
+        record.schema_version = SCHEMA_VERSION;
+        writeln!(writer, "{}", serde_json::to_string(&record)?)?;
+        count += 1;
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+/// Convert a stream of records to JSON (one record per line) or CSV.
+pub fn convert_records<R: Read, W: Write>(
+    records: RecordReader<R>,
+    format: RecordFormat,
+    writer: &mut W,
+) -> Result<()> {
+    if format == RecordFormat::Csv {
+        writeln!(writer, "{}", csv_header())?;
+    }
+    for record in records {
+        let record = record?;
+        let line = match format {
+            RecordFormat::Json => serde_json::to_string(&VersionedRecord {
+                schema_version: SCHEMA_VERSION,
+                record,
+            })?,
+            RecordFormat::Csv => csv_row(&record),
+        };
+        writeln!(writer, "{line}")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Collect the latency of successful connections by destination.
+pub fn destination_latencies<R: Read>(records: RecordReader<R>) -> Result<HashMap<String, Vec<f64>>> {
+    let mut latencies: HashMap<String, Vec<f64>> = HashMap::new();
+    for record in records {
+        let record = record?;
+        let samples = latencies.entry(record.destination).or_default();
+        if record.success {
+            samples.push(record.time);
+        }
+    }
+    Ok(latencies)
+}
+
+/// Summarise the records of each destination series, as a client would at the end of a run.
+pub fn destination_results<R: Read>(records: RecordReader<R>) -> Result<Vec<ClientResult>> {
+    let mut samples: BTreeMap<AddrKey, (ConnectMethod, Vec<ProbeSample>)> = BTreeMap::new();
+    let mut phase_accumulator = PhaseAccumulator::default();
+    for record in records {
+        let record = record?;
+        phase_accumulator.push(&record);
+        let (_, series) = samples
+            .entry(AddrKey::new(&record.series()))
+            .or_insert((record.protocol, vec![]));
+        series.push(ProbeSample::from_record(&record));
+    }
+    Ok(samples
+        .into_iter()
+        .map(|(destination, (protocol, samples))| {
+            let send_count = samples.len().min(u16::MAX.into()) as u16;
+            let mut result = client_summary_result(
+                &destination.to_string(),
+                protocol,
+                ClientSummary { send_count, samples },
+            );
+            result.phases = phase_accumulator.averages(&result.destination);
+            result
+        })
+        .collect())
+}
+
+fn csv_header() -> String {
+    "result,protocol,source,destination,time,success,error_msg,tags".to_owned()
+}
+
+fn csv_row(record: &ConnectRecord) -> String {
+    [
+        record.result.to_string(),
+        record.protocol.to_string(),
+        record.source.to_owned(),
+        record.destination.to_owned(),
+        record.time.to_string(),
+        record.success.to_string(),
+        record.error_msg.to_owned().unwrap_or_default(),
+        tags_msg(&record.tags),
+    ]
+    .iter()
+    .map(|field| csv_field(field))
+    .collect::<Vec<String>>()
+    .join(",")
+}
+
+/// Quote a CSV field if it contains a delimiter, quote or newline.
+pub fn csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::common::{ConnectMethod, ConnectResult, PayloadRecord, Phase};
+    use crate::util::message::replay_summary_table_msg;
+    use std::collections::BTreeMap;
+
+    fn connect_record() -> ConnectRecord {
+        ConnectRecord {
+            result: ConnectResult::Ping,
+            protocol: ConnectMethod::TCP,
+            source: "127.0.0.1:50000".to_owned(),
+            destination: "127.0.0.1:443".to_owned(),
+            time: 1.5,
+            success: true,
+            error_msg: None,
+            tags: BTreeMap::from([("site".to_owned(), "syd".to_owned())]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let mut buffer = Vec::new();
+        let mut writer = RecordWriter::new(&mut buffer).unwrap();
+        writer.write(&connect_record()).unwrap();
+        writer.write(&connect_record()).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+
+        let records: Vec<ConnectRecord> = RecordReader::new(buffer.as_slice())
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].destination, "127.0.0.1:443");
+        assert_eq!(records[0].tags.get("site").unwrap(), "syd");
+    }
+
+    #[test]
+    fn destination_results_summarise_each_destination() {
+        let mut failed = connect_record();
+        failed.success = false;
+        failed.time = -1.0;
+        let mut other = connect_record();
+        other.destination = "127.0.0.1:80".to_owned();
+
+        let mut buffer = Vec::new();
+        let mut writer = RecordWriter::new(&mut buffer).unwrap();
+        for record in [connect_record(), failed, other] {
+            writer.write(&record).unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+
+        let results = destination_results(RecordReader::new(buffer.as_slice()).unwrap()).unwrap();
+        assert_eq!(results.len(), 2);
+        // Destinations are in numeric order, so port 80 is first.
+        assert_eq!(results[0].destination, "127.0.0.1:80");
+        assert_eq!(results[0].avg, 1.5);
+        assert_eq!(results[1].destination, "127.0.0.1:443");
+        assert_eq!(
+            (results[1].sent, results[1].received, results[1].loss_percent),
+            (2, 1, 50.0)
+        );
+    }
+
+    #[test]
+    fn replayed_records_are_summarised_as_the_run_did() {
+        let mut record = connect_record();
+        record.time = 1.0;
+        record.payload = Some(PayloadRecord {
+            size: 64,
+            time: 2.0,
+            verified: true,
+        });
+        let mut slower = record.clone();
+        slower.time = 3.0;
+
+        let mut buffer = Vec::new();
+        let mut writer = RecordWriter::new(&mut buffer).unwrap();
+        for record in [record, slower] {
+            writer.write(&record).unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+
+        let results = destination_results(RecordReader::new(buffer.as_slice()).unwrap()).unwrap();
+        assert_eq!((results[0].sent, results[0].received, results[0].avg), (2, 2, 2.0));
+        assert_eq!(results[0].phases, vec![(Phase::Connect, 2.0), (Phase::App, 2.0)]);
+
+        let summary_table = replay_summary_table_msg("run.nkr", &results, true);
+        assert!(summary_table.contains("--- Statistics for records in run.nkr ---"));
+        assert!(summary_table.contains("--- Latency Breakdown ---"));
+        assert!(summary_table.contains("127.0.0.1:443"));
+    }
+
+    #[test]
+    fn record_sampler_selects_every_nth_record() {
+        let mut sampler = RecordSampler::new(3);
+        let selected: Vec<bool> = (0..7).map(|_| sampler.sample()).collect();
+
+        assert_eq!(selected, vec![true, false, false, true, false, false, true]);
+    }
+
+    #[test]
+    fn record_reader_rejects_bad_magic() {
+        let result = RecordReader::new(b"NOPE\x01\x00".as_slice());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn record_reader_rejects_newer_schema_version() {
+        let result = RecordReader::new(b"NKRF\xff\x00".as_slice());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn schema_version_1_records_are_decoded() {
+        let record = connect_record();
+        let v1_record = (
+            &record.result,
+            &record.protocol,
+            &record.source,
+            &record.destination,
+            record.time,
+            record.success,
+            &record.error_msg,
+            &record.mail,
+            &record.telnet,
+            &record.tags,
+        );
+        let bytes = rmp_serde::to_vec(&v1_record).unwrap();
+        let decoded = decode_record(1, &bytes).unwrap();
+
+        assert_eq!(decoded.destination, "127.0.0.1:443");
+        assert!(decoded.payload.is_none());
+
+        // Records written before tags were added do not have them.
+        let untagged_record = (
+            &record.result,
+            &record.protocol,
+            &record.source,
+            &record.destination,
+            record.time,
+            record.success,
+            &record.error_msg,
+            &record.mail,
+            &record.telnet,
+        );
+        let bytes = rmp_serde::to_vec(&untagged_record).unwrap();
+        let decoded = decode_record(1, &bytes).unwrap();
+        assert!(decoded.tags.is_empty());
+
+        let mut json_record = serde_json::to_value(&record).unwrap();
+        json_record.as_object_mut().unwrap().remove("tags");
+        let decoded: ConnectRecord = serde_json::from_value(json_record).unwrap();
+        assert!(decoded.tags.is_empty());
+    }
+
+    #[test]
+    fn json_records_without_schema_version_are_migrated() {
+        let mut record = serde_json::to_value(connect_record()).unwrap();
+        record.as_object_mut().unwrap().remove("schema_version");
+        let input = format!("{record}\n\n");
+
+        let mut output = Vec::new();
+        let count = migrate_json_records(input.as_bytes(), &mut output).unwrap();
+        let migrated: VersionedRecord = serde_json::from_slice(&output).unwrap();
+
+        assert_eq!(count, 1);
+        assert_eq!(migrated.schema_version, SCHEMA_VERSION);
+        assert_eq!(migrated.record.destination, "127.0.0.1:443");
+    }
+
+    #[test]
+    fn records_convert_to_csv() {
+        let mut buffer = Vec::new();
+        let mut writer = RecordWriter::new(&mut buffer).unwrap();
+        let mut record = connect_record();
+        record.error_msg = Some("failed, \"badly\"".to_owned());
+        writer.write(&record).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+
+        let mut output = Vec::new();
+        let records = RecordReader::new(buffer.as_slice()).unwrap();
+        convert_records(records, RecordFormat::Csv, &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "result,protocol,source,destination,time,success,error_msg,tags\n\
+            ping,tcp,127.0.0.1:50000,127.0.0.1:443,1.5,true,\"failed, \"\"badly\"\"\",site=syd\n"
+        );
+    }
+}
@@ -0,0 +1,354 @@
+use std::fmt::Display;
+use std::net::{IpAddr, SocketAddr};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+
+use crate::core::common::ConnectResult;
+
+const UDP_PROTOCOL: u8 = 17;
+
+/// An ICMP (or ICMPv6) error received by a socket.
+/// `mtu` is the next hop MTU of fragmentation needed errors.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct IcmpError {
+    pub ipv6: bool,
+    pub icmp_type: u8,
+    pub code: u8,
+    pub offender: Option<IpAddr>,
+    pub mtu: u32,
+}
+
+impl IcmpError {
+    /// Map the ICMP error into a connection result.
+    pub fn connect_result(&self) -> ConnectResult {
+        match (self.ipv6, self.icmp_type, self.code) {
+            // Destination unreachable
+            (false, 3, 0 | 6 | 11) => ConnectResult::NetworkUnreachable,
+            (false, 3, 1 | 7 | 12) => ConnectResult::HostUnreachable,
+            (false, 3, 2 | 3) => ConnectResult::Refused,
+            (false, 3, 4) => ConnectResult::FragmentationNeeded,
+            (false, 3, 9 | 10 | 13) => ConnectResult::Prohibited,
+            (true, 1, 0) => ConnectResult::NetworkUnreachable,
+            (true, 1, 1 | 5 | 6) => ConnectResult::Prohibited,
+            (true, 1, 3) => ConnectResult::HostUnreachable,
+            (true, 1, 4) => ConnectResult::Refused,
+            // Packet too big
+            (true, 2, _) => ConnectResult::FragmentationNeeded,
+            _ => ConnectResult::Unknown,
+        }
+    }
+
+    /// Return whether a router dropped the packet because its TTL (hop limit) expired.
+    pub fn is_time_exceeded(&self) -> bool {
+        matches!((self.ipv6, self.icmp_type), (false, 11) | (true, 3))
+    }
+}
+
+impl Display for IcmpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let icmp = match self.ipv6 {
+            true => "ICMPv6",
+            false => "ICMP",
+        };
+        write!(f, "{icmp} error type={} code={}", self.icmp_type, self.code)
+    }
+}
+
+/// A handle to the error queue of a socket with IP_RECVERR enabled.
+///
+/// The handle shares the socket, so ICMP errors can still be read
+/// after the socket is consumed by a failed connect.
+#[cfg(target_os = "linux")]
+pub struct ErrorQueue {
+    socket: socket2::Socket,
+}
+
+#[cfg(target_os = "linux")]
+impl ErrorQueue {
+    /// Enable IP_RECVERR (IPV6_RECVERR) on a socket, returning
+    /// a handle to its error queue.
+    pub fn new<S: std::os::fd::AsFd>(socket: &S, ipv6: bool) -> Option<ErrorQueue> {
+        use std::os::fd::AsRawFd;
+
+        let socket = socket2::SockRef::from(socket).try_clone().ok()?;
+        let (level, name) = match ipv6 {
+            true => (libc::SOL_IPV6, libc::IPV6_RECVERR),
+            false => (libc::SOL_IP, libc::IP_RECVERR),
+        };
+        let enable: libc::c_int = 1;
+        // SAFETY: `enable` is a valid c_int that outlives the call.
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                level,
+                name,
+                &enable as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        match result {
+            0 => Some(ErrorQueue { socket }),
+            _ => None,
+        }
+    }
+
+    /// Take the first ICMP error from the error queue without blocking.
+    pub fn take_icmp_error(&self) -> Option<IcmpError> {
+        self.take_icmp_error_to().map(|(icmp_error, _)| icmp_error)
+    }
+
+    /// Take the first ICMP error from the error queue without blocking, with
+    /// the destination of the datagram it was sent for, which tells apart the
+    /// destinations of an unconnected socket.
+    pub fn take_icmp_error_to(&self) -> Option<(IcmpError, Option<SocketAddr>)> {
+        use std::os::fd::AsRawFd;
+
+        let mut data = [0u8; 64];
+        let mut iov = libc::iovec {
+            iov_base: data.as_mut_ptr().cast(),
+            iov_len: data.len(),
+        };
+        // u64 keeps the control buffer aligned for `cmsghdr`.
+        let mut control = [0u64; 32];
+        // SAFETY: an all zero `sockaddr_storage` is a valid empty address.
+        let mut name: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+        // SAFETY: an all zero `msghdr` is a valid empty message.
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_name = (&mut name as *mut libc::sockaddr_storage).cast();
+        msg.msg_namelen = std::mem::size_of_val(&name) as _;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+        // SAFETY: `msg` points to buffers that outlive the call.
+        let len = unsafe {
+            libc::recvmsg(
+                self.socket.as_raw_fd(),
+                &mut msg,
+                libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT,
+            )
+        };
+        if len < 0 {
+            return None;
+        }
+        // SAFETY: the kernel wrote the destination of the datagram, `msg_namelen` bytes long, to `name`.
+        let destination = unsafe { socket2::SockAddr::new(name, msg.msg_namelen) }.as_socket();
+
+        // SAFETY: the kernel wrote `msg_controllen` bytes of control messages
+        // to `control`, which the CMSG macros walk within those bounds.
+        let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+        while !cmsg.is_null() {
+            let (level, kind) = unsafe { ((*cmsg).cmsg_level, (*cmsg).cmsg_type) };
+            if (level, kind) == (libc::SOL_IP, libc::IP_RECVERR)
+                || (level, kind) == (libc::SOL_IPV6, libc::IPV6_RECVERR)
+            {
+                let data = unsafe { libc::CMSG_DATA(cmsg) } as *const libc::sock_extended_err;
+                let err = unsafe { std::ptr::read_unaligned(data) };
+                match err.ee_origin {
+                    libc::SO_EE_ORIGIN_ICMP | libc::SO_EE_ORIGIN_ICMP6 => {
+                        let icmp_error = IcmpError {
+                            ipv6: err.ee_origin == libc::SO_EE_ORIGIN_ICMP6,
+                            icmp_type: err.ee_type,
+                            code: err.ee_code,
+                            // SAFETY: the offender address follows the error within the control message.
+                            offender: unsafe { offender_addr(libc::SO_EE_OFFENDER(data)) },
+                            mtu: err.ee_info,
+                        };
+                        return Some((icmp_error, destination));
+                    }
+                    _ => {}
+                }
+            }
+            cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+        }
+        None
+    }
+}
+
+/// Read the address of the router that sent an ICMP error.
+///
+/// # Safety
+///
+/// `addr` must point to a readable `sockaddr_in` or `sockaddr_in6`.
+#[cfg(target_os = "linux")]
+unsafe fn offender_addr(addr: *const libc::sockaddr) -> Option<IpAddr> {
+    match (*addr).sa_family as libc::c_int {
+        libc::AF_INET => {
+            let addr = std::ptr::read_unaligned(addr as *const libc::sockaddr_in);
+            Some(IpAddr::from(u32::from_be(addr.sin_addr.s_addr).to_be_bytes()))
+        }
+        libc::AF_INET6 => {
+            let addr = std::ptr::read_unaligned(addr as *const libc::sockaddr_in6);
+            Some(IpAddr::from(addr.sin6_addr.s6_addr))
+        }
+        _ => None,
+    }
+}
+
+/// IP_RECVERR is only supported on Linux.
+#[cfg(not(target_os = "linux"))]
+pub struct ErrorQueue;
+
+#[cfg(not(target_os = "linux"))]
+impl ErrorQueue {
+    pub fn new<S>(_socket: &S, _ipv6: bool) -> Option<ErrorQueue> {
+        None
+    }
+
+    pub fn take_icmp_error(&self) -> Option<IcmpError> {
+        None
+    }
+
+    pub fn take_icmp_error_to(&self) -> Option<(IcmpError, Option<SocketAddr>)> {
+        None
+    }
+}
+
+/// A raw ICMP socket that reads the ICMP errors for a UDP socket,
+/// on platforms without socket error queues, such as the BSDs.
+/// Raw sockets need privileges, so the listener may not be available.
+pub struct IcmpListener {
+    socket: UdpSocket,
+    ipv6: bool,
+    src_port: u16,
+}
+
+impl IcmpListener {
+    /// Open a raw ICMP (or ICMPv6) socket for the errors of the UDP socket bound to `local_addr`.
+    pub fn new(local_addr: SocketAddr) -> Option<IcmpListener> {
+        let (domain, protocol) = match local_addr.is_ipv6() {
+            true => (Domain::IPV6, Protocol::ICMPV6),
+            false => (Domain::IPV4, Protocol::ICMPV4),
+        };
+        let socket = Socket::new(domain, Type::RAW, Some(protocol)).ok()?;
+        socket.set_nonblocking(true).ok()?;
+        Some(IcmpListener {
+            socket: UdpSocket::from_std(socket.into()).ok()?,
+            ipv6: local_addr.is_ipv6(),
+            src_port: local_addr.port(),
+        })
+    }
+
+    /// Wait for the next ICMP error for the UDP socket.
+    pub async fn recv_icmp_error(&self) -> Option<IcmpError> {
+        let mut buffer = [0u8; 1500];
+        loop {
+            let (len, from) = self.socket.recv_from(&mut buffer).await.ok()?;
+            if let Some(icmp_error) = quoted_icmp_error(self.ipv6, &buffer[..len], self.src_port, from.ip()) {
+                return Some(icmp_error);
+            }
+        }
+    }
+}
+
+/// Return the ICMP (or ICMPv6) error in a packet read from a raw socket
+/// when it quotes a UDP datagram sent from `src_port`. IPv4 packets
+/// start with their IP header, and IPv6 packets with the ICMPv6 header.
+pub fn quoted_icmp_error(ipv6: bool, packet: &[u8], src_port: u16, offender: IpAddr) -> Option<IcmpError> {
+    let icmp = match ipv6 {
+        true => packet,
+        false => packet.get(((packet.first()? & 0x0f) as usize) * 4..)?,
+    };
+    let (icmp_type, code) = (*icmp.first()?, *icmp.get(1)?);
+    let is_error = match ipv6 {
+        // Destination unreachable, packet too big and time exceeded
+        true => matches!(icmp_type, 1..=3),
+        false => matches!(icmp_type, 3 | 11),
+    };
+    if !is_error {
+        return None;
+    }
+    // The quoted packet follows the 8 byte ICMP header.
+    let quoted = icmp.get(8..)?;
+    let (protocol, udp) = match ipv6 {
+        true => (*quoted.get(6)?, quoted.get(40..)?),
+        false => (*quoted.get(9)?, quoted.get(((quoted.first()? & 0x0f) as usize) * 4..)?),
+    };
+    if protocol != UDP_PROTOCOL || udp.len() < 2 || u16::from_be_bytes([udp[0], udp[1]]) != src_port {
+        return None;
+    }
+    let mtu = match (ipv6, icmp_type) {
+        (false, 3) => u16::from_be_bytes([icmp[6], icmp[7]]).into(),
+        (true, 2) => u32::from_be_bytes([icmp[4], icmp[5], icmp[6], icmp[7]]),
+        _ => 0,
+    };
+    Some(IcmpError {
+        ipv6,
+        icmp_type,
+        code,
+        offender: Some(offender),
+        mtu,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(target_os = "linux")]
+    use tokio::net::TcpSocket;
+
+    #[test]
+    fn icmp_errors_map_to_connect_results() {
+        let icmp_error = |ipv6, icmp_type, code| IcmpError {
+            ipv6,
+            icmp_type,
+            code,
+            ..Default::default()
+        };
+
+        assert_eq!(icmp_error(false, 3, 1).connect_result(), ConnectResult::HostUnreachable);
+        assert_eq!(
+            icmp_error(false, 3, 4).connect_result(),
+            ConnectResult::FragmentationNeeded
+        );
+        assert_eq!(icmp_error(false, 3, 13).connect_result(), ConnectResult::Prohibited);
+        assert_eq!(icmp_error(true, 1, 1).connect_result(), ConnectResult::Prohibited);
+        assert_eq!(
+            icmp_error(true, 2, 0).connect_result(),
+            ConnectResult::FragmentationNeeded
+        );
+        assert_eq!(icmp_error(false, 11, 0).connect_result(), ConnectResult::Unknown);
+        assert!(icmp_error(false, 11, 0).is_time_exceeded());
+        assert!(icmp_error(true, 3, 0).is_time_exceeded());
+        assert!(!icmp_error(false, 3, 3).is_time_exceeded());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn error_queue_is_empty_before_connect() {
+        let socket = TcpSocket::new_v4().unwrap();
+        let error_queue = ErrorQueue::new(&socket, false).unwrap();
+
+        assert_eq!(error_queue.take_icmp_error(), None);
+    }
+
+    #[test]
+    fn quoted_icmp_errors_are_matched_to_their_socket() {
+        let router: IpAddr = "192.0.2.1".parse().unwrap();
+        // IP header, ICMP time exceeded, quoted IP header and UDP header from port 50000
+        let mut packet = vec![0x45];
+        packet.extend_from_slice(&[0; 19]);
+        packet.extend_from_slice(&[11, 0, 0, 0, 0, 0, 0, 0]);
+        packet.extend_from_slice(&[0x45, 0, 0, 0, 0, 0, 0, 0, 1, UDP_PROTOCOL]);
+        packet.extend_from_slice(&[0; 10]);
+        packet.extend_from_slice(&[0xc3, 0x50, 0x82, 0x9b]);
+
+        let icmp_error = quoted_icmp_error(false, &packet, 50000, router).unwrap();
+        assert!(icmp_error.is_time_exceeded());
+        assert_eq!(icmp_error.offender, Some(router));
+        assert_eq!(quoted_icmp_error(false, &packet, 50001, router), None);
+        assert_eq!(quoted_icmp_error(false, &packet[..40], 50000, router), None);
+
+        // ICMPv6 packet too big, quoted IPv6 header and UDP header from port 50000
+        let mut packet = vec![2, 0, 0, 0, 0, 0, 0x05, 0x00];
+        packet.extend_from_slice(&[0x60, 0, 0, 0, 0, 0, UDP_PROTOCOL]);
+        packet.extend_from_slice(&[0; 33]);
+        packet.extend_from_slice(&[0xc3, 0x50, 0x82, 0x9b]);
+
+        let icmp_error = quoted_icmp_error(true, &packet, 50000, router).unwrap();
+        assert_eq!(icmp_error.connect_result(), ConnectResult::FragmentationNeeded);
+        assert_eq!(icmp_error.mtu, 1280);
+    }
+}
@@ -0,0 +1,363 @@
+use std::collections::BTreeMap;
+use std::io::BufRead;
+
+use anyhow::Result;
+use serde_derive::Deserialize;
+use time::OffsetDateTime;
+
+use crate::core::common::AddrKey;
+use crate::util::parser::parse_rfc3339;
+use crate::util::stats::percentile;
+
+/// A line of a JSON log file written with `--json`.
+#[derive(Deserialize)]
+struct LogLine {
+    timestamp: String,
+    fields: LogFields,
+}
+
+/// The fields of a result event. Summary events and
+/// other messages do not have a success field.
+#[derive(Deserialize)]
+struct LogFields {
+    #[serde(default)]
+    summary: bool,
+    destination: Option<String>,
+    protocol: Option<String>,
+    time: Option<f64>,
+    success: Option<bool>,
+    #[serde(default)]
+    tags: String,
+}
+
+/// A probe result read from a JSON log file.
+#[derive(Clone, Debug)]
+pub struct LogRecord {
+    pub timestamp: OffsetDateTime,
+    pub destination: String,
+    pub protocol: String,
+    pub time: f64,
+    pub success: bool,
+    pub tags: BTreeMap<String, String>,
+}
+
+/// Availability and latency of a destination over the report period.
+#[derive(Clone, Debug)]
+pub struct SlaResult {
+    pub destination: String,
+    pub protocol: String,
+    pub sent: usize,
+    pub received: usize,
+    pub availability: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+/// A run of consecutive failed probes to a destination. An outage
+/// ends with the next successful probe, or is ongoing at the end
+/// of the report period.
+#[derive(Clone, Debug)]
+pub struct Outage {
+    pub destination: String,
+    pub start: OffsetDateTime,
+    pub end: Option<OffsetDateTime>,
+    pub failures: usize,
+}
+
+/// The results and outages of a group of destinations.
+#[derive(Clone, Debug)]
+pub struct SlaGroup {
+    pub name: String,
+    pub results: Vec<SlaResult>,
+    pub outages: Vec<Outage>,
+}
+
+/// Read the probe results between `from` and `to` from a JSON log file.
+/// Lines that are not result events are skipped.
+pub fn read_log_records<R: BufRead>(reader: R, from: OffsetDateTime, to: OffsetDateTime) -> Result<Vec<LogRecord>> {
+    let mut records = Vec::new();
+    for line in reader.lines() {
+        let Ok(log_line) = serde_json::from_str::<LogLine>(&line?) else {
+            continue;
+        };
+        let fields = log_line.fields;
+        let (Some(destination), Some(protocol), Some(time), Some(success), false) = (
+            fields.destination,
+            fields.protocol,
+            fields.time,
+            fields.success,
+            fields.summary,
+        ) else {
+            continue;
+        };
+        let timestamp = parse_rfc3339(&log_line.timestamp)?;
+        if timestamp < from || timestamp >= to {
+            continue;
+        }
+        let tags = fields
+            .tags
+            .split(',')
+            .filter_map(|tag| tag.split_once('='))
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+            .collect();
+        records.push(LogRecord {
+            timestamp,
+            destination,
+            protocol,
+            time,
+            success,
+            tags,
+        });
+    }
+    records.sort_by_key(|record| record.timestamp);
+    Ok(records)
+}
+
+/// Summarise records by group, then destination. Destinations are grouped
+/// by the value of the `group_by` tag, or all in one group without it.
+pub fn sla_report(records: &[LogRecord], group_by: Option<&str>) -> Vec<SlaGroup> {
+    let mut groups: BTreeMap<String, BTreeMap<AddrKey, Vec<&LogRecord>>> = BTreeMap::new();
+    for record in records {
+        let group = match group_by {
+            Some(key) => record.tags.get(key).cloned().unwrap_or_else(|| "-".to_owned()),
+            None => "all".to_owned(),
+        };
+        groups
+            .entry(group)
+            .or_default()
+            .entry(AddrKey::new(&record.destination))
+            .or_default()
+            .push(record);
+    }
+    groups
+        .into_iter()
+        .map(|(name, destinations)| SlaGroup {
+            name,
+            results: destinations.values().map(|records| sla_result(records)).collect(),
+            outages: destinations.values().flat_map(|records| outages(records)).collect(),
+        })
+        .collect()
+}
+
+fn sla_result(records: &[&LogRecord]) -> SlaResult {
+    let latencies: Vec<f64> = records.iter().filter(|r| r.success).map(|r| r.time).collect();
+    SlaResult {
+        destination: records[0].destination.to_owned(),
+        protocol: records[0].protocol.to_owned(),
+        sent: records.len(),
+        received: latencies.len(),
+        availability: latencies.len() as f64 / records.len() as f64 * 100.0,
+        p50: percentile(&latencies, 50.0),
+        p90: percentile(&latencies, 90.0),
+        p95: percentile(&latencies, 95.0),
+        p99: percentile(&latencies, 99.0),
+        max: latencies.iter().cloned().fold(0.0, f64::max),
+    }
+}
+
+fn outages(records: &[&LogRecord]) -> Vec<Outage> {
+    let mut outages: Vec<Outage> = Vec::new();
+    let mut current: Option<Outage> = None;
+    for record in records {
+        match (record.success, current.as_mut()) {
+            (false, Some(outage)) => outage.failures += 1,
+            (false, None) => {
+                current = Some(Outage {
+                    destination: record.destination.to_owned(),
+                    start: record.timestamp,
+                    end: None,
+                    failures: 1,
+                })
+            }
+            (true, Some(_)) => {
+                if let Some(mut outage) = current.take() {
+                    outage.end = Some(record.timestamp);
+                    outages.push(outage);
+                }
+            }
+            (true, None) => {}
+        }
+    }
+    outages.extend(current);
+    outages
+}
+
+/// Return an outage duration, eg: 1h 2m 3s. Ongoing outages
+/// last until the end of the period, or now if that is earlier.
+pub fn outage_duration_msg(outage: &Outage, to: OffsetDateTime) -> String {
+    let end = outage.end.unwrap_or(to.min(OffsetDateTime::now_utc()));
+    let seconds = (end - outage.start).whole_seconds().max(0);
+    match (seconds / 3600, seconds % 3600 / 60, seconds % 60) {
+        (0, 0, s) => format!("{s}s"),
+        (0, m, s) => format!("{m}m {s}s"),
+        (h, m, s) => format!("{h}h {m}m {s}s"),
+    }
+}
+
+/// Return a time as an RFC3339 UTC timestamp, to the second.
+pub fn timestamp_msg(time: OffsetDateTime) -> String {
+    let time = time.to_offset(time::UtcOffset::UTC);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        time.year(),
+        u8::from(time.month()),
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second()
+    )
+}
+
+/// Returns a self contained HTML SLA report.
+pub fn sla_report_html(from: OffsetDateTime, to: OffsetDateTime, groups: &[SlaGroup]) -> String {
+    let period = format!("{} to {}", timestamp_msg(from), timestamp_msg(to));
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>NetKraken SLA report {period}</title>\n\
+         <style>\nbody {{ font-family: sans-serif; }}\ntable {{ border-collapse: collapse; margin-bottom: 1em; }}\n\
+         th, td {{ border: 1px solid #999; padding: 4px 8px; text-align: right; }}\n\
+         th:first-child, td:first-child {{ text-align: left; }}\n.breach {{ color: #b00; }}\n</style>\n\
+         </head>\n<body>\n<h1>NetKraken SLA report</h1>\n<p>{period}</p>\n"
+    );
+    for group in groups {
+        html.push_str(&format!("<h2>{}</h2>\n<table>\n<tr>", html_escape(&group.name)));
+        for heading in [
+            "Destination",
+            "Protocol",
+            "Sent",
+            "Received",
+            "Availability",
+            "p50 (ms)",
+            "p90 (ms)",
+            "p95 (ms)",
+            "p99 (ms)",
+            "Max (ms)",
+        ] {
+            html.push_str(&format!("<th>{heading}</th>"));
+        }
+        html.push_str("</tr>\n");
+        for result in &group.results {
+            let class = match result.received < result.sent {
+                true => " class=\"breach\"",
+                false => "",
+            };
+            html.push_str(&format!(
+                "<tr{class}><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.3}%</td>\
+                 <td>{:.3}</td><td>{:.3}</td><td>{:.3}</td><td>{:.3}</td><td>{:.3}</td></tr>\n",
+                html_escape(&result.destination),
+                html_escape(&result.protocol),
+                result.sent,
+                result.received,
+                result.availability,
+                result.p50,
+                result.p90,
+                result.p95,
+                result.p99,
+                result.max,
+            ));
+        }
+        html.push_str("</table>\n");
+        match group.outages.is_empty() {
+            true => html.push_str("<p>No outages.</p>\n"),
+            false => {
+                html.push_str(
+                    "<table>\n<tr><th>Destination</th><th>Start</th><th>End</th><th>Duration</th>\
+                     <th>Failed Probes</th></tr>\n",
+                );
+                for outage in &group.outages {
+                    html.push_str(&format!(
+                        "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                        html_escape(&outage.destination),
+                        timestamp_msg(outage.start),
+                        outage.end.map(timestamp_msg).unwrap_or_else(|| "ongoing".to_owned()),
+                        outage_duration_msg(outage, to),
+                        outage.failures,
+                    ));
+                }
+                html.push_str("</table>\n");
+            }
+        }
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = r#"{"timestamp":"2024-01-01T00:00:00.000000Z","level":"INFO","fields":{"schema_version":11,"result":"ping","protocol":"tcp","source":"10.0.0.1:50000","destination":"10.0.0.9:443","time":1.0,"success":true,"error_msg":"","tags":"site=syd"},"target":"NK"}
+{"timestamp":"2024-01-01T00:01:00.000000Z","level":"ERROR","fields":{"message":"timeout => proto=TCP"},"target":"NK"}
+{"timestamp":"2024-01-01T00:01:00.000000Z","level":"INFO","fields":{"schema_version":11,"result":"timeout","protocol":"tcp","source":"10.0.0.1:50000","destination":"10.0.0.9:443","time":-1.0,"success":false,"error_msg":"","tags":"site=syd"},"target":"NK"}
+{"timestamp":"2024-01-01T00:02:00.000000Z","level":"INFO","fields":{"schema_version":11,"result":"timeout","protocol":"tcp","source":"10.0.0.1:50000","destination":"10.0.0.9:443","time":-1.0,"success":false,"error_msg":"","tags":"site=syd"},"target":"NK"}
+{"timestamp":"2024-01-01T00:03:30.000000Z","level":"INFO","fields":{"schema_version":11,"result":"ping","protocol":"tcp","source":"10.0.0.1:50000","destination":"10.0.0.9:443","time":3.0,"success":true,"error_msg":"","tags":"site=syd"},"target":"NK"}
+{"timestamp":"2024-01-01T00:03:00.000000Z","level":"INFO","fields":{"schema_version":11,"result":"ping","protocol":"tcp","source":"10.0.0.1:50000","destination":"10.0.0.10:443","time":2.0,"success":true,"error_msg":"","tags":"site=mel"},"target":"NK"}
+{"timestamp":"2024-01-01T00:04:00.000000Z","level":"INFO","fields":{"schema_version":11,"summary":true,"protocol":"tcp","destination":"10.0.0.9:443","success":true,"tags":"site=syd"},"target":"NK"}
+{"timestamp":"2024-02-01T00:00:00.000000Z","level":"INFO","fields":{"schema_version":11,"result":"timeout","protocol":"tcp","source":"10.0.0.1:50000","destination":"10.0.0.9:443","time":-1.0,"success":false,"error_msg":"","tags":"site=syd"},"target":"NK"}
+"#;
+
+    fn records() -> Vec<LogRecord> {
+        let from = parse_rfc3339("2024-01-01T00:00:00Z").unwrap();
+        let to = parse_rfc3339("2024-01-02T00:00:00Z").unwrap();
+        read_log_records(LOG.as_bytes(), from, to).unwrap()
+    }
+
+    #[test]
+    fn results_in_the_period_are_read() {
+        let records = records();
+
+        assert_eq!(records.len(), 5);
+        assert_eq!(records[0].tags.get("site"), Some(&"syd".to_owned()));
+        assert!(records.windows(2).all(|pair| pair[0].timestamp <= pair[1].timestamp));
+    }
+
+    #[test]
+    fn results_are_summarised_by_group() {
+        let groups = sla_report(&records(), Some("site"));
+
+        assert_eq!(
+            groups.iter().map(|g| g.name.as_str()).collect::<Vec<&str>>(),
+            ["mel", "syd"]
+        );
+        let syd = &groups[1];
+        assert_eq!(syd.results[0].sent, 4);
+        assert_eq!(syd.results[0].received, 2);
+        assert_eq!(syd.results[0].availability, 50.0);
+        assert_eq!(syd.results[0].max, 3.0);
+        assert_eq!(syd.outages.len(), 1);
+        assert_eq!(syd.outages[0].failures, 2);
+        assert_eq!(
+            outage_duration_msg(&syd.outages[0], OffsetDateTime::now_utc()),
+            "2m 30s"
+        );
+
+        let all = sla_report(&records(), None);
+        assert_eq!(all.len(), 1);
+        // Destinations are ordered numerically.
+        assert_eq!(all[0].results[0].destination, "10.0.0.9:443");
+    }
+
+    #[test]
+    fn ongoing_outages_have_no_end() {
+        let mut records = records();
+        records.truncate(3);
+        let groups = sla_report(&records, None);
+
+        assert_eq!(groups[0].outages[0].end, None);
+        let html = sla_report_html(
+            parse_rfc3339("2024-01-01T00:00:00Z").unwrap(),
+            parse_rfc3339("2024-01-01T00:10:00Z").unwrap(),
+            &groups,
+        );
+        assert!(html.contains("<td>ongoing</td><td>9m 0s</td>"));
+    }
+}
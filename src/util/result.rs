@@ -1,13 +1,23 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
 
-use crate::core::common::{ClientResult, ClientSummary, ConnectMethod, HostRecord};
+use crate::core::common::{
+    AddrKey, ClientResult, ClientSummary, ConnectMethod, ConnectRecord, HostRecord, HostServices, LoggingOptions,
+    LossBursts, LossPattern, Phase, ProbeSample, RunDiff, SortBy,
+};
+#[cfg(feature = "server")]
+use crate::core::common::{ConnectResult, SourceSummary};
+use crate::core::konst::{HISTOGRAM_BOUNDS, LOSS_BURST_CORRELATION};
+use crate::util::stats::{histogram, jitter, percentile, std_dev};
 
 /// Return a results_map hash from a Vec of HostRecords
-pub fn get_results_map(host_records: &[HostRecord]) -> HashMap<String, HashMap<String, Vec<f64>>> {
-    let mut results_map: HashMap<String, HashMap<String, Vec<f64>>> = HashMap::new();
+pub fn get_results_map(host_records: &[HostRecord]) -> HashMap<String, HashMap<String, Vec<ProbeSample>>> {
+    let mut results_map: HashMap<String, HashMap<String, Vec<ProbeSample>>> = HashMap::new();
 
     for record in host_records.iter().cloned() {
-        results_map.insert(record.host.to_owned(), HashMap::new());
+        // Destinations on the same host share a section.
+        results_map.entry(record.host.to_owned()).or_default();
 
         for addr in record.ipv4_sockets {
             results_map
@@ -28,6 +38,90 @@ pub fn get_results_map(host_records: &[HostRecord]) -> HashMap<String, HashMap<S
     results_map
 }
 
+/// Evaluate the `--assert` expression, if any, against each summary.
+pub fn assert_results(client_results: &mut [ClientResult], logging_options: &LoggingOptions) {
+    if let Some(assertion) = &logging_options.assertion {
+        for result in client_results {
+            result.passed = Some(assertion.evaluate(result));
+        }
+    }
+}
+
+/// Label each summary with the label of its destination address.
+/// Warm requests keep their series suffix.
+pub fn label_results(client_results: &mut [ClientResult], socket_labels: &BTreeMap<String, String>) {
+    for result in client_results {
+        let (addr, suffix) = match result.destination.strip_suffix(" (warm)") {
+            Some(addr) => (addr, " (warm)"),
+            None => (result.destination.as_str(), ""),
+        };
+        result.label = socket_labels.get(addr).map(|label| format!("{label}{suffix}"));
+    }
+}
+
+/// Sort summaries by the `--sort-by` key, breaking ties by destination.
+pub fn sort_results(client_results: &mut [ClientResult], logging_options: &LoggingOptions) {
+    client_results.sort_by(|a, b| {
+        let ordering = match logging_options.sort_by {
+            SortBy::Dest => AddrKey::new(&a.destination).cmp(&AddrKey::new(&b.destination)),
+            SortBy::Name => a.destination.cmp(&b.destination),
+            SortBy::Loss => a.loss_percent.total_cmp(&b.loss_percent),
+            SortBy::P95 => latency_key(a, a.p95).total_cmp(&latency_key(b, b.p95)),
+            SortBy::Avg => latency_key(a, a.avg).total_cmp(&latency_key(b, b.avg)),
+        };
+        match logging_options.sort_desc {
+            true => ordering.reverse(),
+            false => ordering,
+        }
+        .then_with(|| AddrKey::new(&a.destination).cmp(&AddrKey::new(&b.destination)))
+    });
+}
+
+/// Pair the summaries of each destination in two runs. A destination
+/// regressed when its loss rose by more than `loss_threshold` percentage
+/// points, or its average or P95 latency by more than `latency_threshold`
+/// percent. Destinations only probed in one run are not regressions.
+pub fn diff_results(
+    before: &[ClientResult],
+    after: &[ClientResult],
+    latency_threshold: f64,
+    loss_threshold: f64,
+) -> Vec<RunDiff> {
+    let mut destinations: Vec<&String> = before.iter().chain(after).map(|r| &r.destination).collect();
+    destinations.sort_by_cached_key(|destination| AddrKey::new(destination));
+    destinations.dedup();
+
+    let slower = |before: f64, after: f64| after > before * (1.0 + latency_threshold / 100.0);
+    destinations
+        .into_iter()
+        .map(|destination| {
+            let before = before.iter().find(|r| &r.destination == destination);
+            let after = after.iter().find(|r| &r.destination == destination);
+            let regressed = match (before, after) {
+                (Some(b), Some(a)) => {
+                    a.loss_percent - b.loss_percent > loss_threshold
+                        || (b.received > 0 && a.received > 0 && (slower(b.avg, a.avg) || slower(b.p95, a.p95)))
+                }
+                _ => false,
+            };
+            RunDiff {
+                destination: destination.to_owned(),
+                before: before.cloned(),
+                after: after.cloned(),
+                regressed,
+            }
+        })
+        .collect()
+}
+
+/// Destinations without a reply are slower than any with one.
+fn latency_key(result: &ClientResult, latency: f64) -> f64 {
+    match result.received {
+        0 => f64::INFINITY,
+        _ => latency,
+    }
+}
+
 /// Returns a client summary result
 pub fn client_summary_result(
     destination: &String,
@@ -37,12 +131,20 @@ pub fn client_summary_result(
     let mut min: f64 = 0.0;
     let mut max: f64 = 0.0;
     let mut avg: f64 = 0.0;
-    let mut latencies = client_summary.latencies;
+    let samples = client_summary.samples;
+    let loss_bursts = calc_loss_bursts(&samples);
+    let failures: Vec<&ProbeSample> = samples.iter().filter(|s| s.time.is_none()).collect();
+    let first_failure = failures.first().and_then(|s| s.timestamp.clone());
+    let last_failure = failures.last().and_then(|s| s.timestamp.clone());
 
     // Filetr our any f64::NAN
+    let mut latencies: Vec<f64> = samples.iter().filter_map(|s| s.time).collect();
     latencies.retain(|f| !f.is_nan());
     latencies.retain(|f| f > &0.0);
 
+    // Jitter is measured between consecutive replies, before sorting.
+    let jitter = jitter(&latencies);
+
     // Sort lowest to highest
     latencies.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
 
@@ -65,6 +167,269 @@ pub fn client_summary_result(
         min,
         max,
         avg,
+        p50: percentile(&latencies, 50.0),
+        p90: percentile(&latencies, 90.0),
+        p95: percentile(&latencies, 95.0),
+        p99: percentile(&latencies, 99.0),
+        jitter,
+        std_dev: std_dev(&latencies),
+        histogram: histogram(&latencies, &HISTOGRAM_BOUNDS),
+        loss_bursts,
+        first_failure,
+        last_failure,
+        phases: vec![],
+        passed: None,
+        label: None,
+    }
+}
+
+/// Returns a connection record for a request a server received from a client.
+/// The time is the one way time from a NetKraken peer, otherwise -1.
+#[cfg(feature = "server")]
+pub fn server_record(
+    protocol: ConnectMethod,
+    peer_addr: &str,
+    local_addr: &str,
+    client_server_time: f64,
+    warm: bool,
+    logging_options: &LoggingOptions,
+) -> ConnectRecord {
+    ConnectRecord {
+        result: ConnectResult::Ping,
+        protocol,
+        source: peer_addr.to_owned(),
+        destination: local_addr.to_owned(),
+        time: match client_server_time == 0.0 {
+            true => -1.0,
+            false => client_server_time,
+        },
+        success: true,
+        error_msg: None,
+        tags: logging_options.tags.clone(),
+        warm,
+        ..Default::default()
+    }
+}
+
+/// Accumulates the requests a server receives by source IP,
+/// as clients use a new source port for each probe.
+#[cfg(feature = "server")]
+#[derive(Default)]
+pub struct SourceAccumulator {
+    sources: BTreeMap<AddrKey, (u64, u64, Vec<f64>)>,
+}
+
+#[cfg(feature = "server")]
+impl SourceAccumulator {
+    pub fn push(&mut self, record: &ConnectRecord, bytes: usize) {
+        let source = match record.source.parse::<SocketAddr>() {
+            Ok(addr) => addr.ip().to_string(),
+            Err(_) => record.source.to_owned(),
+        };
+        let (requests, total_bytes, one_way_times) = self.sources.entry(AddrKey::new(&source)).or_default();
+        *requests += 1;
+        *total_bytes += bytes as u64;
+        if record.time > 0.0 {
+            one_way_times.push(record.time);
+        }
+    }
+
+    /// Return a summary of the requests received from each source.
+    pub fn summaries(&self) -> Vec<SourceSummary> {
+        self.sources
+            .iter()
+            .map(|(source, (requests, bytes, one_way_times))| SourceSummary {
+                source: source.to_string(),
+                requests: *requests,
+                bytes: *bytes,
+                avg_one_way: match one_way_times.is_empty() {
+                    true => 0.0,
+                    false => one_way_times.iter().sum::<f64>() / one_way_times.len() as f64,
+                },
+            })
+            .collect()
+    }
+}
+
+/// Accumulates the phase times of successful probes by destination.
+#[derive(Default)]
+pub struct PhaseAccumulator {
+    phases: HashMap<String, BTreeMap<Phase, Vec<f64>>>,
+}
+
+impl PhaseAccumulator {
+    pub fn push(&mut self, record: &ConnectRecord) {
+        if !record.success {
+            return;
+        }
+        let phases = self.phases.entry(record.series()).or_default();
+        for (phase, time) in record.phases() {
+            phases.entry(phase).or_default().push(time);
+        }
+    }
+
+    /// Return the average time of each phase for a destination.
+    pub fn averages(&self, destination: &str) -> Vec<(Phase, f64)> {
+        match self.phases.get(destination) {
+            Some(phases) => phases
+                .iter()
+                .map(|(phase, times)| (*phase, times.iter().sum::<f64>() / times.len() as f64))
+                .collect(),
+            None => vec![],
+        }
+    }
+}
+
+/// Accumulates the ports probed on each host, whether they were open,
+/// and the services seen on them, from the details the probes gathered.
+#[derive(Default)]
+pub struct ServiceAccumulator {
+    hosts: BTreeMap<AddrKey, BTreeMap<u16, (bool, BTreeSet<String>)>>,
+}
+
+impl ServiceAccumulator {
+    pub fn push(&mut self, record: &ConnectRecord) {
+        let Ok(addr) = record.destination.parse::<SocketAddr>() else {
+            return;
+        };
+        let (open, services) = self
+            .hosts
+            .entry(AddrKey::new(&addr.ip().to_string()))
+            .or_default()
+            .entry(addr.port())
+            .or_default();
+        if !record.success {
+            return;
+        }
+        *open = true;
+        if let Some(mail) = &record.mail {
+            if !mail.banner.is_empty() {
+                services.insert(mail.banner.to_owned());
+            }
+        }
+        if let Some(tls) = &record.tls {
+            services.insert(tls.version.to_owned());
+        }
+        if record.peer.is_some() {
+            services.insert("NetKraken peer".to_owned());
+        }
+    }
+
+    /// Return the services of each host probed on more than one port.
+    pub fn summaries(&self) -> Vec<HostServices> {
+        self.hosts
+            .iter()
+            .filter(|(_, ports)| ports.len() > 1)
+            .map(|(host, ports)| HostServices {
+                host: host.to_string(),
+                open: ports
+                    .iter()
+                    .filter(|(_, (open, _))| *open)
+                    .map(|(port, _)| *port)
+                    .collect(),
+                closed: ports
+                    .iter()
+                    .filter(|(_, (open, _))| !*open)
+                    .map(|(port, _)| *port)
+                    .collect(),
+                services: ports
+                    .iter()
+                    .flat_map(|(port, (_, services))| services.iter().map(|service| (*port, service.to_owned())))
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+/// The last probes to each destination, up to a window of probes.
+#[derive(Clone, Debug)]
+pub struct SampleWindow {
+    window: usize,
+    samples: BTreeMap<AddrKey, (ConnectMethod, VecDeque<ProbeSample>)>,
+}
+
+impl SampleWindow {
+    /// A window of 0 probes keeps every probe.
+    pub fn new(window: u16) -> SampleWindow {
+        SampleWindow {
+            window: window.into(),
+            samples: BTreeMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, record: &ConnectRecord) {
+        let samples = &mut self
+            .samples
+            .entry(AddrKey::new(&record.series()))
+            .or_insert_with(|| (record.protocol, VecDeque::new()))
+            .1;
+        samples.push_back(ProbeSample::new(record));
+        if self.window > 0 && samples.len() > self.window {
+            samples.pop_front();
+        }
+    }
+
+    /// Return a summary of the probes in the window to each destination.
+    pub fn results(&self) -> Vec<ClientResult> {
+        self.samples
+            .iter()
+            .map(|(destination, (protocol, samples))| {
+                let client_summary = ClientSummary {
+                    send_count: samples.len() as u16,
+                    samples: samples.iter().cloned().collect(),
+                };
+                client_summary_result(&destination.to_string(), *protocol, client_summary)
+            })
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+}
+
+/// Accumulates results between interim summaries, so each
+/// summary describes only the interval it covers. When watching,
+/// the last probes to each destination are kept across intervals
+/// instead, so each summary describes a rolling window.
+pub struct IntervalAccumulator {
+    interval: Duration,
+    watching: bool,
+    started: Instant,
+    samples: SampleWindow,
+}
+
+impl IntervalAccumulator {
+    /// An interval of 0 seconds disables interim summaries.
+    /// A window of 0 probes disables rolling summaries.
+    pub fn new(interval: u16, window: u16) -> IntervalAccumulator {
+        IntervalAccumulator {
+            interval: Duration::from_secs(interval.into()),
+            watching: window > 0,
+            started: Instant::now(),
+            samples: SampleWindow::new(window),
+        }
+    }
+
+    pub fn push(&mut self, record: &ConnectRecord) {
+        if self.interval.is_zero() {
+            return;
+        }
+        self.samples.push(record);
+    }
+
+    /// Return the results of the interval once it has elapsed
+    /// and start a new interval.
+    pub fn take_due(&mut self) -> Option<Vec<ClientResult>> {
+        if self.interval.is_zero() || self.started.elapsed() < self.interval {
+            return None;
+        }
+        self.started = Instant::now();
+        let results = self.samples.results();
+        if !self.watching {
+            self.samples.clear();
+        }
+        Some(results)
     }
 }
 
@@ -75,14 +440,137 @@ pub fn calc_loss_percent(sent: u16, received: u16) -> f64 {
     percent * 100.0
 }
 
+/// Calculate loss burst statistics from samples in the order they were sent.
+/// A sample without a time greater than zero is a lost connection.
+/// Loss is bursty when a loss makes another loss more likely,
+/// which the Gilbert-Elliott model expresses as `1 - p - r > 0`.
+pub fn calc_loss_bursts(samples: &[ProbeSample]) -> LossBursts {
+    let lost: Vec<bool> = samples
+        .iter()
+        .map(|s| s.time.is_none_or(|f| f.is_nan() || f <= 0.0))
+        .collect();
+
+    let mut bursts: Vec<u16> = Vec::new();
+    let mut run: u16 = 0;
+    for is_lost in &lost {
+        match is_lost {
+            true => run += 1,
+            false if run > 0 => {
+                bursts.push(run);
+                run = 0;
+            }
+            false => {}
+        }
+    }
+    if run > 0 {
+        bursts.push(run);
+    }
+    if bursts.is_empty() {
+        return LossBursts::default();
+    }
+
+    // Count transitions between success (good) and loss (bad) states.
+    let (mut good, mut good_bad, mut bad, mut bad_good) = (0, 0, 0, 0);
+    for pair in lost.windows(2) {
+        match (pair[0], pair[1]) {
+            (false, next) => {
+                good += 1;
+                good_bad += next as u16;
+            }
+            (true, next) => {
+                bad += 1;
+                bad_good += !next as u16;
+            }
+        }
+    }
+    let ratio = |n: u16, d: u16| if d == 0 { 0.0 } else { n as f64 / d as f64 };
+    let p = ratio(good_bad, good);
+    let r = ratio(bad_good, bad);
+
+    let longest = *bursts.iter().max().unwrap_or(&0);
+    let pattern = match longest > 1 && 1.0 - p - r > LOSS_BURST_CORRELATION {
+        true => LossPattern::Bursty,
+        false => LossPattern::Random,
+    };
+
+    LossBursts {
+        bursts: bursts.len() as u16,
+        longest,
+        mean_length: bursts.iter().map(|b| *b as f64).sum::<f64>() / bursts.len() as f64,
+        p,
+        r,
+        pattern,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
     use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 
-    use crate::core::common::HostRecord;
+    use crate::core::common::{ConnectResult, HostRecord, TlsRecord};
     use crate::util::result::*;
 
+    #[cfg(feature = "server")]
+    #[test]
+    fn server_records_have_no_time_without_a_peer() {
+        let logging_options = LoggingOptions::default();
+
+        let record = server_record(
+            ConnectMethod::TCP,
+            "127.0.0.1:50000",
+            "127.0.0.1:13337",
+            0.0,
+            false,
+            &logging_options,
+        );
+        assert_eq!(record.time, -1.0);
+        assert_eq!(record.series(), "127.0.0.1:13337");
+
+        let record = server_record(
+            ConnectMethod::TCP,
+            "127.0.0.1:50000",
+            "127.0.0.1:13337",
+            0.25,
+            true,
+            &logging_options,
+        );
+        assert_eq!(record.time, 0.25);
+        assert_eq!(record.series(), "127.0.0.1:13337 (warm)");
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn source_accumulator_summarises_each_source() {
+        let logging_options = LoggingOptions::default();
+        let record = |source, time| {
+            server_record(
+                ConnectMethod::UDP,
+                source,
+                "127.0.0.1:13337",
+                time,
+                false,
+                &logging_options,
+            )
+        };
+        let mut accumulator = SourceAccumulator::default();
+        accumulator.push(&record("127.0.0.1:50000", 1.0), 10);
+        accumulator.push(&record("127.0.0.1:50001", 0.0), 10);
+        accumulator.push(&record("127.0.0.1:50002", 2.0), 10);
+        accumulator.push(&record("[::1]:50000", 0.0), 5);
+
+        let summaries = accumulator.summaries();
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(
+            (summaries[0].requests, summaries[0].bytes, summaries[0].avg_one_way),
+            (3, 30, 1.5)
+        );
+        assert_eq!(
+            (summaries[1].requests, summaries[1].bytes, summaries[1].avg_one_way),
+            (1, 5, 0.0)
+        );
+    }
+
     #[test]
     fn result_map_with_no_ips_is_expected() {
         let host_record = HostRecord {
@@ -94,8 +582,8 @@ mod tests {
         let host = host_record.host.to_owned();
 
         let results_map = get_results_map(&[host_record]);
-        let mut expected: HashMap<String, HashMap<String, Vec<f64>>> = HashMap::new();
-        let ip_map: HashMap<String, Vec<f64>> = HashMap::new();
+        let mut expected: HashMap<String, HashMap<String, Vec<ProbeSample>>> = HashMap::new();
+        let ip_map: HashMap<String, Vec<ProbeSample>> = HashMap::new();
 
         expected.insert(host.to_owned(), ip_map);
 
@@ -114,8 +602,8 @@ mod tests {
         let ipv4_sockets = host_record.ipv4_sockets.clone();
         let ipv6_sockets = host_record.ipv6_sockets.clone();
         let results_map = get_results_map(&[host_record]);
-        let mut expected: HashMap<String, HashMap<String, Vec<f64>>> = HashMap::new();
-        let mut ipv4_map: HashMap<String, Vec<f64>> = HashMap::new();
+        let mut expected: HashMap<String, HashMap<String, Vec<ProbeSample>>> = HashMap::new();
+        let mut ipv4_map: HashMap<String, Vec<ProbeSample>> = HashMap::new();
 
         ipv4_map.insert(ipv4_sockets[0].to_string(), vec![]);
         expected.insert(host.to_owned(), ipv4_map);
@@ -127,10 +615,291 @@ mod tests {
         assert_eq!(results_map, expected);
     }
 
+    fn samples(times: &[f64]) -> Vec<ProbeSample> {
+        times
+            .iter()
+            .enumerate()
+            .map(|(i, time)| ProbeSample {
+                time: (*time > 0.0).then_some(*time),
+                timestamp: Some(format!("t{i}")),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn calc_loss_bursts_with_no_loss_is_none() {
+        let loss_bursts = calc_loss_bursts(&samples(&[1.0, 2.0, 3.0]));
+
+        assert_eq!(loss_bursts.bursts, 0);
+        assert_eq!(loss_bursts.pattern, LossPattern::None);
+    }
+
+    #[test]
+    fn calc_loss_bursts_with_scattered_loss_is_random() {
+        let loss_bursts = calc_loss_bursts(&samples(&[1.0, -1.0, 1.0, 1.0, -1.0, 1.0, 1.0, 1.0, -1.0, 1.0]));
+
+        assert_eq!(loss_bursts.bursts, 3);
+        assert_eq!(loss_bursts.longest, 1);
+        assert_eq!(loss_bursts.r, 1.0);
+        assert_eq!(loss_bursts.pattern, LossPattern::Random);
+    }
+
+    #[test]
+    fn calc_loss_bursts_with_consecutive_loss_is_bursty() {
+        let loss_bursts = calc_loss_bursts(&samples(&[1.0, 1.0, 1.0, -1.0, -1.0, -1.0, -1.0, 1.0, 1.0, 1.0]));
+
+        assert_eq!(loss_bursts.bursts, 1);
+        assert_eq!(loss_bursts.longest, 4);
+        assert_eq!(loss_bursts.mean_length, 4.0);
+        assert_eq!(loss_bursts.p, 0.2);
+        assert_eq!(loss_bursts.r, 0.25);
+        assert_eq!(loss_bursts.pattern, LossPattern::Bursty);
+    }
+
+    #[test]
+    fn client_summary_reports_first_and_last_failure() {
+        let client_summary = ClientSummary {
+            send_count: 6,
+            samples: samples(&[1.0, -1.0, -1.0, 1.0, -1.0, 1.0]),
+        };
+        let result = client_summary_result(&"127.0.0.1:443".to_owned(), ConnectMethod::TCP, client_summary);
+
+        assert_eq!(result.lost, 3);
+        assert_eq!(result.loss_bursts.longest, 2);
+        assert_eq!(result.first_failure.as_deref(), Some("t1"));
+        assert_eq!(result.last_failure.as_deref(), Some("t4"));
+    }
+
     #[test]
     fn calc_loss_percent_is_expected() {
         let loss = calc_loss_percent(100, 99);
 
         assert_eq!(loss, 1.0);
     }
+
+    #[test]
+    fn client_summary_jitter_skips_lost_connections() {
+        let client_summary = ClientSummary {
+            send_count: 5,
+            samples: samples(&[10.0, -1.0, 14.0, 12.0, 16.0]),
+        };
+        let result = client_summary_result(&"127.0.0.1:443".to_owned(), ConnectMethod::TCP, client_summary);
+
+        assert_eq!(result.jitter, 10.0 / 3.0);
+        assert_eq!(result.std_dev, 5.0_f64.sqrt());
+        assert_eq!(result.p90, 16.0);
+    }
+    #[test]
+    fn sort_results_orders_addresses_numerically() {
+        let result = |destination: &str, times: &[f64]| {
+            let client_summary = ClientSummary {
+                send_count: times.len() as u16,
+                samples: samples(times),
+            };
+            client_summary_result(&destination.to_owned(), ConnectMethod::TCP, client_summary)
+        };
+        let mut client_results = vec![
+            result("10.0.0.10:80", &[1.0, 1.0]),
+            result("example.com", &[3.0, -1.0]),
+            result("10.0.0.9:80", &[2.0, -1.0]),
+            result("[2001:db8::1]:80", &[-1.0, -1.0]),
+        ];
+        let destinations = |client_results: &[ClientResult]| {
+            client_results
+                .iter()
+                .map(|r| r.destination.to_owned())
+                .collect::<Vec<String>>()
+        };
+
+        let mut logging_options = LoggingOptions::default();
+        sort_results(&mut client_results, &logging_options);
+        assert_eq!(
+            destinations(&client_results),
+            ["10.0.0.9:80", "10.0.0.10:80", "[2001:db8::1]:80", "example.com"]
+        );
+
+        // Ties are broken by destination in ascending order.
+        logging_options.sort_by = SortBy::Loss;
+        logging_options.sort_desc = true;
+        sort_results(&mut client_results, &logging_options);
+        assert_eq!(
+            destinations(&client_results),
+            ["[2001:db8::1]:80", "10.0.0.9:80", "example.com", "10.0.0.10:80"]
+        );
+
+        // Destinations without a reply are the slowest.
+        logging_options.sort_by = SortBy::Avg;
+        logging_options.sort_desc = false;
+        sort_results(&mut client_results, &logging_options);
+        assert_eq!(
+            destinations(&client_results),
+            ["10.0.0.10:80", "10.0.0.9:80", "example.com", "[2001:db8::1]:80"]
+        );
+    }
+
+    #[test]
+    fn diff_results_flags_regressions_beyond_the_thresholds() {
+        let result = |destination: &str, times: &[f64]| {
+            let client_summary = ClientSummary {
+                send_count: times.len() as u16,
+                samples: samples(times),
+            };
+            client_summary_result(&destination.to_owned(), ConnectMethod::TCP, client_summary)
+        };
+        let before = vec![
+            result("10.0.0.1:443", &[10.0, 10.0, 10.0, 10.0]),
+            result("10.0.0.2:443", &[10.0, 10.0, 10.0, 10.0]),
+            result("10.0.0.3:443", &[10.0, 10.0, 10.0, 10.0]),
+            result("10.0.0.4:443", &[10.0]),
+        ];
+        let after = vec![
+            result("10.0.0.1:443", &[11.0, 11.0, 11.0, 11.0]),
+            result("10.0.0.2:443", &[13.0, 13.0, 13.0, 13.0]),
+            result("10.0.0.3:443", &[10.0, 10.0, 10.0, -1.0]),
+            result("10.0.0.5:443", &[10.0]),
+        ];
+
+        let diffs = diff_results(&before, &after, 20.0, 1.0);
+        let regressed: Vec<(&str, bool)> = diffs.iter().map(|d| (d.destination.as_str(), d.regressed)).collect();
+        assert_eq!(
+            regressed,
+            [
+                ("10.0.0.1:443", false),
+                ("10.0.0.2:443", true),
+                ("10.0.0.3:443", true),
+                ("10.0.0.4:443", false),
+                ("10.0.0.5:443", false),
+            ]
+        );
+        assert!(diffs[3].after.is_none() && diffs[4].before.is_none());
+    }
+
+    #[test]
+    fn label_results_labels_the_addresses_of_labelled_hosts() {
+        let logging_options = LoggingOptions {
+            labels: BTreeMap::from([("Core-FW.example.com.:443".to_owned(), "core-fw".to_owned())]),
+            ..Default::default()
+        };
+        let host_record = |host: &str, port: u16| HostRecord {
+            host: host.to_owned(),
+            port,
+            ipv4_sockets: vec![SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 1, 1, 1)), port)],
+            ipv6_sockets: vec![],
+        };
+        let socket_labels =
+            logging_options.socket_labels(&[host_record("core-fw.example.com", 443), host_record("web", 80)]);
+        assert_eq!(
+            socket_labels,
+            BTreeMap::from([("10.1.1.1:443".to_owned(), "core-fw".to_owned())])
+        );
+
+        let result = |destination: &str| {
+            let client_summary = ClientSummary {
+                send_count: 1,
+                samples: samples(&[1.0]),
+            };
+            client_summary_result(&destination.to_owned(), ConnectMethod::TCP, client_summary)
+        };
+        let mut client_results = vec![
+            result("10.1.1.1:443"),
+            result("10.1.1.1:443 (warm)"),
+            result("10.1.1.1:80"),
+        ];
+        label_results(&mut client_results, &socket_labels);
+
+        let labels: Vec<&str> = client_results.iter().map(|r| r.display_destination()).collect();
+        assert_eq!(labels, ["core-fw", "core-fw (warm)", "10.1.1.1:80"]);
+    }
+    #[test]
+    fn interval_accumulator_reports_each_interval() {
+        let record = ConnectRecord {
+            result: ConnectResult::Ping,
+            protocol: ConnectMethod::TCP,
+            source: "127.0.0.1:50000".to_owned(),
+            destination: "127.0.0.1:443".to_owned(),
+            time: 2.0,
+            success: true,
+            error_msg: None,
+            ..Default::default()
+        };
+        let mut disabled = IntervalAccumulator::new(0, 0);
+        disabled.push(&record);
+        assert!(disabled.take_due().is_none());
+
+        let mut accumulator = IntervalAccumulator::new(1, 0);
+        accumulator.push(&record);
+        assert!(accumulator.take_due().is_none());
+
+        accumulator.started -= Duration::from_secs(1);
+        let results = accumulator.take_due().unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].sent, 1);
+        assert_eq!(results[0].min, 2.0);
+
+        accumulator.started -= Duration::from_secs(1);
+        assert!(accumulator.take_due().unwrap().is_empty());
+    }
+    #[test]
+    fn service_accumulator_summarises_hosts_with_many_ports() {
+        let record = |destination: &str, success: bool, tls: Option<&str>| ConnectRecord {
+            result: match success {
+                true => ConnectResult::Ping,
+                false => ConnectResult::Refused,
+            },
+            protocol: ConnectMethod::TCP,
+            source: "127.0.0.1:50000".to_owned(),
+            destination: destination.to_owned(),
+            time: 2.0,
+            success,
+            error_msg: None,
+            tls: tls.map(|version| TlsRecord {
+                version: version.to_owned(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut accumulator = ServiceAccumulator::default();
+        accumulator.push(&record("10.0.0.1:443", true, Some("TLSv1_3")));
+        accumulator.push(&record("10.0.0.1:443", true, Some("TLSv1_3")));
+        accumulator.push(&record("10.0.0.1:80", false, None));
+        accumulator.push(&record("10.0.0.1:22", true, None));
+        accumulator.push(&record("10.0.0.2:443", true, Some("TLSv1_2")));
+
+        let summaries = accumulator.summaries();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].host, "10.0.0.1");
+        assert_eq!(summaries[0].open, [22, 443]);
+        assert_eq!(summaries[0].closed, [80]);
+        assert_eq!(summaries[0].services, [(443, "TLSv1_3".to_owned())]);
+    }
+    #[test]
+    fn interval_accumulator_keeps_rolling_window() {
+        let mut record = ConnectRecord {
+            result: ConnectResult::Ping,
+            protocol: ConnectMethod::TCP,
+            source: "127.0.0.1:50000".to_owned(),
+            destination: "127.0.0.1:443".to_owned(),
+            time: 2.0,
+            success: true,
+            error_msg: None,
+            ..Default::default()
+        };
+        let mut accumulator = IntervalAccumulator::new(1, 2);
+        accumulator.push(&record);
+        record.time = 4.0;
+        accumulator.push(&record);
+        record.time = -1.0;
+        record.success = false;
+        accumulator.push(&record);
+
+        accumulator.started -= Duration::from_secs(1);
+        let results = accumulator.take_due().unwrap();
+        assert_eq!(results[0].sent, 2);
+        assert_eq!(results[0].received, 1);
+        assert_eq!(results[0].min, 4.0);
+
+        // The window is kept for the next summary.
+        accumulator.started -= Duration::from_secs(1);
+        assert_eq!(accumulator.take_due().unwrap()[0].sent, 2);
+    }
 }
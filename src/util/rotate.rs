@@ -0,0 +1,107 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Result, Write};
+use std::path::{Path, PathBuf};
+
+use crate::core::common::LogRotation;
+use crate::util::time::time_now_us;
+
+/// A log file that is rotated when it reaches a size, or a new hour
+/// or day starts. Rotated files are renamed with a number, the most
+/// recent being `.1`, and only the most recent `keep` are retained.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64, // bytes, 0 disables
+    rotation: LogRotation,
+    period: u128,
+    keep: u16,
+}
+
+impl RotatingFile {
+    pub fn open(dir: &str, filename: &str, max_size: u64, rotation: LogRotation, keep: u16) -> Result<RotatingFile> {
+        fs::create_dir_all(dir)?;
+        let path = Path::new(dir).join(filename);
+        let file = append(&path)?;
+        Ok(RotatingFile {
+            size: file.metadata()?.len(),
+            file,
+            path,
+            max_size,
+            rotation,
+            period: rotation.period(time_now_us()),
+            keep,
+        })
+    }
+
+    fn rotated_path(&self, n: u16) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{n}"));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        self.file.flush()?;
+        match self.keep {
+            // Without retention the log is truncated.
+            0 => {}
+            keep => {
+                for n in (1..keep).rev() {
+                    let from = self.rotated_path(n);
+                    if from.exists() {
+                        fs::rename(from, self.rotated_path(n + 1))?;
+                    }
+                }
+                fs::rename(&self.path, self.rotated_path(1))?;
+            }
+        }
+        self.file = File::create(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let period = self.rotation.period(time_now_us());
+        let full = self.max_size > 0 && self.size > 0 && self.size + buf.len() as u64 > self.max_size;
+        if full || period != self.period {
+            self.rotate()?;
+            self.period = period;
+        }
+        let len = self.file.write(buf)?;
+        self.size += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.file.flush()
+    }
+}
+
+fn append(path: &Path) -> Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logs_are_rotated_by_size_and_retained() {
+        let dir = std::env::temp_dir().join(format!("nk-rotate-{}", uuid::Uuid::new_v4()));
+        let dir = dir.to_str().unwrap();
+        let mut file = RotatingFile::open(dir, "nk.log", 10, LogRotation::Never, 2).unwrap();
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+
+        let read = |name: &str| fs::read_to_string(Path::new(dir).join(name)).ok();
+        assert_eq!(read("nk.log").as_deref(), Some("fourth\n"));
+        assert_eq!(read("nk.log.1").as_deref(), Some("third\n"));
+        assert_eq!(read("nk.log.2").as_deref(), Some("second\n"));
+        assert_eq!(read("nk.log.3"), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,252 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::core::common::{ConnectMethod, HostRecord, IpProtocol};
+
+/// A traffic flow from an initiator to a responder.
+/// Unspecified addresses and port 0 match any address or port.
+#[derive(Clone, Debug)]
+pub struct Flow {
+    pub protocol: ConnectMethod,
+    pub src_ip: IpAddr,
+    pub src_port: u16,
+    pub dst_ip: IpAddr,
+    pub dst_port: u16,
+}
+
+/// Which end of a flow the local host is.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FlowRole {
+    Client,
+    Server,
+}
+
+/// Return the flows from the source addresses to each of
+/// the resolved destination addresses of a host.
+pub fn client_flows(
+    protocol: ConnectMethod,
+    src_ipv4: IpAddr,
+    src_ipv6: IpAddr,
+    src_port: u16,
+    host_record: &HostRecord,
+    ip_protocol: IpProtocol,
+) -> Vec<Flow> {
+    let sockets = match ip_protocol {
+        IpProtocol::All => [host_record.ipv4_sockets.clone(), host_record.ipv6_sockets.clone()].concat(),
+        IpProtocol::V4 => host_record.ipv4_sockets.clone(),
+        IpProtocol::V6 => host_record.ipv6_sockets.clone(),
+    };
+    sockets
+        .iter()
+        .map(|dst| Flow {
+            protocol,
+            src_ip: if dst.is_ipv4() { src_ipv4 } else { src_ipv6 },
+            src_port,
+            dst_ip: dst.ip(),
+            dst_port: dst.port(),
+        })
+        .collect()
+}
+
+/// Return the flow from any client to a listening server.
+pub fn server_flow(protocol: ConnectMethod, listen_ip: IpAddr, listen_port: u16) -> Flow {
+    let src_ip = match listen_ip.is_ipv4() {
+        true => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        false => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    Flow {
+        protocol,
+        src_ip,
+        src_port: 0,
+        dst_ip: listen_ip,
+        dst_port: listen_port,
+    }
+}
+
+/// Return iptables, nftables and ACL rules that permit a
+/// set of flows and their return traffic.
+pub fn suggest_rules(flows: &[Flow], role: FlowRole) -> String {
+    let iptables: Vec<String> = flows.iter().flat_map(|f| iptables_rules(f, role)).collect();
+    let nftables: Vec<String> = flows.iter().flat_map(|f| nftables_rules(f, role)).collect();
+    let acl: Vec<String> = flows.iter().flat_map(acl_rules).collect();
+
+    format!(
+        "# iptables/ip6tables\n\
+        {}\n\n\
+        # nftables\n\
+        {}\n\n\
+        # ACL\n\
+        {}\n",
+        iptables.join("\n"),
+        nftables.join("\n"),
+        acl.join("\n"),
+    )
+}
+
+fn iptables_rules(flow: &Flow, role: FlowRole) -> Vec<String> {
+    let cmd = match flow.dst_ip.is_ipv4() {
+        true => "iptables",
+        false => "ip6tables",
+    };
+    let (new_chain, return_chain) = match role {
+        FlowRole::Client => ("OUTPUT", "INPUT"),
+        FlowRole::Server => ("INPUT", "OUTPUT"),
+    };
+    let matches = |src_ip: &IpAddr, src_port: u16, dst_ip: &IpAddr, dst_port: u16| {
+        let mut m = vec![format!("-p {}", flow.protocol)];
+        if !src_ip.is_unspecified() {
+            m.push(format!("-s {src_ip}"));
+        }
+        if !dst_ip.is_unspecified() {
+            m.push(format!("-d {dst_ip}"));
+        }
+        if src_port != 0 {
+            m.push(format!("--sport {src_port}"));
+        }
+        if dst_port != 0 {
+            m.push(format!("--dport {dst_port}"));
+        }
+        m.join(" ")
+    };
+
+    vec![
+        format!(
+            "{cmd} -A {new_chain} {} -m conntrack --ctstate NEW,ESTABLISHED -j ACCEPT",
+            matches(&flow.src_ip, flow.src_port, &flow.dst_ip, flow.dst_port),
+        ),
+        format!(
+            "{cmd} -A {return_chain} {} -m conntrack --ctstate ESTABLISHED -j ACCEPT",
+            matches(&flow.dst_ip, flow.dst_port, &flow.src_ip, flow.src_port),
+        ),
+    ]
+}
+
+fn nftables_rules(flow: &Flow, role: FlowRole) -> Vec<String> {
+    let family = match flow.dst_ip.is_ipv4() {
+        true => "ip",
+        false => "ip6",
+    };
+    let (new_chain, return_chain) = match role {
+        FlowRole::Client => ("output", "input"),
+        FlowRole::Server => ("input", "output"),
+    };
+    let matches = |src_ip: &IpAddr, src_port: u16, dst_ip: &IpAddr, dst_port: u16| {
+        let mut m = vec![];
+        if !src_ip.is_unspecified() {
+            m.push(format!("{family} saddr {src_ip}"));
+        }
+        if !dst_ip.is_unspecified() {
+            m.push(format!("{family} daddr {dst_ip}"));
+        }
+        if src_port != 0 {
+            m.push(format!("{} sport {src_port}", flow.protocol));
+        }
+        if dst_port != 0 {
+            m.push(format!("{} dport {dst_port}", flow.protocol));
+        }
+        m.join(" ")
+    };
+
+    vec![
+        format!(
+            "nft add rule inet filter {new_chain} {} ct state new,established accept",
+            matches(&flow.src_ip, flow.src_port, &flow.dst_ip, flow.dst_port),
+        ),
+        format!(
+            "nft add rule inet filter {return_chain} {} ct state established accept",
+            matches(&flow.dst_ip, flow.dst_port, &flow.src_ip, flow.src_port),
+        ),
+    ]
+}
+
+/// Cisco style extended ACL entries. ACLs are applied to an interface
+/// rather than a host, so they are the same for clients and servers.
+fn acl_rules(flow: &Flow) -> Vec<String> {
+    let endpoint = |ip: &IpAddr, port: u16| {
+        let host = match ip.is_unspecified() {
+            true => "any".to_owned(),
+            false => format!("host {ip}"),
+        };
+        match port {
+            0 => host,
+            p => format!("{host} eq {p}"),
+        }
+    };
+    let src = endpoint(&flow.src_ip, flow.src_port);
+    let dst = endpoint(&flow.dst_ip, flow.dst_port);
+
+    let return_rule = match flow.protocol {
+        ConnectMethod::TCP => format!("permit tcp {dst} {src} established"),
+        _ => format!("permit {} {dst} {src}", flow.protocol),
+    };
+    vec![format!("permit {} {src} {dst}", flow.protocol), return_rule]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::common::ConnectMethod;
+    use crate::util::rules::*;
+
+    #[test]
+    fn suggest_rules_for_ipv4_client_is_expected() {
+        let flow = Flow {
+            protocol: ConnectMethod::TCP,
+            src_ip: "0.0.0.0".parse().unwrap(),
+            src_port: 0,
+            dst_ip: "198.51.100.1".parse().unwrap(),
+            dst_port: 443,
+        };
+        let rules = suggest_rules(&[flow], FlowRole::Client);
+
+        let expected = "# iptables/ip6tables\n\
+        iptables -A OUTPUT -p tcp -d 198.51.100.1 --dport 443 -m conntrack --ctstate NEW,ESTABLISHED -j ACCEPT\n\
+        iptables -A INPUT -p tcp -s 198.51.100.1 --sport 443 -m conntrack --ctstate ESTABLISHED -j ACCEPT\n\n\
+        # nftables\n\
+        nft add rule inet filter output ip daddr 198.51.100.1 tcp dport 443 ct state new,established accept\n\
+        nft add rule inet filter input ip saddr 198.51.100.1 tcp sport 443 ct state established accept\n\n\
+        # ACL\n\
+        permit tcp any host 198.51.100.1 eq 443\n\
+        permit tcp host 198.51.100.1 eq 443 any established\n";
+
+        assert_eq!(rules, expected);
+    }
+
+    #[test]
+    fn suggest_rules_for_ipv6_server_is_expected() {
+        let flow = Flow {
+            protocol: ConnectMethod::UDP,
+            src_ip: "::".parse().unwrap(),
+            src_port: 0,
+            dst_ip: "2001:db8::1".parse().unwrap(),
+            dst_port: 13337,
+        };
+        let rules = suggest_rules(&[flow], FlowRole::Server);
+
+        let expected = "# iptables/ip6tables\n\
+        ip6tables -A INPUT -p udp -d 2001:db8::1 --dport 13337 -m conntrack --ctstate NEW,ESTABLISHED -j ACCEPT\n\
+        ip6tables -A OUTPUT -p udp -s 2001:db8::1 --sport 13337 -m conntrack --ctstate ESTABLISHED -j ACCEPT\n\n\
+        # nftables\n\
+        nft add rule inet filter input ip6 daddr 2001:db8::1 udp dport 13337 ct state new,established accept\n\
+        nft add rule inet filter output ip6 saddr 2001:db8::1 udp sport 13337 ct state established accept\n\n\
+        # ACL\n\
+        permit udp any host 2001:db8::1 eq 13337\n\
+        permit udp host 2001:db8::1 eq 13337 any\n";
+
+        assert_eq!(rules, expected);
+    }
+
+    #[test]
+    fn suggest_rules_with_source_address_and_port() {
+        let flow = Flow {
+            protocol: ConnectMethod::TCP,
+            src_ip: "192.0.2.1".parse().unwrap(),
+            src_port: 40000,
+            dst_ip: "198.51.100.1".parse().unwrap(),
+            dst_port: 25,
+        };
+        let rules = suggest_rules(&[flow], FlowRole::Client);
+
+        assert!(rules
+            .contains("iptables -A OUTPUT -p tcp -s 192.0.2.1 -d 198.51.100.1 --sport 40000 --dport 25 -m conntrack"));
+        assert!(rules.contains("permit tcp host 192.0.2.1 eq 40000 host 198.51.100.1 eq 25\n"));
+    }
+}
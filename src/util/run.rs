@@ -0,0 +1,259 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc;
+
+use crate::core::common::{AddrKey, ClientResult, ConnectMethod, ConnectRecord, ProbeSample};
+use crate::util::result::SampleWindow;
+use crate::util::schedule::Throttle;
+
+/// A handle to a client run, shared with the client so an embedding
+/// application can query the statistics and recent records of a run
+/// while it is in progress, or subscribe to every record.
+#[derive(Clone, Debug)]
+pub struct RunHandle {
+    state: Arc<Mutex<RunState>>,
+}
+
+#[derive(Debug)]
+struct RunState {
+    window: SampleWindow,
+    totals: BTreeMap<AddrKey, RunTotals>,
+    // Records are kept with the time they were pushed, as they have no timestamp.
+    records: VecDeque<(Instant, ConnectRecord)>,
+    capacity: usize,
+    subscribers: Vec<mpsc::UnboundedSender<ConnectRecord>>,
+    throttle: Arc<Throttle>,
+}
+
+/// Running totals of the probes to a destination over a whole run.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RunTotals {
+    pub sent: u64,
+    pub received: u64,
+    pub min: f64,
+    pub max: f64,
+    pub sum: f64,
+}
+
+impl RunTotals {
+    fn push(&mut self, sample: &ProbeSample) {
+        self.sent += 1;
+        if let Some(time) = sample.time.filter(|t| *t > 0.0) {
+            self.min = match self.received {
+                0 => time,
+                _ => self.min.min(time),
+            };
+            self.max = self.max.max(time);
+            self.sum += time;
+            self.received += 1;
+        }
+    }
+
+    pub fn loss_percent(&self) -> f64 {
+        match self.sent {
+            0 => 0.0,
+            sent => (sent - self.received) as f64 / sent as f64 * 100.0,
+        }
+    }
+
+    pub fn avg(&self) -> f64 {
+        match self.received {
+            0 => 0.0,
+            received => self.sum / received as f64,
+        }
+    }
+}
+
+/// Selects records by destination, protocol and result.
+/// A filter without any criteria matches every record.
+#[derive(Clone, Debug, Default)]
+pub struct RecordFilter {
+    pub destination: Option<String>,
+    pub protocol: Option<ConnectMethod>,
+    pub success: Option<bool>,
+}
+
+impl RecordFilter {
+    pub fn matches(&self, record: &ConnectRecord) -> bool {
+        self.destination.as_ref().is_none_or(|d| *d == record.destination)
+            && self.protocol.is_none_or(|p| p == record.protocol)
+            && self.success.is_none_or(|s| s == record.success)
+    }
+}
+
+impl RunHandle {
+    /// Keep the last `window` probes to each destination for the
+    /// statistics, and the last `capacity` records of the run.
+    pub fn new(window: u16, capacity: usize) -> RunHandle {
+        RunHandle {
+            state: Arc::new(Mutex::new(RunState {
+                window: SampleWindow::new(window),
+                totals: BTreeMap::new(),
+                records: VecDeque::with_capacity(capacity),
+                capacity,
+                subscribers: Vec::new(),
+                throttle: Arc::new(Throttle::default()),
+            })),
+        }
+    }
+
+    /// Start a run of the client, returning the throttle its probes are
+    /// scheduled with. A client that ran out of resources in an earlier
+    /// run is not throttled in the next.
+    pub fn start_run(&self) -> Arc<Throttle> {
+        let throttle = Arc::new(Throttle::default());
+        self.state.lock().unwrap().throttle = throttle.clone();
+        throttle
+    }
+
+    /// Return the number of probes of the current run that ran out of local
+    /// resources and the limit of probes in flight they were throttled to,
+    /// if any did.
+    pub fn resource_exhaustion(&self) -> Option<(usize, usize)> {
+        self.state.lock().unwrap().throttle.exhaustion()
+    }
+
+    /// Add a record from the run.
+    pub fn push(&self, record: &ConnectRecord) {
+        let mut state = self.state.lock().unwrap();
+        state.window.push(record);
+        state
+            .totals
+            .entry(AddrKey::new(&record.series()))
+            .or_default()
+            .push(&ProbeSample::from_record(record));
+        // Subscribers that went away are dropped.
+        state.subscribers.retain(|s| s.send(record.clone()).is_ok());
+        if state.capacity == 0 {
+            return;
+        }
+        if state.records.len() == state.capacity {
+            state.records.pop_front();
+        }
+        state.records.push_back((Instant::now(), record.clone()));
+    }
+
+    /// Subscribe to the records of the run, as they are pushed.
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<ConnectRecord> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.state.lock().unwrap().subscribers.push(sender);
+        receiver
+    }
+
+    /// End the subscriptions to the records of the run.
+    pub fn end(&self) {
+        self.state.lock().unwrap().subscribers.clear();
+    }
+
+    /// Return the statistics of the last probes to each destination.
+    pub fn stats(&self) -> Vec<ClientResult> {
+        self.state.lock().unwrap().window.results()
+    }
+
+    /// Return the totals of the probes to each destination since the run started.
+    pub fn totals(&self) -> Vec<(String, RunTotals)> {
+        let state = self.state.lock().unwrap();
+        state
+            .totals
+            .iter()
+            .map(|(d, totals)| (d.to_string(), *totals))
+            .collect()
+    }
+
+    /// Return the recent records that match a filter, oldest first.
+    pub fn records(&self, filter: &RecordFilter) -> Vec<ConnectRecord> {
+        let state = self.state.lock().unwrap();
+        state
+            .records
+            .iter()
+            .map(|(_, record)| record)
+            .filter(|r| filter.matches(r))
+            .cloned()
+            .collect()
+    }
+
+    /// Return the records of the last `period` that match a filter, oldest first.
+    /// Records older than the last `capacity` records are no longer available.
+    pub fn recent(&self, period: Duration, filter: &RecordFilter) -> Vec<ConnectRecord> {
+        let state = self.state.lock().unwrap();
+        let now = Instant::now();
+        state
+            .records
+            .iter()
+            .filter(|(pushed, record)| now.duration_since(*pushed) <= period && filter.matches(record))
+            .map(|(_, record)| record.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::common::ConnectResult;
+
+    fn connect_record(destination: &str, success: bool) -> ConnectRecord {
+        ConnectRecord {
+            result: match success {
+                true => ConnectResult::Ping,
+                false => ConnectResult::Timeout,
+            },
+            protocol: ConnectMethod::TCP,
+            source: "127.0.0.1:50000".to_owned(),
+            destination: destination.to_owned(),
+            time: match success {
+                true => 1.5,
+                false => -1.0,
+            },
+            success,
+            error_msg: None,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn runs_are_queried_while_in_progress() {
+        let handle = RunHandle::new(2, 3);
+        let client = handle.clone();
+        client.push(&connect_record("192.0.2.1:443", false));
+        client.push(&connect_record("192.0.2.1:443", true));
+        client.push(&connect_record("192.0.2.1:443", true));
+        client.push(&connect_record("192.0.2.2:443", false));
+
+        let stats = handle.stats();
+        assert_eq!(stats.len(), 2);
+        assert_eq!((stats[0].sent, stats[0].received), (2, 2));
+        assert_eq!((stats[1].sent, stats[1].received), (1, 0));
+
+        let (destination, totals) = &handle.totals()[0];
+        assert_eq!(destination, "192.0.2.1:443");
+        assert_eq!((totals.sent, totals.received, totals.avg()), (3, 2, 1.5));
+        assert!((totals.loss_percent() - 100.0 / 3.0).abs() < 1e-9);
+
+        assert_eq!(handle.records(&RecordFilter::default()).len(), 3);
+        let failed = RecordFilter {
+            success: Some(false),
+            ..Default::default()
+        };
+        let records = handle.records(&failed);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].destination, "192.0.2.2:443");
+    }
+
+    #[test]
+    fn recent_records_are_within_the_period() {
+        let handle = RunHandle::new(2, 3);
+        handle.push(&connect_record("192.0.2.1:443", true));
+        handle.state.lock().unwrap().records[0].0 -= Duration::from_secs(600);
+        handle.push(&connect_record("192.0.2.2:443", true));
+
+        let recent = handle.recent(Duration::from_secs(300), &RecordFilter::default());
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].destination, "192.0.2.2:443");
+        assert_eq!(
+            handle.recent(Duration::from_secs(900), &RecordFilter::default()).len(),
+            2
+        );
+    }
+}
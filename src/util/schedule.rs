@@ -0,0 +1,283 @@
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+use futures::StreamExt;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio::time::{sleep_until, Duration, Instant};
+
+use crate::core::common::{ConnectRecord, ConnectResult, IpOptions};
+
+/// The result of a probe, which tells whether the local host ran out of
+/// resources, rather than the probe failing on the network.
+pub trait ProbeOutcome {
+    fn resource_exhausted(&self) -> bool;
+}
+
+impl ProbeOutcome for ConnectRecord {
+    fn resource_exhausted(&self) -> bool {
+        self.result == ConnectResult::ResourceExhausted
+    }
+}
+
+impl ProbeOutcome for Vec<ConnectRecord> {
+    fn resource_exhausted(&self) -> bool {
+        self.iter().any(|record| record.resource_exhausted())
+    }
+}
+
+/// Throttles probes once the local host runs out of resources.
+/// Probes are unlimited until then, after which the probes in flight
+/// are limited to half of those in flight when resources ran out, and
+/// to one less each time they run out again, down to a single probe.
+/// Each run of a client starts with its own throttle.
+#[derive(Debug, Default)]
+pub struct Throttle {
+    permits: OnceLock<Semaphore>,
+    limit: AtomicUsize,
+    in_flight: AtomicUsize,
+    exhausted: AtomicUsize,
+}
+
+impl Throttle {
+    /// Run a probe, waiting for a permit once the local host ran out of resources.
+    pub async fn run<Fut, T>(&self, probe: Fut) -> T
+    where
+        Fut: Future<Output = T>,
+        T: ProbeOutcome,
+    {
+        let permit = match self.permits.get() {
+            Some(permits) => permits.acquire().await.ok(),
+            None => None,
+        };
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let result = probe.await;
+        let in_flight = self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+        if result.resource_exhausted() {
+            self.exhausted.fetch_add(1, Ordering::SeqCst);
+            self.back_off(in_flight, permit);
+        }
+        result
+    }
+
+    fn back_off(&self, in_flight: usize, permit: Option<SemaphorePermit<'_>>) {
+        match permit {
+            None => {
+                let limit = (in_flight / 2).max(1);
+                if self.permits.set(Semaphore::new(limit)).is_ok() {
+                    self.limit.store(limit, Ordering::SeqCst);
+                }
+            }
+            Some(permit) => {
+                let reduced = self.limit.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |limit| {
+                    (limit > 1).then(|| limit - 1)
+                });
+                if reduced.is_ok() {
+                    permit.forget();
+                }
+            }
+        }
+    }
+
+    /// Return the number of probes that ran out of resources and the
+    /// limit of probes in flight, if any probe ran out of resources.
+    pub fn exhaustion(&self) -> Option<(usize, usize)> {
+        match self.exhausted.load(Ordering::SeqCst) {
+            0 => None,
+            exhausted => Some((exhausted, self.limit.load(Ordering::SeqCst).max(1))),
+        }
+    }
+}
+
+/// Probe each socket, scheduling each address family with its own
+/// concurrency and rate limit, so a fragile path for one family
+/// can be probed gently without slowing down the other. Probes are
+/// throttled by the throttle of the run when the local host runs out
+/// of resources.
+pub async fn schedule_sockets<F, Fut, T>(
+    sockets: Vec<SocketAddr>,
+    ip_options: IpOptions,
+    throttle: &Throttle,
+    probe: F,
+) -> Vec<T>
+where
+    F: Fn(SocketAddr) -> Fut,
+    Fut: Future<Output = T>,
+    T: ProbeOutcome,
+{
+    let (ipv4_sockets, ipv6_sockets): (Vec<SocketAddr>, Vec<SocketAddr>) =
+        sockets.into_iter().partition(|s| s.is_ipv4());
+
+    let (mut results, ipv6_results) = futures::join!(
+        schedule_family(
+            ipv4_sockets,
+            ip_options.v4_concurrency,
+            ip_options.v4_rate,
+            throttle,
+            &probe
+        ),
+        schedule_family(
+            ipv6_sockets,
+            ip_options.v6_concurrency,
+            ip_options.v6_rate,
+            throttle,
+            &probe
+        ),
+    );
+    results.extend(ipv6_results);
+    results
+}
+
+/// Probe sockets with at most `concurrency` probes in flight,
+/// starting at most `rate` probes per second (0 is unlimited).
+async fn schedule_family<F, Fut, T>(
+    sockets: Vec<SocketAddr>,
+    concurrency: usize,
+    rate: u16,
+    throttle: &Throttle,
+    probe: &F,
+) -> Vec<T>
+where
+    F: Fn(SocketAddr) -> Fut,
+    Fut: Future<Output = T>,
+    T: ProbeOutcome,
+{
+    let start = Instant::now();
+    let pacing = match rate {
+        0 => Duration::ZERO,
+        rate => Duration::from_secs(1) / u32::from(rate),
+    };
+
+    futures::stream::iter(sockets.into_iter().enumerate())
+        .map(|(i, socket)| async move {
+            sleep_until(start + pacing * i as u32).await;
+            throttle.run(probe(socket)).await
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    impl ProbeOutcome for SocketAddr {
+        fn resource_exhausted(&self) -> bool {
+            false
+        }
+    }
+
+    impl ProbeOutcome for () {
+        fn resource_exhausted(&self) -> bool {
+            false
+        }
+    }
+
+    impl ProbeOutcome for (SocketAddr, Duration) {
+        fn resource_exhausted(&self) -> bool {
+            false
+        }
+    }
+
+    impl ProbeOutcome for bool {
+        fn resource_exhausted(&self) -> bool {
+            *self
+        }
+    }
+
+    fn sockets() -> Vec<SocketAddr> {
+        vec![
+            "192.0.2.1:80".parse().unwrap(),
+            "[2001:db8::1]:80".parse().unwrap(),
+            "192.0.2.2:80".parse().unwrap(),
+            "[2001:db8::2]:80".parse().unwrap(),
+        ]
+    }
+
+    #[tokio::test]
+    async fn every_socket_is_probed() {
+        let mut results = schedule_sockets(
+            sockets(),
+            IpOptions::default(),
+            &Throttle::default(),
+            |s| async move { s },
+        )
+        .await;
+        results.sort();
+        let mut expected = sockets();
+        expected.sort();
+
+        assert_eq!(results, expected);
+    }
+
+    #[tokio::test]
+    async fn concurrency_is_limited_per_family() {
+        let in_flight = AtomicUsize::new(0);
+        let max_in_flight = AtomicUsize::new(0);
+        let ip_options = IpOptions {
+            v4_concurrency: 1,
+            v6_concurrency: 1,
+            ..Default::default()
+        };
+        schedule_sockets(sockets(), ip_options, &Throttle::default(), |_| async {
+            let n = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            max_in_flight.fetch_max(n, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+        })
+        .await;
+
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn rate_is_limited_per_family() {
+        let start = Instant::now();
+        let ip_options = IpOptions {
+            v6_rate: 2,
+            ..Default::default()
+        };
+        let results = schedule_sockets(sockets(), ip_options, &Throttle::default(), |s| async move {
+            (s, start.elapsed())
+        })
+        .await;
+
+        for (socket, elapsed) in results {
+            match socket.to_string().as_str() {
+                "[2001:db8::2]:80" => assert!(elapsed >= Duration::from_millis(500)),
+                _ => assert!(elapsed < Duration::from_millis(250)),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn probes_are_throttled_when_resources_run_out() {
+        let throttle = Throttle::default();
+        assert!(!throttle.run(async { false }).await);
+        assert_eq!(throttle.exhaustion(), None);
+
+        // Eight probes are in flight when the first runs out of resources.
+        let probes = (0..8).map(|i| {
+            let throttle = &throttle;
+            async move {
+                throttle
+                    .run(async move {
+                        let exhausted = i == 0;
+                        let delay = if exhausted { 10 } else { 50 };
+                        tokio::time::sleep(Duration::from_millis(delay)).await;
+                        exhausted
+                    })
+                    .await
+            }
+        });
+        futures::future::join_all(probes).await;
+        assert_eq!(throttle.exhaustion(), Some((1, 4)));
+
+        throttle.run(async { true }).await;
+        assert_eq!(throttle.exhaustion(), Some((2, 3)));
+    }
+}
@@ -0,0 +1,257 @@
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::core::common::{ConnectRecord, LoggingOptions};
+#[cfg(feature = "export")]
+use crate::util::influx::InfluxWriter;
+#[cfg(feature = "export")]
+use crate::util::otlp::OtlpExporter;
+use crate::util::record::RecordWriter;
+#[cfg(feature = "export")]
+use crate::util::syslog::SyslogWriter;
+
+/// A destination for connection records.
+///
+/// Records are delivered in batches so sinks with a high per-write
+/// cost only pay it once per batch. A sink may buffer records until
+/// `flush` is called, after which all written records must be persisted.
+/// Sinks are `Send` so the probes of an agent can run as separate tasks.
+pub trait RecordSink: Send {
+    fn write_batch(&mut self, records: &[ConnectRecord]) -> Result<()>;
+    fn flush(&mut self) -> Result<()>;
+}
+
+impl<W: Write + Send> RecordSink for RecordWriter<W> {
+    fn write_batch(&mut self, records: &[ConnectRecord]) -> Result<()> {
+        for record in records {
+            self.write(record)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        RecordWriter::flush(self)
+    }
+}
+
+/// Writes records to each of the sinks in the logging options.
+pub struct RecordSinks {
+    sinks: Vec<Box<dyn RecordSink>>,
+}
+
+impl RecordSinks {
+    /// Returns the batched sinks, or None if no sink is configured.
+    pub fn create(logging_options: &LoggingOptions) -> Result<Option<RecordBatcher<RecordSinks>>> {
+        let mut sinks: Vec<Box<dyn RecordSink>> = Vec::new();
+        if let Some(record_file) = &logging_options.record_file {
+            sinks.push(Box::new(RecordWriter::create(record_file)?));
+        }
+        #[cfg(feature = "export")]
+        {
+            if let Some(otlp_endpoint) = &logging_options.otlp_endpoint {
+                sinks.push(Box::new(OtlpExporter::new(otlp_endpoint, logging_options)?));
+            }
+            if let Some(syslog_target) = &logging_options.syslog_target {
+                sinks.push(Box::new(SyslogWriter::new(syslog_target)?));
+            }
+            if let Some(influx_url) = &logging_options.influx_url {
+                sinks.push(Box::new(InfluxWriter::new(influx_url, logging_options)?));
+            }
+        }
+        #[cfg(not(feature = "export"))]
+        if logging_options.otlp_endpoint.is_some()
+            || logging_options.syslog_target.is_some()
+            || logging_options.influx_url.is_some()
+        {
+            anyhow::bail!("OTLP, syslog and InfluxDB exports require the `export` feature.");
+        }
+        Ok(match sinks.is_empty() {
+            true => None,
+            false => Some(RecordBatcher::new(RecordSinks { sinks }, logging_options)),
+        })
+    }
+}
+
+impl RecordSink for RecordSinks {
+    fn write_batch(&mut self, records: &[ConnectRecord]) -> Result<()> {
+        for sink in self.sinks.iter_mut() {
+            sink.write_batch(records)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        for sink in self.sinks.iter_mut() {
+            sink.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Buffers records for a sink and writes them as a batch once
+/// the batch size is reached or the batch interval has elapsed.
+pub struct RecordBatcher<S: RecordSink> {
+    sink: S,
+    records: Vec<ConnectRecord>,
+    size: usize,
+    interval: Duration,
+    started: Instant,
+}
+
+impl<S: RecordSink> RecordBatcher<S> {
+    pub fn new(sink: S, logging_options: &LoggingOptions) -> RecordBatcher<S> {
+        let size = (logging_options.batch_size as usize).max(1);
+        RecordBatcher {
+            sink,
+            records: Vec::with_capacity(size),
+            size,
+            interval: Duration::from_millis(logging_options.batch_interval.into()),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn push(&mut self, record: ConnectRecord) -> Result<()> {
+        self.records.push(record);
+        if self.records.len() >= self.size || self.started.elapsed() >= self.interval {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    /// Write any buffered records and flush the sink.
+    pub fn flush(&mut self) -> Result<()> {
+        self.write_batch()?;
+        self.sink.flush()
+    }
+
+    fn write_batch(&mut self) -> Result<()> {
+        self.started = Instant::now();
+        if self.records.is_empty() {
+            return Ok(());
+        }
+        self.sink.write_batch(&self.records)?;
+        self.records.clear();
+        Ok(())
+    }
+}
+
+/// Buffered records are written when the batcher is dropped, so they
+/// are not lost when a client returns early with an error or is aborted.
+impl<S: RecordSink> Drop for RecordBatcher<S> {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            eprintln!("Error flushing records: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::common::{ConnectMethod, ConnectResult};
+
+    #[derive(Default)]
+    struct TestSink {
+        batches: Vec<usize>,
+        flushed: bool,
+    }
+
+    impl RecordSink for &mut TestSink {
+        fn write_batch(&mut self, records: &[ConnectRecord]) -> Result<()> {
+            self.batches.push(records.len());
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            self.flushed = true;
+            Ok(())
+        }
+    }
+
+    fn connect_record() -> ConnectRecord {
+        ConnectRecord {
+            result: ConnectResult::Ping,
+            protocol: ConnectMethod::TCP,
+            source: "127.0.0.1:50000".to_owned(),
+            destination: "127.0.0.1:443".to_owned(),
+            time: 1.5,
+            success: true,
+            error_msg: None,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn records_are_written_in_batches() {
+        let mut sink = TestSink::default();
+        let logging_options = LoggingOptions {
+            batch_size: 2,
+            batch_interval: u16::MAX,
+            ..Default::default()
+        };
+        let mut batcher = RecordBatcher::new(&mut sink, &logging_options);
+        for _ in 0..5 {
+            batcher.push(connect_record()).unwrap();
+        }
+        batcher.flush().unwrap();
+        drop(batcher);
+
+        assert_eq!(sink.batches, vec![2, 2, 1]);
+        assert!(sink.flushed);
+    }
+
+    #[test]
+    fn records_are_written_when_batch_interval_elapses() {
+        let mut sink = TestSink::default();
+        let logging_options = LoggingOptions {
+            batch_size: 100,
+            batch_interval: 0,
+            ..Default::default()
+        };
+        let mut batcher = RecordBatcher::new(&mut sink, &logging_options);
+        batcher.push(connect_record()).unwrap();
+        batcher.push(connect_record()).unwrap();
+        batcher.flush().unwrap();
+        drop(batcher);
+
+        assert_eq!(sink.batches, vec![1, 1]);
+    }
+
+    #[test]
+    fn records_are_flushed_when_dropped() {
+        let mut sink = TestSink::default();
+        let logging_options = LoggingOptions {
+            batch_size: 100,
+            batch_interval: u16::MAX,
+            ..Default::default()
+        };
+        let mut batcher = RecordBatcher::new(&mut sink, &logging_options);
+        batcher.push(connect_record()).unwrap();
+        drop(batcher);
+
+        assert_eq!(sink.batches, vec![1]);
+        assert!(sink.flushed);
+    }
+
+    #[cfg(not(feature = "export"))]
+    #[test]
+    fn exports_require_the_export_feature() {
+        let logging_options = LoggingOptions {
+            influx_url: Some("http://localhost:8086/api/v2/write?org=nk&bucket=probes".to_owned()),
+            ..Default::default()
+        };
+        let error = RecordSinks::create(&logging_options).err().unwrap();
+        assert!(error.to_string().contains("`export` feature"));
+
+        // Record files are written by the client-only build.
+        let record_file = std::env::temp_dir().join(format!("nk-sink-{}.nkr", uuid::Uuid::new_v4()));
+        let logging_options = LoggingOptions {
+            record_file: Some(record_file.display().to_string()),
+            ..Default::default()
+        };
+        assert!(RecordSinks::create(&logging_options).unwrap().is_some());
+        let _ = std::fs::remove_file(&record_file);
+    }
+}
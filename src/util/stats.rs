@@ -0,0 +1,378 @@
+use std::cmp::Ordering;
+#[cfg(feature = "export")]
+use std::collections::BTreeMap;
+
+#[cfg(feature = "export")]
+use crate::core::konst::{EXPONENTIAL_HISTOGRAM_BUCKETS, EXPONENTIAL_HISTOGRAM_SCALE};
+
+/// Result of a two-sample significance test.
+#[derive(Clone, Copy, Debug)]
+pub struct TestResult {
+    pub statistic: f64,
+    pub p_value: f64,
+}
+
+/// Returns the median of a set of samples.
+pub fn median(samples: &[f64]) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let sorted = sorted(samples);
+    let mid = sorted.len() / 2;
+    match sorted.len() % 2 {
+        0 => (sorted[mid - 1] + sorted[mid]) / 2.0,
+        _ => sorted[mid],
+    }
+}
+
+/// Returns the nearest-rank percentile (0-100) of a set of samples.
+pub fn percentile(samples: &[f64], percentile: f64) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let sorted = sorted(samples);
+    let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Returns the mean absolute difference between consecutive samples.
+pub fn jitter(samples: &[f64]) -> f64 {
+    if samples.len() < 2 {
+        return 0.0;
+    }
+    let sum: f64 = samples.windows(2).map(|pair| (pair[1] - pair[0]).abs()).sum();
+    sum / (samples.len() - 1) as f64
+}
+
+/// Returns the population standard deviation of a set of samples.
+pub fn std_dev(samples: &[f64]) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let n = samples.len() as f64;
+    let mean = samples.iter().sum::<f64>() / n;
+    (samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n).sqrt()
+}
+
+/// Returns the number of samples in each bucket, where a bucket holds
+/// the samples below its upper bound and at or above the one before it.
+/// The last bucket holds the samples at or above the last bound.
+pub fn histogram(samples: &[f64], bounds: &[f64]) -> Vec<u16> {
+    let mut counts = vec![0; bounds.len() + 1];
+    for sample in samples {
+        counts[bounds.partition_point(|bound| bound <= sample)] += 1;
+    }
+    counts
+}
+
+/// A histogram with exponential buckets, as OpenTelemetry exponential
+/// histograms and Prometheus native histograms. At a scale `s`, bucket
+/// `i` holds the values above `2^(i/2^s)` up to and including
+/// `2^((i+1)/2^s)`. The scale starts high and is reduced as values
+/// are recorded, so the buckets span the values within a bucket limit.
+#[cfg(feature = "export")]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExponentialHistogram {
+    pub scale: i32,
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+    pub zero_count: u64,
+    buckets: BTreeMap<i32, u64>,
+}
+
+#[cfg(feature = "export")]
+impl Default for ExponentialHistogram {
+    fn default() -> Self {
+        Self {
+            scale: EXPONENTIAL_HISTOGRAM_SCALE,
+            count: 0,
+            sum: 0.0,
+            min: 0.0,
+            max: 0.0,
+            zero_count: 0,
+            buckets: BTreeMap::new(),
+        }
+    }
+}
+
+#[cfg(feature = "export")]
+impl ExponentialHistogram {
+    /// Record a value. Negative values are not recorded.
+    pub fn record(&mut self, value: f64) {
+        if value.is_nan() || value < 0.0 {
+            return;
+        }
+        self.min = match self.count {
+            0 => value,
+            _ => self.min.min(value),
+        };
+        self.max = self.max.max(value);
+        self.sum += value;
+        self.count += 1;
+        if value == 0.0 {
+            self.zero_count += 1;
+            return;
+        }
+        *self.buckets.entry(bucket_index(value, self.scale)).or_default() += 1;
+        // Halving the scale merges each pair of buckets.
+        while self.span() > EXPONENTIAL_HISTOGRAM_BUCKETS {
+            self.scale -= 1;
+            let mut buckets = BTreeMap::new();
+            for (index, count) in &self.buckets {
+                *buckets.entry(index >> 1).or_default() += count;
+            }
+            self.buckets = buckets;
+        }
+    }
+
+    /// Return the index of the first bucket.
+    pub fn offset(&self) -> i32 {
+        self.buckets.keys().next().copied().unwrap_or_default()
+    }
+
+    /// Return the count of each bucket from the first bucket with a value to the last.
+    pub fn bucket_counts(&self) -> Vec<u64> {
+        let mut counts = vec![0; self.span()];
+        for (index, count) in &self.buckets {
+            counts[(index - self.offset()) as usize] = *count;
+        }
+        counts
+    }
+
+    fn span(&self) -> usize {
+        match (self.buckets.keys().next(), self.buckets.keys().next_back()) {
+            (Some(first), Some(last)) => (last - first + 1) as usize,
+            _ => 0,
+        }
+    }
+}
+
+/// Return the index of the bucket of a positive value at a scale.
+#[cfg(feature = "export")]
+fn bucket_index(value: f64, scale: i32) -> i32 {
+    (value.log2() * 2f64.powi(scale)).ceil() as i32 - 1
+}
+
+/// Two-sided Mann-Whitney U test using the normal approximation
+/// with tie and continuity correction.
+/// The statistic is the U value of the first sample.
+pub fn mann_whitney_u(a: &[f64], b: &[f64]) -> TestResult {
+    let n1 = a.len() as f64;
+    let n2 = b.len() as f64;
+    let n = n1 + n2;
+
+    // Rank the combined samples, averaging the rank of ties.
+    let mut combined: Vec<(f64, bool)> = a
+        .iter()
+        .map(|x| (*x, true))
+        .chain(b.iter().map(|x| (*x, false)))
+        .collect();
+    combined.sort_by(|x, y| x.0.partial_cmp(&y.0).unwrap_or(Ordering::Equal));
+
+    let mut rank_sum_a = 0.0;
+    let mut tie_sum = 0.0;
+    let mut i = 0;
+    while i < combined.len() {
+        let mut j = i;
+        while j + 1 < combined.len() && combined[j + 1].0 == combined[i].0 {
+            j += 1;
+        }
+        let rank = (i + j) as f64 / 2.0 + 1.0;
+        let ties = (j - i + 1) as f64;
+        tie_sum += ties.powi(3) - ties;
+        rank_sum_a += combined[i..=j].iter().filter(|x| x.1).count() as f64 * rank;
+        i = j + 1;
+    }
+
+    let u = rank_sum_a - n1 * (n1 + 1.0) / 2.0;
+    let mean = n1 * n2 / 2.0;
+    let variance = n1 * n2 / 12.0 * ((n + 1.0) - tie_sum / (n * (n - 1.0)));
+    if variance <= 0.0 {
+        return TestResult {
+            statistic: u,
+            p_value: 1.0,
+        };
+    }
+    let delta = u - mean;
+    let z = (delta.abs() - 0.5).max(0.0) / variance.sqrt();
+
+    TestResult {
+        statistic: u,
+        p_value: erfc(z / std::f64::consts::SQRT_2).min(1.0),
+    }
+}
+
+/// Two-sample Kolmogorov-Smirnov test using the asymptotic distribution.
+/// The statistic is the maximum distance between the two empirical CDFs.
+pub fn kolmogorov_smirnov(a: &[f64], b: &[f64]) -> TestResult {
+    let a = sorted(a);
+    let b = sorted(b);
+    let n1 = a.len() as f64;
+    let n2 = b.len() as f64;
+
+    let (mut i, mut j) = (0, 0);
+    let mut d: f64 = 0.0;
+    while i < a.len() && j < b.len() {
+        let x = a[i].min(b[j]);
+        while i < a.len() && a[i] <= x {
+            i += 1;
+        }
+        while j < b.len() && b[j] <= x {
+            j += 1;
+        }
+        d = d.max((i as f64 / n1 - j as f64 / n2).abs());
+    }
+
+    let en = (n1 * n2 / (n1 + n2)).sqrt();
+    TestResult {
+        statistic: d,
+        p_value: ks_probability((en + 0.12 + 0.11 / en) * d),
+    }
+}
+
+fn sorted(samples: &[f64]) -> Vec<f64> {
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    sorted
+}
+
+/// Kolmogorov distribution survival function.
+fn ks_probability(lambda: f64) -> f64 {
+    if lambda < 0.2 {
+        return 1.0;
+    }
+    let mut sum = 0.0;
+    let mut sign = 1.0;
+    for j in 1..=100 {
+        let term = sign * (-2.0 * (j as f64).powi(2) * lambda.powi(2)).exp();
+        sum += term;
+        if term.abs() < 1e-10 {
+            break;
+        }
+        sign = -sign;
+    }
+    (2.0 * sum).clamp(0.0, 1.0)
+}
+
+/// Complementary error function (fractional error < 1.2e-7).
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let r = t
+        * (-z * z - 1.26551223
+            + t * (1.00002368
+                + t * (0.37409196
+                    + t * (0.09678418
+                        + t * (-0.18628806
+                            + t * (0.27886807
+                                + t * (-1.13520398 + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277)))))))))
+            .exp();
+    match x >= 0.0 {
+        true => r,
+        false => 2.0 - r,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "export")]
+    #[test]
+    fn exponential_histogram_buckets_are_upper_inclusive() {
+        assert_eq!(bucket_index(1.0, 0), -1);
+        assert_eq!(bucket_index(2.0, 0), 0);
+        assert_eq!(bucket_index(3.0, 0), 1);
+        assert_eq!(bucket_index(4.0, 1), 3);
+        assert_eq!(bucket_index(0.5, 2), -5);
+
+        let mut histogram = ExponentialHistogram::default();
+        for value in [0.0, 1.5, 1.5, 1.6] {
+            histogram.record(value);
+        }
+        assert_eq!((histogram.count, histogram.zero_count), (4, 1));
+        assert_eq!((histogram.min, histogram.max), (0.0, 1.6));
+        assert_eq!(histogram.scale, EXPONENTIAL_HISTOGRAM_SCALE);
+        assert_eq!(histogram.bucket_counts().iter().sum::<u64>(), 3);
+    }
+
+    #[cfg(feature = "export")]
+    #[test]
+    fn exponential_histogram_is_downscaled_to_the_bucket_limit() {
+        let mut histogram = ExponentialHistogram::default();
+        for value in [0.1, 1.0, 10.0, 100.0, 1000.0, 10000.0] {
+            histogram.record(value);
+        }
+        assert!(histogram.bucket_counts().len() <= EXPONENTIAL_HISTOGRAM_BUCKETS);
+        assert!(histogram.scale < EXPONENTIAL_HISTOGRAM_SCALE);
+        assert_eq!(histogram.bucket_counts().iter().sum::<u64>(), 6);
+        // Each value is in the bucket for its scale.
+        let scale = histogram.scale;
+        for value in [0.1, 10000.0] {
+            let index = bucket_index(value, scale) - histogram.offset();
+            assert!(histogram.bucket_counts()[index as usize] > 0);
+        }
+    }
+
+    #[test]
+    fn histogram_is_expected() {
+        let bounds = [1.0, 5.0, 10.0];
+        assert_eq!(histogram(&[0.5, 1.0, 4.9, 7.0, 10.0, 250.0], &bounds), vec![1, 2, 1, 2]);
+        assert_eq!(histogram(&[], &bounds), vec![0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn median_is_expected() {
+        assert_eq!(median(&[3.0, 1.0, 2.0]), 2.0);
+        assert_eq!(median(&[4.0, 1.0, 2.0, 3.0]), 2.5);
+        assert_eq!(median(&[]), 0.0);
+    }
+
+    #[test]
+    fn percentile_is_expected() {
+        let samples: Vec<f64> = (1..=20).rev().map(f64::from).collect();
+
+        assert_eq!(percentile(&samples, 50.0), 10.0);
+        assert_eq!(percentile(&samples, 95.0), 19.0);
+        assert_eq!(percentile(&samples, 100.0), 20.0);
+        assert_eq!(percentile(&samples, 0.0), 1.0);
+        assert_eq!(percentile(&[], 95.0), 0.0);
+    }
+
+    #[test]
+    fn jitter_and_std_dev_are_expected() {
+        let samples = [10.0, 12.0, 11.0, 15.0];
+
+        assert_eq!(jitter(&samples), 7.0 / 3.0);
+        assert_eq!(jitter(&[10.0]), 0.0);
+        assert_eq!(std_dev(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]), 2.0);
+        assert_eq!(std_dev(&[]), 0.0);
+    }
+
+    #[test]
+    fn identical_samples_are_not_significant() {
+        let a = [1.0, 2.0, 3.0, 4.0, 5.0];
+        let mw = mann_whitney_u(&a, &a);
+        let ks = kolmogorov_smirnov(&a, &a);
+
+        assert_eq!(mw.statistic, 12.5);
+        assert!(mw.p_value > 0.9);
+        assert_eq!(ks.statistic, 0.0);
+        assert_eq!(ks.p_value, 1.0);
+    }
+
+    #[test]
+    fn shifted_samples_are_significant() {
+        let a: Vec<f64> = (0..30).map(|x| 10.0 + x as f64 * 0.1).collect();
+        let b: Vec<f64> = (0..30).map(|x| 20.0 + x as f64 * 0.1).collect();
+        let mw = mann_whitney_u(&a, &b);
+        let ks = kolmogorov_smirnov(&a, &b);
+
+        assert_eq!(mw.statistic, 0.0);
+        assert!(mw.p_value < 0.001);
+        assert_eq!(ks.statistic, 1.0);
+        assert!(ks.p_value < 0.001);
+    }
+}
@@ -0,0 +1,53 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use anyhow::Result;
+use futures::Stream;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::core::common::{ClientResult, ConnectRecord};
+
+/// The records of the probes of a client run, yielded as the probes
+/// complete. The client runs in a task of its own and the stream ends
+/// with the run, after which `finish()` returns its summary.
+pub struct ProbeStream {
+    records: mpsc::UnboundedReceiver<ConnectRecord>,
+    run: JoinHandle<Result<Vec<ClientResult>>>,
+}
+
+impl ProbeStream {
+    pub fn new(
+        records: mpsc::UnboundedReceiver<ConnectRecord>,
+        run: JoinHandle<Result<Vec<ClientResult>>>,
+    ) -> ProbeStream {
+        ProbeStream { records, run }
+    }
+
+    /// Call `callback` with each record as the probes complete,
+    /// returning the summary of each destination when the run ends.
+    pub async fn run_with<F, Fut>(mut self, mut callback: F) -> Result<Vec<ClientResult>>
+    where
+        F: FnMut(ConnectRecord) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        while let Some(record) = self.records.recv().await {
+            callback(record).await;
+        }
+        self.finish().await
+    }
+
+    /// Wait for the run to end, returning the summary of each destination.
+    pub async fn finish(self) -> Result<Vec<ClientResult>> {
+        self.run.await?
+    }
+}
+
+impl Stream for ProbeStream {
+    type Item = ConnectRecord;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<ConnectRecord>> {
+        self.records.poll_recv(cx)
+    }
+}
@@ -0,0 +1,263 @@
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use time::OffsetDateTime;
+
+use crate::core::common::{ConnectRecord, SyslogTarget};
+use crate::core::konst::EXPORT_TIMEOUT;
+use crate::util::message::client_result_msg;
+use crate::util::parser::parse_syslog_target;
+#[cfg(not(unix))]
+use crate::util::platform::PlatformFeature;
+use crate::util::sink::RecordSink;
+
+// Syslog facility `user`.
+const FACILITY: u8 = 1;
+const SEVERITY_ERROR: u8 = 3;
+const SEVERITY_INFO: u8 = 6;
+// The private enterprise number reserved for documentation.
+const SD_ID: &str = "nk@32473";
+
+enum SyslogSocket {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixDatagram),
+}
+
+/// Forwards connection records to a syslog server in RFC5424 format.
+/// Successful probes are logged with an informational severity and
+/// failed probes with an error severity. A failed write is reported
+/// without stopping the client, and the server is reconnected to on
+/// the next batch.
+pub struct SyslogWriter {
+    target: SyslogTarget,
+    socket: Option<SyslogSocket>,
+    hostname: String,
+    failing: bool,
+}
+
+impl SyslogWriter {
+    pub fn new(syslog_target: &str) -> Result<SyslogWriter> {
+        let target = parse_syslog_target(syslog_target)?;
+        let hostname = hostname::get()
+            .map(|h| h.to_string_lossy().to_string())
+            .unwrap_or_default();
+        Ok(SyslogWriter {
+            socket: Some(connect(&target)?),
+            target,
+            hostname,
+            failing: false,
+        })
+    }
+
+    fn send(&mut self, message: &str) -> Result<()> {
+        let socket = match self.socket.as_mut() {
+            Some(socket) => socket,
+            None => self.socket.insert(connect(&self.target)?),
+        };
+        let sent = match socket {
+            SyslogSocket::Udp(socket) => socket.send(message.as_bytes()).map(|_| ()),
+            // Messages on a stream are framed with their length (RFC6587).
+            SyslogSocket::Tcp(stream) => stream.write_all(format!("{} {message}", message.len()).as_bytes()),
+            #[cfg(unix)]
+            SyslogSocket::Unix(socket) => socket.send(message.as_bytes()).map(|_| ()),
+        };
+        if sent.is_err() {
+            self.socket = None;
+        }
+        Ok(sent?)
+    }
+}
+
+impl RecordSink for SyslogWriter {
+    fn write_batch(&mut self, records: &[ConnectRecord]) -> Result<()> {
+        // Records do not have a timestamp, so messages are stamped when their batch is written.
+        let now = OffsetDateTime::now_utc();
+        for record in records {
+            let message = rfc5424_msg(record, &self.hostname, now);
+            match self.send(&message) {
+                Ok(()) => self.failing = false,
+                // Only the first of a run of failures is reported.
+                Err(e) => {
+                    if !self.failing {
+                        eprintln!("syslog export to {} failed: {e}", self.target);
+                        self.failing = true;
+                    }
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Some(SyslogSocket::Tcp(stream)) = self.socket.as_mut() {
+            stream.flush()?;
+        }
+        Ok(())
+    }
+}
+
+fn connect(target: &SyslogTarget) -> Result<SyslogSocket> {
+    let timeout = Duration::from_millis(EXPORT_TIMEOUT.into());
+    let resolve = |host: &str, port: u16| match (host, port).to_socket_addrs()?.next() {
+        Some(addr) => Ok(addr),
+        None => bail!("{host} did not resolve to an IP address"),
+    };
+    match target {
+        SyslogTarget::Udp(host, port) => {
+            let addr = resolve(host, *port)?;
+            let bind_addr = match addr.is_ipv6() {
+                true => "[::]:0",
+                false => "0.0.0.0:0",
+            };
+            let socket = UdpSocket::bind(bind_addr)?;
+            socket.connect(addr)?;
+            Ok(SyslogSocket::Udp(socket))
+        }
+        SyslogTarget::Tcp(host, port) => {
+            let stream = TcpStream::connect_timeout(&resolve(host, *port)?, timeout)?;
+            stream.set_write_timeout(Some(timeout))?;
+            Ok(SyslogSocket::Tcp(stream))
+        }
+        #[cfg(unix)]
+        SyslogTarget::Unix(path) => {
+            let socket = UnixDatagram::unbound()?;
+            socket.connect(path)?;
+            Ok(SyslogSocket::Unix(socket))
+        }
+        #[cfg(not(unix))]
+        SyslogTarget::Unix(_) => bail!(PlatformFeature::UnixSocket.unsupported_msg()),
+    }
+}
+
+/// Return the RFC5424 message for a record. The result fields
+/// are structured data and the message is the console result.
+fn rfc5424_msg(record: &ConnectRecord, hostname: &str, time: OffsetDateTime) -> String {
+    let severity = match record.success {
+        true => SEVERITY_INFO,
+        false => SEVERITY_ERROR,
+    };
+    let timestamp = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        time.year(),
+        u8::from(time.month()),
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second(),
+        time.microsecond()
+    );
+    let hostname = match hostname.is_empty() {
+        true => "-",
+        false => hostname,
+    };
+
+    let mut params = vec![
+        ("protocol".to_owned(), record.protocol.to_string()),
+        ("source".to_owned(), record.source.to_owned()),
+        ("destination".to_owned(), record.destination.to_owned()),
+        ("result".to_owned(), record.result.to_string()),
+        ("time".to_owned(), record.time.to_string()),
+        ("success".to_owned(), record.success.to_string()),
+    ];
+    if let Some(label) = &record.label {
+        params.push(("label".to_owned(), label.to_owned()));
+    }
+    params.extend(
+        record
+            .tags
+            .iter()
+            .map(|(key, value)| (format!("tag.{key}"), value.to_owned())),
+    );
+    let params: String = params
+        .iter()
+        .map(|(name, value)| format!(" {}=\"{}\"", sd_name(name), sd_value(value)))
+        .collect();
+
+    format!(
+        "<{}>1 {timestamp} {hostname} nk {} probe [{SD_ID}{params}] {}",
+        FACILITY * 8 + severity,
+        std::process::id(),
+        client_result_msg(record)
+    )
+}
+
+/// Structured data names are at most 32 printable characters, other than `= ]"` and space.
+fn sd_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c.is_ascii_graphic() && !matches!(c, '=' | ']' | '"') {
+            true => c,
+            false => '_',
+        })
+        .take(32)
+        .collect()
+}
+
+/// Structured data values escape `" \ ]` with a backslash.
+fn sd_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::common::{ConnectMethod, ConnectResult};
+    use std::collections::BTreeMap;
+
+    fn connect_record(success: bool) -> ConnectRecord {
+        ConnectRecord {
+            result: match success {
+                true => ConnectResult::Ping,
+                false => ConnectResult::Refused,
+            },
+            protocol: ConnectMethod::TCP,
+            source: "127.0.0.1:50000".to_owned(),
+            destination: "127.0.0.1:443".to_owned(),
+            time: 1.5,
+            success,
+            error_msg: None,
+            tags: BTreeMap::from([("site id".to_owned(), "syd \"1\"".to_owned())]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn records_are_formatted_as_rfc5424() {
+        let time = OffsetDateTime::from_unix_timestamp(1_704_067_205).unwrap();
+        let message = rfc5424_msg(&connect_record(true), "host1", time);
+        let pid = std::process::id();
+
+        assert!(message.starts_with(&format!(
+            "<14>1 2024-01-01T00:00:05.000000Z host1 nk {pid} probe [nk@32473 "
+        )));
+        assert!(message.contains(" destination=\"127.0.0.1:443\" "));
+        assert!(message.contains(" tag.site_id=\"syd \\\"1\\\"\"]"));
+        assert!(message.ends_with(&format!("] {}", client_result_msg(&connect_record(true)))));
+        assert!(rfc5424_msg(&connect_record(false), "", time).starts_with("<11>1 2024-01-01T00:00:05.000000Z - nk "));
+    }
+
+    #[test]
+    fn records_are_sent_over_udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut writer = SyslogWriter::new(&format!("udp://{}", server.local_addr().unwrap())).unwrap();
+        writer.write_batch(&[connect_record(false)]).unwrap();
+
+        let mut buffer = [0u8; 2048];
+        let len = server.recv(&mut buffer).unwrap();
+        assert!(String::from_utf8_lossy(&buffer[..len]).starts_with("<11>1 "));
+    }
+}
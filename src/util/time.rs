@@ -34,9 +34,19 @@ pub fn calc_connect_ms(pre_timestamp: u128, post_timestamp: u128) -> f64 {
     }
 }
 
+/// Estimate the clock offset of a peer in milliseconds, NTP style.
+/// `send`, `receive`, `reply` and `return` are the client send, peer receive,
+/// peer reply and client receive unix timestamps in (u) microseconds.
+/// A positive offset means the peer clock is ahead of the local clock.
+pub fn calc_clock_offset_ms(send: u128, receive: u128, reply: u128, r#return: u128) -> f64 {
+    let outbound = receive as i128 - send as i128;
+    let inbound = reply as i128 - r#return as i128;
+    (outbound + inbound) as f64 / 2.0 / 1000.0
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::util::time::calc_connect_ms;
+    use crate::util::time::{calc_clock_offset_ms, calc_connect_ms};
 
     #[test]
     fn calc_connect_ms_returns_1ms() {
@@ -80,4 +90,16 @@ mod tests {
         let result = calc_connect_ms(pre_timestamp, post_timestamp);
         assert_eq!(result, -1.0);
     }
+    #[test]
+    fn calc_clock_offset_ms_with_peer_ahead_returns_5ms() {
+        // 2ms each way, peer clock 5ms ahead.
+        let result = calc_clock_offset_ms(10_000, 17_000, 18_000, 15_000);
+        assert_eq!(result, 5.0);
+    }
+    #[test]
+    fn calc_clock_offset_ms_with_peer_behind_returns_neg_5ms() {
+        // 2ms each way, peer clock 5ms behind.
+        let result = calc_clock_offset_ms(10_000, 7_000, 8_000, 15_000);
+        assert_eq!(result, -5.0);
+    }
 }
@@ -0,0 +1,176 @@
+use std::sync::Arc;
+
+#[cfg(feature = "quic")]
+use anyhow::Result;
+#[cfg(feature = "quic")]
+use quinn::crypto::rustls::QuicClientConfig;
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time};
+use tokio_rustls::rustls::{ClientConfig, Error, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+use crate::core::common::ConnectResult;
+
+/// TLS alerts sent when a certificate fails validation (RFC8446).
+#[cfg(feature = "quic")]
+const CERT_ALERTS: [u8; 6] = [42, 43, 44, 45, 46, 48];
+
+/// Build a TLS client config that verifies servers with the Mozilla root certificates.
+pub fn tls_client_config() -> ClientConfig {
+    let mut roots = RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth()
+}
+
+/// Build a TLS connector that verifies servers with the Mozilla root certificates.
+pub fn tls_connector() -> TlsConnector {
+    TlsConnector::from(Arc::new(tls_client_config()))
+}
+
+/// Build a QUIC client config that negotiates the ALPN protocol.
+#[cfg(feature = "quic")]
+pub fn quic_client_config(alpn: &str) -> Result<quinn::ClientConfig> {
+    let mut config = tls_client_config();
+    config.alpn_protocols = vec![alpn.as_bytes().to_vec()];
+    Ok(quinn::ClientConfig::new(Arc::new(QuicClientConfig::try_from(config)?)))
+}
+
+/// Map a failed TLS handshake to a result.
+/// Certificates that fail validation are reported separately.
+pub fn tls_error_result(e: &std::io::Error) -> ConnectResult {
+    match e.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+        Some(Error::InvalidCertificate(_)) => ConnectResult::CertError,
+        _ => ConnectResult::TlsError,
+    }
+}
+
+/// Map a TLS alert that closed a QUIC handshake to a result.
+#[cfg(feature = "quic")]
+pub fn tls_alert_result(alert: u8) -> ConnectResult {
+    match CERT_ALERTS.contains(&alert) {
+        true => ConnectResult::CertError,
+        false => ConnectResult::TlsError,
+    }
+}
+
+/// Return the number of whole days until a DER encoded certificate expires.
+/// Expired certificates have a negative number of days.
+pub fn cert_expiry_days(der: &[u8], now: OffsetDateTime) -> Option<i64> {
+    Some((cert_not_after(der)? - now).whole_days())
+}
+
+/// Return the end of the validity period of a DER encoded X.509 certificate.
+pub fn cert_not_after(der: &[u8]) -> Option<OffsetDateTime> {
+    let (_, certificate, _) = der_element(der)?;
+    let (_, mut fields, _) = der_element(certificate)?;
+    // The version is an optional explicit [0] tagged field.
+    let (tag, _, rest) = der_element(fields)?;
+    if tag == 0xa0 {
+        fields = rest;
+    }
+    // Skip the serial number, signature algorithm and issuer.
+    for _ in 0..3 {
+        fields = der_element(fields)?.2;
+    }
+    let (_, validity, _) = der_element(fields)?;
+    let (_, _not_before, rest) = der_element(validity)?;
+    let (tag, not_after, _) = der_element(rest)?;
+    parse_asn1_time(tag, not_after)
+}
+
+/// Read a DER element, returning its tag, its contents and the bytes after it.
+fn der_element(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = der.split_first()?;
+    let (&len, rest) = rest.split_first()?;
+    let (len, rest) = match len {
+        0..=0x7f => (usize::from(len), rest),
+        0x81..=0x84 => {
+            let octets = usize::from(len & 0x7f);
+            if rest.len() < octets {
+                return None;
+            }
+            let len = rest[..octets]
+                .iter()
+                .fold(0usize, |len, octet| len << 8 | usize::from(*octet));
+            (len, &rest[octets..])
+        }
+        _ => return None,
+    };
+    if rest.len() < len {
+        return None;
+    }
+    Some((tag, &rest[..len], &rest[len..]))
+}
+
+/// Parse a UTCTime (`YYMMDDHHMMSSZ`) or GeneralizedTime (`YYYYMMDDHHMMSSZ`).
+fn parse_asn1_time(tag: u8, contents: &[u8]) -> Option<OffsetDateTime> {
+    let s = std::str::from_utf8(contents).ok()?.strip_suffix('Z')?;
+    let (year, rest) = match (tag, s.len()) {
+        // UTCTime years from 50 are in the 20th century (RFC5280).
+        (0x17, 12) => {
+            let year: i32 = s[..2].parse().ok()?;
+            (if year >= 50 { 1900 + year } else { 2000 + year }, &s[2..])
+        }
+        (0x18, 14) => (s[..4].parse().ok()?, &s[4..]),
+        _ => return None,
+    };
+    let field = |i: usize| rest.get(i..i + 2)?.parse::<u8>().ok();
+    let date = Date::from_calendar_date(year, Month::try_from(field(0)?).ok()?, field(2)?).ok()?;
+    let time = Time::from_hms(field(4)?, field(6)?, field(8)?).ok()?;
+    Some(PrimitiveDateTime::new(date, time).assume_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+        [&[tag, contents.len() as u8], contents].concat()
+    }
+
+    fn certificate(not_after: Vec<u8>) -> Vec<u8> {
+        let validity = der(0x30, &[der(0x17, b"240101000000Z"), not_after].concat());
+        let tbs = [
+            der(0xa0, &der(0x02, &[2])),
+            der(0x02, &[1]),
+            der(0x30, &[]),
+            der(0x30, &[]),
+            validity,
+        ]
+        .concat();
+        der(0x30, &der(0x30, &tbs))
+    }
+
+    #[test]
+    fn cert_expiry_days_is_expected() {
+        // Monday 2024-01-01 00:00 UTC.
+        let now = OffsetDateTime::from_unix_timestamp(1_704_067_200).unwrap();
+
+        let cert = certificate(der(0x17, b"240131120000Z"));
+        assert_eq!(cert_expiry_days(&cert, now), Some(30));
+
+        let cert = certificate(der(0x18, b"20231225000000Z"));
+        assert_eq!(cert_expiry_days(&cert, now), Some(-7));
+    }
+
+    #[cfg(feature = "quic")]
+    #[test]
+    fn tls_alert_result_is_expected() {
+        // unknown_ca
+        assert_eq!(tls_alert_result(48), ConnectResult::CertError);
+        // certificate_expired
+        assert_eq!(tls_alert_result(45), ConnectResult::CertError);
+        // no_application_protocol
+        assert_eq!(tls_alert_result(120), ConnectResult::TlsError);
+    }
+
+    #[test]
+    fn invalid_certificates_have_no_expiry() {
+        let now = OffsetDateTime::now_utc();
+
+        assert_eq!(cert_expiry_days(&certificate(der(0x17, b"241301000000Z")), now), None);
+        assert_eq!(cert_expiry_days(&certificate(der(0x04, b"240101000000Z")), now), None);
+        assert_eq!(cert_expiry_days(&[0x30, 0x05, 0x30], now), None);
+    }
+}